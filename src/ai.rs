@@ -0,0 +1,219 @@
+//! What mobs decide to do, and how they go about it.
+//!
+//! Every mob has a [`Brain`] that picks one [`Behavior`] each frame: each
+//! behavior scores how much the mob wants to do it given what the mob senses,
+//! and the highest score wins. Passive mobs wander until they notice the
+//! player, then flee; hostile mobs chase a player they notice and attack once
+//! in reach. The chosen behavior steers the mob through its
+//! [`MovementInput`], like the keyboard steers the player. Mobs away from the
+//! player think only as often as their [`SimulationLod`] lets them, and
+//! dormant ones not at all.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::{
+    health::{Dead, Health},
+    lod::SimulationLod,
+    mobs::{Mob, MobKind, MobRng},
+    simulation::WorldSimulation,
+    MovementInput, Player, Position, Rotation,
+};
+
+/// How far mobs can see.
+const SIGHT_RANGE: f32 = 20.0;
+/// The cosine of half the angle of a mob's field of view.
+const SIGHT_CONE_COS: f32 = 0.5;
+/// How close the player has to be for a mob to notice them from any side.
+const NOTICE_RANGE: f32 = 4.0;
+/// How close a passive mob lets the player come before fleeing.
+const FLEE_RANGE: f32 = 8.0;
+/// How close a hostile mob has to be to the player to attack.
+const ATTACK_RANGE: f32 = 1.5;
+/// How long a mob waits between attacks, in seconds.
+const ATTACK_INTERVAL: f32 = 1.0;
+/// How much mobs want to wander when nothing else is going on.
+const WANDER_SCORE: f32 = 0.1;
+/// How long a wandering mob keeps walking in one direction, or standing
+/// still, in seconds.
+const MIN_WANDER_TIME: f32 = 2.0;
+const MAX_WANDER_TIME: f32 = 6.0;
+/// How likely a wandering mob is to stand still rather than walk when it
+/// picks what to do next.
+const IDLE_CHANCE: f32 = 0.4;
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, think.in_set(WorldSimulation));
+    }
+}
+
+/// Something a mob can be doing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Behavior {
+    #[default]
+    Wander,
+    Flee,
+    Chase,
+    Attack,
+}
+
+impl Behavior {
+    const ALL: [Behavior; 4] = [
+        Behavior::Wander,
+        Behavior::Flee,
+        Behavior::Chase,
+        Behavior::Attack,
+    ];
+
+    /// How much a mob of `kind` wants to do this, from 0 up to 1.
+    fn score(self, kind: MobKind, senses: &Senses) -> f32 {
+        match self {
+            Behavior::Wander => WANDER_SCORE,
+            Behavior::Flee if !kind.is_hostile() && senses.sees_player => {
+                (1.0 - senses.distance / FLEE_RANGE).max(0.0)
+            }
+            Behavior::Chase if kind.is_hostile() && senses.sees_player => 0.5,
+            Behavior::Attack
+                if kind.is_hostile() && senses.sees_player && senses.distance < ATTACK_RANGE =>
+            {
+                1.0
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// The mind of a mob: what it is doing and how long it has been at it.
+#[derive(Component, Default)]
+pub struct Brain {
+    pub behavior: Behavior,
+    /// Seconds until a wandering mob picks a new direction.
+    wander_time: f32,
+    /// Seconds until the mob can attack again.
+    attack_cooldown: f32,
+}
+
+/// What a mob knows about the player this frame. Nothing blocks a mob's
+/// sight yet, as there is no terrain to hide behind.
+struct Senses {
+    /// The horizontal direction from the mob towards the player.
+    towards_player: Vec3,
+    distance: f32,
+    sees_player: bool,
+}
+
+impl Senses {
+    fn new(
+        position: &Position,
+        rotation: &Rotation,
+        player: Option<&Position>,
+        tracking: bool,
+    ) -> Self {
+        let Some(player) = player else {
+            return Self {
+                towards_player: Vec3::ZERO,
+                distance: f32::INFINITY,
+                sees_player: false,
+            };
+        };
+
+        let offset = (player.current - position.current) * Vec3::new(1.0, 0.0, 1.0);
+        let distance = offset.length();
+        let towards_player = offset.normalize_or_zero();
+        let facing = Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos());
+        // A mob that is already after or running from the player keeps track
+        // of them even when it isn't looking their way.
+        let in_view = tracking || facing.dot(towards_player) > SIGHT_CONE_COS;
+        Self {
+            towards_player,
+            distance,
+            sees_player: distance < NOTICE_RANGE || (distance < SIGHT_RANGE && in_view),
+        }
+    }
+}
+
+/// The living player, whom mobs watch and attack.
+type Quarry = (&'static Position, &'static mut Health);
+
+/// The parts of a mob that thinking reads and steers.
+type MobMind = (
+    &'static Mob,
+    &'static mut Brain,
+    &'static Position,
+    &'static Rotation,
+    &'static mut MovementInput,
+    Option<&'static SimulationLod>,
+);
+
+/// Lets each living mob pick its behavior from what it senses, and carry it
+/// out.
+fn think(
+    time: Res<Time>,
+    mut rng: ResMut<MobRng>,
+    mut players: Query<Quarry, (With<Player>, Without<Dead>)>,
+    mut mobs: Query<MobMind, (Without<Dead>, Without<Player>)>,
+) {
+    let mut player = players.get_single_mut().ok();
+
+    for (mob, mut brain, position, rotation, mut input, lod) in mobs.iter_mut() {
+        let dt = match lod {
+            Some(lod) => lod.step(),
+            None => Some(time.delta_seconds()),
+        };
+        let Some(dt) = dt else {
+            continue;
+        };
+        brain.attack_cooldown = (brain.attack_cooldown - dt).max(0.0);
+
+        let tracking = brain.behavior != Behavior::Wander;
+        let senses = Senses::new(
+            position,
+            rotation,
+            player.as_ref().map(|(position, _)| *position),
+            tracking,
+        );
+        let behavior = Behavior::ALL
+            .into_iter()
+            .max_by(|a, b| {
+                a.score(mob.kind, &senses)
+                    .total_cmp(&b.score(mob.kind, &senses))
+            })
+            .unwrap_or_default();
+        if behavior != brain.behavior {
+            brain.behavior = behavior;
+            // Pick a fresh direction when going back to wandering.
+            brain.wander_time = 0.0;
+        }
+
+        input.direction = match behavior {
+            Behavior::Wander => {
+                brain.wander_time -= dt;
+                if brain.wander_time > 0.0 {
+                    continue;
+                }
+                brain.wander_time = rng.range(MIN_WANDER_TIME, MAX_WANDER_TIME);
+                if rng.next() < IDLE_CHANCE {
+                    Vec3::ZERO
+                } else {
+                    let angle = rng.range(0.0, TAU);
+                    Vec3::new(angle.cos(), 0.0, angle.sin()) * mob.kind.walk_speed()
+                }
+            }
+            Behavior::Flee => -senses.towards_player * mob.kind.run_speed(),
+            Behavior::Chase => senses.towards_player * mob.kind.run_speed(),
+            Behavior::Attack => {
+                if brain.attack_cooldown <= 0.0 {
+                    if let Some((_, health)) = player.as_mut() {
+                        health.damage(mob.kind.attack_damage());
+                    }
+                    brain.attack_cooldown = ATTACK_INTERVAL;
+                }
+                Vec3::ZERO
+            }
+        };
+    }
+}
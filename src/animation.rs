@@ -0,0 +1,194 @@
+//! Plays animations for any animated character, each with its own clip set
+//! and state machine.
+//!
+//! A character's root entity gets an [`AnimationBinding`] naming the clips it
+//! plays and an [`AnimFsm`] holding the state it is in. Gameplay systems only
+//! ever change the [`AnimFsm`]; this module finds the [`AnimationPlayer`] that
+//! the character's scene spawns, links the two with an [`AnimationOwner`], and
+//! keeps the player playing the clip for the current state. Every character is
+//! handled on its own, so any number of them can animate side by side.
+
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How long it takes to blend from one state's clip to the next.
+const TRANSITION_DURATION: Duration = Duration::from_millis(250);
+
+pub struct CharacterAnimationPlugin;
+
+impl Plugin for CharacterAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (bind_animation_players, play_animation_states).chain(),
+        );
+    }
+}
+
+/// The animation states a character can be in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub enum AnimState {
+    #[default]
+    Idle,
+    Run,
+    Attack,
+    Dead,
+}
+
+/// A clip in an animation graph, the speed it plays at, and whether it
+/// loops.
+#[derive(Clone, Copy)]
+struct Clip {
+    node: AnimationNodeIndex,
+    speed: f32,
+    repeat: bool,
+}
+
+/// The animation graph of a kind of character and the clip it plays in each
+/// state. Cheap to clone, so characters of the same kind can share one.
+#[derive(Clone)]
+pub struct ClipSet {
+    graph: Handle<AnimationGraph>,
+    clips: HashMap<AnimState, Clip>,
+}
+
+impl ClipSet {
+    pub fn new(graph: Handle<AnimationGraph>) -> Self {
+        Self {
+            graph,
+            clips: HashMap::new(),
+        }
+    }
+
+    /// Plays the clip at `node` of the graph, looping at `speed`, whenever the
+    /// character is in `state`.
+    pub fn with_clip(mut self, state: AnimState, node: AnimationNodeIndex, speed: f32) -> Self {
+        self.clips.insert(
+            state,
+            Clip {
+                node,
+                speed,
+                repeat: true,
+            },
+        );
+        self
+    }
+
+    /// Plays the clip at `node` of the graph once at `speed` whenever the
+    /// character enters `state`, holding its last frame afterwards.
+    pub fn with_clip_once(
+        mut self,
+        state: AnimState,
+        node: AnimationNodeIndex,
+        speed: f32,
+    ) -> Self {
+        self.clips.insert(
+            state,
+            Clip {
+                node,
+                speed,
+                repeat: false,
+            },
+        );
+        self
+    }
+}
+
+/// Links a character to the clips it plays.
+#[derive(Component)]
+pub struct AnimationBinding {
+    pub clips: ClipSet,
+}
+
+impl AnimationBinding {
+    pub fn new(clips: ClipSet) -> Self {
+        Self { clips }
+    }
+}
+
+/// Points from an [`AnimationPlayer`] back to the character that owns it,
+/// which may be several levels up the scene hierarchy.
+#[derive(Component)]
+pub struct AnimationOwner(pub Entity);
+
+/// The animation state machine of a character.
+#[derive(Component, Default)]
+pub struct AnimFsm {
+    state: AnimState,
+    /// The state whose clip is playing, if any.
+    playing: Option<AnimState>,
+}
+
+impl AnimFsm {
+    pub fn state(&self) -> AnimState {
+        self.state
+    }
+
+    /// Moves to `state`, which starts its clip on the next update.
+    pub fn set(&mut self, state: AnimState) {
+        self.state = state;
+    }
+}
+
+/// Connects newly spawned animation players to the character whose scene
+/// they belong to, by walking up the hierarchy to the nearest
+/// [`AnimationBinding`].
+fn bind_animation_players(
+    mut commands: Commands,
+    players: Query<Entity, Added<AnimationPlayer>>,
+    parents: Query<&Parent>,
+    mut bindings: Query<(&AnimationBinding, Option<&mut AnimFsm>)>,
+) {
+    for player in players.iter() {
+        let Some(owner) = parents
+            .iter_ancestors(player)
+            .find(|&ancestor| bindings.contains(ancestor))
+        else {
+            continue;
+        };
+
+        let (binding, fsm) = bindings.get_mut(owner).unwrap();
+        commands.entity(player).insert((
+            AnimationOwner(owner),
+            binding.clips.graph.clone(),
+            AnimationTransitions::new(),
+        ));
+
+        // Restart the current state's clip on the new player.
+        if let Some(mut fsm) = fsm {
+            fsm.playing = None;
+        }
+    }
+}
+
+/// Starts the clip for each character's state when the state changes.
+fn play_animation_states(
+    mut players: Query<(
+        &AnimationOwner,
+        &mut AnimationPlayer,
+        &mut AnimationTransitions,
+    )>,
+    mut characters: Query<(&AnimationBinding, &mut AnimFsm)>,
+) {
+    for (owner, mut player, mut transitions) in players.iter_mut() {
+        let Ok((binding, mut fsm)) = characters.get_mut(owner.0) else {
+            continue;
+        };
+        if fsm.playing == Some(fsm.state) {
+            continue;
+        }
+
+        let state = fsm.state;
+        if let Some(clip) = binding.clips.clips.get(&state) {
+            let animation = transitions
+                .play(&mut player, clip.node, TRANSITION_DURATION)
+                .set_speed(clip.speed);
+            if clip.repeat {
+                animation.repeat();
+            }
+        }
+        fsm.playing = Some(state);
+    }
+}
@@ -0,0 +1,372 @@
+//! A scripted benchmark that flies the camera along a fixed path and reports
+//! frame rate statistics, exportable to JSON for comparing hardware and
+//! builds.
+//!
+//! Besides whole frames, it times the simulation, the preparation for
+//! rendering and the UI on their own, from just before the first system of
+//! each of their sets to just after the last. Systems outside a set that run
+//! alongside it count towards it too, so these are upper bounds.
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    ecs::schedule::{InternedSystemSet, ScheduleLabel},
+    math::cubic_splines::CubicCurve,
+    prelude::*,
+    render::{renderer::RenderAdapterInfo, view::VisibilitySystems},
+    transform::TransformSystem,
+    ui::UiSystem,
+    utils::HashMap,
+};
+use bevy_egui::{egui, EguiContexts, EguiSet};
+use serde::Serialize;
+
+use crate::{menu::PauseState, simulation::WorldSimulation, MainCamera};
+
+/// How long the camera takes to fly the whole path, in seconds.
+const BENCHMARK_DURATION: f32 = 30.0;
+/// The points the benchmark camera flies through, in order.
+const BENCHMARK_CAMERA_PATH: [Vec3; 8] = [
+    Vec3::new(-40.0, 20.0, -40.0),
+    Vec3::new(0.0, 12.0, -50.0),
+    Vec3::new(40.0, 25.0, -30.0),
+    Vec3::new(50.0, 6.0, 10.0),
+    Vec3::new(20.0, 15.0, 45.0),
+    Vec3::new(-25.0, 30.0, 40.0),
+    Vec3::new(-50.0, 8.0, 0.0),
+    Vec3::new(-40.0, 20.0, -40.0),
+];
+/// The directory exported results are written to.
+const BENCHMARK_OUTPUT_DIR: &str = "benchmarks";
+
+pub struct BenchmarkPlugin;
+
+impl Plugin for BenchmarkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<BenchmarkState>()
+            .add_systems(OnEnter(BenchmarkState::Running), start_benchmark)
+            .add_systems(First, start_update_timer.run_if(recording()))
+            .add_systems(Last, stop_update_timer.run_if(recording()))
+            // Every fixed step is simulation.
+            .add_systems(FixedFirst, start_fixed_step_timer.run_if(recording()))
+            .add_systems(FixedLast, stop_fixed_step_timer.run_if(recording()))
+            .add_systems(
+                Update,
+                (
+                    fly_benchmark_camera.run_if(recording()),
+                    benchmark_results.run_if(in_state(BenchmarkState::Results)),
+                ),
+            );
+
+        time_set(app, Update, WorldSimulation, Subsystem::Simulation);
+        time_set(
+            app,
+            PostUpdate,
+            TransformSystem::TransformPropagate,
+            Subsystem::RenderPrep,
+        );
+        time_set(
+            app,
+            PostUpdate,
+            VisibilitySystems::CheckVisibility,
+            Subsystem::RenderPrep,
+        );
+        time_set(app, PreUpdate, EguiSet::BeginFrame, Subsystem::Ui);
+        time_set(app, PostUpdate, UiSystem::Layout, Subsystem::Ui);
+        time_set(app, PostUpdate, EguiSet::ProcessOutput, Subsystem::Ui);
+    }
+}
+
+/// Whether the benchmark is measuring frames. The run is gone for the rest
+/// of the frame it finishes in, while the state is still running.
+fn recording() -> impl Condition<()> {
+    resource_exists::<BenchmarkRun>.and_then(in_state(PauseState::Running))
+}
+
+/// Times `set` in `schedule` as part of `subsystem`.
+fn time_set(
+    app: &mut App,
+    schedule: impl ScheduleLabel + Clone,
+    set: impl SystemSet + Clone,
+    subsystem: Subsystem,
+) {
+    let key = set.intern();
+    app.add_systems(
+        schedule.clone(),
+        (move |mut run: ResMut<BenchmarkRun>| {
+            run.set_started.insert(key, Instant::now());
+        })
+        .before(set.clone())
+        .run_if(recording()),
+    )
+    .add_systems(
+        schedule,
+        (move |mut run: ResMut<BenchmarkRun>| {
+            if let Some(started) = run.set_started.remove(&key) {
+                run.add_frame_time(subsystem, started);
+            }
+        })
+        .after(set)
+        .run_if(recording()),
+    );
+}
+
+/// The parts of a frame that are timed on their own.
+#[derive(Clone, Copy)]
+enum Subsystem {
+    Simulation,
+    RenderPrep,
+    Ui,
+}
+
+/// Whether the benchmark is running or showing its results.
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum BenchmarkState {
+    #[default]
+    Idle,
+    Running,
+    Results,
+}
+
+/// The measurements of the benchmark in progress.
+#[derive(Resource)]
+struct BenchmarkRun {
+    camera_path: CubicCurve<Vec3>,
+    elapsed: f32,
+    frame_times_ms: Vec<f32>,
+    /// How long each frame spent updating the main world, from `First` to
+    /// `Last`, in milliseconds.
+    update_times_ms: Vec<f32>,
+    update_started: Option<Instant>,
+    /// How long each frame spent in each [`Subsystem`], in milliseconds.
+    subsystem_times_ms: [Vec<f32>; 3],
+    /// How long this frame has spent in each [`Subsystem`] so far.
+    frame_subsystem_ms: [f32; 3],
+    /// When each timed set that is running started.
+    set_started: HashMap<InternedSystemSet, Instant>,
+    fixed_step_started: Option<Instant>,
+}
+
+impl BenchmarkRun {
+    /// Counts the time since `started` towards this frame's `subsystem`.
+    fn add_frame_time(&mut self, subsystem: Subsystem, started: Instant) {
+        self.frame_subsystem_ms[subsystem as usize] += started.elapsed().as_secs_f32() * 1000.0;
+    }
+}
+
+/// The summary of a finished benchmark.
+#[derive(Resource, Serialize)]
+struct BenchmarkResults {
+    version: String,
+    adapter: String,
+    backend: String,
+    cpu_threads: usize,
+    duration_seconds: f32,
+    frames: usize,
+    average_fps: f32,
+    one_percent_low_fps: f32,
+    average_frame_time_ms: f32,
+    worst_frame_time_ms: f32,
+    average_update_time_ms: f32,
+    /// How long each frame spent in the fixed steps and the world
+    /// simulation.
+    average_simulation_time_ms: f32,
+    /// How long each frame spent propagating transforms and working out
+    /// what is visible.
+    average_render_prep_time_ms: f32,
+    /// How long each frame spent laying out the HUD and running egui.
+    average_ui_time_ms: f32,
+    /// The outcome of the last export, shown under the results.
+    #[serde(skip)]
+    export_status: Option<String>,
+}
+
+fn start_benchmark(mut commands: Commands) {
+    commands.insert_resource(BenchmarkRun {
+        camera_path: CubicCardinalSpline::new_catmull_rom(BENCHMARK_CAMERA_PATH).to_curve(),
+        elapsed: 0.0,
+        frame_times_ms: Vec::new(),
+        update_times_ms: Vec::new(),
+        update_started: None,
+        subsystem_times_ms: Default::default(),
+        frame_subsystem_ms: [0.0; 3],
+        set_started: HashMap::new(),
+        fixed_step_started: None,
+    });
+}
+
+fn start_update_timer(mut run: ResMut<BenchmarkRun>) {
+    run.update_started = Some(Instant::now());
+}
+
+fn stop_update_timer(mut run: ResMut<BenchmarkRun>) {
+    if let Some(started) = run.update_started.take() {
+        run.update_times_ms
+            .push(started.elapsed().as_secs_f32() * 1000.0);
+    }
+    let run = &mut *run;
+    for (times, frame_time) in run
+        .subsystem_times_ms
+        .iter_mut()
+        .zip(&mut run.frame_subsystem_ms)
+    {
+        times.push(std::mem::take(frame_time));
+    }
+}
+
+fn start_fixed_step_timer(mut run: ResMut<BenchmarkRun>) {
+    run.fixed_step_started = Some(Instant::now());
+}
+
+fn stop_fixed_step_timer(mut run: ResMut<BenchmarkRun>) {
+    if let Some(started) = run.fixed_step_started.take() {
+        run.add_frame_time(Subsystem::Simulation, started);
+    }
+}
+
+/// Moves the camera along the benchmark path, records the frame time, and
+/// finishes the benchmark at the end of the path.
+fn fly_benchmark_camera(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    adapter_info: Option<Res<RenderAdapterInfo>>,
+    mut run: ResMut<BenchmarkRun>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+    mut next_state: ResMut<NextState<BenchmarkState>>,
+) {
+    run.elapsed += time.delta_seconds();
+    run.frame_times_ms.push(time.delta_seconds() * 1000.0);
+
+    let progress = (run.elapsed / BENCHMARK_DURATION).min(1.0);
+    let segments = run.camera_path.segments().len() as f32;
+    let position = run.camera_path.position(progress * segments);
+    for mut transform in cameras.iter_mut() {
+        *transform = Transform::from_translation(position).looking_at(Vec3::ZERO, Vec3::Y);
+    }
+
+    if progress >= 1.0 {
+        commands.insert_resource(summarize(&run, adapter_info.as_deref()));
+        commands.remove_resource::<BenchmarkRun>();
+        next_state.set(BenchmarkState::Results);
+    }
+}
+
+fn summarize(run: &BenchmarkRun, adapter_info: Option<&RenderAdapterInfo>) -> BenchmarkResults {
+    let frames = run.frame_times_ms.len();
+    let average = |times: &[f32]| times.iter().sum::<f32>() / times.len().max(1) as f32;
+
+    // The 1% low is the frame rate over the slowest 1% of frames.
+    let mut sorted = run.frame_times_ms.clone();
+    sorted.sort_by(|a, b| b.total_cmp(a));
+    let slowest = &sorted[..(frames / 100).max(1).min(frames)];
+
+    let average_frame_time_ms = average(&run.frame_times_ms);
+    BenchmarkResults {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        adapter: adapter_info.map_or_else(|| "unknown".to_string(), |info| info.name.clone()),
+        backend: adapter_info.map_or_else(
+            || "unknown".to_string(),
+            |info| format!("{:?}", info.backend),
+        ),
+        cpu_threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        duration_seconds: run.elapsed,
+        frames,
+        average_fps: 1000.0 / average_frame_time_ms,
+        one_percent_low_fps: 1000.0 / average(slowest),
+        average_frame_time_ms,
+        worst_frame_time_ms: sorted.first().copied().unwrap_or_default(),
+        average_update_time_ms: average(&run.update_times_ms),
+        average_simulation_time_ms: average(
+            &run.subsystem_times_ms[Subsystem::Simulation as usize],
+        ),
+        average_render_prep_time_ms: average(
+            &run.subsystem_times_ms[Subsystem::RenderPrep as usize],
+        ),
+        average_ui_time_ms: average(&run.subsystem_times_ms[Subsystem::Ui as usize]),
+        export_status: None,
+    }
+}
+
+fn benchmark_results(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut results: ResMut<BenchmarkResults>,
+    mut next_state: ResMut<NextState<BenchmarkState>>,
+) {
+    egui::Window::new("Benchmark results")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("benchmark_results")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let rows = [
+                        (
+                            "Adapter",
+                            format!("{} ({})", results.adapter, results.backend),
+                        ),
+                        ("Frames", results.frames.to_string()),
+                        ("Average FPS", format!("{:.1}", results.average_fps)),
+                        ("1% low FPS", format!("{:.1}", results.one_percent_low_fps)),
+                        (
+                            "Average frame time",
+                            format!("{:.2} ms", results.average_frame_time_ms),
+                        ),
+                        (
+                            "Worst frame time",
+                            format!("{:.2} ms", results.worst_frame_time_ms),
+                        ),
+                        (
+                            "Average update time",
+                            format!("{:.2} ms", results.average_update_time_ms),
+                        ),
+                        (
+                            "  Simulation",
+                            format!("{:.2} ms", results.average_simulation_time_ms),
+                        ),
+                        (
+                            "  Rendering prep",
+                            format!("{:.2} ms", results.average_render_prep_time_ms),
+                        ),
+                        ("  UI", format!("{:.2} ms", results.average_ui_time_ms)),
+                    ];
+                    for (label, value) in rows {
+                        ui.label(label);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
+
+            if let Some(status) = &results.export_status {
+                ui.label(status);
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Export JSON").clicked() {
+                    results.export_status = Some(match export(&results) {
+                        Ok(path) => format!("Saved to {}", path.display()),
+                        Err(error) => format!("Export failed: {error}"),
+                    });
+                }
+                if ui.button("Close").clicked() {
+                    commands.remove_resource::<BenchmarkResults>();
+                    next_state.set(BenchmarkState::Idle);
+                }
+            });
+        });
+}
+
+/// Writes the results to a timestamped JSON file and returns its path.
+fn export(results: &BenchmarkResults) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = PathBuf::from(BENCHMARK_OUTPUT_DIR).join(format!("benchmark-{timestamp}.json"));
+
+    fs::create_dir_all(BENCHMARK_OUTPUT_DIR)?;
+    fs::write(&path, serde_json::to_string_pretty(results)?)?;
+    Ok(path)
+}
@@ -0,0 +1,334 @@
+//! Chatting with the other players, and commands typed into the chat.
+//!
+//! T opens the chat, and / opens it with a command started. Enter sends what
+//! was typed and Esc closes it; while it is open, keys type into it rather
+//! than playing. Messages go to everyone in the session, through the server.
+//! Lines starting with `/` are commands instead, which run on this game
+//! only: `/help` lists them, and other modules add their own with
+//! [`ChatCommandsExt::add_chat_command`]. While the chat is closed, new lines
+//! show for a few seconds before fading away.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::{ecs::system::SystemId, input::InputSystem, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{menu::PauseState, net::NetClient, photo_mode::PhotoMode, Player, Position};
+
+/// The most characters a chat message can have.
+pub const MAX_CHAT_LENGTH: usize = 256;
+/// How many lines the chat keeps.
+const MAX_CHAT_LINES: usize = 100;
+/// How long new lines show while the chat is closed, in seconds, and how
+/// long they then take to fade away.
+const LINE_SHOW_TIME: f32 = 8.0;
+const LINE_FADE_TIME: f32 = 1.0;
+const CHAT_WIDTH: f32 = 420.0;
+const CHAT_HEIGHT: f32 = 200.0;
+const SENDER_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 215, 90);
+const INFO_COLOR: egui::Color32 = egui::Color32::from_rgb(170, 200, 255);
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Chat>()
+            .init_resource::<ChatLog>()
+            .init_resource::<ChatCommands>()
+            .add_event::<SendChat>()
+            .add_chat_command("help", "", "Lists the commands", help)
+            .add_chat_command("tp", "<x> <y> <z>", "Moves the player to a place", teleport)
+            .add_chat_command("seed", "", "Shows the seed the world was made from", seed)
+            .add_systems(
+                PreUpdate,
+                block_game_input
+                    .after(InputSystem)
+                    .run_if(|chat: Res<Chat>| chat.open),
+            )
+            .add_systems(
+                Update,
+                (
+                    open_chat
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off)))
+                        .run_if(|chat: Res<Chat>| !chat.open),
+                    chat_window.run_if(in_state(PhotoMode::Off)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether the chat is open, and what has been typed into it.
+#[derive(Resource, Default)]
+struct Chat {
+    open: bool,
+    draft: String,
+}
+
+/// Everything said in the chat, and the replies to commands.
+#[derive(Resource, Default)]
+pub struct ChatLog {
+    lines: VecDeque<ChatLine>,
+}
+
+/// A line of the chat.
+struct ChatLine {
+    /// Who said it, or `None` for replies to commands and other notices.
+    from: Option<String>,
+    text: String,
+    /// Real seconds since the line was added.
+    age: f32,
+}
+
+impl ChatLog {
+    /// Adds something a player said.
+    pub fn message(&mut self, from: impl Into<String>, text: impl Into<String>) {
+        self.push(Some(from.into()), text.into());
+    }
+
+    /// Adds a notice from the game, such as a command's reply.
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(None, text.into());
+    }
+
+    fn push(&mut self, from: Option<String>, text: String) {
+        self.lines.push_back(ChatLine {
+            from,
+            text,
+            age: 0.0,
+        });
+        if self.lines.len() > MAX_CHAT_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+/// Asks for a message to be sent to everyone in the session.
+#[derive(Event)]
+pub struct SendChat(pub String);
+
+/// The words typed after a command's name.
+pub type CommandArgs = Vec<String>;
+
+/// A command that can be typed into the chat.
+struct ChatCommand {
+    /// The arguments the command takes, as shown by `/help`.
+    usage: &'static str,
+    help: &'static str,
+    system: SystemId<CommandArgs>,
+}
+
+/// Every chat command, by name.
+#[derive(Resource, Default)]
+struct ChatCommands(BTreeMap<&'static str, ChatCommand>);
+
+/// Adds commands to the chat.
+pub trait ChatCommandsExt {
+    /// Makes `/name` run `system` with the words typed after it. The system
+    /// answers through the [`ChatLog`].
+    fn add_chat_command<M>(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        system: impl IntoSystem<CommandArgs, (), M> + 'static,
+    ) -> &mut Self;
+}
+
+impl ChatCommandsExt for App {
+    fn add_chat_command<M>(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        system: impl IntoSystem<CommandArgs, (), M> + 'static,
+    ) -> &mut Self {
+        let world = self.world_mut();
+        let system = world.register_system(system);
+        // Other plugins may add their commands before this one is built.
+        world
+            .get_resource_or_insert_with(ChatCommands::default)
+            .0
+            .insert(
+                name,
+                ChatCommand {
+                    usage,
+                    help,
+                    system,
+                },
+            );
+        self
+    }
+}
+
+/// Keeps the keys typed into the chat from also moving the player and the
+/// like, and clicks on it from swinging. The chat reads them through egui,
+/// which has already had them.
+fn block_game_input(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut buttons: ResMut<ButtonInput<MouseButton>>,
+) {
+    keys.reset_all();
+    buttons.reset_all();
+}
+
+fn open_chat(input: Res<ButtonInput<KeyCode>>, mut chat: ResMut<Chat>) {
+    let draft = if input.just_pressed(KeyCode::KeyT) {
+        ""
+    } else if input.just_pressed(KeyCode::Slash) {
+        "/"
+    } else {
+        return;
+    };
+    chat.open = true;
+    chat.draft = draft.to_string();
+}
+
+/// Shows the chat, and sends or runs what is typed into it. While it is
+/// closed, only the lines that just came in show.
+fn chat_window(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    time: Res<Time<Real>>,
+    mut chat: ResMut<Chat>,
+    mut log: ResMut<ChatLog>,
+    registry: Res<ChatCommands>,
+    mut outgoing: EventWriter<SendChat>,
+) {
+    for line in log.lines.iter_mut() {
+        line.age += time.delta_seconds();
+    }
+    let chat = &mut *chat;
+
+    let mut submitted = None;
+    egui::Area::new(egui::Id::new("chat"))
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(8.0, -90.0))
+        .interactable(chat.open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.set_width(CHAT_WIDTH);
+            if !chat.open {
+                for line in log.lines.iter() {
+                    let fade =
+                        ((LINE_SHOW_TIME + LINE_FADE_TIME - line.age) / LINE_FADE_TIME).min(1.0);
+                    if fade > 0.0 {
+                        ui.label(line_text(line, fade));
+                    }
+                }
+                return;
+            }
+
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(CHAT_HEIGHT)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in log.lines.iter() {
+                            ui.label(line_text(line, 1.0));
+                        }
+                    });
+                let draft = ui.add(
+                    egui::TextEdit::singleline(&mut chat.draft)
+                        .char_limit(MAX_CHAT_LENGTH)
+                        .desired_width(f32::INFINITY),
+                );
+                draft.request_focus();
+                let (enter, escape) = ui.input(|input| {
+                    (
+                        input.key_pressed(egui::Key::Enter),
+                        input.key_pressed(egui::Key::Escape),
+                    )
+                });
+                if enter {
+                    submitted = Some(std::mem::take(&mut chat.draft));
+                }
+                if enter || escape {
+                    chat.open = false;
+                }
+            });
+        });
+
+    let Some(text) = submitted else {
+        return;
+    };
+    let text = text.trim();
+    if let Some(command) = text.strip_prefix('/') {
+        log.info(format!("> {text}"));
+        let mut words = command.split_whitespace().map(str::to_string);
+        let name = words.next().unwrap_or_default();
+        match registry.0.get(name.as_str()) {
+            Some(command) => commands.run_system_with_input(command.system, words.collect()),
+            None => log.info(format!("Unknown command /{name}. /help lists them.")),
+        }
+    } else if !text.is_empty() {
+        outgoing.send(SendChat(text.to_string()));
+    }
+}
+
+/// A chat line as shown, `fade` of the way opaque.
+fn line_text(line: &ChatLine, fade: f32) -> egui::text::LayoutJob {
+    let alpha = |color: egui::Color32| color.gamma_multiply(fade);
+    let format = |color: egui::Color32| egui::TextFormat {
+        color: alpha(color),
+        ..default()
+    };
+    let mut job = egui::text::LayoutJob::default();
+    match &line.from {
+        Some(from) => {
+            job.append(&format!("{from}: "), 0.0, format(SENDER_COLOR));
+            job.append(&line.text, 0.0, format(egui::Color32::WHITE));
+        }
+        None => job.append(&line.text, 0.0, format(INFO_COLOR)),
+    }
+    job
+}
+
+fn help(_: In<CommandArgs>, registry: Res<ChatCommands>, mut log: ResMut<ChatLog>) {
+    for (name, command) in registry.0.iter() {
+        let usage = if command.usage.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", command.usage)
+        };
+        log.info(format!("/{name}{usage}: {}", command.help));
+    }
+}
+
+/// Moves the player straight to the place given, unless the game joined a
+/// server, which decides where its players are.
+fn teleport(
+    In(args): In<CommandArgs>,
+    mut log: ResMut<ChatLog>,
+    client: Option<Res<NetClient>>,
+    mut players: Query<(&mut Position, &mut Transform), With<Player>>,
+) {
+    if client.is_some() {
+        log.info("Only the host can teleport");
+        return;
+    }
+    // `parse` takes "nan" and "inf" too, which aren't anywhere.
+    let coordinates: Option<Vec<f32>> = args
+        .iter()
+        .map(|arg| {
+            arg.parse()
+                .ok()
+                .filter(|coordinate: &f32| coordinate.is_finite())
+        })
+        .collect();
+    let Some(&[x, y, z]) = coordinates.as_deref() else {
+        log.info("Usage: /tp <x> <y> <z>");
+        return;
+    };
+    let Ok((mut position, mut transform)) = players.get_single_mut() else {
+        return;
+    };
+
+    // Below the ground, the player would only fall through it.
+    let spot = Vec3::new(x, y.max(0.0), z);
+    position.teleport(spot);
+    transform.translation = spot;
+    log.info(format!("Teleported to {x:.1} {:.1} {z:.1}", spot.y));
+}
+
+fn seed(_: In<CommandArgs>, mut log: ResMut<ChatLog>) {
+    log.info("This world isn't generated, so it has no seed");
+}
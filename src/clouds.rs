@@ -0,0 +1,264 @@
+//! A procedurally textured cloud layer that drifts with the wind and casts
+//! soft moving shadows onto the ground below.
+//!
+//! The clouds seen in the sky are blended, which can't cast shadows, so a
+//! second, hidden layer casts them instead. Only the sun sees it, on
+//! [`CLOUD_SHADOW_LAYER`]. Its texture dithers the cloud density into a fine
+//! pattern of cut-outs, which the shadow filtering blurs into soft edges
+//! instead of the hard ones a plain alpha cut-off gives.
+
+use bevy::{
+    math::Affine2,
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+        view::{Layer, RenderLayers},
+    },
+};
+
+use crate::{settings::GraphicsSettings, simulation::WorldSimulation, wind::Wind};
+
+/// The height of the cloud layer above the ground.
+const CLOUD_ALTITUDE: f32 = 40.0;
+/// Half of the side length of the cloud layer.
+const CLOUD_LAYER_HALF_SIZE: f32 = 300.0;
+/// How many times the cloud texture repeats across the layer.
+const CLOUD_TEXTURE_REPEAT: f32 = 6.0;
+/// The side length of the generated cloud texture, in pixels.
+const CLOUD_TEXTURE_SIZE: u32 = 256;
+/// The number of noise cells across the texture at the coarsest octave.
+const CLOUD_NOISE_PERIOD: u32 = 4;
+const CLOUD_NOISE_OCTAVES: u32 = 5;
+/// The noise value above which the sky is covered by cloud.
+const CLOUD_COVERAGE: f32 = 0.5;
+/// How gradually cloud edges fade out.
+const CLOUD_EDGE_SOFTNESS: f32 = 0.12;
+/// The render layer of the layer that casts the clouds' shadows, which the
+/// sun sees but cameras don't.
+pub const CLOUD_SHADOW_LAYER: Layer = 1;
+/// A 4x4 ordered dithering matrix, for turning cloud density into the share
+/// of texels that cast a shadow.
+const DITHER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+pub struct CloudsPlugin;
+
+impl Plugin for CloudsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_clouds).add_systems(
+            Update,
+            (scroll_clouds.in_set(WorldSimulation), toggle_clouds),
+        );
+    }
+}
+
+/// Marks the cloud layer entity.
+#[derive(Component)]
+struct CloudLayer;
+
+fn spawn_clouds(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    graphics_settings: Res<GraphicsSettings>,
+) {
+    let density = cloud_density();
+    let mut cloud_material = |texture: Image, alpha_mode| StandardMaterial {
+        base_color_texture: Some(images.add(texture)),
+        alpha_mode,
+        unlit: true,
+        // The layer is mostly seen from below.
+        double_sided: true,
+        cull_mode: None,
+        uv_transform: Affine2::from_scale(Vec2::splat(CLOUD_TEXTURE_REPEAT)),
+        ..default()
+    };
+    let material = materials.add(cloud_material(cloud_texture(&density), AlphaMode::Blend));
+    // Masked materials still cast shadows, unlike blended ones, which is what
+    // projects the clouds onto the terrain.
+    let shadow_material = materials.add(cloud_material(
+        cloud_shadow_texture(&density),
+        AlphaMode::Mask(0.5),
+    ));
+
+    let mesh = meshes.add(Mesh::from(Plane3d {
+        normal: Dir3::Y,
+        half_size: Vec2::splat(CLOUD_LAYER_HALF_SIZE),
+    }));
+
+    commands.spawn((
+        PbrBundle {
+            mesh: mesh.clone(),
+            material,
+            transform: Transform::from_xyz(0.0, CLOUD_ALTITUDE, 0.0),
+            visibility: cloud_visibility(&graphics_settings),
+            ..default()
+        },
+        NotShadowCaster,
+        NotShadowReceiver,
+        CloudLayer,
+    ));
+    commands.spawn((
+        PbrBundle {
+            mesh,
+            material: shadow_material,
+            transform: Transform::from_xyz(0.0, CLOUD_ALTITUDE, 0.0),
+            visibility: cloud_visibility(&graphics_settings),
+            ..default()
+        },
+        NotShadowReceiver,
+        RenderLayers::layer(CLOUD_SHADOW_LAYER),
+        CloudLayer,
+    ));
+}
+
+/// Drifts the cloud texture along with the wind.
+fn scroll_clouds(
+    time: Res<Time>,
+    wind: Res<Wind>,
+    clouds: Query<&Handle<StandardMaterial>, With<CloudLayer>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Convert the wind speed from world units into texture repeats.
+    let drift = wind.direction * wind.strength * time.delta_seconds() * CLOUD_TEXTURE_REPEAT
+        / (2.0 * CLOUD_LAYER_HALF_SIZE);
+
+    for handle in clouds.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            let offset = material.uv_transform.translation - drift;
+            // Wrap the offset so it never grows large enough to lose precision.
+            material.uv_transform.translation = offset.rem_euclid(Vec2::ONE);
+        }
+    }
+}
+
+/// Shows or hides the clouds when the graphics settings change.
+fn toggle_clouds(
+    graphics_settings: Res<GraphicsSettings>,
+    mut clouds: Query<&mut Visibility, With<CloudLayer>>,
+) {
+    if !graphics_settings.is_changed() {
+        return;
+    }
+
+    for mut visibility in clouds.iter_mut() {
+        *visibility = cloud_visibility(&graphics_settings);
+    }
+}
+
+fn cloud_visibility(graphics_settings: &GraphicsSettings) -> Visibility {
+    if graphics_settings.clouds {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    }
+}
+
+/// The tileable cloud density, between 0 and 1, of each texel of the cloud
+/// textures, row by row.
+fn cloud_density() -> Vec<f32> {
+    let mut density = Vec::with_capacity((CLOUD_TEXTURE_SIZE * CLOUD_TEXTURE_SIZE) as usize);
+    for y in 0..CLOUD_TEXTURE_SIZE {
+        for x in 0..CLOUD_TEXTURE_SIZE {
+            let uv = Vec2::new(x as f32, y as f32) / CLOUD_TEXTURE_SIZE as f32;
+            density.push(smoothstep(
+                CLOUD_COVERAGE - CLOUD_EDGE_SOFTNESS,
+                CLOUD_COVERAGE + CLOUD_EDGE_SOFTNESS,
+                fractal_noise(uv),
+            ));
+        }
+    }
+    density
+}
+
+/// Generates a white cloud texture whose alpha holds the cloud density.
+fn cloud_texture(density: &[f32]) -> Image {
+    let alpha = density.iter().map(|&density| (density * 255.0) as u8);
+    white_texture(alpha, ImageSamplerDescriptor::linear())
+}
+
+/// Generates the shadow layer's texture, whose texels are either clear or
+/// solid, with more of them solid the denser the cloud.
+fn cloud_shadow_texture(density: &[f32]) -> Image {
+    let alpha = density.iter().enumerate().map(|(index, &density)| {
+        let x = index % CLOUD_TEXTURE_SIZE as usize;
+        let y = index / CLOUD_TEXTURE_SIZE as usize;
+        let threshold = (DITHER_MATRIX[y % 4][x % 4] as f32 + 0.5) / 16.0;
+        if density > threshold {
+            255
+        } else {
+            0
+        }
+    });
+    // Blending neighbouring texels would smooth the pattern away again.
+    white_texture(alpha, ImageSamplerDescriptor::nearest())
+}
+
+/// Builds a tiling white texture with the given alpha for each texel.
+fn white_texture(alpha: impl Iterator<Item = u8>, sampler: ImageSamplerDescriptor) -> Image {
+    let data = alpha.flat_map(|alpha| [255, 255, 255, alpha]).collect();
+
+    let mut image = Image::new(
+        Extent3d {
+            width: CLOUD_TEXTURE_SIZE,
+            height: CLOUD_TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..sampler
+    });
+    image
+}
+
+/// Sums octaves of tileable value noise into a value between 0 and 1.
+fn fractal_noise(uv: Vec2) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut total_amplitude = 0.0;
+    for octave in 0..CLOUD_NOISE_OCTAVES {
+        sum += value_noise(uv, CLOUD_NOISE_PERIOD << octave, octave) * amplitude;
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+    }
+    sum / total_amplitude
+}
+
+/// Smoothly interpolated random values on a lattice that wraps every
+/// `period` cells, so the texture tiles seamlessly.
+fn value_noise(uv: Vec2, period: u32, seed: u32) -> f32 {
+    let p = uv * period as f32;
+    let cell = p.floor();
+    let f = p - cell;
+    let f = f * f * (3.0 - 2.0 * f);
+
+    let (x, y) = (cell.x as u32, cell.y as u32);
+    let corner = |dx: u32, dy: u32| lattice_value((x + dx) % period, (y + dy) % period, seed);
+
+    let bottom = corner(0, 0).lerp(corner(1, 0), f.x);
+    let top = corner(0, 1).lerp(corner(1, 1), f.x);
+    bottom.lerp(top, f.y)
+}
+
+/// A pseudo-random value between 0 and 1 for a lattice point.
+fn lattice_value(x: u32, y: u32, seed: u32) -> f32 {
+    let mut h = x.wrapping_mul(374_761_393)
+        ^ y.wrapping_mul(668_265_263)
+        ^ seed.wrapping_mul(2_246_822_519);
+    h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+    (h ^ (h >> 16)) as f32 / u32::MAX as f32
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
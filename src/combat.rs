@@ -0,0 +1,160 @@
+//! Melee attacks for the player.
+//!
+//! Left click, or the attack key, swings at whatever is in front of the fox,
+//! unless the player is in throw mode.
+//! Every living mob within reach and inside the arc of the swing takes
+//! damage and is knocked back, and the game freezes for a moment on a hit so
+//! it lands with some weight, unless joined to a server, which keeps its own
+//! time.
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    health::{Dead, Health},
+    menu::PauseState,
+    mobs::Mob,
+    net::NetClient,
+    photo_mode::PhotoMode,
+    projectile::throwing,
+    settings::ControlSettings,
+    simulation::GameSpeed,
+    Player, Position, Rotation,
+};
+
+/// How far in front of the player a swing reaches.
+const ATTACK_REACH: f32 = 2.5;
+/// The cosine of half the angle a swing sweeps through.
+const ATTACK_ARC_COS: f32 = 0.5;
+/// How much health each hit takes.
+const ATTACK_DAMAGE: f32 = 4.0;
+/// How long a swing lasts, in seconds.
+const SWING_TIME: f32 = 0.3;
+/// How long after a swing starts the player can swing again, in seconds.
+const ATTACK_COOLDOWN: f32 = 0.5;
+/// How far a hit pushes a mob away from the player.
+const KNOCKBACK_DISTANCE: f32 = 3.0;
+/// How fast a hit lifts a mob off the ground.
+const KNOCKBACK_LIFT: f32 = 12.0;
+/// How long the game freezes on a hit, in real seconds.
+const HIT_STOP_TIME: f32 = 0.06;
+/// How fast the game runs during hit-stop.
+const HIT_STOP_SPEED: f32 = 0.05;
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HitStop>().add_systems(
+            Update,
+            (
+                (recover_from_swing, attack.run_if(not(throwing)))
+                    .chain()
+                    .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                apply_hit_stop.run_if(not(resource_exists::<NetClient>)),
+            )
+                .chain(),
+        );
+    }
+}
+
+/// A character's melee swing.
+#[derive(Component, Default)]
+pub struct Melee {
+    /// Seconds left in the current swing.
+    swing: f32,
+    /// Seconds until the character can swing again.
+    cooldown: f32,
+}
+
+impl Melee {
+    /// Whether the character is in the middle of a swing.
+    pub fn swinging(&self) -> bool {
+        self.swing > 0.0
+    }
+}
+
+/// Real seconds left until the game runs at full speed again after a hit.
+#[derive(Resource, Default)]
+struct HitStop(f32);
+
+/// The player, swinging at mobs.
+type Attacker = (&'static mut Melee, &'static Position, &'static Rotation);
+
+/// The living mobs a swing can hit.
+type Targets = (With<Mob>, Without<Dead>, Without<Player>);
+
+/// Counts down swings and their cooldowns.
+fn recover_from_swing(time: Res<Time>, mut characters: Query<&mut Melee>) {
+    let dt = time.delta_seconds();
+    for mut melee in characters.iter_mut() {
+        if melee.swing > 0.0 || melee.cooldown > 0.0 {
+            melee.swing = (melee.swing - dt).max(0.0);
+            melee.cooldown = (melee.cooldown - dt).max(0.0);
+        }
+    }
+}
+
+/// Swings at mobs in front of the player when the attack button is pressed,
+/// hurting and knocking back the ones the swing catches.
+fn attack(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    controls: Res<ControlSettings>,
+    mut contexts: EguiContexts,
+    mut hit_stop: ResMut<HitStop>,
+    mut players: Query<Attacker, (With<Player>, Without<Dead>)>,
+    mut mobs: Query<(&mut Health, &mut Position), Targets>,
+) {
+    let Ok((mut melee, player, rotation)) = players.get_single_mut() else {
+        return;
+    };
+
+    // Clicks on windows such as the crafting grid aren't attacks.
+    let clicked =
+        mouse_buttons.just_pressed(MouseButton::Left) && !contexts.ctx_mut().wants_pointer_input();
+    if !(clicked || keys.just_pressed(controls.attack)) || melee.cooldown > 0.0 {
+        return;
+    }
+    melee.swing = SWING_TIME;
+    melee.cooldown = ATTACK_COOLDOWN;
+
+    let facing = Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos());
+    let mut hit = false;
+    for (mut health, mut position) in mobs.iter_mut() {
+        let offset = (position.current - player.current) * Vec3::new(1.0, 0.0, 1.0);
+        let direction = offset.normalize_or_zero();
+        if offset.length() > ATTACK_REACH || facing.dot(direction) < ATTACK_ARC_COS {
+            continue;
+        }
+
+        health.damage(ATTACK_DAMAGE);
+        // The character controller eases the mob towards its new target.
+        position.target += direction * KNOCKBACK_DISTANCE;
+        position.vertical_velocity = KNOCKBACK_LIFT;
+        hit = true;
+    }
+
+    if hit {
+        hit_stop.0 = HIT_STOP_TIME;
+    }
+}
+
+/// Slows the game down while hit-stop lasts, and brings it back to the game
+/// speed after.
+fn apply_hit_stop(
+    real_time: Res<Time<Real>>,
+    mut hit_stop: ResMut<HitStop>,
+    mut game_speed: ResMut<GameSpeed>,
+) {
+    if hit_stop.0 <= 0.0 {
+        return;
+    }
+
+    hit_stop.0 -= real_time.delta_seconds();
+    game_speed.slow_down = if hit_stop.0 > 0.0 {
+        HIT_STOP_SPEED
+    } else {
+        1.0
+    };
+}
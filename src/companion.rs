@@ -0,0 +1,218 @@
+//! A fox companion that keeps the player company.
+//!
+//! The companion is a character like the player and the mobs, with its own
+//! scene, clips and state machine, moved by the same controller through its
+//! [`MovementInput`]. It trots after the player when they get too far ahead,
+//! lies about or sniffs around nearby while they stand still, and comes
+//! running when the player whistles. If it falls far behind, it catches up
+//! by appearing just behind the player. There is nothing to walk around yet,
+//! so it heads straight for the player rather than finding a path.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::{
+    animation::{AnimFsm, AnimState, AnimationBinding, ClipSet},
+    mobs::MobRng,
+    nameplates::Nameplate,
+    settings::ControlSettings,
+    simulation::WorldSimulation,
+    Checks, MovementInput, Player, Position, Rotation,
+};
+
+/// How far from the player the companion is first placed.
+const SPAWN_OFFSET: Vec3 = Vec3::new(2.0, 0.0, 2.0);
+/// What the companion is called, shown above it.
+const COMPANION_NAME: &str = "Ember";
+/// How far above the ground the companion's name floats.
+const NAMEPLATE_HEIGHT: f32 = 1.0;
+/// How big the companion is next to the player.
+const COMPANION_SCALE: f32 = 0.008;
+/// How far the player can get before the companion follows.
+const FOLLOW_DISTANCE: f32 = 6.0;
+/// How far behind the companion sprints to catch up.
+const CATCH_UP_DISTANCE: f32 = 14.0;
+/// How far behind the companion gives up walking and appears next to the
+/// player instead.
+const TELEPORT_DISTANCE: f32 = 40.0;
+/// How close a called companion comes before it stops running.
+const HEEL_DISTANCE: f32 = 2.0;
+/// How fast the companion trots after the player, as a share of the
+/// player's running speed.
+const FOLLOW_SPEED: f32 = 0.8;
+/// How fast the companion pads about while idle, as a share of the player's
+/// running speed.
+const SNIFF_SPEED: f32 = 0.2;
+/// How long the companion keeps at one idle behavior, in seconds.
+const MIN_IDLE_TIME: f32 = 2.0;
+const MAX_IDLE_TIME: f32 = 5.0;
+/// How likely an idle companion is to rest rather than sniff around when it
+/// picks what to do next.
+const REST_CHANCE: f32 = 0.6;
+
+pub struct CompanionPlugin;
+
+impl Plugin for CompanionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_companion).add_systems(
+            Update,
+            (whistle, follow_player).chain().in_set(WorldSimulation),
+        );
+    }
+}
+
+/// What the companion is doing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompanionBehavior {
+    /// Resting or sniffing around near the player.
+    #[default]
+    Idle,
+    /// Trotting after the player.
+    Follow,
+    /// Running to the player after a whistle.
+    Come,
+}
+
+/// The player's companion, and what it is up to.
+#[derive(Component, Default)]
+pub struct Companion {
+    pub behavior: CompanionBehavior,
+    /// Seconds until an idle companion picks something else to do.
+    idle_time: f32,
+}
+
+fn spawn_companion(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    let mut graph = AnimationGraph::new();
+    let [survey, run] = [0, 2].map(|index| {
+        graph.add_clip(
+            asset_server.load(GltfAssetLabel::Animation(index).from_asset("models/Fox.glb")),
+            1.0,
+            graph.root,
+        )
+    });
+    // The companion is smaller than the player, so it takes quicker steps.
+    let clips = ClipSet::new(graphs.add(graph))
+        .with_clip(AnimState::Idle, survey, 1.5)
+        .with_clip(AnimState::Run, run, 3.5);
+
+    commands.spawn((
+        SceneBundle {
+            scene: asset_server.load("models/Fox.glb#Scene0"),
+            transform: Transform::from_translation(SPAWN_OFFSET)
+                .with_scale(Vec3::splat(COMPANION_SCALE)),
+            ..default()
+        },
+        Position::at(SPAWN_OFFSET),
+        Rotation { radians_y: 0.0 },
+        Checks::default(),
+        MovementInput::default(),
+        AnimationBinding::new(clips),
+        AnimFsm::default(),
+        Nameplate::new(COMPANION_NAME, NAMEPLATE_HEIGHT),
+        Companion::default(),
+    ));
+}
+
+/// Calls the companion over when the whistle key is pressed.
+fn whistle(
+    keys: Res<ButtonInput<KeyCode>>,
+    controls: Res<ControlSettings>,
+    mut companions: Query<&mut Companion>,
+) {
+    if !keys.just_pressed(controls.whistle) {
+        return;
+    }
+    for mut companion in companions.iter_mut() {
+        companion.behavior = CompanionBehavior::Come;
+    }
+}
+
+/// The parts of the companion that following the player moves.
+type CompanionMotion = (
+    &'static mut Companion,
+    &'static mut Position,
+    &'static mut Transform,
+    &'static mut MovementInput,
+);
+
+/// Steers the companion after the player, idles it near them, and brings it
+/// back next to them when it falls too far behind.
+fn follow_player(
+    time: Res<Time>,
+    mut rng: ResMut<MobRng>,
+    players: Query<(&Position, &Rotation), With<Player>>,
+    mut companions: Query<CompanionMotion, Without<Player>>,
+) {
+    let Ok((player, player_rotation)) = players.get_single() else {
+        return;
+    };
+    let dt = time.delta_seconds();
+
+    for (mut companion, mut position, mut transform, mut input) in companions.iter_mut() {
+        let offset = (player.current - position.current) * Vec3::new(1.0, 0.0, 1.0);
+        let distance = offset.length();
+        let towards_player = offset.normalize_or_zero();
+        input.sprint = false;
+
+        if distance > TELEPORT_DISTANCE {
+            let behind = -Vec3::new(
+                player_rotation.radians_y.sin(),
+                0.0,
+                player_rotation.radians_y.cos(),
+            );
+            let mut spot = player.current + behind * HEEL_DISTANCE;
+            spot.y = 0.0;
+            position.teleport(spot);
+            transform.translation = spot;
+            input.direction = Vec3::ZERO;
+            companion.behavior = CompanionBehavior::Idle;
+            companion.idle_time = 0.0;
+            continue;
+        }
+
+        let behavior = match companion.behavior {
+            CompanionBehavior::Come if distance > HEEL_DISTANCE => CompanionBehavior::Come,
+            _ if distance > FOLLOW_DISTANCE => CompanionBehavior::Follow,
+            // Keep following until properly caught up, so it doesn't stop
+            // and start at the edge of the follow distance.
+            CompanionBehavior::Follow if distance > FOLLOW_DISTANCE / 2.0 => {
+                CompanionBehavior::Follow
+            }
+            _ => CompanionBehavior::Idle,
+        };
+        if behavior != companion.behavior {
+            companion.behavior = behavior;
+            // Pick something fresh to do when settling down.
+            companion.idle_time = 0.0;
+        }
+
+        match behavior {
+            CompanionBehavior::Come => {
+                input.direction = towards_player;
+                input.sprint = true;
+            }
+            CompanionBehavior::Follow => {
+                input.direction = towards_player * FOLLOW_SPEED;
+                input.sprint = distance > CATCH_UP_DISTANCE;
+            }
+            CompanionBehavior::Idle => {
+                companion.idle_time -= dt;
+                if companion.idle_time > 0.0 {
+                    continue;
+                }
+                companion.idle_time = rng.range(MIN_IDLE_TIME, MAX_IDLE_TIME);
+                input.direction = if rng.next() < REST_CHANCE {
+                    Vec3::ZERO
+                } else {
+                    let angle = rng.range(0.0, TAU);
+                    Vec3::new(angle.cos(), 0.0, angle.sin()) * SNIFF_SPEED
+                };
+            }
+        }
+    }
+}
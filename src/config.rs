@@ -0,0 +1,549 @@
+//! Loads the user's settings from `config.toml` in the platform config
+//! directory at startup, and saves them back whenever they change.
+//!
+//! Loading is forgiving. Files written by older versions are migrated to the
+//! current format, missing settings take their defaults, a setting that
+//! can't be read falls back to its default without taking the rest of its
+//! section with it, and out-of-range values are clamped. Everything that had
+//! to be fixed up is logged, and the file is rewritten in the current format,
+//! unless a newer version wrote it: then it is left alone, so the settings
+//! only that version knows aren't lost.
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use serde::{de::DeserializeOwned, Serialize};
+use toml::{Table, Value};
+
+use crate::{
+    screenshot::MAX_SCREENSHOT_SCALE,
+    server_browser::MultiplayerSettings,
+    settings::{
+        hotkey, AudioSettings, ControlAction, ControlSettings, GraphicsSettings,
+        MAX_RENDER_DISTANCE, MIN_RENDER_DISTANCE,
+    },
+    stamina::StaminaSettings,
+    AppSettings, MIN_APERTURE_F_STOPS, MIN_FOCAL_DISTANCE,
+};
+
+/// How long the settings have to stay unchanged before they are written, so
+/// dragging a slider doesn't rewrite the file every frame.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+/// The version of the config format this build writes. Bump it and add a
+/// step to [`MIGRATIONS`] when a setting is renamed, moved, removed or
+/// changes meaning; new settings only need a default.
+const CONFIG_VERSION: u32 = 2;
+/// The steps that bring a config from format `index + 1` to the next.
+const MIGRATIONS: [fn(&mut Table); CONFIG_VERSION as usize - 1] = [drop_unused_volumes];
+
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        // Loading while building the app means the settings are in place
+        // before any startup system reads them.
+        if config_path().is_some_and(|path| !path.exists()) {
+            app.insert_resource(FirstRun);
+        }
+        let config = load_config();
+        if config.version <= CONFIG_VERSION {
+            app.add_systems(Last, save_config);
+        }
+        app.insert_resource(config.dof)
+            .insert_resource(config.graphics)
+            .insert_resource(config.audio)
+            .insert_resource(config.controls)
+            .insert_resource(config.stamina)
+            .insert_resource(config.multiplayer);
+    }
+}
+
+/// Present when the game was started without a config file, most likely
+/// for the first time, until the first launch setup is done.
+#[derive(Resource)]
+pub struct FirstRun;
+
+/// The contents of `config.toml`.
+#[derive(Default, Serialize)]
+struct Config {
+    /// The format the settings were written in, which is only ever newer
+    /// than [`CONFIG_VERSION`] if a newer version of the game wrote them.
+    version: u32,
+    dof: AppSettings,
+    graphics: GraphicsSettings,
+    audio: AudioSettings,
+    controls: ControlSettings,
+    stamina: StaminaSettings,
+    multiplayer: MultiplayerSettings,
+}
+
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("voxel").join("config.toml"))
+}
+
+/// Reads the config file, falling back to the defaults for whatever is
+/// missing from it or can't be used.
+fn load_config() -> Config {
+    let Some(path) = config_path() else {
+        warn!("No config directory on this platform, using default settings");
+        return Config::default();
+    };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) => {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to read {}: {error}", path.display());
+            }
+            return Config::default();
+        }
+    };
+
+    let mut table: Table = match toml::from_str(&text) {
+        Ok(table) => table,
+        Err(error) => {
+            warn!(
+                "Failed to parse {}, using default settings: {error}",
+                path.display()
+            );
+            return Config::default();
+        }
+    };
+
+    let mut issues = Vec::new();
+    let version = migrate(&mut table, &mut issues);
+    let config = read_config(&table, version, &mut issues);
+    for issue in issues {
+        warn!("{}: {issue}", path.display());
+    }
+    config
+}
+
+/// Reads the settings out of a migrated config table.
+fn read_config(table: &Table, version: u32, issues: &mut Vec<String>) -> Config {
+    find_unknown_settings(table, &default_table(), "", issues);
+    let mut config = Config {
+        version: version.max(CONFIG_VERSION),
+        dof: section(table, "dof", issues),
+        graphics: section(table, "graphics", issues),
+        audio: section(table, "audio", issues),
+        controls: section(table, "controls", issues),
+        stamina: section(table, "stamina", issues),
+        multiplayer: section(table, "multiplayer", issues),
+    };
+    validate(&mut config, issues);
+    config
+}
+
+/// Brings a config written by an older version up to the current format,
+/// returning the format it was written in.
+fn migrate(table: &mut Table, issues: &mut Vec<String>) -> u32 {
+    // Files from before the format was versioned have no version at all.
+    // Versioning it changed nothing else, so they are the first format.
+    let version = match table.get("version") {
+        None => 1,
+        Some(Value::Integer(version)) => u32::try_from(*version).unwrap_or(u32::MAX).max(1),
+        Some(other) => {
+            issues.push(format!("`version` should be a number, not {other}"));
+            1
+        }
+    };
+    if version > CONFIG_VERSION {
+        issues.push(format!(
+            "written by a newer version (format {version}, this one reads up to \
+             {CONFIG_VERSION}), settings this version doesn't know are ignored and \
+             changes aren't saved"
+        ));
+        return version;
+    }
+
+    for step in &MIGRATIONS[version as usize - 1..] {
+        step(table);
+    }
+    if version < CONFIG_VERSION {
+        info!("Migrated the config from format {version} to {CONFIG_VERSION}");
+    }
+    version
+}
+
+/// Format 2 dropped the music and effects volumes, which nothing played.
+fn drop_unused_volumes(table: &mut Table) {
+    if let Some(Value::Table(audio)) = table.get_mut("audio") {
+        audio.remove("music_volume");
+        audio.remove("effects_volume");
+    }
+}
+
+/// The default config as a table, to tell known settings from unknown ones.
+fn default_table() -> Table {
+    Table::try_from(Config::default()).unwrap_or_default()
+}
+
+/// Notes every setting in `table` that `known` doesn't have, which is most
+/// likely a typo.
+fn find_unknown_settings(table: &Table, known: &Table, prefix: &str, issues: &mut Vec<String>) {
+    for (key, value) in table {
+        let name = format!("{prefix}{key}");
+        match (value, known.get(key)) {
+            (_, None) => issues.push(format!("unknown setting `{name}` is ignored")),
+            (Value::Table(table), Some(Value::Table(known))) => {
+                find_unknown_settings(table, known, &format!("{name}."), issues);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads the section `name` of the config. Settings that are missing or
+/// can't be read take their defaults, one by one.
+fn section<T: Serialize + DeserializeOwned + Default>(
+    table: &Table,
+    name: &str,
+    issues: &mut Vec<String>,
+) -> T {
+    let Some(value) = table.get(name) else {
+        return T::default();
+    };
+    if let Ok(section) = value.clone().try_into() {
+        return section;
+    }
+    let Value::Table(settings) = value else {
+        issues.push(format!("[{name}] should be a table, using its defaults"));
+        return T::default();
+    };
+
+    // Add the settings to the defaults one at a time, keeping those that
+    // still read.
+    let mut kept = Table::try_from(T::default()).unwrap_or_default();
+    for (key, setting) in settings {
+        let mut attempt = kept.clone();
+        attempt.insert(key.clone(), setting.clone());
+        match Value::Table(attempt).try_into::<T>() {
+            Ok(_) => {
+                kept.insert(key.clone(), setting.clone());
+            }
+            Err(error) => {
+                let error = error.message().trim_end().to_string();
+                issues.push(format!("using the default for `{name}.{key}`: {error}"));
+            }
+        }
+    }
+    Value::Table(kept).try_into().unwrap_or_else(|error| {
+        issues.push(format!("using the defaults for [{name}]: {error}"));
+        T::default()
+    })
+}
+
+/// Clamps settings that are out of range into it.
+fn validate(config: &mut Config, issues: &mut Vec<String>) {
+    let mut check = |name: &str, value: &mut f32, min: f32, max: f32| {
+        if !(min..=max).contains(value) {
+            let clamped = if value.is_nan() {
+                min
+            } else {
+                value.clamp(min, max)
+            };
+            issues.push(format!(
+                "`{name}` = {value} is out of range, using {clamped} instead"
+            ));
+            *value = clamped;
+        }
+    };
+
+    let dof = &mut config.dof;
+    check(
+        "dof.focal_distance",
+        &mut dof.focal_distance,
+        MIN_FOCAL_DISTANCE,
+        f32::MAX,
+    );
+    check(
+        "dof.aperture_f_stops",
+        &mut dof.aperture_f_stops,
+        MIN_APERTURE_F_STOPS,
+        f32::MAX,
+    );
+
+    let graphics = &mut config.graphics;
+    check(
+        "graphics.render_distance",
+        &mut graphics.render_distance,
+        MIN_RENDER_DISTANCE,
+        MAX_RENDER_DISTANCE,
+    );
+
+    let audio = &mut config.audio;
+    check("audio.master_volume", &mut audio.master_volume, 0.0, 1.0);
+
+    let stamina = &mut config.stamina;
+    check("stamina.max", &mut stamina.max, 1.0, f32::MAX);
+    check(
+        "stamina.sprint_cost",
+        &mut stamina.sprint_cost,
+        0.0,
+        f32::MAX,
+    );
+    check("stamina.jump_cost", &mut stamina.jump_cost, 0.0, f32::MAX);
+    check(
+        "stamina.recovery_rate",
+        &mut stamina.recovery_rate,
+        0.0,
+        f32::MAX,
+    );
+    check(
+        "stamina.recovery_delay",
+        &mut stamina.recovery_delay,
+        0.0,
+        f32::MAX,
+    );
+
+    let scale = &mut config.graphics.screenshot_scale;
+    if !(1..=MAX_SCREENSHOT_SCALE).contains(scale) {
+        let clamped = (*scale).clamp(1, MAX_SCREENSHOT_SCALE);
+        issues.push(format!(
+            "`graphics.screenshot_scale` = {scale} is out of range, using {clamped} instead"
+        ));
+        *scale = clamped;
+    }
+
+    // Two actions on one key, or an action on a hotkey, is allowed but
+    // rarely what was meant.
+    let mut actions_by_key: HashMap<KeyCode, Vec<&str>> = HashMap::new();
+    for action in ControlAction::ALL {
+        actions_by_key
+            .entry(config.controls.key(action))
+            .or_default()
+            .push(action.label());
+    }
+    for (key, actions) in actions_by_key {
+        if let Some(taken_by) = hotkey(key) {
+            let actions = actions.join(" and ");
+            issues.push(format!(
+                "{key:?} is for {taken_by}, but is bound to {actions}"
+            ));
+        } else if actions.len() > 1 {
+            issues.push(format!("{key:?} is bound to {}", actions.join(" and ")));
+        }
+    }
+}
+
+/// Every settings resource that is saved to the config.
+#[derive(SystemParam)]
+struct SavedSettings<'w> {
+    dof: Res<'w, AppSettings>,
+    graphics: Res<'w, GraphicsSettings>,
+    audio: Res<'w, AudioSettings>,
+    controls: Res<'w, ControlSettings>,
+    stamina: Res<'w, StaminaSettings>,
+    multiplayer: Res<'w, MultiplayerSettings>,
+}
+
+impl SavedSettings<'_> {
+    fn is_changed(&self) -> bool {
+        self.dof.is_changed()
+            || self.graphics.is_changed()
+            || self.audio.is_changed()
+            || self.controls.is_changed()
+            || self.stamina.is_changed()
+            || self.multiplayer.is_changed()
+    }
+
+    fn config(&self) -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            dof: *self.dof,
+            graphics: self.graphics.clone(),
+            audio: self.audio.clone(),
+            controls: self.controls.clone(),
+            stamina: self.stamina.clone(),
+            multiplayer: self.multiplayer.clone(),
+        }
+    }
+}
+
+/// Writes the settings once they have settled after a change, or right away
+/// when the app is exiting.
+fn save_config(
+    settings: SavedSettings,
+    mut exit_events: EventReader<AppExit>,
+    mut changed_at: Local<Option<Instant>>,
+) {
+    // The resources count as changed on the first run, which writes out a
+    // config file with the defaults if there wasn't one yet.
+    if settings.is_changed() {
+        *changed_at = Some(Instant::now());
+    }
+
+    let exiting = exit_events.read().count() > 0;
+    let Some(changed) = *changed_at else {
+        return;
+    };
+    if !exiting && changed.elapsed() < SAVE_DELAY {
+        return;
+    }
+    *changed_at = None;
+
+    if let Err(error) = write_config(&settings.config()) {
+        warn!("Failed to save settings: {error}");
+    }
+}
+
+fn write_config(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config_path().ok_or("no config directory on this platform")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+/// (De)serializes an optional DOF mode as "off", "gaussian" or "bokeh".
+pub mod dof_mode {
+    use bevy::core_pipeline::dof::DepthOfFieldMode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        mode: &Option<DepthOfFieldMode>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match mode {
+            None => "off",
+            Some(DepthOfFieldMode::Gaussian) => "gaussian",
+            Some(DepthOfFieldMode::Bokeh) => "bokeh",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DepthOfFieldMode>, D::Error> {
+        match String::deserialize(deserializer)?.as_str() {
+            "off" => Ok(None),
+            "gaussian" => Ok(Some(DepthOfFieldMode::Gaussian)),
+            "bokeh" => Ok(Some(DepthOfFieldMode::Bokeh)),
+            other => Err(D::Error::unknown_variant(
+                other,
+                &["off", "gaussian", "bokeh"],
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Table {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn unversioned_files_are_the_first_format() {
+        let mut table = parse("[audio]\nmaster_volume = 0.5\n");
+        let mut issues = Vec::new();
+        assert_eq!(migrate(&mut table, &mut issues), 1);
+        assert!(issues.is_empty());
+
+        let config = read_config(&table, 1, &mut issues);
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.audio.master_volume, 0.5);
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn format_1_files_lose_the_unused_volumes() {
+        let mut table = parse(
+            "version = 1\n[audio]\nmaster_volume = 0.5\nmusic_volume = 0.8\n\
+             effects_volume = 1.0\n",
+        );
+        let mut issues = Vec::new();
+        assert_eq!(migrate(&mut table, &mut issues), 1);
+
+        let config = read_config(&table, 1, &mut issues);
+        assert_eq!(config.audio.master_volume, 0.5);
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn newer_files_are_read_but_not_saved_over() {
+        let mut table = parse("version = 99\n[audio]\nmaster_volume = 0.5\n");
+        let mut issues = Vec::new();
+        let version = migrate(&mut table, &mut issues);
+        assert_eq!(version, 99);
+        assert_eq!(issues.len(), 1);
+
+        let config = read_config(&table, version, &mut issues);
+        assert_eq!(config.version, 99);
+        assert_eq!(config.audio.master_volume, 0.5);
+    }
+
+    #[test]
+    fn a_version_that_is_not_a_number_is_reported() {
+        let mut table = parse("version = \"one\"\n");
+        let mut issues = Vec::new();
+        assert_eq!(migrate(&mut table, &mut issues), 1);
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn a_bad_setting_only_resets_itself() {
+        let table = parse("[stamina]\nmax = \"lots\"\njump_cost = 5.0\n");
+        let mut issues = Vec::new();
+        let stamina: StaminaSettings = section(&table, "stamina", &mut issues);
+        assert_eq!(stamina.max, StaminaSettings::default().max);
+        assert_eq!(stamina.jump_cost, 5.0);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("stamina.max"), "{issues:?}");
+    }
+
+    #[test]
+    fn a_section_that_is_not_a_table_takes_its_defaults() {
+        let table = parse("audio = 3\n");
+        let mut issues = Vec::new();
+        let audio: AudioSettings = section(&table, "audio", &mut issues);
+        assert!(audio == AudioSettings::default());
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn unknown_settings_are_reported_by_their_full_name() {
+        let table = parse("[audio]\nmaster_volum = 0.5\n");
+        let mut issues = Vec::new();
+        find_unknown_settings(&table, &default_table(), "", &mut issues);
+        assert_eq!(issues, ["unknown setting `audio.master_volum` is ignored"]);
+    }
+
+    #[test]
+    fn out_of_range_settings_are_clamped() {
+        let mut config = Config::default();
+        config.audio.master_volume = 2.0;
+        config.stamina.jump_cost = f32::NAN;
+        config.graphics.screenshot_scale = 0;
+        let mut issues = Vec::new();
+        validate(&mut config, &mut issues);
+        assert_eq!(config.audio.master_volume, 1.0);
+        assert_eq!(config.stamina.jump_cost, 0.0);
+        assert_eq!(config.graphics.screenshot_scale, 1);
+        assert_eq!(issues.len(), 3);
+    }
+
+    #[test]
+    fn actions_bound_to_hotkeys_are_reported() {
+        let mut config = Config::default();
+        config.controls.jump = KeyCode::KeyT;
+        let mut issues = Vec::new();
+        validate(&mut config, &mut issues);
+        assert_eq!(issues, ["KeyT is for chat, but is bound to Jump"]);
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        let mut config = Config::default();
+        let mut issues = Vec::new();
+        validate(&mut config, &mut issues);
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+}
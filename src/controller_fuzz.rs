@@ -0,0 +1,224 @@
+//! Fuzzes the character controller: random input sequences are stepped
+//! through [`step_motion`] on the ground plane, checking after every step
+//! that nothing became NaN or infinite, that characters never end up below
+//! the ground, and that they never move faster than they can. Some inputs
+//! aren't finite, as a client's could be, and those steps must do nothing.
+//!
+//! Each sequence is recorded as it is generated and then replayed, which has
+//! to end in exactly the same place, since the server and its clients step
+//! players through the same frames. The quick run is part of `cargo test`;
+//! the long run is ignored by default and runs with
+//! `cargo test controller_fuzz -- --ignored`.
+
+use bevy::prelude::*;
+
+use crate::{
+    step_motion, MovementInput, Position, Rotation, FLY_SPEED, FLY_VERTICAL_SPEED, JUMP_VELOCITY,
+    PLAYER_SPEED, SPRINT_SPEED_MULTIPLIER,
+};
+
+/// The longest step a sequence takes: a character at reduced level of
+/// detail stepping four fixed updates at once, with some to spare.
+const MAX_FUZZ_STEP: f32 = 0.5;
+/// How far past full speed a step may go, for rounding.
+const SPEED_TOLERANCE: f32 = 1e-3;
+
+/// A small, seedable random number generator (xorshift64*), so failures can
+/// be reproduced from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero.
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number from 0 up to 1.
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.unit()
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        self.unit() < probability
+    }
+}
+
+/// One step of a recorded input sequence.
+struct FuzzFrame {
+    input: MovementInput,
+    sprinting: bool,
+    dt: f32,
+}
+
+/// A number that isn't finite, or becomes infinite in an `f32`.
+fn non_finite(rng: &mut Rng) -> f32 {
+    match rng.next() % 4 {
+        0 => f32::NAN,
+        1 => f32::INFINITY,
+        2 => f32::NEG_INFINITY,
+        _ => "1e39".parse().unwrap(),
+    }
+}
+
+/// A random input sequence. Inputs tend to be held for a while, like real
+/// key presses, and sometimes go out of range, as a client's could.
+fn random_sequence(rng: &mut Rng, steps: usize) -> Vec<FuzzFrame> {
+    let mut input = MovementInput::default();
+    let mut sprinting = false;
+    (0..steps)
+        .map(|_| {
+            if rng.chance(0.1) {
+                let heading = rng.range(0.0, std::f32::consts::TAU);
+                // Sometimes standing still, sometimes creeping.
+                let length = if rng.chance(0.2) { 0.0 } else { rng.unit() };
+                input.direction = Vec3::new(heading.sin(), 0.0, heading.cos()) * length;
+            }
+            if rng.chance(0.05) {
+                input.fly = !input.fly;
+            }
+            if rng.chance(0.1) {
+                input.vertical = rng.range(-2.0, 2.0);
+            }
+            if rng.chance(0.05) {
+                input.damped_flight = !input.damped_flight;
+            }
+            if rng.chance(0.1) {
+                sprinting = !sprinting;
+            }
+            input.jump = rng.chance(0.05);
+            input.sprint = sprinting;
+
+            let mut dt = match rng.unit() {
+                chance if chance < 0.05 => 0.0,
+                chance if chance < 0.1 => rng.range(0.1, MAX_FUZZ_STEP),
+                _ => rng.range(0.001, 0.05),
+            };
+
+            // Now and then, one number in the frame isn't finite.
+            let mut frame_input = input.clone();
+            if rng.chance(0.02) {
+                match rng.next() % 3 {
+                    0 => frame_input.direction.x = non_finite(rng),
+                    1 => frame_input.vertical = non_finite(rng),
+                    _ => dt = non_finite(rng),
+                }
+            }
+            FuzzFrame {
+                sprinting: sprinting && frame_input.direction != Vec3::ZERO,
+                input: frame_input,
+                dt,
+            }
+        })
+        .collect()
+}
+
+/// Steps a character through `frames` from standing at `start`, checking
+/// every step, and returns where it ended up.
+fn run_sequence(seed: u64, start: Vec3, frames: &[FuzzFrame]) -> Position {
+    let mut position = Position::at(start);
+    let mut rotation = Rotation { radians_y: 0.0 };
+    for (index, frame) in frames.iter().enumerate() {
+        let before = position.target;
+        let before_velocities = (position.vertical_velocity, position.flight_velocity);
+        let rising = position.vertical_velocity > 0.0;
+        let finite = frame.dt.is_finite()
+            && frame.input.direction.is_finite()
+            && frame.input.vertical.is_finite();
+        let motion = step_motion(
+            &frame.input,
+            frame.sprinting,
+            &mut position,
+            &mut rotation,
+            frame.dt,
+        );
+        let context = format!("seed {seed}, step {index}");
+
+        assert!(
+            position.target.is_finite(),
+            "{context}: {}",
+            position.target
+        );
+        assert!(position.flight_velocity.is_finite(), "{context}");
+        assert!(position.vertical_velocity.is_finite(), "{context}");
+        assert!(rotation.radians_y.is_finite(), "{context}");
+        assert!(position.target.y >= 0.0, "{context}: below the ground");
+        if !finite {
+            assert_eq!(position.target, before, "{context}: moved on bad input");
+            assert_eq!(
+                (position.vertical_velocity, position.flight_velocity),
+                before_velocities,
+                "{context}: sped up on bad input"
+            );
+            continue;
+        }
+        if let Some(speed) = motion.landed {
+            assert!(
+                speed.is_finite() && speed >= 0.0,
+                "{context}: landed at {speed}"
+            );
+        }
+
+        let moved = position.target - before;
+        let horizontal = Vec2::new(moved.x, moved.z).length();
+        let max_speed = if frame.input.fly {
+            FLY_SPEED
+        } else {
+            PLAYER_SPEED * SPRINT_SPEED_MULTIPLIER
+        };
+        assert!(
+            horizontal <= max_speed * frame.dt + SPEED_TOLERANCE,
+            "{context}: moved {horizontal} in {}s",
+            frame.dt
+        );
+        let max_rise = FLY_VERTICAL_SPEED.max(JUMP_VELOCITY) * frame.dt + 0.1;
+        assert!(
+            moved.y <= max_rise + SPEED_TOLERANCE,
+            "{context}: rose {} in {}s",
+            moved.y,
+            frame.dt
+        );
+        // Only a jump, or rising on from flying up, leaves the ground.
+        if !frame.input.fly && !motion.jumped && !rising && before.y == 0.0 {
+            assert_eq!(position.target.y, 0.0, "{context}: left the ground");
+        }
+    }
+    position
+}
+
+/// Runs `sequences` random sequences of `steps` steps each, and replays
+/// each one to check it ends in the same place.
+fn fuzz(sequences: u64, steps: usize) {
+    for seed in 0..sequences {
+        let mut rng = Rng::new(seed);
+        let start = Vec3::new(rng.range(-100.0, 100.0), 0.0, rng.range(-100.0, 100.0));
+        let frames = random_sequence(&mut rng, steps);
+
+        let first = run_sequence(seed, start, &frames);
+        let replayed = run_sequence(seed, start, &frames);
+        assert_eq!(first.target, replayed.target, "seed {seed}: replay differs");
+        assert_eq!(first.vertical_velocity, replayed.vertical_velocity);
+        assert_eq!(first.flight_velocity, replayed.flight_velocity);
+    }
+}
+
+#[test]
+fn controller_keeps_its_invariants() {
+    fuzz(200, 500);
+}
+
+#[test]
+#[ignore = "long-form, run with --ignored"]
+fn controller_keeps_its_invariants_for_long() {
+    fuzz(20_000, 2_000);
+}
@@ -0,0 +1,338 @@
+//! Crafting items out of others, following the recipes in
+//! `assets/recipes/*.ron`.
+//!
+//! I opens the crafting panel. Items laid out on its 3x3 grid that match a
+//! recipe can be crafted into the recipe's result, using up one of each item
+//! on the grid from the inventory, or nothing in creative. Shaped recipes
+//! need their items in a fixed arrangement, which may sit anywhere on the
+//! grid and be mirrored; shapeless recipes only need the right items.
+//! Picking a recipe from the list lays its items out on the grid.
+
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::Deserialize;
+
+use crate::{
+    game_mode::GameMode,
+    inventory::{Inventory, ItemId},
+    menu::PauseState,
+    photo_mode::PhotoMode,
+    Player,
+};
+
+/// The directory recipes are loaded from.
+const RECIPE_DIR: &str = "assets/recipes";
+/// The width and height of the crafting grid.
+const GRID_SIZE: usize = 3;
+
+pub struct CraftingPlugin;
+
+impl Plugin for CraftingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_recipes())
+            .init_resource::<CraftingMenu>()
+            .add_systems(
+                Update,
+                (
+                    toggle_crafting_menu
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                    crafting_menu.run_if(|menu: Res<CraftingMenu>| menu.open),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A way of crafting an item, as written in a recipe file.
+#[derive(Deserialize)]
+struct Recipe {
+    result: RecipeResult,
+    ingredients: Ingredients,
+}
+
+#[derive(Deserialize)]
+struct RecipeResult {
+    item: ItemId,
+    count: u32,
+}
+
+/// The items a recipe uses up.
+#[derive(Deserialize)]
+enum Ingredients {
+    /// Items in a fixed arrangement, given as rows of keys with spaces for
+    /// empty cells.
+    Shaped {
+        pattern: Vec<String>,
+        key: HashMap<char, ItemId>,
+    },
+    /// Items in any arrangement.
+    Shapeless(Vec<ItemId>),
+}
+
+/// The items laid out on the crafting grid, by row and column.
+type Grid = [[Option<ItemId>; GRID_SIZE]; GRID_SIZE];
+
+impl Ingredients {
+    /// Whether the grid holds exactly these ingredients.
+    fn matches(&self, grid: &Grid) -> bool {
+        match self {
+            Ingredients::Shaped { pattern, key } => {
+                let placed = trim(
+                    grid.iter()
+                        .map(|row| row.iter().map(Option::as_ref).collect()),
+                );
+                let expected = trim(
+                    pattern
+                        .iter()
+                        .map(|row| row.chars().map(|symbol| key.get(&symbol)).collect()),
+                );
+                let mirrored: Vec<Vec<_>> = expected
+                    .iter()
+                    .map(|row| row.iter().rev().copied().collect())
+                    .collect();
+                placed == expected || placed == mirrored
+            }
+            Ingredients::Shapeless(items) => {
+                let mut placed: Vec<_> = grid.iter().flatten().flatten().collect();
+                let mut expected: Vec<_> = items.iter().collect();
+                placed.sort();
+                expected.sort();
+                placed == expected
+            }
+        }
+    }
+
+    /// The ingredients laid out on an empty grid.
+    fn layout(&self) -> Grid {
+        let mut grid = Grid::default();
+        match self {
+            Ingredients::Shaped { pattern, key } => {
+                for (row, symbols) in pattern.iter().take(GRID_SIZE).enumerate() {
+                    for (column, symbol) in symbols.chars().take(GRID_SIZE).enumerate() {
+                        grid[row][column] = key.get(&symbol).cloned();
+                    }
+                }
+            }
+            Ingredients::Shapeless(items) => {
+                for (cell, item) in grid.iter_mut().flatten().zip(items) {
+                    *cell = Some(item.clone());
+                }
+            }
+        }
+        grid
+    }
+}
+
+/// Pads rows of cells to the same length and crops away the empty rows and
+/// columns around the cells that are filled.
+fn trim<'a>(rows: impl Iterator<Item = Vec<Option<&'a ItemId>>>) -> Vec<Vec<Option<&'a ItemId>>> {
+    let mut rows: Vec<_> = rows.collect();
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    for row in rows.iter_mut() {
+        row.resize(width, None);
+    }
+
+    let filled_rows: Vec<_> = (0..rows.len())
+        .filter(|&row| rows[row].iter().any(Option::is_some))
+        .collect();
+    let filled_columns: Vec<_> = (0..width)
+        .filter(|&column| rows.iter().any(|row| row[column].is_some()))
+        .collect();
+    let (Some(&top), Some(&bottom), Some(&left), Some(&right)) = (
+        filled_rows.first(),
+        filled_rows.last(),
+        filled_columns.first(),
+        filled_columns.last(),
+    ) else {
+        return Vec::new();
+    };
+
+    rows[top..=bottom]
+        .iter()
+        .map(|row| row[left..=right].to_vec())
+        .collect()
+}
+
+/// Every recipe that was loaded.
+#[derive(Resource)]
+struct Recipes(Vec<Recipe>);
+
+/// Whether the crafting panel is open, and what is on its grid.
+#[derive(Resource, Default)]
+struct CraftingMenu {
+    open: bool,
+    grid: Grid,
+    /// Why the last attempt to craft failed, shown in the panel.
+    status: Option<String>,
+}
+
+/// Reads every recipe file, skipping and warning about those that can't be
+/// read.
+fn load_recipes() -> Recipes {
+    let entries = match fs::read_dir(RECIPE_DIR) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to read {RECIPE_DIR}, no recipes loaded: {error}");
+            return Recipes(Vec::new());
+        }
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    paths.sort();
+
+    let recipes = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path)
+                .map_err(|error| warn!("Failed to read {}: {error}", path.display()))
+                .ok()?;
+            ron::from_str(&text)
+                .map_err(|error| warn!("Failed to parse {}: {error}", path.display()))
+                .ok()
+        })
+        .collect();
+    Recipes(recipes)
+}
+
+fn toggle_crafting_menu(input: Res<ButtonInput<KeyCode>>, mut menu: ResMut<CraftingMenu>) {
+    if input.just_pressed(KeyCode::KeyI) {
+        menu.open = !menu.open;
+        menu.status = None;
+    }
+}
+
+fn crafting_menu(
+    mut contexts: EguiContexts,
+    mut menu: ResMut<CraftingMenu>,
+    recipes: Res<Recipes>,
+    game_mode: Res<GameMode>,
+    mut players: Query<&mut Inventory, With<Player>>,
+) {
+    let Ok(mut inventory) = players.get_single_mut() else {
+        return;
+    };
+    let menu = &mut *menu;
+
+    // The items that can go on the grid.
+    let mut items: Vec<_> = inventory.stacks().map(|stack| &stack.item).collect();
+    items.sort();
+    items.dedup();
+    let items: Vec<ItemId> = items.into_iter().cloned().collect();
+
+    let recipe = recipes
+        .0
+        .iter()
+        .find(|recipe| recipe.ingredients.matches(&menu.grid));
+
+    let mut open = menu.open;
+    egui::Window::new("Crafting")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                egui::Grid::new("crafting_grid").show(ui, |ui| {
+                    for (row, cells) in menu.grid.iter_mut().enumerate() {
+                        for (column, cell) in cells.iter_mut().enumerate() {
+                            grid_cell(ui, (row, column), cell, &items);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.label("→");
+                match recipe {
+                    Some(recipe) => {
+                        ui.label(format!(
+                            "{} x{}",
+                            recipe.result.item.label(),
+                            recipe.result.count
+                        ));
+                    }
+                    None => {
+                        ui.label("Nothing");
+                    }
+                }
+            });
+
+            if let Some(status) = &menu.status {
+                ui.label(status);
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(recipe.is_some(), egui::Button::new("Craft"))
+                    .clicked()
+                {
+                    if let Some(recipe) = recipe {
+                        menu.status = craft(recipe, &menu.grid, *game_mode, &mut inventory).err();
+                    }
+                }
+                if ui.button("Clear").clicked() {
+                    menu.grid = Grid::default();
+                    menu.status = None;
+                }
+            });
+            ui.separator();
+
+            ui.label("Recipes");
+            for recipe in recipes.0.iter() {
+                if ui.button(recipe.result.item.label()).clicked() {
+                    menu.grid = recipe.ingredients.layout();
+                    menu.status = None;
+                }
+            }
+        });
+    menu.open = open;
+}
+
+/// A cell of the crafting grid, picking the item on it.
+fn grid_cell(ui: &mut egui::Ui, id: (usize, usize), cell: &mut Option<ItemId>, items: &[ItemId]) {
+    let label = |item: &Option<ItemId>| item.as_ref().map_or(String::new(), ItemId::label);
+    egui::ComboBox::from_id_source(("crafting_cell", id))
+        .width(90.0)
+        .selected_text(label(cell))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(cell, None, "Empty");
+            for item in items {
+                ui.selectable_value(cell, Some(item.clone()), item.label());
+            }
+        });
+}
+
+/// Uses up the items on the grid to make the recipe's result, if the
+/// inventory holds them all and has room for the result. In creative, the
+/// items are needed but not used up.
+fn craft(
+    recipe: &Recipe,
+    grid: &Grid,
+    game_mode: GameMode,
+    inventory: &mut Inventory,
+) -> Result<(), String> {
+    let mut needed: HashMap<&ItemId, u32> = HashMap::new();
+    for item in grid.iter().flatten().flatten() {
+        *needed.entry(item).or_default() += 1;
+    }
+
+    // Craft into a copy, so that nothing changes unless everything fits.
+    let mut crafted = inventory.clone();
+    for (item, count) in needed {
+        let held = match game_mode {
+            GameMode::Survival => crafted.remove(item, count),
+            GameMode::Creative => inventory.count(item) >= count,
+        };
+        if !held {
+            return Err(format!("Not enough {}", item.label()));
+        }
+    }
+    if crafted.add(&recipe.result.item, recipe.result.count) > 0 {
+        return Err("Not enough room in the inventory".to_string());
+    }
+
+    *inventory = crafted;
+    Ok(())
+}
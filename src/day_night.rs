@@ -0,0 +1,135 @@
+//! A day/night cycle that moves the sun across the sky.
+//!
+//! The [`TimeOfDay`] resource is the clock everything else reads; the sun's
+//! direction, brightness and the ambient light all follow from it. The
+//! `/time` chat command shows it, and `/time set` moves it to another hour.
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::{pbr::light_consts::lux, prelude::*};
+
+use crate::{
+    chat::{ChatCommandsExt, ChatLog, CommandArgs},
+    simulation::WorldSimulation,
+};
+
+/// How long a full day lasts, in seconds of game time.
+const DAY_LENGTH: f32 = 20.0 * 60.0;
+/// The hour of day the game starts at.
+const START_HOUR: f32 = 10.0;
+/// How far the sun's path is tilted away from passing straight overhead, in
+/// radians.
+const SUN_TILT: f32 = 0.5;
+/// The ambient light brightness at noon and at midnight.
+const DAY_AMBIENT_BRIGHTNESS: f32 = 80.0;
+const NIGHT_AMBIENT_BRIGHTNESS: f32 = 10.0;
+
+pub struct DayNightPlugin;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>()
+            .add_chat_command(
+                "time",
+                "[set <hour|day|noon|night|midnight>]",
+                "Shows or sets the time of day",
+                time_command,
+            )
+            .add_systems(
+                Update,
+                (advance_time_of_day.in_set(WorldSimulation), update_sun).chain(),
+            );
+    }
+}
+
+/// The time of day as an hour from 0 (midnight) up to 24.
+#[derive(Resource)]
+pub struct TimeOfDay {
+    pub hour: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self { hour: START_HOUR }
+    }
+}
+
+impl TimeOfDay {
+    /// Whether the sun is above the horizon.
+    pub fn is_day(&self) -> bool {
+        self.sun_direction().y > 0.0
+    }
+
+    /// The direction from the ground towards the sun.
+    fn sun_direction(&self) -> Vec3 {
+        // Zero at 6:00, a quarter turn at noon.
+        let angle = (self.hour - 6.0) / 24.0 * TAU;
+        Quat::from_rotation_x(SUN_TILT) * Vec3::new(angle.cos(), angle.sin(), 0.0)
+    }
+}
+
+/// Moves the clock forward with game time, so it stops while paused.
+fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    time_of_day.hour = (time_of_day.hour + time.delta_seconds() / DAY_LENGTH * 24.0) % 24.0;
+}
+
+/// Shows the time of day, or with `set`, moves it to the hour given, by
+/// number or by name.
+fn time_command(
+    In(args): In<CommandArgs>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut log: ResMut<ChatLog>,
+) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let hour = match args[..] {
+        [] => {
+            log.info(format!("It is {}", clock(time_of_day.hour)));
+            return;
+        }
+        ["set", "day"] => Some(7.0),
+        ["set", "noon"] => Some(12.0),
+        ["set", "night"] => Some(19.0),
+        ["set", "midnight"] => Some(0.0),
+        ["set", hour] => hour
+            .parse::<f32>()
+            .ok()
+            .filter(|hour| (0.0..24.0).contains(hour)),
+        _ => None,
+    };
+    let Some(hour) = hour else {
+        log.info("Usage: /time set <0-24|day|noon|night|midnight>");
+        return;
+    };
+    time_of_day.hour = hour;
+    log.info(format!("Set the time to {}", clock(hour)));
+}
+
+/// An hour of the day as on a clock, e.g. "18:30".
+fn clock(hour: f32) -> String {
+    let minutes = (hour * 60.0) as u32;
+    format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+}
+
+/// Points the sun along its path for the current hour and dims it, and the
+/// ambient light, as it sets.
+fn update_sun(
+    time_of_day: Res<TimeOfDay>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut suns: Query<(&mut DirectionalLight, &mut Transform)>,
+) {
+    if !time_of_day.is_changed() {
+        return;
+    }
+
+    let direction = time_of_day.sun_direction();
+    // How high the sun is, from 0 at the horizon to 1 straight overhead.
+    let elevation = (direction.y.asin() / (PI / 2.0)).max(0.0);
+    let daylight = elevation.sqrt();
+
+    for (mut light, mut transform) in suns.iter_mut() {
+        light.illuminance = lux::AMBIENT_DAYLIGHT * daylight;
+        *transform = Transform::IDENTITY.looking_to(-direction, Vec3::Y);
+    }
+    ambient_light.brightness =
+        NIGHT_AMBIENT_BRIGHTNESS + (DAY_AMBIENT_BRIGHTNESS - NIGHT_AMBIENT_BRIGHTNESS) * daylight;
+}
@@ -0,0 +1,236 @@
+//! A debug overlay, toggled with F3, showing performance and player
+//! information.
+
+use std::collections::VecDeque;
+
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+
+use crate::{
+    governor::PerformanceGovernor, simulation::GameSpeed, AppSettings, Player, Position, Rotation,
+};
+
+/// How many frames the frame time graph shows.
+const FRAME_GRAPH_SAMPLES: usize = 120;
+const FRAME_GRAPH_BAR_WIDTH: f32 = 2.0;
+const FRAME_GRAPH_HEIGHT: f32 = 40.0;
+/// The frame time that fills the graph to the top, in milliseconds.
+const FRAME_GRAPH_MAX_MS: f32 = 33.3;
+
+pub struct DebugOverlayPlugin;
+
+impl Plugin for DebugOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(FrameTimeDiagnosticsPlugin)
+            .init_resource::<FrameTimeHistory>()
+            .add_systems(Startup, spawn_debug_overlay)
+            .add_systems(
+                Update,
+                (
+                    toggle_debug_overlay,
+                    record_frame_time,
+                    (update_debug_text, update_frame_graph).run_if(debug_overlay_visible),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Marks the root node of the overlay.
+#[derive(Component)]
+struct DebugOverlay;
+
+/// Marks the text block of the overlay.
+#[derive(Component)]
+struct DebugText;
+
+/// A bar of the frame time graph, showing the frame this many samples ago.
+#[derive(Component)]
+struct FrameGraphBar(usize);
+
+/// The most recent frame times in milliseconds, newest first.
+#[derive(Resource, Default)]
+struct FrameTimeHistory(VecDeque<f32>);
+
+fn spawn_debug_overlay(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    padding: UiRect::all(Val::Px(6.0)),
+                    row_gap: Val::Px(6.0),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.6).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            DebugOverlay,
+        ))
+        .with_children(|overlay| {
+            overlay.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                DebugText,
+            ));
+
+            // The frame time graph, with the newest frame on the right.
+            overlay
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(FRAME_GRAPH_SAMPLES as f32 * FRAME_GRAPH_BAR_WIDTH),
+                        height: Val::Px(FRAME_GRAPH_HEIGHT),
+                        align_items: AlignItems::FlexEnd,
+                        ..default()
+                    },
+                    background_color: Color::srgba(1.0, 1.0, 1.0, 0.1).into(),
+                    ..default()
+                })
+                .with_children(|graph| {
+                    for age in (0..FRAME_GRAPH_SAMPLES).rev() {
+                        graph.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Px(FRAME_GRAPH_BAR_WIDTH),
+                                    height: Val::Px(0.0),
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            FrameGraphBar(age),
+                        ));
+                    }
+                });
+        });
+}
+
+/// Shows or hides the overlay when F3 is pressed.
+fn toggle_debug_overlay(
+    input: Res<ButtonInput<KeyCode>>,
+    mut overlays: Query<&mut Visibility, With<DebugOverlay>>,
+) {
+    if !input.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    for mut visibility in overlays.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn debug_overlay_visible(overlays: Query<&Visibility, With<DebugOverlay>>) -> bool {
+    overlays
+        .get_single()
+        .is_ok_and(|visibility| *visibility != Visibility::Hidden)
+}
+
+/// Records frame times even while the overlay is hidden, so the graph is
+/// already filled in when it is opened.
+fn record_frame_time(time: Res<Time<Real>>, mut history: ResMut<FrameTimeHistory>) {
+    history.0.push_front(time.delta_seconds() * 1000.0);
+    history.0.truncate(FRAME_GRAPH_SAMPLES);
+}
+
+fn update_debug_text(
+    diagnostics: Res<DiagnosticsStore>,
+    app_settings: Res<AppSettings>,
+    governor: Res<PerformanceGovernor>,
+    game_speed: Res<GameSpeed>,
+    players: Query<(&Position, &Rotation), With<Player>>,
+    mut texts: Query<&mut Text, With<DebugText>>,
+) {
+    let fps = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or_default();
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|frame_time| frame_time.smoothed())
+        .unwrap_or_default();
+
+    let mut value = format!("FPS: {fps:.0} ({frame_time:.2} ms)\n");
+    value += &format!("Game speed: {:.2}x\n", game_speed.speed());
+
+    if let Ok((position, rotation)) = players.get_single() {
+        let p = position.current;
+        value += &format!("Position: {:.2} / {:.2} / {:.2}\n", p.x, p.y, p.z);
+        value += &format!(
+            "Facing: {} ({:.0}°)\n",
+            facing_axis(rotation.radians_y),
+            rotation.radians_y.to_degrees().rem_euclid(360.0)
+        );
+    }
+
+    value += &match app_settings.mode {
+        Some(mode) => format!(
+            "DOF: {:?} at {} quality, focal distance {:.2}{}, aperture f/{:.3}",
+            mode,
+            governor.dof_cap.min(app_settings.quality).label(),
+            app_settings.focal_distance,
+            if app_settings.auto_focus {
+                " (auto)"
+            } else {
+                ""
+            },
+            app_settings.aperture_f_stops
+        ),
+        None => "DOF: off".to_string(),
+    };
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value.clone_from(&value);
+    }
+}
+
+/// Names the world axis closest to the direction the player faces.
+fn facing_axis(radians_y: f32) -> &'static str {
+    // The player's yaw is measured from +Z towards +X.
+    let (x, z) = radians_y.sin_cos();
+    if x.abs() > z.abs() {
+        if x > 0.0 {
+            "+X"
+        } else {
+            "-X"
+        }
+    } else if z > 0.0 {
+        "+Z"
+    } else {
+        "-Z"
+    }
+}
+
+fn update_frame_graph(
+    history: Res<FrameTimeHistory>,
+    mut bars: Query<(&FrameGraphBar, &mut Style, &mut BackgroundColor)>,
+) {
+    for (bar, mut style, mut color) in bars.iter_mut() {
+        let frame_time = history.0.get(bar.0).copied().unwrap_or_default();
+        let fill = (frame_time / FRAME_GRAPH_MAX_MS).min(1.0);
+        style.height = Val::Px(fill * FRAME_GRAPH_HEIGHT);
+
+        // Green while above 60 FPS, yellow while above 30 FPS, red below that.
+        *color = if frame_time < 1000.0 / 60.0 {
+            Color::srgb(0.2, 0.9, 0.2)
+        } else if frame_time < 1000.0 / 30.0 {
+            Color::srgb(0.9, 0.8, 0.2)
+        } else {
+            Color::srgb(0.9, 0.2, 0.2)
+        }
+        .into();
+    }
+}
@@ -0,0 +1,355 @@
+//! NPCs to talk to, following the dialogue in `assets/dialogue/*.ron`.
+//!
+//! Walking up to an NPC and pressing the interact key opens a conversation:
+//! the NPC's name, what they say, and the answers the player can pick. Each
+//! answer leads on to another line or ends the conversation. Answers can
+//! be offered only once the player's quests reach some stage, and can have
+//! effects such as starting a quest or opening a shop. Walking away ends the
+//! conversation.
+
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::Deserialize;
+
+use crate::{
+    animation::{AnimFsm, AnimState, AnimationBinding, ClipSet},
+    health::Dead,
+    menu::PauseState,
+    nameplates::Nameplate,
+    photo_mode::PhotoMode,
+    quests::{QuestLog, QuestState, StartQuest},
+    settings::ControlSettings,
+    trade::OpenTrade,
+    Player, Position,
+};
+
+/// The directory dialogue is loaded from.
+const DIALOGUE_DIR: &str = "assets/dialogue";
+/// The NPCs in the world: the dialogue they speak, where they stand and the
+/// way they face, in radians.
+const NPCS: [(&str, Vec3, f32); 1] = [("ranger", Vec3::new(10.0, 0.0, -8.0), -2.4)];
+/// How close the player has to be to an NPC to talk to them.
+const TALK_DISTANCE: f32 = 2.5;
+/// How far the player can walk from an NPC before the conversation ends.
+const LEAVE_DISTANCE: f32 = 5.0;
+/// How big NPCs are, the same as the player.
+const NPC_SCALE: f32 = 0.012;
+/// How far above the ground NPCs' names float.
+const NAMEPLATE_HEIGHT: f32 = 1.4;
+
+pub struct DialoguePlugin;
+
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_dialogues())
+            .add_systems(Startup, spawn_npcs)
+            .add_systems(
+                Update,
+                (
+                    start_conversation
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off)))
+                        .run_if(not(resource_exists::<Conversation>)),
+                    end_distant_conversations.run_if(resource_exists::<Conversation>),
+                    conversation
+                        .run_if(resource_exists::<Conversation>)
+                        .run_if(in_state(PhotoMode::Off)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// What an NPC has to say, as written in a dialogue file.
+#[derive(Deserialize)]
+struct Dialogue {
+    /// The name NPCs are given this dialogue by.
+    id: String,
+    /// The name of the NPC speaking.
+    speaker: String,
+    /// The line a conversation opens with.
+    start: String,
+    /// Each line the NPC can say, by name.
+    nodes: HashMap<String, DialogueNode>,
+}
+
+/// Something an NPC says, and the answers to it.
+#[derive(Deserialize)]
+struct DialogueNode {
+    text: String,
+    /// The answers the player can pick. With none available, the player can
+    /// only say goodbye.
+    #[serde(default)]
+    choices: Vec<Choice>,
+}
+
+/// An answer the player can give.
+#[derive(Deserialize)]
+struct Choice {
+    text: String,
+    /// The line this answer leads to, or `None` to end the conversation.
+    #[serde(default)]
+    next: Option<String>,
+    /// What has to hold for this answer to be offered.
+    #[serde(default)]
+    requires: Vec<Requirement>,
+    /// What happens when this answer is picked.
+    #[serde(default)]
+    effects: Vec<Effect>,
+}
+
+/// Something that has to hold for an answer to be offered.
+#[derive(Deserialize)]
+enum Requirement {
+    /// The quest with this id is at this stage.
+    Quest(String, QuestState),
+}
+
+impl Requirement {
+    fn holds(&self, log: &QuestLog) -> bool {
+        match self {
+            Requirement::Quest(id, state) => log.state(id) == *state,
+        }
+    }
+}
+
+/// Something that happens when an answer is picked.
+#[derive(Deserialize)]
+enum Effect {
+    /// Gives the player the quest with this id.
+    StartQuest(String),
+    /// Opens the trade window of the shop with this id.
+    OpenTrade(String),
+}
+
+impl Dialogue {
+    /// Warns about lines that are missing, which would end the conversation
+    /// early.
+    fn check(&self) {
+        let targets = self
+            .nodes
+            .values()
+            .flat_map(|node| &node.choices)
+            .filter_map(|choice| choice.next.as_ref());
+        for name in std::iter::once(&self.start).chain(targets) {
+            if !self.nodes.contains_key(name) {
+                warn!("The dialogue {} has no line called {name}", self.id);
+            }
+        }
+    }
+}
+
+/// Every dialogue that was loaded, by id.
+#[derive(Resource)]
+struct Dialogues(HashMap<String, Dialogue>);
+
+/// Marks a character the player can talk to, with the id of their dialogue.
+#[derive(Component)]
+pub struct Npc {
+    pub dialogue: String,
+}
+
+/// The conversation the player is having.
+#[derive(Resource)]
+struct Conversation {
+    npc: Entity,
+    dialogue: String,
+    /// The line the NPC is saying.
+    node: String,
+}
+
+/// Reads every dialogue file, skipping and warning about those that can't
+/// be read.
+fn load_dialogues() -> Dialogues {
+    let entries = match fs::read_dir(DIALOGUE_DIR) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to read {DIALOGUE_DIR}, no dialogue loaded: {error}");
+            return Dialogues(HashMap::new());
+        }
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    paths.sort();
+
+    let dialogues = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path)
+                .map_err(|error| warn!("Failed to read {}: {error}", path.display()))
+                .ok()?;
+            ron::from_str::<Dialogue>(&text)
+                .map_err(|error| warn!("Failed to parse {}: {error}", path.display()))
+                .ok()
+        })
+        .inspect(Dialogue::check)
+        .map(|dialogue| (dialogue.id.clone(), dialogue))
+        .collect();
+    Dialogues(dialogues)
+}
+
+fn spawn_npcs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+    dialogues: Res<Dialogues>,
+) {
+    let mut graph = AnimationGraph::new();
+    let survey = graph.add_clip(
+        asset_server.load(GltfAssetLabel::Animation(0).from_asset("models/Fox.glb")),
+        1.0,
+        graph.root,
+    );
+    let clips = ClipSet::new(graphs.add(graph)).with_clip(AnimState::Idle, survey, 1.0);
+
+    for (id, position, radians_y) in NPCS {
+        let Some(dialogue) = dialogues.0.get(id) else {
+            warn!("No dialogue called {id}, leaving its NPC out");
+            continue;
+        };
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load("models/Fox.glb#Scene0"),
+                transform: Transform::from_translation(position)
+                    .with_rotation(Quat::from_rotation_y(radians_y))
+                    .with_scale(Vec3::splat(NPC_SCALE)),
+                ..default()
+            },
+            AnimationBinding::new(clips.clone()),
+            AnimFsm::default(),
+            Nameplate::new(dialogue.speaker.clone(), NAMEPLATE_HEIGHT),
+            Npc {
+                dialogue: id.to_string(),
+            },
+        ));
+    }
+}
+
+/// Talks to the nearest NPC in reach when the interact key is pressed.
+fn start_conversation(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    controls: Res<ControlSettings>,
+    dialogues: Res<Dialogues>,
+    players: Query<&Position, (With<Player>, Without<Dead>)>,
+    npcs: Query<(Entity, &Npc, &Transform)>,
+) {
+    if !keys.just_pressed(controls.interact) {
+        return;
+    }
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+
+    let nearest = npcs
+        .iter()
+        .map(|(entity, npc, transform)| {
+            (entity, npc, transform.translation.distance(player.current))
+        })
+        .filter(|(_, _, distance)| *distance <= TALK_DISTANCE)
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    let Some((entity, npc, _)) = nearest else {
+        return;
+    };
+    let Some(dialogue) = dialogues.0.get(&npc.dialogue) else {
+        return;
+    };
+    commands.insert_resource(Conversation {
+        npc: entity,
+        dialogue: dialogue.id.clone(),
+        node: dialogue.start.clone(),
+    });
+}
+
+/// Ends the conversation once the player walks away, dies, or the NPC is
+/// gone.
+fn end_distant_conversations(
+    mut commands: Commands,
+    conversation: Res<Conversation>,
+    players: Query<&Position, (With<Player>, Without<Dead>)>,
+    npcs: Query<&Transform, With<Npc>>,
+) {
+    let in_reach = match (players.get_single(), npcs.get(conversation.npc)) {
+        (Ok(player), Ok(npc)) => npc.translation.distance(player.current) <= LEAVE_DISTANCE,
+        _ => false,
+    };
+    if !in_reach {
+        commands.remove_resource::<Conversation>();
+    }
+}
+
+/// Shows what the NPC is saying and the answers the player can give, and
+/// carries out the answer picked.
+fn conversation(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut conversation: ResMut<Conversation>,
+    dialogues: Res<Dialogues>,
+    log: Res<QuestLog>,
+    mut start_quest: EventWriter<StartQuest>,
+    mut open_trade: EventWriter<OpenTrade>,
+) {
+    let Some((dialogue, node)) = dialogues
+        .0
+        .get(&conversation.dialogue)
+        .and_then(|dialogue| Some((dialogue, dialogue.nodes.get(&conversation.node)?)))
+    else {
+        commands.remove_resource::<Conversation>();
+        return;
+    };
+
+    let mut picked = None;
+    let mut done = false;
+    egui::Window::new(&dialogue.speaker)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -110.0))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.set_max_width(420.0);
+            ui.label(&node.text);
+            ui.separator();
+
+            let mut offered = node
+                .choices
+                .iter()
+                .filter(|choice| {
+                    choice
+                        .requires
+                        .iter()
+                        .all(|requirement| requirement.holds(&log))
+                })
+                .peekable();
+            if offered.peek().is_none() {
+                done = ui.button("Goodbye.").clicked();
+            }
+            for choice in offered {
+                if ui.button(&choice.text).clicked() {
+                    picked = Some(choice);
+                }
+            }
+        });
+
+    if let Some(choice) = picked {
+        for effect in &choice.effects {
+            match effect {
+                Effect::StartQuest(id) => {
+                    start_quest.send(StartQuest(id.clone()));
+                }
+                Effect::OpenTrade(id) => {
+                    open_trade.send(OpenTrade(id.clone()));
+                }
+            }
+        }
+        match &choice.next {
+            Some(next) => conversation.node.clone_from(next),
+            None => done = true,
+        }
+    }
+    if done {
+        commands.remove_resource::<Conversation>();
+    }
+}
@@ -0,0 +1,77 @@
+//! Creative and survival play.
+//!
+//! The game starts in survival, where the player walks, takes fall damage,
+//! and uses up what they throw and craft. `/gamemode creative` lifts those
+//! limits for building and looking around: pressing jump twice quickly
+//! starts or stops flying, landing never hurts, and throwing and crafting
+//! take nothing from the inventory. Joined to a server, the player plays in
+//! the server's mode, which its host picks or a dedicated server's config
+//! sets, and the server only lets them fly in creative.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatCommandsExt, ChatLog, CommandArgs},
+    net::NetClient,
+};
+
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameMode>().add_chat_command(
+            "gamemode",
+            "[survival|creative]",
+            "Shows or sets the game mode",
+            game_mode_command,
+        );
+    }
+}
+
+/// How the player plays.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GameMode {
+    /// Walking under gravity, with health and a limited inventory.
+    #[default]
+    Survival,
+    /// Flying, no fall damage, and items that never run out.
+    Creative,
+}
+
+impl GameMode {
+    fn name(self) -> &'static str {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Creative => "creative",
+        }
+    }
+}
+
+fn game_mode_command(
+    In(args): In<CommandArgs>,
+    mut game_mode: ResMut<GameMode>,
+    mut log: ResMut<ChatLog>,
+    client: Option<Res<NetClient>>,
+) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mode = match args[..] {
+        [] => {
+            log.info(format!("The game mode is {}", game_mode.name()));
+            return;
+        }
+        ["survival"] => GameMode::Survival,
+        ["creative"] => GameMode::Creative,
+        _ => {
+            log.info("Usage: /gamemode [survival|creative]");
+            return;
+        }
+    };
+    if client.is_some() {
+        log.info("The server decides the game mode");
+        return;
+    }
+    *game_mode = mode;
+    log.info(format!("Set the game mode to {}", mode.name()));
+}
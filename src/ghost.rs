@@ -0,0 +1,224 @@
+//! Translucent "ghosts" that replay a previous run next to the live player,
+//! for practicing a route.
+//!
+//! F5 starts a run: the player is recorded and the loaded ghost, if any,
+//! starts replaying alongside. F6 finishes the run, saves it to `ghosts/` and
+//! makes it the ghost for the next run. F7 loads the newest saved ghost.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+
+use crate::{
+    animation::{AnimFsm, AnimationBinding},
+    lod::SimulationLod,
+    menu::PauseState,
+    replay::{Recorder, Recording},
+    simulation::WorldSimulation,
+    Player,
+};
+
+/// The directory ghost runs are saved in.
+const GHOST_DIR: &str = "ghosts";
+/// How opaque ghosts are drawn.
+const GHOST_ALPHA: f32 = 0.35;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GhostRun>()
+            .init_resource::<GhostMaterials>()
+            .add_systems(
+                Update,
+                (
+                    ghost_controls.run_if(in_state(PauseState::Running)),
+                    play_ghosts.in_set(WorldSimulation),
+                    make_ghosts_translucent,
+                ),
+            );
+    }
+}
+
+/// The run that ghosts replay.
+#[derive(Resource, Default)]
+struct GhostRun(Option<Arc<Recording>>);
+
+/// A character replaying a recorded run.
+#[derive(Component)]
+struct Ghost {
+    recording: Arc<Recording>,
+    elapsed: f32,
+}
+
+/// Translucent copies of the materials used by ghost scenes, by the original
+/// material, so every ghost shares them.
+#[derive(Resource, Default)]
+struct GhostMaterials(HashMap<AssetId<StandardMaterial>, Handle<StandardMaterial>>);
+
+fn ghost_controls(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut recorder: ResMut<Recorder>,
+    mut ghost_run: ResMut<GhostRun>,
+    players: Query<(&Handle<Scene>, &AnimationBinding), With<Player>>,
+    ghosts: Query<Entity, With<Ghost>>,
+) {
+    if input.just_pressed(KeyCode::F5) {
+        recorder.start();
+
+        // Restart the ghost together with the new run.
+        for ghost in ghosts.iter() {
+            commands.entity(ghost).despawn_recursive();
+        }
+        if let (Some(recording), Ok((scene, binding))) = (&ghost_run.0, players.get_single()) {
+            spawn_ghost(&mut commands, recording.clone(), scene.clone(), binding);
+        }
+        info!("Run started");
+    }
+
+    if input.just_pressed(KeyCode::F6) {
+        if let Some(recording) = recorder.stop() {
+            match save_ghost(&recording) {
+                Ok(path) => info!("Saved ghost to {}", path.display()),
+                Err(error) => warn!("Failed to save ghost: {error}"),
+            }
+            ghost_run.0 = Some(Arc::new(recording));
+        }
+    }
+
+    if input.just_pressed(KeyCode::F7) {
+        match load_newest_ghost() {
+            Ok(recording) => {
+                info!("Loaded ghost of a {:.1}s run", recording.duration());
+                ghost_run.0 = Some(Arc::new(recording));
+            }
+            Err(error) => warn!("Failed to load a ghost: {error}"),
+        }
+    }
+}
+
+fn spawn_ghost(
+    commands: &mut Commands,
+    recording: Arc<Recording>,
+    scene: Handle<Scene>,
+    binding: &AnimationBinding,
+) {
+    let Some((transform, _)) = recording.sample(0.0) else {
+        return;
+    };
+
+    commands.spawn((
+        SceneBundle {
+            scene,
+            transform,
+            ..default()
+        },
+        AnimationBinding::new(binding.clips.clone()),
+        AnimFsm::default(),
+        Ghost {
+            recording,
+            elapsed: 0.0,
+        },
+        SimulationLod::default(),
+    ));
+}
+
+/// Moves each ghost along its recording, and removes it once the recording
+/// is over. Distant ghosts keep time but only update their pose when their
+/// level of detail lets them.
+fn play_ghosts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut ghosts: Query<(
+        Entity,
+        &mut Ghost,
+        &SimulationLod,
+        &mut Transform,
+        &mut AnimFsm,
+    )>,
+) {
+    for (entity, mut ghost, lod, mut transform, mut anim_fsm) in ghosts.iter_mut() {
+        ghost.elapsed += time.delta_seconds();
+        if ghost.elapsed > ghost.recording.duration() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        if lod.step().is_none() {
+            continue;
+        }
+
+        if let Some((pose, anim_state)) = ghost.recording.sample(ghost.elapsed) {
+            *transform = pose;
+            if anim_fsm.state() != anim_state {
+                anim_fsm.set(anim_state);
+            }
+        }
+    }
+}
+
+/// Swaps the materials of newly spawned ghost meshes for translucent ones.
+fn make_ghosts_translucent(
+    mut commands: Commands,
+    meshes: Query<(Entity, &Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    parents: Query<&Parent>,
+    ghosts: Query<(), With<Ghost>>,
+    mut ghost_materials: ResMut<GhostMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, material) in meshes.iter() {
+        if !parents
+            .iter_ancestors(entity)
+            .any(|ancestor| ghosts.contains(ancestor))
+        {
+            continue;
+        }
+
+        let ghost_material = match ghost_materials.0.get(&material.id()) {
+            Some(ghost_material) => ghost_material.clone(),
+            None => {
+                let Some(original) = materials.get(material) else {
+                    continue;
+                };
+                let mut translucent = original.clone();
+                translucent.base_color.set_alpha(GHOST_ALPHA);
+                translucent.alpha_mode = AlphaMode::Blend;
+                let ghost_material = materials.add(translucent);
+                ghost_materials
+                    .0
+                    .insert(material.id(), ghost_material.clone());
+                ghost_material
+            }
+        };
+
+        commands
+            .entity(entity)
+            .insert((ghost_material, NotShadowCaster));
+    }
+}
+
+fn save_ghost(recording: &Recording) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = PathBuf::from(GHOST_DIR).join(format!("ghost-{timestamp}.json"));
+    recording.save(&path)?;
+    Ok(path)
+}
+
+fn load_newest_ghost() -> Result<Recording, Box<dyn std::error::Error>> {
+    // Ghost file names start with a timestamp, so the newest sorts last.
+    let newest = fs::read_dir(GHOST_DIR)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .max()
+        .ok_or("no saved ghosts")?;
+    Recording::load(&newest)
+}
@@ -0,0 +1,151 @@
+//! An adaptive performance governor, which trades away rendering quality
+//! when frames take too long and hands it back once they are fast again.
+//!
+//! For now only depth of field is governed, as it is by far the most
+//! expensive effect at high resolutions. The governor caps the DOF quality
+//! the player picked in the settings, stepping the cap down a level at a
+//! time while the frame rate is below target, and back up after a while
+//! within budget.
+
+use bevy::{
+    core_pipeline::dof::{DepthOfFieldMode, DepthOfFieldSettings},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::benchmark::BenchmarkState;
+
+/// The frame time the governor aims to stay under, in seconds.
+const FRAME_BUDGET: f32 = 1.0 / 55.0;
+/// How quickly the smoothed frame time follows the actual one, per second.
+const SMOOTHING_SPEED: f32 = 4.0;
+/// How long to wait after changing quality before judging its effect, in
+/// seconds.
+const SETTLE_TIME: f32 = 1.0;
+/// How long frames must stay within budget before quality goes back up, in
+/// seconds.
+const RECOVERY_TIME: f32 = 10.0;
+
+pub struct GovernorPlugin;
+
+impl Plugin for GovernorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PerformanceGovernor>().add_systems(
+            Update,
+            govern_quality.run_if(not(in_state(BenchmarkState::Running))),
+        );
+    }
+}
+
+/// How much work the depth of field effect does. Lower levels blur less
+/// and fall back from bokeh to the cheaper gaussian blur.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DofQuality {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
+
+impl DofQuality {
+    pub const ALL: [DofQuality; 3] = [DofQuality::Low, DofQuality::Medium, DofQuality::High];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DofQuality::Low => "Low",
+            DofQuality::Medium => "Medium",
+            DofQuality::High => "High",
+        }
+    }
+
+    /// The largest blur, in pixels, the effect may spread a point into.
+    /// The cost of both blurs grows with it.
+    fn max_blur(self) -> f32 {
+        match self {
+            DofQuality::Low => 16.0,
+            DofQuality::Medium => 32.0,
+            DofQuality::High => 64.0,
+        }
+    }
+
+    /// Scales `settings` down to this quality.
+    pub fn apply(self, settings: &mut DepthOfFieldSettings) {
+        if self < DofQuality::High {
+            settings.mode = DepthOfFieldMode::Gaussian;
+        }
+        settings.max_circle_of_confusion_diameter = self.max_blur();
+    }
+
+    fn lower(self) -> Option<Self> {
+        match self {
+            DofQuality::Low => None,
+            DofQuality::Medium => Some(DofQuality::Low),
+            DofQuality::High => Some(DofQuality::Medium),
+        }
+    }
+
+    fn higher(self) -> Option<Self> {
+        match self {
+            DofQuality::Low => Some(DofQuality::Medium),
+            DofQuality::Medium => Some(DofQuality::High),
+            DofQuality::High => None,
+        }
+    }
+}
+
+/// The quality the governor currently allows, and what it has seen of the
+/// frame rate.
+#[derive(Resource)]
+pub struct PerformanceGovernor {
+    /// The highest DOF quality allowed right now.
+    pub dof_cap: DofQuality,
+    /// The frame time, smoothed over the last few frames, in seconds.
+    frame_time: f32,
+    /// Seconds since the cap last changed.
+    settled: f32,
+    /// Seconds frames have stayed within budget.
+    within_budget: f32,
+}
+
+impl Default for PerformanceGovernor {
+    fn default() -> Self {
+        Self {
+            dof_cap: DofQuality::High,
+            frame_time: FRAME_BUDGET,
+            settled: 0.0,
+            within_budget: 0.0,
+        }
+    }
+}
+
+/// Lowers the DOF quality cap while frames are over budget, and raises it
+/// again once they have been within budget for a while.
+fn govern_quality(time: Res<Time<Real>>, mut governor: ResMut<PerformanceGovernor>) {
+    let dt = time.delta_seconds();
+    // Only the cap changing should trigger change detection, so the DOF
+    // settings are rewritten only then.
+    let state = governor.bypass_change_detection();
+    let blend = 1.0 - (-SMOOTHING_SPEED * dt).exp();
+    state.frame_time += (dt - state.frame_time) * blend;
+    state.settled += dt;
+    if state.settled < SETTLE_TIME {
+        return;
+    }
+
+    let next = if state.frame_time > FRAME_BUDGET {
+        state.within_budget = 0.0;
+        state.dof_cap.lower()
+    } else {
+        state.within_budget += dt;
+        if state.within_budget < RECOVERY_TIME {
+            return;
+        }
+        state.within_budget = 0.0;
+        state.dof_cap.higher()
+    };
+    if let Some(next) = next {
+        governor.dof_cap = next;
+        governor.settled = 0.0;
+    }
+}
@@ -0,0 +1,282 @@
+//! The player's health, fall damage, dying and respawning.
+//!
+//! Landing faster than [`SAFE_LANDING_SPEED`] hurts, more the harder the
+//! landing, except for the player in creative. The player's [`Health`] is
+//! shown as a row of hearts above the hotbar. At zero health the player is
+//! [`Dead`]: they stop taking input, play their death clip, and can respawn
+//! at the spawn point, which B moves to where they stand. Both are
+//! replicated, so other players in a session see the player's health bar
+//! and whether they are dead.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game_mode::GameMode, menu::PauseState, net::NetClient, photo_mode::PhotoMode,
+    replication::ReplicateExt, Landed, MovementInput, Player, Position,
+};
+
+/// The health the player starts and respawns with. Each heart is two points.
+const MAX_HEALTH: f32 = 20.0;
+/// How many points of health a heart on the HUD stands for.
+const HEALTH_PER_HEART: f32 = 2.0;
+/// The fastest the player can land without being hurt. A little faster than
+/// landing from a jump.
+const SAFE_LANDING_SPEED: f32 = 30.0;
+/// How much health is lost per unit of landing speed beyond the safe speed.
+const FALL_DAMAGE_PER_SPEED: f32 = 0.5;
+
+const HEART_SIZE: f32 = 16.0;
+const HEART_COLOR: Color = Color::srgb(0.85, 0.1, 0.15);
+const EMPTY_HEART_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.5);
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnPoint>()
+            .add_event::<Died>()
+            .add_event::<Respawned>()
+            .add_plugins(HealthReplicationPlugin)
+            .add_systems(Startup, spawn_health_bar)
+            .add_systems(
+                Update,
+                (
+                    // Servers pick where their players respawn.
+                    set_spawn_point.run_if(
+                        in_state(PauseState::Running)
+                            .and_then(in_state(PhotoMode::Off))
+                            .and_then(not(resource_exists::<NetClient>)),
+                    ),
+                    apply_fall_damage,
+                    die,
+                    update_health_bar,
+                    respawn_menu.run_if(in_state(PauseState::Running)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Shares health and deaths with everyone in the session. Dedicated servers
+/// add it on its own, to pass them on.
+pub struct HealthReplicationPlugin;
+
+impl Plugin for HealthReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.replicate::<Health>("health").replicate::<Dead>("dead");
+    }
+}
+
+/// Sent when a character's health runs out.
+#[derive(Event)]
+pub struct Died {
+    pub entity: Entity,
+}
+
+/// Sent when the player comes back after dying.
+#[derive(Event)]
+pub struct Respawned {
+    pub entity: Entity,
+}
+
+/// How much more damage a character can take before dying.
+#[derive(Component, Serialize, Deserialize)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(MAX_HEALTH)
+    }
+}
+
+impl Health {
+    /// Full health of `max` points.
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Takes `amount` of health away, down to zero.
+    pub fn damage(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+    }
+}
+
+/// Marks a character whose health ran out.
+#[derive(Component, Serialize, Deserialize)]
+pub struct Dead;
+
+/// Where the player comes back after dying. Players joining this game as a
+/// server come back here too.
+#[derive(Resource, Default)]
+pub struct SpawnPoint(pub Vec3);
+
+/// A heart of the health bar, showing this heart's share of the health.
+#[derive(Component)]
+struct Heart(usize);
+
+fn spawn_health_bar(mut commands: Commands) {
+    let hearts = (MAX_HEALTH / HEALTH_PER_HEART).ceil() as usize;
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                // Just above the hotbar.
+                bottom: Val::Px(68.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                column_gap: Val::Px(3.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|bar| {
+            for index in 0..hearts {
+                bar.spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(HEART_SIZE),
+                        height: Val::Px(HEART_SIZE),
+                        ..default()
+                    },
+                    background_color: EMPTY_HEART_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|heart| {
+                    heart.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(100.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: HEART_COLOR.into(),
+                            ..default()
+                        },
+                        Heart(index),
+                    ));
+                });
+            }
+        });
+}
+
+/// Moves the spawn point to where the player stands.
+fn set_spawn_point(
+    input: Res<ButtonInput<KeyCode>>,
+    mut spawn_point: ResMut<SpawnPoint>,
+    players: Query<&Position, (With<Player>, Without<Dead>)>,
+) {
+    if !input.just_pressed(KeyCode::KeyB) {
+        return;
+    }
+    let Ok(position) = players.get_single() else {
+        return;
+    };
+
+    spawn_point.0 = position.current;
+    info!("Spawn point set to {}", spawn_point.0);
+}
+
+/// Hurts characters that landed too hard, unless it is the player in
+/// creative.
+fn apply_fall_damage(
+    game_mode: Res<GameMode>,
+    mut landings: EventReader<Landed>,
+    mut characters: Query<(&mut Health, Has<Player>)>,
+) {
+    for landing in landings.read() {
+        let Ok((mut health, player)) = characters.get_mut(landing.entity) else {
+            continue;
+        };
+        if player && *game_mode == GameMode::Creative {
+            continue;
+        }
+
+        let excess = landing.speed - SAFE_LANDING_SPEED;
+        if excess > 0.0 {
+            health.damage(excess * FALL_DAMAGE_PER_SPEED);
+        }
+    }
+}
+
+/// Marks characters whose health ran out as dead, and stops them where they
+/// fell.
+fn die(
+    mut commands: Commands,
+    mut characters: Query<(Entity, &Health, Option<&mut MovementInput>), Without<Dead>>,
+    mut deaths: EventWriter<Died>,
+) {
+    for (entity, health, input) in characters.iter_mut() {
+        if health.current > 0.0 {
+            continue;
+        }
+        commands.entity(entity).insert(Dead);
+        deaths.send(Died { entity });
+        if let Some(mut input) = input {
+            *input = MovementInput::default();
+        }
+    }
+}
+
+/// Fills each heart with its share of the player's health.
+fn update_health_bar(
+    players: Query<&Health, (With<Player>, Changed<Health>)>,
+    mut hearts: Query<(&Heart, &mut Style)>,
+) {
+    let Ok(health) = players.get_single() else {
+        return;
+    };
+
+    for (heart, mut style) in hearts.iter_mut() {
+        let fill = (health.current - heart.0 as f32 * HEALTH_PER_HEART) / HEALTH_PER_HEART;
+        style.width = Val::Percent(fill.clamp(0.0, 1.0) * 100.0);
+    }
+}
+
+/// The parts of the player that respawning resets.
+type Respawning = (
+    Entity,
+    &'static mut Health,
+    &'static mut Position,
+    &'static mut Transform,
+);
+
+/// Offers a dead player the way back to the spawn point, or the server's
+/// when joined to one.
+fn respawn_menu(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    spawn_point: Res<SpawnPoint>,
+    client: Option<Res<NetClient>>,
+    mut players: Query<Respawning, (With<Player>, With<Dead>)>,
+    mut respawns: EventWriter<Respawned>,
+) {
+    let Ok((entity, mut health, mut position, mut transform)) = players.get_single_mut() else {
+        return;
+    };
+
+    let mut respawn = false;
+    egui::Window::new("You died")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            respawn = ui.button("Respawn").clicked();
+        });
+    if !respawn {
+        return;
+    }
+
+    let spot = match client {
+        Some(client) => client.spawn_point().unwrap_or_default(),
+        None => spawn_point.0,
+    };
+    health.current = health.max;
+    position.teleport(spot);
+    transform.translation = spot;
+    commands.entity(entity).remove::<Dead>();
+    respawns.send(Respawned { entity });
+}
@@ -0,0 +1,441 @@
+//! The player's inventory and the hotbar along the bottom of the screen.
+//!
+//! The first [`HOTBAR_SLOTS`] slots of the [`Inventory`] make up the hotbar.
+//! The number keys or the mouse wheel pick the selected hotbar slot. The
+//! player's inventory, coins included, is saved to `players/inventory.json`
+//! whenever it changes, and picked up from there next time. The `/give` chat
+//! command puts items straight into it.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bevy::{input::mouse::MouseWheel, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatCommandsExt, ChatLog, CommandArgs},
+    menu::PauseState,
+    photo_mode::PhotoMode,
+    spectator::Spectating,
+    Player,
+};
+
+/// How many slots an inventory has, hotbar included.
+pub const INVENTORY_SLOTS: usize = 36;
+/// How many of the inventory's slots are on the hotbar.
+pub const HOTBAR_SLOTS: usize = 9;
+/// How many of one item fit in a slot.
+pub const MAX_STACK_SIZE: u32 = 64;
+/// What the player starts out carrying.
+const STARTING_ITEMS: [(&str, u32); 4] = [
+    ("oak_log", 16),
+    ("cobblestone", 32),
+    ("torch", 8),
+    ("coin", 20),
+];
+/// Where the player's inventory is saved.
+const INVENTORY_PATH: &str = "players/inventory.json";
+/// How long the inventory has to stay unchanged before it is written, so
+/// scrolling through the hotbar doesn't rewrite the file every frame.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+const HOTBAR_SLOT_SIZE: f32 = 52.0;
+const SLOT_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.5);
+const SLOT_BORDER_COLOR: Color = Color::srgba(0.6, 0.6, 0.6, 0.8);
+const SELECTED_SLOT_BORDER_COLOR: Color = Color::WHITE;
+
+/// The keys that select each hotbar slot.
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_chat_command(
+            "give",
+            "<item> [count]",
+            "Puts items in the inventory",
+            give,
+        )
+        .add_systems(Startup, spawn_hotbar)
+        .add_systems(
+            Update,
+            (
+                // The mouse wheel sets the spectator camera's speed instead.
+                select_hotbar_slot.run_if(
+                    in_state(PauseState::Running)
+                        .and_then(in_state(PhotoMode::Off))
+                        .and_then(in_state(Spectating::Off)),
+                ),
+                update_hotbar,
+                save_inventory,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// The name an item is known by, e.g. "oak_log".
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ItemId(pub String);
+
+impl ItemId {
+    pub fn new(name: &str) -> Self {
+        Self(name.to_string())
+    }
+
+    /// A readable name for the item, e.g. "Oak log".
+    pub fn label(&self) -> String {
+        let mut label = self.0.replace('_', " ");
+        if let Some(first) = label.get_mut(..1) {
+            first.make_ascii_uppercase();
+        }
+        label
+    }
+}
+
+/// Some number of one item, filling a slot.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub item: ItemId,
+    pub count: u32,
+}
+
+/// The items a character carries, in [`INVENTORY_SLOTS`] slots.
+#[derive(Component, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+    /// The hotbar slot in hand.
+    selected: usize,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self {
+            slots: vec![None; INVENTORY_SLOTS],
+            selected: 0,
+        }
+    }
+}
+
+impl Inventory {
+    /// An inventory holding what the player starts out with.
+    pub fn starting() -> Self {
+        let mut inventory = Self::default();
+        for (item, count) in STARTING_ITEMS {
+            inventory.add(&ItemId::new(item), count);
+        }
+        inventory
+    }
+
+    /// The player's saved inventory, or what they start out with if there
+    /// is none.
+    pub fn load() -> Self {
+        let text = match fs::read_to_string(INVENTORY_PATH) {
+            Ok(text) => text,
+            Err(error) => {
+                if error.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to read {INVENTORY_PATH}: {error}");
+                }
+                return Self::starting();
+            }
+        };
+
+        let mut inventory: Self = match serde_json::from_str(&text) {
+            Ok(inventory) => inventory,
+            Err(error) => {
+                warn!("Failed to parse {INVENTORY_PATH}, starting afresh: {error}");
+                return Self::starting();
+            }
+        };
+        // Files written with a different number of slots still load, and
+        // what doesn't fit into the slots there are now is lost.
+        inventory.slots.resize(INVENTORY_SLOTS, None);
+        inventory.selected = inventory.selected.min(HOTBAR_SLOTS - 1);
+        for slot in inventory.slots.iter_mut() {
+            if let Some(stack) = slot {
+                stack.count = stack.count.min(MAX_STACK_SIZE);
+            }
+            if slot.as_ref().is_some_and(|stack| stack.count == 0) {
+                *slot = None;
+            }
+        }
+        inventory
+    }
+
+    pub fn slot(&self, index: usize) -> Option<&ItemStack> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    /// The stacks in the inventory, in slot order.
+    pub fn stacks(&self) -> impl Iterator<Item = &ItemStack> {
+        self.slots.iter().flatten()
+    }
+
+    /// How many of `item` the inventory holds in total.
+    pub fn count(&self, item: &ItemId) -> u32 {
+        self.stacks()
+            .filter(|stack| stack.item == *item)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    /// Removes `count` of `item`, emptying the last slots first, if there are
+    /// that many. Returns whether they were removed.
+    pub fn remove(&mut self, item: &ItemId, mut count: u32) -> bool {
+        if self.count(item) < count {
+            return false;
+        }
+
+        for slot in self.slots.iter_mut().rev() {
+            let Some(stack) = slot.as_mut().filter(|stack| stack.item == *item) else {
+                continue;
+            };
+            let taken = count.min(stack.count);
+            stack.count -= taken;
+            count -= taken;
+            if stack.count == 0 {
+                *slot = None;
+            }
+            if count == 0 {
+                break;
+            }
+        }
+        true
+    }
+
+    /// The stack in the selected hotbar slot, if any.
+    pub fn selected_stack(&self) -> Option<&ItemStack> {
+        self.slot(self.selected)
+    }
+
+    /// Takes up to `count` items out of the selected hotbar slot.
+    pub fn take_selected(&mut self, count: u32) -> Option<ItemStack> {
+        let slot = &mut self.slots[self.selected];
+        let stack = slot.as_mut()?;
+        let taken = count.min(stack.count);
+        stack.count -= taken;
+        let item = stack.item.clone();
+        if stack.count == 0 {
+            *slot = None;
+        }
+        Some(ItemStack { item, count: taken })
+    }
+
+    /// Adds `count` of `item`, topping up stacks of it before filling empty
+    /// slots, hotbar first. Returns how many didn't fit.
+    pub fn add(&mut self, item: &ItemId, mut count: u32) -> u32 {
+        for stack in self.slots.iter_mut().flatten() {
+            if count == 0 {
+                return 0;
+            }
+            if stack.item == *item {
+                let moved = count.min(MAX_STACK_SIZE - stack.count);
+                stack.count += moved;
+                count -= moved;
+            }
+        }
+
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if count == 0 {
+                return 0;
+            }
+            let moved = count.min(MAX_STACK_SIZE);
+            *slot = Some(ItemStack {
+                item: item.clone(),
+                count: moved,
+            });
+            count -= moved;
+        }
+
+        count
+    }
+}
+
+/// A slot of the hotbar UI, showing the inventory slot with this index.
+#[derive(Component)]
+struct HotbarSlot(usize);
+
+/// The label of a hotbar slot, showing the inventory slot with this index.
+#[derive(Component)]
+struct HotbarLabel(usize);
+
+fn spawn_hotbar(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(8.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                column_gap: Val::Px(4.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|hotbar| {
+            for index in 0..HOTBAR_SLOTS {
+                hotbar
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(HOTBAR_SLOT_SIZE),
+                                height: Val::Px(HOTBAR_SLOT_SIZE),
+                                border: UiRect::all(Val::Px(2.0)),
+                                padding: UiRect::all(Val::Px(2.0)),
+                                align_items: AlignItems::FlexEnd,
+                                ..default()
+                            },
+                            background_color: SLOT_COLOR.into(),
+                            border_color: SLOT_BORDER_COLOR.into(),
+                            ..default()
+                        },
+                        HotbarSlot(index),
+                    ))
+                    .with_children(|slot| {
+                        slot.spawn((
+                            TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font_size: 12.0,
+                                    color: Color::WHITE,
+                                    ..default()
+                                },
+                            ),
+                            HotbarLabel(index),
+                        ));
+                    });
+            }
+        });
+}
+
+/// Selects a hotbar slot with the number keys, or steps through them with the
+/// mouse wheel.
+fn select_hotbar_slot(
+    input: Res<ButtonInput<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut inventories: Query<&mut Inventory, With<Player>>,
+) {
+    let scroll: f32 = wheel.read().map(|event| event.y).sum();
+    let Ok(mut inventory) = inventories.get_single_mut() else {
+        return;
+    };
+
+    let selected = if let Some(index) = HOTBAR_KEYS.iter().position(|&key| input.just_pressed(key))
+    {
+        index
+    } else if scroll < 0.0 {
+        (inventory.selected + 1) % HOTBAR_SLOTS
+    } else if scroll > 0.0 {
+        (inventory.selected + HOTBAR_SLOTS - 1) % HOTBAR_SLOTS
+    } else {
+        return;
+    };
+    if inventory.selected != selected {
+        inventory.selected = selected;
+    }
+}
+
+/// Shows the player's hotbar items and highlights the selected slot.
+fn update_hotbar(
+    inventories: Query<&Inventory, (With<Player>, Changed<Inventory>)>,
+    mut slots: Query<(&HotbarSlot, &mut BorderColor)>,
+    mut labels: Query<(&HotbarLabel, &mut Text)>,
+) {
+    let Ok(inventory) = inventories.get_single() else {
+        return;
+    };
+
+    for (slot, mut border) in slots.iter_mut() {
+        *border = if slot.0 == inventory.selected {
+            SELECTED_SLOT_BORDER_COLOR.into()
+        } else {
+            SLOT_BORDER_COLOR.into()
+        };
+    }
+    for (label, mut text) in labels.iter_mut() {
+        text.sections[0].value = match inventory.slot(label.0) {
+            Some(stack) => format!("{}\n{}", stack.item.label(), stack.count),
+            None => String::new(),
+        };
+    }
+}
+
+/// Puts the items given into the player's inventory, as many as fit.
+fn give(
+    In(args): In<CommandArgs>,
+    mut players: Query<&mut Inventory, With<Player>>,
+    mut log: ResMut<ChatLog>,
+) {
+    let (item, count) = match &args[..] {
+        [item] => (item, Some(1)),
+        [item, count] => (item, count.parse::<u32>().ok().filter(|count| *count > 0)),
+        _ => (&String::new(), None),
+    };
+    let (Some(count), false) = (count, item.is_empty()) else {
+        log.info("Usage: /give <item> [count]");
+        return;
+    };
+    let Ok(mut inventory) = players.get_single_mut() else {
+        return;
+    };
+
+    let item = ItemId::new(item);
+    let given = count - inventory.add(&item, count);
+    log.info(format!("Gave {given} {}", item.label()));
+    if given < count {
+        log.info("The rest didn't fit in the inventory");
+    }
+}
+
+/// Writes the player's inventory once it has settled after a change, or
+/// right away when the app is exiting.
+fn save_inventory(
+    inventories: Query<Ref<Inventory>, With<Player>>,
+    mut exit_events: EventReader<AppExit>,
+    mut changed_at: Local<Option<Instant>>,
+) {
+    let Ok(inventory) = inventories.get_single() else {
+        return;
+    };
+    // The starting inventory doesn't need saving until something happens
+    // to it.
+    if inventory.is_changed() && !inventory.is_added() {
+        *changed_at = Some(Instant::now());
+    }
+
+    let exiting = exit_events.read().count() > 0;
+    let Some(changed) = *changed_at else {
+        return;
+    };
+    if !exiting && changed.elapsed() < SAVE_DELAY {
+        return;
+    }
+    *changed_at = None;
+
+    if let Err(error) = write_inventory(&inventory) {
+        warn!("Failed to save the inventory: {error}");
+    }
+}
+
+fn write_inventory(inventory: &Inventory) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = Path::new(INVENTORY_PATH).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(INVENTORY_PATH, serde_json::to_string_pretty(inventory)?)?;
+    Ok(())
+}
@@ -0,0 +1,266 @@
+//! Items lying in the world, which the player picks up by walking close.
+//!
+//! Q drops one of the selected hotbar item in front of the fox, and Ctrl+Q
+//! drops the whole stack. Dropped items fall and come to rest on the ground,
+//! spin in place, merge with matching drops nearby, and are pulled towards
+//! the player once they come within reach. Drops of more than one item show
+//! how many they hold. Items left lying around for too long disappear.
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    inventory::{Inventory, ItemId, ItemStack, MAX_STACK_SIZE},
+    menu::PauseState,
+    nameplates::Nameplate,
+    photo_mode::PhotoMode,
+    simulation::WorldSimulation,
+    Player, Rotation,
+};
+
+/// The edge length of a dropped item's cube.
+const DROP_SIZE: f32 = 0.3;
+/// The downward acceleration of falling drops.
+const DROP_GRAVITY: f32 = -20.0;
+/// How fast dropped items spin, in radians per second.
+const DROP_SPIN_SPEED: f32 = 1.5;
+/// How fast an item is thrown forwards and upwards when dropped.
+const DROP_THROW_SPEED: f32 = 5.0;
+const DROP_THROW_LIFT: f32 = 4.0;
+/// How long a dropped item ignores the player who dropped it, in seconds.
+const DROP_PICKUP_DELAY: f32 = 1.5;
+/// How far away the player starts pulling items in.
+const DROP_MAGNET_RADIUS: f32 = 3.0;
+/// How fast items are pulled towards the player.
+const DROP_MAGNET_SPEED: f32 = 8.0;
+/// How close an item has to be to the player to be picked up.
+const DROP_PICKUP_RADIUS: f32 = 0.6;
+/// How close two drops of the same item have to be to merge.
+const DROP_MERGE_RADIUS: f32 = 1.0;
+/// How long an item can lie around before it disappears, in seconds.
+const DROP_DESPAWN_TIME: f32 = 300.0;
+
+pub struct ItemDropPlugin;
+
+impl Plugin for ItemDropPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DropAssets>()
+            .add_event::<ItemsPickedUp>()
+            .add_systems(
+                Update,
+                (
+                    drop_selected_item
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                    (move_item_drops, merge_item_drops, pick_up_item_drops)
+                        .chain()
+                        .in_set(WorldSimulation),
+                    label_item_drops,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Sent when the player picks up items.
+#[derive(Event)]
+pub struct ItemsPickedUp {
+    pub item: ItemId,
+    pub count: u32,
+}
+
+/// An item lying in the world.
+#[derive(Component)]
+struct ItemDrop {
+    stack: ItemStack,
+    velocity: Vec3,
+    /// How long the drop has existed, in seconds.
+    age: f32,
+    /// How long the drop has to exist before the player can pick it up.
+    pickup_delay: f32,
+}
+
+/// The mesh all drops share, and a material for each item.
+#[derive(Resource, Default)]
+struct DropAssets {
+    mesh: Option<Handle<Mesh>>,
+    materials: HashMap<ItemId, Handle<StandardMaterial>>,
+}
+
+/// Drops the selected hotbar item in front of the player.
+fn drop_selected_item(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut assets: ResMut<DropAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut players: Query<(&Transform, &Rotation, &mut Inventory), With<Player>>,
+) {
+    if !input.just_pressed(KeyCode::KeyQ) {
+        return;
+    }
+    let Ok((transform, rotation, mut inventory)) = players.get_single_mut() else {
+        return;
+    };
+
+    let count = if input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        MAX_STACK_SIZE
+    } else {
+        1
+    };
+    let Some(stack) = inventory.take_selected(count) else {
+        return;
+    };
+
+    let forward = Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos());
+    let mesh = assets
+        .mesh
+        .get_or_insert_with(|| meshes.add(Cuboid::from_length(DROP_SIZE)))
+        .clone();
+    let material = assets
+        .materials
+        .entry(stack.item.clone())
+        .or_insert_with(|| materials.add(item_color(&stack.item)))
+        .clone();
+    commands.spawn((
+        PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(transform.translation + Vec3::Y + forward * 0.5),
+            ..default()
+        },
+        ItemDrop {
+            stack,
+            velocity: forward * DROP_THROW_SPEED + Vec3::Y * DROP_THROW_LIFT,
+            age: 0.0,
+            pickup_delay: DROP_PICKUP_DELAY,
+        },
+        Nameplate::new("", DROP_SIZE + 0.2),
+    ));
+}
+
+/// A color to tell an item's drops apart by, until items have their own
+/// models.
+fn item_color(item: &ItemId) -> Color {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    Color::hsl((hasher.finish() % 360) as f32, 0.6, 0.5)
+}
+
+/// Lets drops fall and rest on the ground, spin, and drift towards a nearby
+/// player, and removes those that have lain around too long.
+fn move_item_drops(
+    mut commands: Commands,
+    time: Res<Time>,
+    players: Query<&Transform, (With<Player>, Without<ItemDrop>)>,
+    mut drops: Query<(Entity, &mut ItemDrop, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    let player = players.get_single().ok();
+
+    for (entity, mut drop, mut transform) in drops.iter_mut() {
+        drop.age += dt;
+        if drop.age > DROP_DESPAWN_TIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let rest_height = DROP_SIZE / 2.0;
+        let pull = player
+            .filter(|_| drop.age >= drop.pickup_delay)
+            .map(|player| player.translation + Vec3::Y * rest_height - transform.translation)
+            .filter(|offset| offset.length() < DROP_MAGNET_RADIUS);
+        if let Some(offset) = pull {
+            drop.velocity = offset.normalize_or_zero() * DROP_MAGNET_SPEED;
+        } else {
+            drop.velocity.y += DROP_GRAVITY * dt;
+        }
+
+        transform.translation += drop.velocity * dt;
+        if transform.translation.y <= rest_height {
+            transform.translation.y = rest_height;
+            drop.velocity = Vec3::ZERO;
+        }
+        transform.rotate_y(DROP_SPIN_SPEED * dt);
+    }
+}
+
+/// Merges drops of the same item that lie close together, up to a full
+/// stack.
+fn merge_item_drops(mut commands: Commands, mut drops: Query<(Entity, &mut ItemDrop, &Transform)>) {
+    let mut combinations = drops.iter_combinations_mut();
+    while let Some([(_, mut drop_a, transform_a), (b, mut drop_b, transform_b)]) =
+        combinations.fetch_next()
+    {
+        if drop_a.stack.count == 0
+            || drop_b.stack.count == 0
+            || drop_a.stack.item != drop_b.stack.item
+            || transform_a.translation.distance(transform_b.translation) > DROP_MERGE_RADIUS
+        {
+            continue;
+        }
+
+        let moved = drop_b.stack.count.min(MAX_STACK_SIZE - drop_a.stack.count);
+        if moved == 0 {
+            continue;
+        }
+        drop_a.stack.count += moved;
+        drop_b.stack.count -= moved;
+        drop_a.age = drop_a.age.min(drop_b.age);
+        if drop_b.stack.count == 0 {
+            commands.entity(b).despawn_recursive();
+        }
+    }
+}
+
+/// Moves drops that reached the player into their inventory, leaving behind
+/// whatever doesn't fit.
+fn pick_up_item_drops(
+    mut commands: Commands,
+    mut players: Query<(&Transform, &mut Inventory), With<Player>>,
+    mut drops: Query<(Entity, &mut ItemDrop, &Transform), Without<Player>>,
+    mut pickups: EventWriter<ItemsPickedUp>,
+) {
+    let Ok((player, mut inventory)) = players.get_single_mut() else {
+        return;
+    };
+
+    for (entity, mut drop, transform) in drops.iter_mut() {
+        if drop.stack.count == 0
+            || drop.age < drop.pickup_delay
+            || transform.translation.distance(player.translation) > DROP_PICKUP_RADIUS + DROP_SIZE
+        {
+            continue;
+        }
+
+        let left = inventory.add(&drop.stack.item, drop.stack.count);
+        if left < drop.stack.count {
+            pickups.send(ItemsPickedUp {
+                item: drop.stack.item.clone(),
+                count: drop.stack.count - left,
+            });
+        }
+        if left == 0 {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            drop.stack.count = left;
+        }
+    }
+}
+
+/// Shows how many items each drop of more than one holds.
+fn label_item_drops(mut drops: Query<(&ItemDrop, &mut Nameplate)>) {
+    for (drop, mut nameplate) in drops.iter_mut() {
+        let text = if drop.stack.count > 1 {
+            format!("{} x{}", drop.stack.item.label(), drop.stack.count)
+        } else {
+            String::new()
+        };
+        if nameplate.text != text {
+            nameplate.text = text;
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Level of detail for the simulation, so that many characters far from the
+//! player don't cost as much as the few close to them.
+//!
+//! Characters with a [`SimulationLod`] update every frame near the player,
+//! every few frames with their animation frozen further out, and not at all
+//! beyond the simulation distance. Systems that move or steer characters ask
+//! [`SimulationLod::step`] whether to update one this frame, or
+//! [`SimulationLod::fixed_step`] whether to in this fixed update.
+
+use bevy::prelude::*;
+
+use crate::{animation::AnimationOwner, Player};
+
+/// Characters closer than this to the player update every frame.
+const FULL_RATE_DISTANCE: f32 = 32.0;
+/// Characters further than this from the player don't update at all.
+pub const SIMULATION_DISTANCE: f32 = 96.0;
+/// How many frames apart characters at medium range update.
+const REDUCED_UPDATE_INTERVAL: u32 = 4;
+
+pub struct LodPlugin;
+
+impl Plugin for LodPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            (schedule_simulation_lod, freeze_distant_animations).chain(),
+        );
+    }
+}
+
+/// How much of a character is simulated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum LodLevel {
+    #[default]
+    Full,
+    Reduced,
+    Dormant,
+}
+
+/// Schedules a character's updates by its distance from the player.
+#[derive(Component, Default)]
+pub struct SimulationLod {
+    level: LodLevel,
+    /// Frames left until the next update at reduced rate.
+    countdown: u32,
+    /// Game time gathered since the last update.
+    pending: f32,
+    step: Option<f32>,
+    /// The same as `countdown` and `pending`, in fixed updates.
+    fixed_countdown: u32,
+    fixed_pending: f32,
+}
+
+impl SimulationLod {
+    /// The time to advance the character by this frame, or `None` if it
+    /// skips this frame.
+    pub fn step(&self) -> Option<f32> {
+        self.step
+    }
+
+    /// The time to advance the character by in a fixed update `dt` long, or
+    /// `None` if it skips this one.
+    pub fn fixed_step(&mut self, dt: f32) -> Option<f32> {
+        self.fixed_pending += dt;
+        let step = match self.level {
+            LodLevel::Full => Some(self.fixed_pending),
+            LodLevel::Reduced if self.fixed_countdown == 0 => {
+                self.fixed_countdown = REDUCED_UPDATE_INTERVAL - 1;
+                Some(self.fixed_pending)
+            }
+            LodLevel::Reduced => {
+                self.fixed_countdown -= 1;
+                None
+            }
+            LodLevel::Dormant => {
+                self.fixed_pending = 0.0;
+                None
+            }
+        };
+        if step.is_some() {
+            self.fixed_pending = 0.0;
+        }
+        step
+    }
+}
+
+/// Picks each character's level from its distance to the player, and
+/// decides whether it updates this frame.
+fn schedule_simulation_lod(
+    time: Res<Time>,
+    players: Query<&Transform, With<Player>>,
+    mut characters: Query<(&Transform, &mut SimulationLod), Without<Player>>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+
+    for (transform, mut lod) in characters.iter_mut() {
+        let distance = transform.translation.distance(player.translation);
+        lod.level = if distance < FULL_RATE_DISTANCE {
+            LodLevel::Full
+        } else if distance < SIMULATION_DISTANCE {
+            LodLevel::Reduced
+        } else {
+            LodLevel::Dormant
+        };
+
+        lod.pending += time.delta_seconds();
+        lod.step = match lod.level {
+            LodLevel::Full => Some(lod.pending),
+            LodLevel::Reduced if lod.countdown == 0 => {
+                lod.countdown = REDUCED_UPDATE_INTERVAL - 1;
+                Some(lod.pending)
+            }
+            LodLevel::Reduced => {
+                lod.countdown -= 1;
+                None
+            }
+            // Dormant characters don't catch up on the time they slept
+            // through.
+            LodLevel::Dormant => {
+                lod.pending = 0.0;
+                None
+            }
+        };
+        if lod.step.is_some() {
+            lod.pending = 0.0;
+        }
+    }
+}
+
+/// Pauses the animations of characters beyond full-rate range, and resumes
+/// them when they come back.
+fn freeze_distant_animations(
+    mut players: Query<(&AnimationOwner, &mut AnimationPlayer)>,
+    characters: Query<&SimulationLod>,
+) {
+    for (owner, mut player) in players.iter_mut() {
+        let Ok(lod) = characters.get(owner.0) else {
+            continue;
+        };
+
+        let frozen = lod.level != LodLevel::Full;
+        if frozen && !player.all_paused() {
+            player.pause_all();
+        } else if !frozen && player.all_paused() {
+            player.resume_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_level(level: LodLevel) -> SimulationLod {
+        SimulationLod { level, ..default() }
+    }
+
+    #[test]
+    fn full_rate_steps_every_fixed_update() {
+        let mut lod = at_level(LodLevel::Full);
+        for _ in 0..10 {
+            assert_eq!(lod.fixed_step(0.25), Some(0.25));
+        }
+    }
+
+    #[test]
+    fn reduced_rate_steps_the_time_it_skipped() {
+        let mut lod = at_level(LodLevel::Reduced);
+        let steps: Vec<_> = (0..REDUCED_UPDATE_INTERVAL * 2)
+            .map(|_| lod.fixed_step(0.25))
+            .collect();
+
+        let taken: Vec<f32> = steps.iter().flatten().copied().collect();
+        assert_eq!(taken.len(), 2);
+        // The first step comes straight away; after that, each makes up for
+        // the updates skipped before it.
+        assert_eq!(taken[0], 0.25);
+        assert_eq!(taken[1], 0.25 * REDUCED_UPDATE_INTERVAL as f32);
+    }
+
+    #[test]
+    fn dormant_never_steps_or_catches_up() {
+        let mut lod = at_level(LodLevel::Dormant);
+        for _ in 0..10 {
+            assert_eq!(lod.fixed_step(0.25), None);
+        }
+
+        lod.level = LodLevel::Full;
+        assert_eq!(lod.fixed_step(0.25), Some(0.25));
+    }
+}
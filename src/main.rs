@@ -9,48 +9,164 @@
 //!
 //! [a blog post on depth of field in Unity]: https://catlikecoding.com/unity/tutorials/advanced-rendering/depth-of-field/
 
-use std::time::Duration;
+mod ai;
+mod animation;
+mod benchmark;
+mod chat;
+mod clouds;
+mod combat;
+mod companion;
+mod config;
+#[cfg(test)]
+mod controller_fuzz;
+mod crafting;
+mod day_night;
+mod debug_overlay;
+mod dialogue;
+mod game_mode;
+mod ghost;
+mod governor;
+mod health;
+mod inventory;
+mod item_drop;
+mod lod;
+mod menu;
+mod mirror;
+mod mobs;
+mod nameplates;
+mod net;
+#[cfg(feature = "net-conditions")]
+mod net_conditions;
+mod photo_mode;
+mod post_processing;
+mod presets;
+mod projectile;
+mod quests;
+mod race;
+mod replay;
+mod replication;
+mod screenshot;
+mod server_browser;
+mod settings;
+mod simulation;
+mod spectator;
+mod stamina;
+mod teams;
+mod trade;
+mod viewer;
+#[cfg(feature = "visual-tests")]
+mod visual_test;
+mod wind;
 
+use animation::{AnimFsm, AnimState, AnimationBinding, ClipSet};
+use benchmark::BenchmarkState;
 use bevy::{
-    core_pipeline::{
-        bloom::BloomSettings,
-        dof::{self, DepthOfFieldMode, DepthOfFieldSettings},
-        tonemapping::Tonemapping,
-    },
+    core_pipeline::dof::{DepthOfFieldMode, DepthOfFieldSettings},
     prelude::*,
 };
+use bevy_egui::EguiPlugin;
+use clouds::CLOUD_SHADOW_LAYER;
+use combat::Melee;
+use game_mode::GameMode;
+use governor::DofQuality;
+use health::{Dead, Health};
+use inventory::Inventory;
+use lod::SimulationLod;
+use menu::PauseState;
+use photo_mode::PhotoMode;
+use post_processing::{FollowsSettings, PostProcessing};
+use serde::{Deserialize, Serialize};
+use settings::ControlSettings;
+use spectator::Spectating;
+use stamina::{Stamina, StaminaSettings};
 
 use bevy::{
     math::Affine2,
-    render::texture::{
-        ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor,
+    render::{
+        texture::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor},
+        view::RenderLayers,
     },
 };
 
 const FOCAL_DISTANCE_SPEED: f32 = 0.05;
 const APERTURE_F_STOP_SPEED: f32 = 0.01;
 const MIN_FOCAL_DISTANCE: f32 = 0.01;
-const MIN_APERTURE_F_STOPS: f32 = 0.05;
+const MIN_APERTURE_F_STOPS: f32 = 0.01;
+/// How quickly auto focus catches up with the player's distance.
+const AUTO_FOCUS_SPEED: f32 = 4.0;
+/// How much the focal distance may differ from the player's distance before
+/// auto focus adjusts it.
+const AUTO_FOCUS_TOLERANCE: f32 = 0.01;
 
-const PLAYER_SPEED: f32 = 24.0;
-const PLAYER_LERP_SPEED: f32 = 0.1;
-const PLAYER_ROTATION_SPEED: f32 = 0.2;
+/// How fast characters run, in world units a second.
+const PLAYER_SPEED: f32 = 9.6;
+/// How many times a second characters are moved, whatever the frame rate.
+const SIMULATION_HZ: f64 = 60.0;
+/// How quickly a character's shown position and heading catch up with where
+/// it moved to, per second. At 60 FPS that is a tenth and a fifth of the way
+/// each frame.
+const PLAYER_FOLLOW_RATE: f32 = 6.3;
+const PLAYER_TURN_RATE: f32 = 13.4;
+/// How many times faster than running sprinting is.
+const SPRINT_SPEED_MULTIPLIER: f32 = 1.6;
 const JUMP_VELOCITY: f32 = 25.0;
 const GRAVITY: f32 = -100.;
+/// How fast a flying character moves across and up or down.
+const FLY_SPEED: f32 = 12.0;
+const FLY_VERTICAL_SPEED: f32 = 12.0;
+/// How quickly damped flight catches up with the speed asked for, per
+/// second.
+const FLY_DAMPING_RATE: f32 = 4.0;
+/// How soon after pressing jump pressing it again starts or stops flying,
+/// in seconds.
+const DOUBLE_TAP_TIME: f32 = 0.3;
 
 /// A resource that stores the settings that the user can change.
-#[derive(Clone, Copy, Resource)]
+#[derive(Clone, Copy, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
 struct AppSettings {
     focal_distance: f32,
     aperture_f_stops: f32,
+    #[serde(with = "config::dof_mode")]
     mode: Option<DepthOfFieldMode>,
+    /// Whether the focal distance follows the player instead of being set
+    /// by hand.
+    auto_focus: bool,
+    /// The highest DOF quality to render at. The performance governor may
+    /// lower it further while the frame rate is low.
+    quality: DofQuality,
 }
 
+/// Where a character is. Movement steps `target` at a fixed rate, and
+/// `current`, where the character is shown, follows it smoothly.
 #[derive(Component)]
 struct Position {
     current: Vec3,
     target: Vec3,
+    /// The target before the last movement step, so the character can be
+    /// shown between steps.
+    previous: Vec3,
     vertical_velocity: f32,
+    /// How fast the character is flying, if it is.
+    flight_velocity: Vec3,
+}
+
+impl Position {
+    /// Standing still at `spot`.
+    fn at(spot: Vec3) -> Self {
+        Self {
+            current: spot,
+            target: spot,
+            previous: spot,
+            vertical_velocity: 0.0,
+            flight_velocity: Vec3::ZERO,
+        }
+    }
+
+    /// Moves straight to `spot`, without easing over there.
+    fn teleport(&mut self, spot: Vec3) {
+        *self = Self::at(spot);
+    }
 }
 
 #[derive(Component)]
@@ -58,11 +174,49 @@ struct Rotation {
     pub radians_y: f32,
 }
 
-#[derive(Component)]
+#[derive(Component, Default)]
 struct Checks {
     is_moving: bool,
+    /// Whether the character sprinted on its last move.
+    is_sprinting: bool,
+}
+
+/// The movement a character wants to make this frame. The keyboard sets it
+/// for the player; anything else that moves a character sets it for theirs.
+#[derive(Component, Clone, Default)]
+struct MovementInput {
+    /// The horizontal direction to move in, or zero to stand still. Shorter
+    /// than one to move slower than full speed.
+    direction: Vec3,
+    jump: bool,
+    sprint: bool,
+    /// Whether the character flies rather than walking under gravity.
+    fly: bool,
+    /// How fast a flying character rises, from -1 to sink at full speed up
+    /// to 1.
+    vertical: f32,
+    /// Whether flying eases into and out of moves, rather than starting and
+    /// stopping at once.
+    damped_flight: bool,
 }
 
+/// Sent when a character comes down on the ground, with how fast it was
+/// falling.
+#[derive(Event)]
+struct Landed {
+    entity: Entity,
+    speed: f32,
+}
+
+/// Marks the camera the game is seen through, as opposed to cameras that
+/// render into textures.
+#[derive(Component)]
+struct MainCamera;
+
+/// Marks the character that the keyboard controls and the camera follows.
+#[derive(Component)]
+struct Player;
+
 #[derive(Bundle)]
 struct PlayerBundle {
     position: Position,
@@ -70,37 +224,48 @@ struct PlayerBundle {
     #[bundle()]
     pbr: SceneBundle,
     checks: Checks,
+    movement_input: MovementInput,
+    animation: AnimationBinding,
+    anim_fsm: AnimFsm,
+    inventory: Inventory,
+    health: Health,
+    stamina: Stamina,
+    melee: Melee,
 }
 
 impl PlayerBundle {
-    fn new(scene: Handle<Scene>) -> Self {
+    fn new(scene: Handle<Scene>, clips: ClipSet, stamina: &StaminaSettings) -> Self {
         Self {
-            position: Position {
-                current: Vec3::ZERO,
-                target: Vec3::ZERO,
-                vertical_velocity: 0.0,
-            },
+            position: Position::at(Vec3::ZERO),
             rotation: Rotation { radians_y: 0.0 },
             pbr: SceneBundle {
-                scene: scene,
+                scene,
                 transform: Transform::from_scale(Vec3::splat(0.012)),
                 ..default()
             },
-            checks: Checks { is_moving: false },
+            checks: Checks::default(),
+            movement_input: MovementInput::default(),
+            animation: AnimationBinding::new(clips),
+            anim_fsm: AnimFsm::default(),
+            inventory: Inventory::load(),
+            health: Health::default(),
+            stamina: Stamina::new(stamina),
+            melee: Melee::default(),
         }
     }
 }
 
-#[derive(Resource)]
-struct Animations {
-    animations: Vec<AnimationNodeIndex>,
-    #[allow(dead_code)]
-    graph: Handle<AnimationGraph>,
-}
-
 fn main() {
-    App::new()
-        .init_resource::<AppSettings>()
+    let net_mode = net::NetMode::from_args();
+    if let net::NetMode::Dedicated(config) = net_mode {
+        net::run_dedicated_server(config);
+        return;
+    }
+
+    let mut app = App::new();
+    app.init_resource::<AppSettings>()
+        .add_event::<Landed>()
+        .insert_resource(Time::<Fixed>::from_hz(SIMULATION_HZ))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Bevy Depth of Field Example".to_string(),
@@ -108,50 +273,108 @@ fn main() {
             }),
             ..default()
         }))
-        .add_systems(Startup, (setup, update_dof_settings))
+        .add_plugins((
+            EguiPlugin,
+            animation::CharacterAnimationPlugin,
+            lod::LodPlugin,
+            settings::SettingsPlugin,
+            config::ConfigPlugin,
+            wind::WindPlugin,
+            day_night::DayNightPlugin,
+            clouds::CloudsPlugin,
+            debug_overlay::DebugOverlayPlugin,
+            menu::MenuPlugin,
+            screenshot::ScreenshotPlugin,
+            governor::GovernorPlugin,
+            post_processing::PostProcessingPlugin,
+            projectile::ProjectilePlugin,
+            presets::PresetsPlugin,
+        ))
+        .add_plugins((
+            benchmark::BenchmarkPlugin,
+            replay::ReplayPlugin,
+            ghost::GhostPlugin,
+            photo_mode::PhotoModePlugin,
+            race::RacePlugin,
+            inventory::InventoryPlugin,
+            item_drop::ItemDropPlugin,
+            crafting::CraftingPlugin,
+            health::HealthPlugin,
+            stamina::StaminaPlugin,
+            mobs::MobsPlugin,
+            ai::AiPlugin,
+            viewer::ViewerPlugin,
+            mirror::MirrorPlugin,
+            combat::CombatPlugin,
+        ))
+        .add_plugins((
+            companion::CompanionPlugin,
+            simulation::SimulationPlugin,
+            nameplates::NameplatesPlugin,
+            net::NetPlugin { mode: net_mode },
+            quests::QuestsPlugin,
+            dialogue::DialoguePlugin,
+            trade::TradePlugin,
+            chat::ChatPlugin,
+            teams::TeamsPlugin,
+            server_browser::ServerBrowserPlugin,
+            game_mode::GameModePlugin,
+            spectator::SpectatorPlugin,
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(
+            FixedUpdate,
+            (player_controller, consume_jumps)
+                .chain()
+                .run_if(controlling_characters),
+        )
         .add_systems(
             Update,
             (
-                // adjust_focus,
-                player_controller,
-                camera_controller,
+                adjust_focus.run_if(in_state(PauseState::Running)),
+                (
+                    player_input.run_if(in_state(Spectating::Off)),
+                    show_characters,
+                )
+                    .run_if(controlling_characters),
+                camera_controller.run_if(
+                    not(in_state(BenchmarkState::Running))
+                        .and_then(in_state(PhotoMode::Off))
+                        .and_then(in_state(Spectating::Off)),
+                ),
+                auto_focus.run_if(in_state(PauseState::Running)),
                 animation_controller,
-                setup_scene_once_loaded,
             )
                 .chain(),
-        )
-        .run();
+        );
+    #[cfg(feature = "visual-tests")]
+    app.add_plugins(visual_test::VisualTestPlugin);
+    app.run();
 }
 
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    app_settings: Res<AppSettings>,
+    stamina_settings: Res<StaminaSettings>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut graphs: ResMut<Assets<AnimationGraph>>,
 ) {
+    // Build the fox's animation graph and pick a clip for each state
     let mut graph = AnimationGraph::new();
-    let animations = graph
-        .add_clips(
-            [
-                GltfAssetLabel::Animation(2).from_asset("models/Fox.glb"),
-                GltfAssetLabel::Animation(1).from_asset("models/Fox.glb"),
-                GltfAssetLabel::Animation(0).from_asset("models/Fox.glb"),
-            ]
-            .into_iter()
-            .map(|path| asset_server.load(path)),
+    let [survey, walk, run] = [0, 1, 2].map(|index| {
+        graph.add_clip(
+            asset_server.load(GltfAssetLabel::Animation(index).from_asset("models/Fox.glb")),
             1.0,
             graph.root,
         )
-        .collect();
-
-    // Insert a resource with the current scene information
-    let graph = graphs.add(graph);
-    commands.insert_resource(Animations {
-        animations,
-        graph: graph.clone(),
     });
+    let fox_clips = ClipSet::new(graphs.add(graph))
+        .with_clip(AnimState::Idle, survey, 2.0)
+        .with_clip(AnimState::Run, run, 3.0)
+        // A quick lunge, until the fox has a clip of its own for attacking.
+        .with_clip_once(AnimState::Attack, run, 5.0)
+        .with_clip_once(AnimState::Dead, walk, 1.0);
 
     // Load all required textures with settings to repeat
     let ambient_occlusion_texture =
@@ -205,38 +428,45 @@ fn setup(
         ..Default::default()
     });
 
-    // Spawn the camera. Enable HDR and bloom, as that highlights the depth of
-    // field effect.
-    let mut camera = commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0.0, 2.5, 8.25).looking_at(Vec3::ZERO, Vec3::Y),
-        camera: Camera {
-            hdr: true,
+    // Spawn the camera. Enable HDR, as bloom highlights the depth of field
+    // effect. Its depth of field and bloom come from the settings.
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 2.5, 8.25).looking_at(Vec3::ZERO, Vec3::Y),
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
             ..default()
         },
-        tonemapping: Tonemapping::TonyMcMapface,
-        ..default()
-    });
-    camera.insert(BloomSettings::NATURAL);
-
-    // Insert the depth of field settings.
-    if let Some(dof_settings) = Option::<DepthOfFieldSettings>::from(*app_settings) {
-        camera.insert(dof_settings);
-    }
+        PostProcessing::default(),
+        FollowsSettings,
+        MainCamera,
+    ));
 
     // Spawning the player entity
-    commands.spawn(PlayerBundle::new(
-        asset_server.load("models/Fox.glb#Scene0"),
+    commands.spawn((
+        PlayerBundle::new(
+            asset_server.load("models/Fox.glb#Scene0"),
+            fox_clips,
+            &stamina_settings,
+        ),
+        Player,
     ));
 
     // Adding a directional light with shadows
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(5.0, 10.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
             ..default()
         },
-        transform: Transform::from_xyz(5.0, 10.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    });
+        // The sun also sees the layer that casts the clouds' shadows.
+        RenderLayers::default().with(CLOUD_SHADOW_LAYER),
+    ));
 
     // Adding a platform
     let platform_mesh = meshes.add(Mesh::from(Plane3d {
@@ -252,26 +482,8 @@ fn setup(
     });
 }
 
-fn setup_scene_once_loaded(
-    mut commands: Commands,
-    animations: Res<Animations>,
-    mut players: Query<(Entity, &mut AnimationPlayer), Added<AnimationPlayer>>,
-) {
-    for (entity, mut player) in &mut players {
-        let mut transitions = AnimationTransitions::new();
-
-        transitions
-            .play(&mut player, animations.animations[2], Duration::ZERO)
-            .set_speed(2.);
-
-        commands
-            .entity(entity)
-            .insert(animations.graph.clone())
-            .insert(transitions);
-    }
-}
-
-/// Adjusts the focal distance and f-number per user inputs.
+/// Adjusts the focal distance and f-number per user inputs, cycles the DOF
+/// mode with Tab, and toggles auto focus with F.
 fn adjust_focus(input: Res<ButtonInput<KeyCode>>, mut app_settings: ResMut<AppSettings>) {
     // Change the focal distance if the user requested.
     let distance_delta = if input.pressed(KeyCode::ArrowDown) {
@@ -282,10 +494,89 @@ fn adjust_focus(input: Res<ButtonInput<KeyCode>>, mut app_settings: ResMut<AppSe
         0.0
     };
 
-    app_settings.focal_distance =
-        (app_settings.focal_distance + distance_delta).max(MIN_FOCAL_DISTANCE);
+    // Change the f-number if the user requested.
+    let f_stop_delta = if input.pressed(KeyCode::ArrowLeft) {
+        -APERTURE_F_STOP_SPEED
+    } else if input.pressed(KeyCode::ArrowRight) {
+        APERTURE_F_STOP_SPEED
+    } else {
+        0.0
+    };
+
+    // Only touch the settings when something changed, so that the camera's
+    // post-processing is only rewritten when it has to be. Focusing by hand
+    // turns auto focus off.
+    if distance_delta != 0.0 {
+        app_settings.auto_focus = false;
+        app_settings.focal_distance =
+            (app_settings.focal_distance + distance_delta).max(MIN_FOCAL_DISTANCE);
+    }
+    if f_stop_delta != 0.0 {
+        app_settings.aperture_f_stops =
+            (app_settings.aperture_f_stops + f_stop_delta).max(MIN_APERTURE_F_STOPS);
+    }
+
+    if input.just_pressed(KeyCode::KeyF) {
+        app_settings.auto_focus = !app_settings.auto_focus;
+    }
+
+    // Cycle Off -> Gaussian -> Bokeh.
+    if input.just_pressed(KeyCode::Tab) {
+        app_settings.mode = match app_settings.mode {
+            None => Some(DepthOfFieldMode::Gaussian),
+            Some(DepthOfFieldMode::Gaussian) => Some(DepthOfFieldMode::Bokeh),
+            Some(DepthOfFieldMode::Bokeh) => None,
+        };
+    }
+}
+
+/// Smoothly moves the focal distance to the player's distance from the
+/// camera, so the player stays sharp while the background blurs. The camera
+/// that follows the settings focuses through them; cameras with their own
+/// post-processing focus on their own.
+fn auto_focus(
+    time: Res<Time>,
+    mut app_settings: ResMut<AppSettings>,
+    player_query: Query<&Position, With<Player>>,
+    camera_query: Query<&Transform, (With<MainCamera>, With<FollowsSettings>)>,
+    mut owned_cameras: Query<(&Transform, &mut PostProcessing), Without<FollowsSettings>>,
+) {
+    let Ok(position) = player_query.get_single() else {
+        return;
+    };
+    let dt = time.delta_seconds();
+
+    let camera_transform = camera_query.get_single();
+    if let (true, Ok(camera_transform)) = (app_settings.auto_focus, camera_transform) {
+        let distance = camera_transform.translation.distance(position.current);
+        if let Some(focal_distance) = focus_towards(app_settings.focal_distance, distance, dt) {
+            app_settings.focal_distance = focal_distance;
+        }
+    }
+
+    for (camera_transform, mut post_processing) in owned_cameras.iter_mut() {
+        if !post_processing.dof.auto_focus {
+            continue;
+        }
+        let distance = camera_transform.translation.distance(position.current);
+        if let Some(focal_distance) =
+            focus_towards(post_processing.dof.focal_distance, distance, dt)
+        {
+            post_processing.dof.focal_distance = focal_distance;
+        }
+    }
+}
+
+/// The focal distance after `dt` seconds of focusing from `focal_distance`
+/// towards `distance`, or `None` if it is close enough already.
+fn focus_towards(focal_distance: f32, distance: f32, dt: f32) -> Option<f32> {
+    if (distance - focal_distance).abs() < AUTO_FOCUS_TOLERANCE {
+        return None;
+    }
 
-    println!("Focal distance: {}", app_settings.focal_distance);
+    // Exponential smoothing that behaves the same at any frame rate.
+    let blend = follow_blend(AUTO_FOCUS_SPEED, dt);
+    Some((focal_distance + (distance - focal_distance) * blend).max(MIN_FOCAL_DISTANCE))
 }
 
 impl Default for AppSettings {
@@ -294,25 +585,8 @@ impl Default for AppSettings {
             focal_distance: 11.,
             aperture_f_stops: 1.0 / 30.0,
             mode: Some(DepthOfFieldMode::Bokeh),
-        }
-    }
-}
-
-/// Writes the depth of field settings into the camera.
-fn update_dof_settings(
-    mut commands: Commands,
-    view_targets: Query<Entity, With<Camera>>,
-    app_settings: Res<AppSettings>,
-) {
-    let dof_settings: Option<DepthOfFieldSettings> = (*app_settings).into();
-    for view in view_targets.iter() {
-        match dof_settings {
-            None => {
-                commands.entity(view).remove::<DepthOfFieldSettings>();
-            }
-            Some(dof_settings) => {
-                commands.entity(view).insert(dof_settings);
-            }
+            auto_focus: true,
+            quality: DofQuality::High,
         }
     }
 }
@@ -328,104 +602,289 @@ impl From<AppSettings> for Option<DepthOfFieldSettings> {
         })
     }
 }
-fn player_controller(
-    time: Res<Time>,
-    mut player_query: Query<(&mut Position, &mut Rotation, &mut Transform, &mut Checks)>,
+/// Whether characters are moving under their own control, rather than
+/// stopped or moved by the benchmark.
+fn controlling_characters(
+    pause: Res<State<PauseState>>,
+    benchmark: Res<State<BenchmarkState>>,
+    photo_mode: Res<State<PhotoMode>>,
+) -> bool {
+    *pause.get() == PauseState::Running
+        && *benchmark.get() != BenchmarkState::Running
+        && *photo_mode.get() == PhotoMode::Off
+}
 
+/// Turns the keyboard state into movement for the player, relative to where
+/// the camera looks. In creative, pressing jump twice quickly starts or stops
+/// flying, and while flying jump rises and sprint sinks.
+fn player_input(
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    controls: Res<ControlSettings>,
+    game_mode: Res<GameMode>,
+    time: Res<Time<Real>>,
+    mut last_jump_press: Local<Option<f32>>,
+    mut player_query: Query<&mut MovementInput, (With<Player>, Without<Dead>)>,
+    cameras: Query<&Transform, With<MainCamera>>,
 ) {
-    for (mut position, mut rotation, mut transform, mut player) in player_query.iter_mut() {
-        let dt = time.delta_seconds();
+    let now = time.elapsed_seconds();
+    let mut double_tapped = false;
+    if keyboard_input.just_pressed(controls.jump) {
+        double_tapped = last_jump_press.is_some_and(|last| now - last < DOUBLE_TAP_TIME);
+        // A third press starts a new double tap rather than finishing one.
+        *last_jump_press = (!double_tapped).then_some(now);
+    }
+    let creative = *game_mode == GameMode::Creative;
+
+    // Forward is away from the camera, along the ground. The follow camera
+    // looks along -X.
+    let forward = cameras
+        .get_single()
+        .map(|camera| (camera.forward().as_vec3() * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero())
+        .ok()
+        .filter(|forward| *forward != Vec3::ZERO)
+        .unwrap_or(Vec3::NEG_X);
+    let right = forward.cross(Vec3::Y);
 
-        let mut movement = Vec3::ZERO;
+    for mut input in player_query.iter_mut() {
+        let mut direction = Vec3::ZERO;
 
-        if keyboard_input.pressed(KeyCode::KeyW) {
-            movement += Vec3::new(-PLAYER_SPEED, 0.0, 0.0);
-            player.is_moving = true;
+        if keyboard_input.pressed(controls.move_forward) {
+            direction += forward;
         }
-        if keyboard_input.pressed(KeyCode::KeyS) {
-            movement += Vec3::new(PLAYER_SPEED, 0.0, 0.0);
-            player.is_moving = true;
+        if keyboard_input.pressed(controls.move_back) {
+            direction -= forward;
         }
-        if keyboard_input.pressed(KeyCode::KeyA) {
-            movement += Vec3::new(0.0, 0.0, PLAYER_SPEED);
-            player.is_moving = true;
+        if keyboard_input.pressed(controls.move_left) {
+            direction -= right;
         }
-        if keyboard_input.pressed(KeyCode::KeyD) {
-            movement += Vec3::new(0.0, 0.0, -PLAYER_SPEED);
-            player.is_moving = true;
+        if keyboard_input.pressed(controls.move_right) {
+            direction += right;
         }
 
-        // Normalize movement vector if needed
-        if movement.length_squared() > 0.0 {
-            movement = movement.normalize();
-        } else {
-            player.is_moving = false;
+        input.direction = direction.normalize_or_zero();
+        // Hold on to a jump until a movement step takes it, as there may not
+        // be one this frame.
+        input.jump |= keyboard_input.just_pressed(controls.jump);
+
+        if double_tapped {
+            input.fly = !input.fly;
         }
+        input.fly &= creative;
+        input.damped_flight = controls.damped_flight;
 
-        // Apply speed to movement vector
-        movement *= PLAYER_SPEED * dt;
+        let up = keyboard_input.pressed(controls.jump);
+        let down = keyboard_input.pressed(controls.sprint);
+        input.vertical = if input.fly {
+            up as i8 as f32 - down as i8 as f32
+        } else {
+            0.0
+        };
+        // The sprint key sinks while flying, rather than sprinting.
+        input.sprint = down && !input.fly;
+    }
+}
 
-        // Update target position
-        position.target += movement * PLAYER_SPEED * dt;
+/// The parts of a character that `player_controller` reads and moves.
+type CharacterMotion = (
+    Entity,
+    &'static MovementInput,
+    &'static mut Position,
+    &'static mut Rotation,
+    &'static mut Checks,
+    Option<&'static mut SimulationLod>,
+    Option<&'static mut Stamina>,
+);
 
-        // Update rotation to face movement direction
-        if movement.length_squared() > 0.0 {
-            rotation.radians_y = movement.x.atan2(movement.z);
+/// Moves every character a fixed step according to its own movement input,
+/// as often as its simulation level of detail allows.
+fn player_controller(
+    time: Res<Time>,
+    stamina_settings: Res<StaminaSettings>,
+    mut character_query: Query<CharacterMotion>,
+    mut landings: EventWriter<Landed>,
+) {
+    for (entity, input, mut position, mut rotation, mut checks, lod, mut stamina) in
+        character_query.iter_mut()
+    {
+        position.previous = position.target;
+        let dt = match lod {
+            Some(mut lod) => lod.fixed_step(time.delta_seconds()),
+            None => Some(time.delta_seconds()),
+        };
+        let Some(dt) = dt else {
+            continue;
+        };
+
+        checks.is_moving = input.direction != Vec3::ZERO;
+
+        // Sprinting is only possible with stamina to spend
+        checks.is_sprinting =
+            input.sprint && checks.is_moving && stamina.as_deref().is_none_or(Stamina::can_sprint);
+        let step = step_motion(input, checks.is_sprinting, &mut position, &mut rotation, dt);
+        if let Some(stamina) = stamina.as_deref_mut() {
+            if checks.is_sprinting {
+                stamina.spend(stamina_settings.sprint_cost * dt);
+            }
+            if step.jumped {
+                stamina.spend(stamina_settings.jump_cost);
+            }
+        }
+        if let Some(speed) = step.landed {
+            landings.send(Landed { entity, speed });
         }
+    }
+}
+
+/// Forgets the jumps the movement step took.
+fn consume_jumps(mut inputs: Query<&mut MovementInput>) {
+    for mut input in inputs.iter_mut() {
+        if input.jump {
+            input.jump = false;
+        }
+    }
+}
 
-        // Update current position
-        position.current = position.current.lerp(position.target, PLAYER_LERP_SPEED);
+/// Shows each character where its movement has taken it, between the last
+/// two steps by how far the game is into the next, and eases it over there.
+fn show_characters(
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    mut characters: Query<(&mut Position, &Rotation, &mut Transform), With<MovementInput>>,
+) {
+    let between = fixed_time.overstep_fraction();
+    let follow = follow_blend(PLAYER_FOLLOW_RATE, time.delta_seconds());
+    let turn = follow_blend(PLAYER_TURN_RATE, time.delta_seconds());
+    for (mut position, rotation, mut transform) in characters.iter_mut() {
+        let shown = position.previous.lerp(position.target, between);
+        position.current = position.current.lerp(shown, follow);
         transform.translation = position.current;
 
-        // Apply rotation to transform
         let angle = Quat::from_rotation_y(rotation.radians_y);
-        transform.rotation = transform.rotation.lerp(angle, PLAYER_ROTATION_SPEED);
+        transform.rotation = transform.rotation.lerp(angle, turn);
+    }
+}
 
-        // Vertical movement (jump)
-        position.vertical_velocity += GRAVITY * dt;
-        position.target.y += position.vertical_velocity * dt;
+/// How far to go towards something, at `rate` per second for `dt` seconds,
+/// so that following it looks the same at any frame rate.
+fn follow_blend(rate: f32, dt: f32) -> f32 {
+    1.0 - (-rate * dt).exp()
+}
 
-        if keyboard_input.just_pressed(KeyCode::Space) && position.target.y <= 0.0 {
-            position.vertical_velocity = JUMP_VELOCITY;
-            position.target.y = 0.1;
-        }
+/// What happened in one step of a character's movement.
+#[derive(Default)]
+struct MotionStep {
+    jumped: bool,
+    /// How fast the character was falling, if it came down on the ground.
+    landed: Option<f32>,
+}
 
+/// Moves a character's target position and heading by `dt` seconds of its
+/// input. Everything else about moving, such as smoothing and stamina, is up
+/// to the caller, so the server and clients can step players the same way.
+/// A step whose numbers aren't all finite does nothing, rather than making
+/// the character NaN.
+fn step_motion(
+    input: &MovementInput,
+    sprinting: bool,
+    position: &mut Position,
+    rotation: &mut Rotation,
+    dt: f32,
+) -> MotionStep {
+    if !(dt.is_finite() && input.direction.is_finite() && input.vertical.is_finite()) {
+        return MotionStep::default();
+    }
+
+    // Flying characters go where they are asked, without gravity, and never
+    // land. Damped flight eases towards the speed asked for, so it drifts to
+    // a stop.
+    if input.fly {
+        let wanted = input.direction * FLY_SPEED
+            + Vec3::Y * input.vertical.clamp(-1.0, 1.0) * FLY_VERTICAL_SPEED;
+        position.flight_velocity = if input.damped_flight {
+            position
+                .flight_velocity
+                .lerp(wanted, follow_blend(FLY_DAMPING_RATE, dt))
+        } else {
+            wanted
+        };
+        position.target += position.flight_velocity * dt;
         if position.target.y < 0.0 {
             position.target.y = 0.0;
-            position.vertical_velocity = 0.0;
+            position.flight_velocity.y = 0.0;
         }
+        // Carry on falling or rising from here once flying stops.
+        position.vertical_velocity = position.flight_velocity.y;
+        if input.direction != Vec3::ZERO {
+            rotation.radians_y = input.direction.x.atan2(input.direction.z);
+        }
+        return MotionStep {
+            jumped: false,
+            landed: None,
+        };
     }
+
+    let speed = if sprinting {
+        PLAYER_SPEED * SPRINT_SPEED_MULTIPLIER
+    } else {
+        PLAYER_SPEED
+    };
+
+    // Linear in `dt`, so a character stepped less often by its level of
+    // detail still covers the same ground.
+    position.target += input.direction * speed * dt;
+
+    // Update rotation to face movement direction
+    if input.direction != Vec3::ZERO {
+        rotation.radians_y = input.direction.x.atan2(input.direction.z);
+    }
+
+    // Flying starts from standing still in the air.
+    position.flight_velocity = Vec3::ZERO;
+
+    // Vertical movement (jump)
+    let airborne = position.target.y > 0.0;
+    position.vertical_velocity += GRAVITY * dt;
+    position.target.y += position.vertical_velocity * dt;
+
+    let jumped = input.jump && position.target.y <= 0.0;
+    if jumped {
+        position.vertical_velocity = JUMP_VELOCITY;
+        position.target.y = 0.1;
+    }
+
+    let mut landed = None;
+    if position.target.y < 0.0 {
+        if airborne {
+            landed = Some(-position.vertical_velocity);
+        }
+        position.target.y = 0.0;
+        position.vertical_velocity = 0.0;
+    }
+    MotionStep { jumped, landed }
 }
 
-fn animation_controller(
-    mut animation_players: Query<&mut AnimationPlayer>,
-    mut player_query: Query<&mut Checks>,
-    animations: Res<Animations>,
-) {
-    for mut player in player_query.iter_mut() {
-        for mut animation_player in animation_players.iter_mut() {
-            // Check if the 'W' key is just pressed or just released
-            if player.is_moving {
-                animation_player.stop(animations.animations[2].clone());
-                animation_player
-                    .play(animations.animations[0].clone())
-                    .set_speed(3.0) // Adjust speed as needed
-                    .repeat();
-            } else {
-                // Stop any current animations and play the idle animation
-                animation_player.stop(animations.animations[0].clone());
-                animation_player
-                    .play(animations.animations[2].clone())
-                    .repeat();
-            }
+/// Puts each character's animation state machine in the state matching what
+/// it is doing.
+fn animation_controller(mut characters: Query<(&Checks, &mut AnimFsm, Has<Dead>, Option<&Melee>)>) {
+    for (checks, mut anim_fsm, dead, melee) in characters.iter_mut() {
+        let state = if dead {
+            AnimState::Dead
+        } else if melee.is_some_and(Melee::swinging) {
+            AnimState::Attack
+        } else if checks.is_moving {
+            AnimState::Run
+        } else {
+            AnimState::Idle
+        };
+        if anim_fsm.state() != state {
+            anim_fsm.set(state);
         }
     }
 }
 
 fn camera_controller(
-    player_query: Query<&Position>,
-    mut camera_query: Query<&mut Transform, With<Camera>>,
+    player_query: Query<&Position, With<Player>>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
 ) {
     if let Ok(position) = player_query.get_single() {
         for mut camera_transform in camera_query.iter_mut() {
@@ -435,3 +894,151 @@ fn camera_controller(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walking(direction: Vec3) -> MovementInput {
+        MovementInput {
+            direction,
+            ..default()
+        }
+    }
+
+    fn step(input: &MovementInput, position: &mut Position, dt: f32) -> MotionStep {
+        step_motion(
+            input,
+            input.sprint,
+            position,
+            &mut Rotation { radians_y: 0.0 },
+            dt,
+        )
+    }
+
+    #[test]
+    fn walking_is_linear_in_the_step() {
+        let input = walking(Vec3::X);
+        let mut once = Position::at(Vec3::ZERO);
+        step(&input, &mut once, 0.2);
+        let mut twice = Position::at(Vec3::ZERO);
+        step(&input, &mut twice, 0.1);
+        step(&input, &mut twice, 0.1);
+
+        assert!((once.target.x - PLAYER_SPEED * 0.2).abs() < 1e-5);
+        assert!((once.target.x - twice.target.x).abs() < 1e-5);
+        assert_eq!(once.target.y, 0.0);
+    }
+
+    #[test]
+    fn sprinting_is_faster() {
+        let mut walked = Position::at(Vec3::ZERO);
+        step(&walking(Vec3::X), &mut walked, 0.1);
+        let mut sprinted = Position::at(Vec3::ZERO);
+        let input = MovementInput {
+            sprint: true,
+            ..walking(Vec3::X)
+        };
+        step(&input, &mut sprinted, 0.1);
+
+        let ratio = sprinted.target.x / walked.target.x;
+        assert!((ratio - SPRINT_SPEED_MULTIPLIER).abs() < 1e-5);
+    }
+
+    #[test]
+    fn characters_face_where_they_move() {
+        let mut position = Position::at(Vec3::ZERO);
+        let mut rotation = Rotation { radians_y: 0.0 };
+        step_motion(&walking(Vec3::X), false, &mut position, &mut rotation, 0.1);
+        assert!((rotation.radians_y - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+
+        // Standing still keeps the heading.
+        step_motion(
+            &walking(Vec3::ZERO),
+            false,
+            &mut position,
+            &mut rotation,
+            0.1,
+        );
+        assert!((rotation.radians_y - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn jumps_come_back_down() {
+        let mut position = Position::at(Vec3::ZERO);
+        let jump = MovementInput {
+            jump: true,
+            ..default()
+        };
+        assert!(step(&jump, &mut position, 0.01).jumped);
+        assert!(position.target.y > 0.0);
+
+        let mut landed = None;
+        for _ in 0..1000 {
+            landed = step(&MovementInput::default(), &mut position, 0.01).landed;
+            if landed.is_some() {
+                break;
+            }
+        }
+        assert!(landed.is_some_and(|speed| speed > 0.0));
+        assert_eq!(position.target.y, 0.0);
+        assert_eq!(position.vertical_velocity, 0.0);
+    }
+
+    #[test]
+    fn jumping_needs_the_ground() {
+        let mut position = Position::at(Vec3::Y * 5.0);
+        let jump = MovementInput {
+            jump: true,
+            ..default()
+        };
+        assert!(!step(&jump, &mut position, 0.01).jumped);
+    }
+
+    #[test]
+    fn flying_ignores_gravity() {
+        let mut position = Position::at(Vec3::Y * 5.0);
+        let input = MovementInput {
+            direction: Vec3::X,
+            fly: true,
+            ..default()
+        };
+        let motion = step(&input, &mut position, 0.5);
+
+        assert!(!motion.jumped && motion.landed.is_none());
+        assert!((position.target - Vec3::new(FLY_SPEED * 0.5, 5.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn flying_stops_at_the_ground() {
+        let mut position = Position::at(Vec3::Y);
+        let input = MovementInput {
+            fly: true,
+            vertical: -1.0,
+            ..default()
+        };
+        step(&input, &mut position, 1.0);
+
+        assert_eq!(position.target.y, 0.0);
+        assert_eq!(position.flight_velocity.y, 0.0);
+    }
+
+    #[test]
+    fn damped_flight_eases_towards_its_speed() {
+        let mut position = Position::at(Vec3::Y * 5.0);
+        let input = MovementInput {
+            direction: Vec3::X,
+            fly: true,
+            damped_flight: true,
+            ..default()
+        };
+        step(&input, &mut position, 0.1);
+        let first = position.flight_velocity.x;
+        assert!(first > 0.0 && first < FLY_SPEED);
+
+        for _ in 0..100 {
+            step(&input, &mut position, 0.1);
+        }
+        assert!((position.flight_velocity.x - FLY_SPEED).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,339 @@
+//! The pause menu, opened with Esc, where all settings can be changed and
+//! the [server browser](crate::server_browser) opened.
+//!
+//! Edits are made to a draft copy of the settings and only take effect once
+//! applied, so they can be reverted while the menu is open.
+
+use bevy::{core_pipeline::dof::DepthOfFieldMode, ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    benchmark::BenchmarkState,
+    governor::DofQuality,
+    photo_mode::PhotoMode,
+    presets::GraphicsPreset,
+    screenshot::MAX_SCREENSHOT_SCALE,
+    server_browser::{server_browser_open, ServerBrowser},
+    settings::{
+        hotkey, AudioSettings, ControlAction, ControlSettings, GraphicsSettings,
+        MAX_RENDER_DISTANCE, MIN_RENDER_DISTANCE,
+    },
+    AppSettings, MIN_APERTURE_F_STOPS, MIN_FOCAL_DISTANCE,
+};
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<PauseState>()
+            .init_resource::<SettingsMenu>()
+            .add_systems(OnEnter(PauseState::Paused), open_settings)
+            .add_systems(
+                Update,
+                (
+                    toggle_pause.run_if(in_state(PhotoMode::Off)),
+                    (capture_rebinding, settings_menu)
+                        .run_if(in_state(PauseState::Paused))
+                        .run_if(not(server_browser_open)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether the game is running or paused in the menu.
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum SettingsTab {
+    #[default]
+    Graphics,
+    Audio,
+    Controls,
+}
+
+/// The state of the settings menu, including the settings being edited.
+#[derive(Resource, Default)]
+struct SettingsMenu {
+    tab: SettingsTab,
+    draft: SettingsDraft,
+    /// The action waiting for a key press to be bound to it.
+    rebinding: Option<ControlAction>,
+    /// Why the last key pressed while rebinding wasn't bound.
+    refused_key: Option<String>,
+}
+
+/// A copy of every settings resource that the menu edits.
+#[derive(Clone, PartialEq, Default)]
+struct SettingsDraft {
+    app: AppSettings,
+    graphics: GraphicsSettings,
+    audio: AudioSettings,
+    controls: ControlSettings,
+}
+
+/// The settings resources currently in effect.
+#[derive(SystemParam)]
+struct AppliedSettings<'w> {
+    app: ResMut<'w, AppSettings>,
+    graphics: ResMut<'w, GraphicsSettings>,
+    audio: ResMut<'w, AudioSettings>,
+    controls: ResMut<'w, ControlSettings>,
+}
+
+impl AppliedSettings<'_> {
+    fn snapshot(&self) -> SettingsDraft {
+        SettingsDraft {
+            app: *self.app,
+            graphics: self.graphics.clone(),
+            audio: self.audio.clone(),
+            controls: self.controls.clone(),
+        }
+    }
+
+    /// Writes the draft into the resources, only marking the ones that
+    /// actually differ as changed.
+    fn apply(&mut self, draft: &SettingsDraft) {
+        self.app.set_if_neq(draft.app);
+        self.graphics.set_if_neq(draft.graphics.clone());
+        self.audio.set_if_neq(draft.audio.clone());
+        self.controls.set_if_neq(draft.controls.clone());
+    }
+}
+
+fn toggle_pause(
+    input: Res<ButtonInput<KeyCode>>,
+    state: Res<State<PauseState>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut menu: ResMut<SettingsMenu>,
+) {
+    if !input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    // Esc cancels rebinding a key before it closes the menu.
+    if menu.rebinding.take().is_some() {
+        return;
+    }
+
+    next_state.set(match state.get() {
+        PauseState::Running => PauseState::Paused,
+        PauseState::Paused => PauseState::Running,
+    });
+}
+
+/// Starts editing from the settings currently in effect, dropping any edits
+/// left unapplied the last time the menu was open.
+fn open_settings(mut menu: ResMut<SettingsMenu>, applied: AppliedSettings) {
+    menu.rebinding = None;
+    menu.refused_key = None;
+    menu.draft = applied.snapshot();
+}
+
+/// Binds the next key pressed to the action being rebound, unless it is a
+/// hotkey.
+fn capture_rebinding(input: Res<ButtonInput<KeyCode>>, mut menu: ResMut<SettingsMenu>) {
+    let Some(action) = menu.rebinding else {
+        return;
+    };
+
+    if let Some(&key) = input
+        .get_just_pressed()
+        .find(|&&key| key != KeyCode::Escape)
+    {
+        if let Some(taken_by) = hotkey(key) {
+            menu.refused_key = Some(format!("{} is for {taken_by}", key_label(key)));
+            return;
+        }
+        *menu.draft.controls.key_mut(action) = key;
+        menu.rebinding = None;
+        menu.refused_key = None;
+    }
+}
+
+fn settings_menu(
+    mut contexts: EguiContexts,
+    mut menu: ResMut<SettingsMenu>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut next_benchmark_state: ResMut<NextState<BenchmarkState>>,
+    mut server_browser: ResMut<ServerBrowser>,
+    mut applied: AppliedSettings,
+) {
+    let menu = &mut *menu;
+    let dirty = menu.draft != applied.snapshot();
+
+    egui::Window::new("Paused")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut menu.tab, SettingsTab::Graphics, "Graphics");
+                ui.selectable_value(&mut menu.tab, SettingsTab::Audio, "Audio");
+                ui.selectable_value(&mut menu.tab, SettingsTab::Controls, "Controls");
+            });
+            ui.separator();
+
+            match menu.tab {
+                SettingsTab::Graphics => graphics_tab(ui, &mut menu.draft),
+                SettingsTab::Audio => audio_tab(ui, &mut menu.draft.audio),
+                SettingsTab::Controls => controls_tab(
+                    ui,
+                    &mut menu.draft.controls,
+                    &mut menu.rebinding,
+                    &mut menu.refused_key,
+                ),
+            }
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Resume").clicked() {
+                    next_state.set(PauseState::Running);
+                }
+                if ui.add_enabled(dirty, egui::Button::new("Apply")).clicked() {
+                    applied.apply(&menu.draft);
+                }
+                if ui.add_enabled(dirty, egui::Button::new("Revert")).clicked() {
+                    menu.draft = applied.snapshot();
+                    menu.rebinding = None;
+                }
+                if ui.button("Run Benchmark").clicked() {
+                    next_benchmark_state.set(BenchmarkState::Running);
+                    next_state.set(PauseState::Running);
+                }
+                if ui.button("Multiplayer").clicked() {
+                    server_browser.open();
+                }
+            });
+        });
+}
+
+fn graphics_tab(ui: &mut egui::Ui, draft: &mut SettingsDraft) {
+    ui.horizontal(|ui| {
+        ui.label("Preset");
+        for preset in GraphicsPreset::ALL {
+            if ui.button(preset.label()).clicked() {
+                preset.apply(&mut draft.graphics, &mut draft.app);
+            }
+        }
+    });
+    ui.separator();
+
+    dof_controls(ui, &mut draft.app);
+
+    ui.checkbox(&mut draft.graphics.bloom, "Bloom");
+    ui.checkbox(&mut draft.graphics.shadows, "Shadows");
+    ui.checkbox(&mut draft.graphics.clouds, "Clouds");
+    ui.checkbox(&mut draft.graphics.reflections, "Mirror reflections");
+    ui.add(
+        egui::Slider::new(
+            &mut draft.graphics.render_distance,
+            MIN_RENDER_DISTANCE..=MAX_RENDER_DISTANCE,
+        )
+        .text("Render distance"),
+    );
+    ui.add(
+        egui::Slider::new(
+            &mut draft.graphics.screenshot_scale,
+            1..=MAX_SCREENSHOT_SCALE,
+        )
+        .suffix("×")
+        .text("Screenshot scale"),
+    );
+}
+
+/// The depth of field mode, focus and aperture widgets, shared with photo
+/// mode.
+pub fn dof_controls(ui: &mut egui::Ui, app: &mut AppSettings) {
+    egui::ComboBox::from_label("Depth of field")
+        .selected_text(dof_mode_label(app.mode))
+        .show_ui(ui, |ui| {
+            for mode in [
+                None,
+                Some(DepthOfFieldMode::Gaussian),
+                Some(DepthOfFieldMode::Bokeh),
+            ] {
+                ui.selectable_value(&mut app.mode, mode, dof_mode_label(mode));
+            }
+        });
+    ui.add_enabled_ui(app.mode.is_some(), |ui| {
+        egui::ComboBox::from_label("Depth of field quality")
+            .selected_text(app.quality.label())
+            .show_ui(ui, |ui| {
+                for quality in DofQuality::ALL {
+                    ui.selectable_value(&mut app.quality, quality, quality.label());
+                }
+            });
+    });
+    ui.add_enabled(
+        app.mode.is_some(),
+        egui::Checkbox::new(&mut app.auto_focus, "Auto focus on the player"),
+    );
+    ui.add_enabled(
+        app.mode.is_some() && !app.auto_focus,
+        egui::Slider::new(&mut app.focal_distance, MIN_FOCAL_DISTANCE..=30.0)
+            .text("Focal distance"),
+    );
+    ui.add_enabled(
+        app.mode.is_some(),
+        egui::Slider::new(&mut app.aperture_f_stops, MIN_APERTURE_F_STOPS..=8.0)
+            .logarithmic(true)
+            .text("Aperture (f-stops)"),
+    );
+}
+
+fn audio_tab(ui: &mut egui::Ui, audio: &mut AudioSettings) {
+    ui.add(egui::Slider::new(&mut audio.master_volume, 0.0..=1.0).text("Master"));
+}
+
+fn controls_tab(
+    ui: &mut egui::Ui,
+    controls: &mut ControlSettings,
+    rebinding: &mut Option<ControlAction>,
+    refused_key: &mut Option<String>,
+) {
+    egui::Grid::new("controls").num_columns(2).show(ui, |ui| {
+        for action in ControlAction::ALL {
+            ui.label(action.label());
+            let key_label = if *rebinding == Some(action) {
+                "Press a key...".to_string()
+            } else {
+                key_label(controls.key(action))
+            };
+            if ui.button(key_label).clicked() {
+                *rebinding = Some(action);
+                *refused_key = None;
+            }
+            ui.end_row();
+        }
+    });
+    if let (Some(_), Some(refused_key)) = (rebinding, refused_key) {
+        ui.label(format!("{refused_key}, pick another key"));
+    }
+    ui.checkbox(&mut controls.damped_flight, "Damped flight");
+}
+
+fn dof_mode_label(mode: Option<DepthOfFieldMode>) -> &'static str {
+    match mode {
+        None => "Off",
+        Some(DepthOfFieldMode::Gaussian) => "Gaussian",
+        Some(DepthOfFieldMode::Bokeh) => "Bokeh",
+    }
+}
+
+/// A readable name for a key, e.g. "W" rather than "KeyW".
+fn key_label(key: KeyCode) -> String {
+    let name = format!("{key:?}");
+    match name
+        .strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+    {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
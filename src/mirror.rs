@@ -0,0 +1,379 @@
+//! Mirror blocks, whose front face reflects the scene.
+//!
+//! M places a mirror block in front of the player and Shift+M removes the
+//! nearest one. Each mirror has a camera at the main camera's reflection in
+//! the mirror's plane, looking out through the mirror. Its projection is
+//! fitted to the mirror's face with the near plane on the mirror's plane, so
+//! nothing behind the mirror shows up in the reflection and the rendered
+//! image lines up exactly with the face. Only the few mirrors nearest the
+//! player reflect, and none do with reflections turned off in the graphics
+//! settings; the rest are plain panels.
+
+use bevy::{
+    math::{Affine2, Vec3A},
+    pbr::PbrProjectionPlugin,
+    prelude::*,
+    render::{
+        camera::{CameraProjection, CameraProjectionPlugin, RenderTarget},
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+    transform::TransformSystem,
+};
+
+use crate::{
+    menu::PauseState, photo_mode::PhotoMode, settings::GraphicsSettings, MainCamera, Player,
+    Position, Rotation,
+};
+
+/// How many mirrors can reflect at once. The nearest ones win.
+const MAX_ACTIVE_MIRRORS: usize = 2;
+/// Mirrors further than this from the player don't reflect.
+const MIRROR_RANGE: f32 = 32.0;
+/// The width and height of the image a mirror's camera renders into.
+const MIRROR_RESOLUTION: u32 = 512;
+/// The edge length of a mirror's reflective face.
+const MIRROR_SIZE: f32 = 0.9;
+/// How far in front of the block's center the face sits.
+const MIRROR_FACE_OFFSET: f32 = 0.501;
+/// How far beyond the mirror's plane the reflection starts, so the face
+/// doesn't show up in its own reflection.
+const MIRROR_CLIP_OFFSET: f32 = 0.01;
+/// How far the reflection reaches.
+const MIRROR_FAR: f32 = 500.0;
+/// How far in front of the player mirrors are placed.
+const PLACE_DISTANCE: f32 = 1.5;
+/// How close a mirror has to be to the player to be removed.
+const REMOVE_REACH: f32 = 3.0;
+const MIRROR_FRAME_COLOR: Color = Color::srgb(0.35, 0.3, 0.25);
+/// The color of a mirror's face while it isn't reflecting.
+const DULL_MIRROR_COLOR: Color = Color::srgb(0.55, 0.6, 0.65);
+
+pub struct MirrorPlugin;
+
+impl Plugin for MirrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            CameraProjectionPlugin::<MirrorProjection>::default(),
+            PbrProjectionPlugin::<MirrorProjection>::default(),
+        ))
+        .add_systems(Startup, setup_mirror_assets)
+        .add_systems(
+            Update,
+            (place_mirror, remove_mirror)
+                .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+        )
+        .add_systems(
+            PostUpdate,
+            update_mirror_cameras.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// An off-axis perspective projection through a window at the near plane,
+/// given by the window's edges in view space.
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component, Default)]
+struct MirrorProjection {
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+}
+
+impl Default for MirrorProjection {
+    fn default() -> Self {
+        Self {
+            left: -0.5,
+            right: 0.5,
+            bottom: -0.5,
+            top: 0.5,
+            near: 0.5,
+            far: MIRROR_FAR,
+        }
+    }
+}
+
+impl CameraProjection for MirrorProjection {
+    fn get_clip_from_view(&self) -> Mat4 {
+        // Like Bevy's own perspective projection, with reversed depth and
+        // no far plane, but with the window off center.
+        let width = self.right - self.left;
+        let height = self.top - self.bottom;
+        Mat4::from_cols(
+            Vec4::new(2.0 * self.near / width, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 2.0 * self.near / height, 0.0, 0.0),
+            Vec4::new(
+                (self.right + self.left) / width,
+                (self.top + self.bottom) / height,
+                0.0,
+                -1.0,
+            ),
+            Vec4::new(0.0, 0.0, self.near, 0.0),
+        )
+    }
+
+    fn update(&mut self, _width: f32, _height: f32) {
+        // The window is fitted to the mirror, not to the render target.
+    }
+
+    fn far(&self) -> f32 {
+        self.far
+    }
+
+    fn get_frustum_corners(&self, z_near: f32, z_far: f32) -> [Vec3A; 8] {
+        let corners = |z: f32| {
+            let scale = z.abs() / self.near;
+            [
+                Vec3A::new(self.right * scale, self.bottom * scale, z),
+                Vec3A::new(self.right * scale, self.top * scale, z),
+                Vec3A::new(self.left * scale, self.top * scale, z),
+                Vec3A::new(self.left * scale, self.bottom * scale, z),
+            ]
+        };
+        // Bottom right, top right, top left, bottom left, near then far, in
+        // the order cascaded shadow maps expect.
+        let [a, b, c, d] = corners(z_near);
+        let [e, f, g, h] = corners(z_far);
+        [a, b, c, d, e, f, g, h]
+    }
+}
+
+/// A mirror block, and the camera that renders its reflection.
+#[derive(Component)]
+struct Mirror {
+    camera: Entity,
+    image: Handle<Image>,
+    face: Handle<StandardMaterial>,
+}
+
+/// The meshes and material that mirror blocks are built from.
+#[derive(Resource)]
+struct MirrorAssets {
+    block: Handle<Mesh>,
+    face: Handle<Mesh>,
+    frame: Handle<StandardMaterial>,
+}
+
+fn setup_mirror_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(MirrorAssets {
+        block: meshes.add(Cuboid::from_length(1.0)),
+        face: meshes.add(Rectangle::from_length(MIRROR_SIZE)),
+        frame: materials.add(MIRROR_FRAME_COLOR),
+    });
+}
+
+/// Places a mirror in front of the player, facing them.
+fn place_mirror(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    assets: Res<MirrorAssets>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    players: Query<(&Position, &Rotation), With<Player>>,
+) {
+    if !input.just_pressed(KeyCode::KeyM) || input.pressed(KeyCode::ShiftLeft) {
+        return;
+    }
+    let Ok((position, rotation)) = players.get_single() else {
+        return;
+    };
+
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: MIRROR_RESOLUTION,
+            height: MIRROR_RESOLUTION,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    let image = images.add(image);
+
+    let camera = commands
+        .spawn(Camera3dBundle {
+            camera: Camera {
+                target: RenderTarget::Image(image.clone()),
+                // Render before the main camera, which shows the result.
+                order: -1,
+                is_active: false,
+                ..default()
+            },
+            ..default()
+        })
+        .remove::<Projection>()
+        .insert(MirrorProjection::default())
+        .id();
+    let face = materials.add(StandardMaterial {
+        base_color: DULL_MIRROR_COLOR,
+        // The reflection camera sees the face from behind, so its image is
+        // flipped left to right.
+        uv_transform: Affine2::from_scale_angle_translation(
+            Vec2::new(-1.0, 1.0),
+            0.0,
+            Vec2::new(1.0, 0.0),
+        ),
+        ..default()
+    });
+
+    // The block's front face, which the mirror is on, faces the player.
+    let forward = Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos());
+    let mut center = (position.current + forward * PLACE_DISTANCE).floor() + Vec3::splat(0.5);
+    center.y = center.y.max(0.5);
+    commands
+        .spawn((
+            PbrBundle {
+                mesh: assets.block.clone(),
+                material: assets.frame.clone(),
+                transform: Transform::from_translation(center).looking_to(forward, Vec3::Y),
+                ..default()
+            },
+            Mirror {
+                camera,
+                image,
+                face: face.clone(),
+            },
+        ))
+        .with_children(|block| {
+            block.spawn(PbrBundle {
+                mesh: assets.face.clone(),
+                material: face,
+                transform: Transform::from_xyz(0.0, 0.0, MIRROR_FACE_OFFSET),
+                ..default()
+            });
+        });
+}
+
+/// Removes the mirror nearest the player, along with its camera.
+fn remove_mirror(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    players: Query<&Position, With<Player>>,
+    mirrors: Query<(Entity, &Mirror, &Transform)>,
+) {
+    if !input.just_pressed(KeyCode::KeyM) || !input.pressed(KeyCode::ShiftLeft) {
+        return;
+    }
+    let Ok(position) = players.get_single() else {
+        return;
+    };
+
+    let nearest = mirrors
+        .iter()
+        .map(|(entity, mirror, transform)| {
+            let distance = transform.translation.distance(position.current);
+            (entity, mirror, distance)
+        })
+        .filter(|(_, _, distance)| *distance < REMOVE_REACH)
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    if let Some((entity, mirror, _)) = nearest {
+        commands.entity(entity).despawn_recursive();
+        commands.entity(mirror.camera).despawn_recursive();
+        images.remove(&mirror.image);
+        materials.remove(&mirror.face);
+    }
+}
+
+/// The parts of a mirror's camera that follow the main camera.
+type ReflectionView = (
+    &'static mut Camera,
+    &'static mut Transform,
+    &'static mut MirrorProjection,
+);
+
+/// Moves each reflecting mirror's camera to the main camera's reflection and
+/// fits its projection to the mirror, and turns reflections on and off by
+/// distance and the graphics settings.
+fn update_mirror_cameras(
+    graphics_settings: Res<GraphicsSettings>,
+    main_cameras: Query<&Transform, With<MainCamera>>,
+    players: Query<&Position, With<Player>>,
+    mirrors: Query<(&Mirror, &Transform), Without<MainCamera>>,
+    mut reflections: Query<ReflectionView, (Without<Mirror>, Without<MainCamera>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let (Ok(eye), Ok(player)) = (main_cameras.get_single(), players.get_single()) else {
+        return;
+    };
+    let eye = eye.translation;
+
+    let mut mirrors: Vec<_> = mirrors
+        .iter()
+        .map(|(mirror, transform)| {
+            (
+                transform.translation.distance(player.current),
+                mirror,
+                transform,
+            )
+        })
+        .collect();
+    mirrors.sort_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+
+    for (rank, (distance, mirror, transform)) in mirrors.into_iter().enumerate() {
+        let Ok((mut camera, mut camera_transform, mut projection)) =
+            reflections.get_mut(mirror.camera)
+        else {
+            continue;
+        };
+
+        let normal = transform.back();
+        let up = transform.up();
+        let center = transform.translation + normal * MIRROR_FACE_OFFSET;
+        // How far the eye is in front of the mirror.
+        let eye_distance = (eye - center).dot(*normal);
+
+        let active = graphics_settings.reflections
+            && rank < MAX_ACTIVE_MIRRORS
+            && distance < MIRROR_RANGE
+            && eye_distance > MIRROR_CLIP_OFFSET;
+        if camera.is_active != active {
+            camera.is_active = active;
+            if let Some(face) = materials.get_mut(&mirror.face) {
+                face.base_color = if active {
+                    Color::WHITE
+                } else {
+                    DULL_MIRROR_COLOR
+                };
+                face.base_color_texture = active.then(|| mirror.image.clone());
+                face.unlit = active;
+            }
+        }
+        if !active {
+            continue;
+        }
+
+        // Look out through the mirror from behind it, where the eye's
+        // reflection is.
+        let reflected_eye = eye - 2.0 * eye_distance * *normal;
+        *camera_transform = Transform::from_translation(reflected_eye).looking_to(*normal, *up);
+
+        // The mirror's face, in the reflection camera's view, which sees it
+        // with left and right swapped.
+        let offset = center - reflected_eye;
+        let x = offset.dot(*camera_transform.right());
+        let y = offset.dot(*up);
+        let half = MIRROR_SIZE / 2.0;
+        let near = eye_distance + MIRROR_CLIP_OFFSET;
+        let scale = near / eye_distance;
+        *projection = MirrorProjection {
+            left: (x - half) * scale,
+            right: (x + half) * scale,
+            bottom: (y - half) * scale,
+            top: (y + half) * scale,
+            near,
+            far: MIRROR_FAR,
+        };
+    }
+}
@@ -0,0 +1,308 @@
+//! Mobs that roam around the player: passive critters by day and hostile
+//! mobs by night.
+//!
+//! Mobs spawn a little way off from the player and are despawned once the
+//! player leaves them far behind. They are characters like the player, with
+//! their own scene, animations and [`Health`], moved by the same controller
+//! through their [`MovementInput`], which their [`Brain`] steers. Until mobs
+//! have models of their own, every kind is a fox scaled to its size. Their
+//! nameplates have no text, just a health bar once they are hurt.
+
+use std::{f32::consts::TAU, time::Duration};
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use serde::Deserialize;
+
+use crate::{
+    ai::Brain,
+    animation::{AnimFsm, AnimState, AnimationBinding, ClipSet},
+    day_night::TimeOfDay,
+    health::{Dead, Health},
+    lod::{SimulationLod, SIMULATION_DISTANCE},
+    nameplates::Nameplate,
+    simulation::WorldSimulation,
+    Checks, MovementInput, Player, Position, Rotation,
+};
+
+/// How often a new mob may spawn.
+const SPAWN_INTERVAL: Duration = Duration::from_secs(2);
+/// How many passive and hostile mobs can be around at once.
+const MAX_PASSIVE_MOBS: usize = 10;
+const MAX_HOSTILE_MOBS: usize = 6;
+/// How far from the player mobs spawn.
+const MIN_SPAWN_DISTANCE: f32 = 20.0;
+const MAX_SPAWN_DISTANCE: f32 = 40.0;
+/// Mobs further than this from the player are despawned. It is beyond the
+/// simulation distance, so mobs lie dormant for a while first and are still
+/// there if the player turns back.
+const DESPAWN_DISTANCE: f32 = SIMULATION_DISTANCE + 32.0;
+/// How long a dead mob lies around before it is despawned, in seconds.
+const CORPSE_TIME: f32 = 3.0;
+
+pub struct MobsPlugin;
+
+impl Plugin for MobsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MobRng>()
+            .add_systems(Startup, load_mob_assets)
+            .add_systems(
+                Update,
+                (
+                    spawn_mobs.run_if(on_timer(SPAWN_INTERVAL)),
+                    despawn_distant_mobs,
+                    despawn_dead_mobs,
+                )
+                    .chain()
+                    .in_set(WorldSimulation),
+            );
+    }
+}
+
+/// The kinds of mob there are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum MobKind {
+    Rabbit,
+    Chicken,
+    Wolf,
+}
+
+impl MobKind {
+    const PASSIVE: [MobKind; 2] = [MobKind::Rabbit, MobKind::Chicken];
+    const HOSTILE: [MobKind; 1] = [MobKind::Wolf];
+
+    pub fn is_hostile(self) -> bool {
+        Self::HOSTILE.contains(&self)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MobKind::Rabbit => "Rabbit",
+            MobKind::Chicken => "Chicken",
+            MobKind::Wolf => "Wolf",
+        }
+    }
+
+    fn scale(self) -> f32 {
+        match self {
+            MobKind::Rabbit => 0.005,
+            MobKind::Chicken => 0.004,
+            MobKind::Wolf => 0.016,
+        }
+    }
+
+    /// How far above the ground the mob's nameplate floats.
+    fn nameplate_height(self) -> f32 {
+        match self {
+            MobKind::Rabbit => 0.6,
+            MobKind::Chicken => 0.5,
+            MobKind::Wolf => 1.6,
+        }
+    }
+
+    fn max_health(self) -> f32 {
+        match self {
+            MobKind::Rabbit => 3.0,
+            MobKind::Chicken => 4.0,
+            MobKind::Wolf => 20.0,
+        }
+    }
+
+    /// How fast the mob walks, as a share of the player's running speed.
+    pub fn walk_speed(self) -> f32 {
+        match self {
+            MobKind::Rabbit => 0.5,
+            MobKind::Chicken => 0.3,
+            MobKind::Wolf => 0.6,
+        }
+    }
+
+    /// How fast the mob runs when chasing or fleeing, as a share of the
+    /// player's running speed.
+    pub fn run_speed(self) -> f32 {
+        match self {
+            MobKind::Rabbit => 0.9,
+            MobKind::Chicken => 0.6,
+            MobKind::Wolf => 0.85,
+        }
+    }
+
+    /// How much health each of the mob's attacks takes.
+    pub fn attack_damage(self) -> f32 {
+        match self {
+            MobKind::Rabbit | MobKind::Chicken => 0.0,
+            MobKind::Wolf => 3.0,
+        }
+    }
+}
+
+/// A mob of some kind.
+#[derive(Component)]
+pub struct Mob {
+    pub kind: MobKind,
+    /// Seconds since the mob died.
+    dead_time: f32,
+}
+
+/// The scene and animations of each kind of mob.
+#[derive(Resource)]
+struct MobAssets {
+    scene: Handle<Scene>,
+    rabbit: ClipSet,
+    chicken: ClipSet,
+    wolf: ClipSet,
+}
+
+impl MobAssets {
+    fn clips(&self, kind: MobKind) -> &ClipSet {
+        match kind {
+            MobKind::Rabbit => &self.rabbit,
+            MobKind::Chicken => &self.chicken,
+            MobKind::Wolf => &self.wolf,
+        }
+    }
+}
+
+/// A small xorshift generator, enough to pick spawn spots and wander
+/// directions.
+#[derive(Resource)]
+pub struct MobRng(u64);
+
+impl Default for MobRng {
+    fn default() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl MobRng {
+    /// A number from 0 up to 1.
+    pub fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+
+    fn pick<T: Copy>(&mut self, choices: &[T]) -> T {
+        choices[((self.next() * choices.len() as f32) as usize).min(choices.len() - 1)]
+    }
+}
+
+fn load_mob_assets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    let mut graph = AnimationGraph::new();
+    let [survey, walk, run] = [0, 1, 2].map(|index| {
+        graph.add_clip(
+            asset_server.load(GltfAssetLabel::Animation(index).from_asset("models/Fox.glb")),
+            1.0,
+            graph.root,
+        )
+    });
+    let graph = graphs.add(graph);
+
+    // The same clips as the player's, with the run played back at the speed
+    // each kind moves at.
+    let clips = |run_speed: f32| {
+        ClipSet::new(graph.clone())
+            .with_clip(AnimState::Idle, survey, 2.0)
+            .with_clip(AnimState::Run, run, run_speed)
+            .with_clip_once(AnimState::Dead, walk, 1.0)
+    };
+    commands.insert_resource(MobAssets {
+        scene: asset_server.load("models/Fox.glb#Scene0"),
+        rabbit: clips(2.0),
+        chicken: clips(1.5),
+        wolf: clips(1.8),
+    });
+}
+
+/// Spawns a mob around the player now and then, passive ones by day and
+/// hostile ones by night, while there are fewer than the limit.
+fn spawn_mobs(
+    mut commands: Commands,
+    time_of_day: Res<TimeOfDay>,
+    assets: Option<Res<MobAssets>>,
+    mut rng: ResMut<MobRng>,
+    players: Query<&Position, With<Player>>,
+    mobs: Query<&Mob>,
+) {
+    let (Some(assets), Ok(player)) = (assets, players.get_single()) else {
+        return;
+    };
+
+    let (kinds, limit) = if time_of_day.is_day() {
+        (&MobKind::PASSIVE[..], MAX_PASSIVE_MOBS)
+    } else {
+        (&MobKind::HOSTILE[..], MAX_HOSTILE_MOBS)
+    };
+    let count = mobs.iter().filter(|mob| kinds.contains(&mob.kind)).count();
+    if count >= limit {
+        return;
+    }
+
+    let kind = rng.pick(kinds);
+    let angle = rng.range(0.0, TAU);
+    let distance = rng.range(MIN_SPAWN_DISTANCE, MAX_SPAWN_DISTANCE);
+    let mut spot = player.current + Vec3::new(angle.cos(), 0.0, angle.sin()) * distance;
+    spot.y = 0.0;
+
+    commands.spawn((
+        SceneBundle {
+            scene: assets.scene.clone(),
+            transform: Transform::from_translation(spot).with_scale(Vec3::splat(kind.scale())),
+            ..default()
+        },
+        Position::at(spot),
+        Rotation {
+            radians_y: rng.range(0.0, TAU),
+        },
+        Checks::default(),
+        MovementInput::default(),
+        AnimationBinding::new(assets.clips(kind).clone()),
+        AnimFsm::default(),
+        Health::new(kind.max_health()),
+        Nameplate::new("", kind.nameplate_height()),
+        SimulationLod::default(),
+        Mob {
+            kind,
+            dead_time: 0.0,
+        },
+        Brain::default(),
+    ));
+}
+
+fn despawn_distant_mobs(
+    mut commands: Commands,
+    players: Query<&Position, With<Player>>,
+    mobs: Query<(Entity, &Position), With<Mob>>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+
+    for (entity, position) in mobs.iter() {
+        if position.current.distance(player.current) > DESPAWN_DISTANCE {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Removes dead mobs once their death animation has played.
+fn despawn_dead_mobs(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut mobs: Query<(Entity, &mut Mob), With<Dead>>,
+) {
+    for (entity, mut mob) in mobs.iter_mut() {
+        mob.dead_time += time.delta_seconds();
+        if mob.dead_time > CORPSE_TIME {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
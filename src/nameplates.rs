@@ -0,0 +1,358 @@
+//! Labels and health bars floating above things in the world, and numbers
+//! popping up where damage is dealt.
+//!
+//! Anything with a [`Nameplate`] gets a UI node that follows it around the
+//! screen, showing the plate's text and, for characters with [`Health`] that
+//! have been hurt, a health bar. Plates fade out with distance from the
+//! camera and are hidden in photo mode, so they stay out of photos. There is
+//! no terrain to hide behind, so plates are never occluded.
+
+use bevy::{prelude::*, ui::UiSystem};
+
+use crate::{
+    health::{Dead, Health},
+    photo_mode::PhotoMode,
+    MainCamera,
+};
+
+/// How far from the camera plates start to fade out.
+const FADE_START_DISTANCE: f32 = 16.0;
+/// How far from the camera plates are no longer shown.
+const FADE_END_DISTANCE: f32 = 24.0;
+/// The width of a plate, which its text and health bar are centred in.
+const PLATE_WIDTH: f32 = 120.0;
+/// How high a plate with both text and a health bar is. Plates sit on top
+/// of their anchor.
+const PLATE_HEIGHT: f32 = 24.0;
+const HEALTH_BAR_WIDTH: f32 = 48.0;
+const HEALTH_BAR_HEIGHT: f32 = 5.0;
+const HEALTH_BAR_COLOR: Color = Color::srgb(0.85, 0.1, 0.15);
+const HEALTH_BAR_BACKGROUND: Color = Color::srgba(0.0, 0.0, 0.0, 0.6);
+const PLATE_FONT_SIZE: f32 = 14.0;
+/// How long a damage number floats up for, in seconds.
+const DAMAGE_NUMBER_TIME: f32 = 0.8;
+/// How fast damage numbers float up.
+const DAMAGE_NUMBER_RISE_SPEED: f32 = 1.5;
+const DAMAGE_NUMBER_FONT_SIZE: f32 = 18.0;
+const DAMAGE_NUMBER_COLOR: Color = Color::srgb(1.0, 0.85, 0.3);
+
+pub struct NameplatesPlugin;
+
+impl Plugin for NameplatesPlugin {
+    fn build(&self, app: &mut App) {
+        // After everything has moved for the frame, but before the UI is
+        // laid out.
+        app.add_systems(
+            PostUpdate,
+            (
+                remove_orphaned_nameplates,
+                spawn_nameplates,
+                spawn_damage_numbers,
+                update_nameplates,
+                update_damage_numbers,
+            )
+                .chain()
+                .before(UiSystem::Layout),
+        );
+    }
+}
+
+/// Floats a label, and a health bar once hurt, above an entity.
+#[derive(Component)]
+pub struct Nameplate {
+    /// The text to show, such as a name or how many items a drop holds.
+    /// Empty for none.
+    pub text: String,
+    /// How far above the entity's origin the plate floats.
+    pub height: f32,
+}
+
+impl Nameplate {
+    pub fn new(text: impl Into<String>, height: f32) -> Self {
+        Self {
+            text: text.into(),
+            height,
+        }
+    }
+}
+
+/// The UI showing an entity's nameplate.
+#[derive(Component)]
+struct PlateUi {
+    root: Entity,
+    text: Entity,
+    bar: Entity,
+    bar_fill: Entity,
+    /// The health the plate last showed, to tell how much damage was dealt.
+    shown_health: Option<f32>,
+}
+
+/// Marks the root node of a nameplate, with the entity it belongs to.
+#[derive(Component)]
+struct PlateRoot(Entity);
+
+/// A number floating up from where damage was dealt.
+#[derive(Component)]
+struct DamageNumber {
+    position: Vec3,
+    /// Seconds since the damage was dealt.
+    age: f32,
+}
+
+/// Where each entity with a nameplate is and what its plate shows.
+type PlateOwner = (
+    &'static Nameplate,
+    &'static Transform,
+    &'static PlateUi,
+    Option<&'static Health>,
+    Has<Dead>,
+);
+
+/// Removes the plates of entities that were despawned or lost their
+/// [`Nameplate`].
+fn remove_orphaned_nameplates(
+    mut commands: Commands,
+    plates: Query<(Entity, &PlateRoot)>,
+    owners: Query<(), With<Nameplate>>,
+) {
+    for (root, owner) in plates.iter() {
+        if owners.contains(owner.0) {
+            continue;
+        }
+        commands.entity(root).despawn_recursive();
+        if let Some(mut owner) = commands.get_entity(owner.0) {
+            owner.remove::<PlateUi>();
+        }
+    }
+}
+
+/// Entities with a nameplate that has no UI yet.
+type WithoutPlate = (With<Nameplate>, Without<PlateUi>);
+
+fn spawn_nameplates(
+    mut commands: Commands,
+    owners: Query<(Entity, Option<&Health>), WithoutPlate>,
+) {
+    for (owner, health) in owners.iter() {
+        let text = commands
+            .spawn(TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: PLATE_FONT_SIZE,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ))
+            .id();
+        let bar_fill = commands
+            .spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                background_color: HEALTH_BAR_COLOR.into(),
+                ..default()
+            })
+            .id();
+        let bar = commands
+            .spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(HEALTH_BAR_WIDTH),
+                    height: Val::Px(HEALTH_BAR_HEIGHT),
+                    ..default()
+                },
+                background_color: HEALTH_BAR_BACKGROUND.into(),
+                ..default()
+            })
+            .add_child(bar_fill)
+            .id();
+        let root = commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Px(PLATE_WIDTH),
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(2.0),
+                        ..default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                PlateRoot(owner),
+            ))
+            .push_children(&[text, bar])
+            .id();
+
+        commands.entity(owner).insert(PlateUi {
+            root,
+            text,
+            bar,
+            bar_fill,
+            shown_health: health.map(|health| health.current),
+        });
+    }
+}
+
+/// Pops up a number wherever a character with a nameplate lost health.
+fn spawn_damage_numbers(
+    mut commands: Commands,
+    mut owners: Query<(&Nameplate, &Transform, &Health, &mut PlateUi)>,
+) {
+    for (nameplate, transform, health, mut plate) in owners.iter_mut() {
+        let lost = plate.shown_health.unwrap_or(health.current) - health.current;
+        if plate.shown_health != Some(health.current) {
+            plate.shown_health = Some(health.current);
+        }
+        if lost <= 0.0 {
+            continue;
+        }
+
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section(
+                    format!("{lost:.0}"),
+                    TextStyle {
+                        font_size: DAMAGE_NUMBER_FONT_SIZE,
+                        color: DAMAGE_NUMBER_COLOR,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                })
+            },
+            DamageNumber {
+                position: transform.translation + Vec3::Y * nameplate.height,
+                age: 0.0,
+            },
+        ));
+    }
+}
+
+/// How opaque something `distance` from the camera is drawn, from 0 up to
+/// 1.
+fn distance_fade(distance: f32) -> f32 {
+    (1.0 - (distance - FADE_START_DISTANCE) / (FADE_END_DISTANCE - FADE_START_DISTANCE))
+        .clamp(0.0, 1.0)
+}
+
+/// Moves each plate over its entity on screen, fades it with distance, and
+/// fills in its text and health bar.
+fn update_nameplates(
+    photo_mode: Res<State<PhotoMode>>,
+    cameras: Query<(&Camera, &Transform), With<MainCamera>>,
+    owners: Query<PlateOwner>,
+    mut roots: Query<(&mut Style, &mut Visibility), With<PlateRoot>>,
+    mut bars: Query<(&mut Style, &mut Visibility, &mut BackgroundColor), Without<PlateRoot>>,
+    mut texts: Query<&mut Text>,
+) {
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+    // The camera has no parent, so its transform is its global transform,
+    // and is already up to date for this frame.
+    let camera_transform = GlobalTransform::from(*camera_transform);
+
+    for (nameplate, transform, plate, health, dead) in owners.iter() {
+        let Ok((mut style, mut visibility)) = roots.get_mut(plate.root) else {
+            continue;
+        };
+
+        let anchor = transform.translation + Vec3::Y * nameplate.height;
+        let fade = distance_fade(camera_transform.translation().distance(anchor));
+        let on_screen = camera.world_to_viewport(&camera_transform, anchor);
+        let (Some(point), false, true) = (
+            on_screen,
+            dead || fade <= 0.0,
+            *photo_mode.get() == PhotoMode::Off,
+        ) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(Visibility::Inherited);
+        style.left = Val::Px(point.x - PLATE_WIDTH / 2.0);
+        style.top = Val::Px(point.y - PLATE_HEIGHT);
+
+        // Only touch the text when it changes, as changing it lays it out
+        // again.
+        if let Ok(mut text) = texts.get_mut(plate.text) {
+            let section = &mut text.sections[0];
+            if section.value != nameplate.text {
+                section.value.clone_from(&nameplate.text);
+            }
+            let color = Color::WHITE.with_alpha(fade);
+            if section.style.color != color {
+                section.style.color = color;
+            }
+        }
+
+        // Only show a health bar once there is damage to show.
+        let hurt = health.filter(|health| health.current < health.max);
+        if let Ok((_, mut visibility, mut background)) = bars.get_mut(plate.bar) {
+            visibility.set_if_neq(if hurt.is_some() {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            });
+            background.set_if_neq(BackgroundColor(
+                HEALTH_BAR_BACKGROUND.with_alpha(HEALTH_BAR_BACKGROUND.alpha() * fade),
+            ));
+        }
+        if let (Some(health), Ok((mut style, _, mut background))) =
+            (hurt, bars.get_mut(plate.bar_fill))
+        {
+            style.width = Val::Percent((health.current / health.max).clamp(0.0, 1.0) * 100.0);
+            background.set_if_neq(BackgroundColor(HEALTH_BAR_COLOR.with_alpha(fade)));
+        }
+    }
+}
+
+/// Floats damage numbers up and fades them out, removing them once they
+/// are gone.
+fn update_damage_numbers(
+    mut commands: Commands,
+    time: Res<Time>,
+    photo_mode: Res<State<PhotoMode>>,
+    cameras: Query<(&Camera, &Transform), With<MainCamera>>,
+    mut numbers: Query<(
+        Entity,
+        &mut DamageNumber,
+        &mut Style,
+        &mut Text,
+        &mut Visibility,
+    )>,
+) {
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+    let camera_transform = GlobalTransform::from(*camera_transform);
+    let dt = time.delta_seconds();
+
+    for (entity, mut number, mut style, mut text, mut visibility) in numbers.iter_mut() {
+        number.age += dt;
+        if number.age > DAMAGE_NUMBER_TIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        number.position.y += DAMAGE_NUMBER_RISE_SPEED * dt;
+
+        let fade = distance_fade(camera_transform.translation().distance(number.position));
+        let on_screen = camera.world_to_viewport(&camera_transform, number.position);
+        let (Some(point), true) = (on_screen, *photo_mode.get() == PhotoMode::Off) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(Visibility::Inherited);
+        style.left = Val::Px(point.x);
+        style.top = Val::Px(point.y);
+        text.sections[0]
+            .style
+            .color
+            .set_alpha(fade * (1.0 - number.age / DAMAGE_NUMBER_TIME));
+    }
+}
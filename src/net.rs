@@ -0,0 +1,1687 @@
+//! Playing together over the network.
+//!
+//! The game always runs a [`NetServer`]. In single player it has no socket
+//! and nobody ever joins, so the local player is all there is. Started with
+//! `--host [address]`, it listens for other players as well; with
+//! `--server [address]` the game runs only the server, headless, set up by
+//! [`ServerConfig`]. Either way
+//! the server is authoritative over everyone who joined: clients send their
+//! movement input a frame at a time, the server steps their characters
+//! through it with the same movement as the local player, and sends everyone
+//! snapshots of where all players are.
+//!
+//! Started with `--connect <address>`, or once a server is picked in the
+//! server browser, the game joins a server instead. The local player moves
+//! straight away rather than waiting to hear back, so they stay responsive
+//! however far away the server is. Each input frame is numbered, and
+//! snapshots say the last one the server applied; the client takes the
+//! server's word for where the player was then, and steps them through the
+//! frames it hasn't heard back about yet. Where the two disagree, the player
+//! eases over to the corrected spot. The other players appear as puppets,
+//! shown a little in the past so they can move smoothly between the
+//! snapshots either side.
+//!
+//! Chat goes through the server too, which passes each message on to
+//! everyone, and so do the players' [replicated](crate::replication)
+//! components, which go only to the clients near enough to see them. The
+//! server keeps the [`Scoreboard`] and sends it to everyone when it changes;
+//! only the host and the admins in a dedicated server's config can change it.
+//! Servers also answer [status queries](StatusQuery) from players who
+//! haven't joined, which is how the [server browser](crate::server_browser)
+//! lists them. Messages are JSON in UDP datagrams. Only players and chat
+//! are shared; mobs, items and the time of day are each player's own.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::Path,
+    time::Duration,
+};
+
+use bevy::{
+    app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*, time::common_conditions::on_timer,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{AnimFsm, AnimState, AnimationBinding, ClipSet},
+    chat::{ChatLog, SendChat, MAX_CHAT_LENGTH},
+    consume_jumps, controlling_characters, follow_blend,
+    game_mode::GameMode,
+    health::{HealthReplicationPlugin, Respawned, SpawnPoint},
+    nameplates::Nameplate,
+    player_controller,
+    replication::{apply_updates, receive_updates, ComponentUpdate, Replica, StoreReplicas},
+    step_motion,
+    teams::{Scoreboard, ScoreboardChange, ScoreboardRequest},
+    Checks, MovementInput, Player, Position, Rotation, PLAYER_FOLLOW_RATE, PLAYER_TURN_RATE,
+};
+
+/// The version of the game, which servers tell players looking for one.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The port servers listen on unless told otherwise. Servers on the local
+/// network are looked for on it.
+const DEFAULT_PORT: u16 = 24680;
+/// What a server greets players with unless told otherwise.
+const DEFAULT_MOTD: &str = "A voxel server";
+/// How many clients can join a server at once unless told otherwise.
+const DEFAULT_MAX_PLAYERS: usize = 16;
+/// Where a dedicated server looks for its settings unless told otherwise.
+const SERVER_CONFIG_PATH: &str = "server.toml";
+/// How often the server sends out snapshots.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(50);
+/// How often a headless server updates.
+const SERVER_TICK: Duration = Duration::from_micros(16_667);
+/// How often everyone is sent the replicated components and the scoreboard
+/// again in full, in case some of what they were sent was lost.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+/// How far from a client's character other players' replicated components
+/// are still sent to it.
+const INTEREST_RADIUS: f32 = 48.0;
+/// How long a client can go unheard before the server drops it, in seconds.
+const CLIENT_TIMEOUT: f32 = 5.0;
+/// How often a client asks to join until the server answers, in seconds.
+const JOIN_RETRY_INTERVAL: f32 = 0.5;
+/// The largest message either side sends.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+/// The id of the host's own player.
+const HOST_ID: u32 = 0;
+/// The longest input frame the server steps a character through, in
+/// seconds, so a stalled client can't leap ahead in one go.
+const MAX_INPUT_STEP: f32 = 0.1;
+/// How much movement a client can bank, in seconds, to make up for input
+/// frames arriving in bursts. Clients can't move for longer than this ahead
+/// of real time, so they can't go faster by sending more frames.
+const MAX_MOVEMENT_CREDIT: f32 = 0.25;
+/// How many input frames a client keeps around waiting to hear the server
+/// applied them.
+const MAX_PENDING_INPUTS: usize = 256;
+/// How far in the past puppets are shown, in seconds, so there is usually a
+/// snapshot either side of them to move between.
+const INTERPOLATION_DELAY: f32 = 0.1;
+/// How big other players are, the same as the local player.
+const PLAYER_SCALE: f32 = 0.012;
+/// How far above the ground other players' names float.
+const NAMEPLATE_HEIGHT: f32 = 1.4;
+
+/// How this instance of the game takes part in a networked session.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum NetMode {
+    /// Plays alone, against a server nobody else can reach.
+    #[default]
+    SinglePlayer,
+    /// Plays while serving other players on this address.
+    Host(SocketAddr),
+    /// Joins the server at this address.
+    Connect(SocketAddr),
+    /// Only serves other players, without a window.
+    Dedicated(ServerConfig),
+}
+
+impl NetMode {
+    /// Reads the mode from the command line: `--host [address]`,
+    /// `--connect <address>` or `--server [address]`, where an address can
+    /// also be just a port. A dedicated server that can't be set up exits,
+    /// rather than opening a window nobody is there to see.
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        for (index, arg) in args.iter().enumerate() {
+            let (mode, connecting): (fn(SocketAddr) -> NetMode, bool) = match arg.as_str() {
+                "--host" => (NetMode::Host, false),
+                "--connect" => (NetMode::Connect, true),
+                "--server" => {
+                    // Logging isn't set up yet this early.
+                    match ServerConfig::from_args(&args) {
+                        Ok(config) => return NetMode::Dedicated(config),
+                        Err(error) => {
+                            eprintln!("Couldn't set up the server: {error}");
+                            std::process::exit(2);
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            let Some(address) = resolve(value_after(&args, index), connecting) else {
+                eprintln!("Couldn't resolve the address for {arg}, playing alone");
+                return NetMode::SinglePlayer;
+            };
+            return mode(address);
+        }
+        NetMode::SinglePlayer
+    }
+}
+
+/// How a dedicated server is set up.
+///
+/// The settings are read from `server.toml` in the working directory, or
+/// the file given with `--config <path>`, all of them optional:
+///
+/// ```toml
+/// address = "0.0.0.0"
+/// port = 24680
+/// max_players = 16
+/// motd = "A voxel server"
+/// game_mode = "survival"
+/// admins = ["192.168.1.20"]
+/// ```
+///
+/// Admins are the addresses of the players who may change the scoreboard.
+///
+/// Flags on the command line win over the file: the address after
+/// `--server`, `--port <port>` and `--max-players <count>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    /// How many clients can join at once.
+    pub max_players: usize,
+    /// The message of the day, shown in the server browser.
+    pub motd: String,
+    /// The mode everyone on the server plays in.
+    pub game_mode: GameMode,
+    /// The addresses of the players who may change the scoreboard.
+    pub admins: Vec<IpAddr>,
+}
+
+/// The settings in a server's config file.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerFile {
+    address: String,
+    port: u16,
+    max_players: usize,
+    motd: String,
+    game_mode: GameMode,
+    admins: Vec<IpAddr>,
+}
+
+impl Default for ServerFile {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            max_players: DEFAULT_MAX_PLAYERS,
+            motd: DEFAULT_MOTD.to_string(),
+            game_mode: GameMode::default(),
+            admins: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the server's config file, then the flags that override it.
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let flag = |name: &str| {
+            let index = args.iter().position(|arg| arg == name)?;
+            Some(value_after(args, index).ok_or(format!("{name} needs a value")))
+        };
+
+        let mut file = match flag("--config").transpose()? {
+            Some(path) => read_server_file(path)?,
+            // Without a file, the defaults are fine.
+            None if !Path::new(SERVER_CONFIG_PATH).exists() => ServerFile::default(),
+            None => read_server_file(SERVER_CONFIG_PATH)?,
+        };
+        if let Some(port) = flag("--port").transpose()? {
+            file.port = port.parse().map_err(|_| format!("{port} isn't a port"))?;
+        }
+        if let Some(count) = flag("--max-players").transpose()? {
+            file.max_players = count
+                .parse()
+                .map_err(|_| format!("{count} isn't a number of players"))?;
+        }
+
+        let address = match flag("--server").and_then(Result::ok) {
+            Some(address) => resolve(Some(address), false),
+            None => (file.address.as_str(), file.port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addresses| addresses.next()),
+        };
+        Ok(Self {
+            address: address.ok_or("couldn't resolve the address to serve on")?,
+            max_players: file.max_players,
+            motd: file.motd,
+            game_mode: file.game_mode,
+            admins: file.admins,
+        })
+    }
+}
+
+fn read_server_file(path: &str) -> Result<ServerFile, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("{path}: {error}"))?;
+    toml::from_str(&text).map_err(|error| format!("{path}: {error}"))
+}
+
+/// The value given to the flag at `index`, if the next argument isn't
+/// another flag.
+fn value_after(args: &[String], index: usize) -> Option<&str> {
+    args.get(index + 1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(String::as_str)
+}
+
+/// Turns a command line address into a socket address. A lone port means
+/// every interface for servers and this machine for clients; servers can
+/// leave the address out altogether, clients can't.
+fn resolve(address: Option<&str>, connecting: bool) -> Option<SocketAddr> {
+    let default_host = if connecting { "127.0.0.1" } else { "0.0.0.0" };
+    let address = match address {
+        None if connecting => return None,
+        None => format!("{default_host}:{DEFAULT_PORT}"),
+        Some(port) if port.parse::<u16>().is_ok() => format!("{default_host}:{port}"),
+        Some(address) if address.contains(':') => address.to_string(),
+        Some(host) => format!("{host}:{DEFAULT_PORT}"),
+    };
+    address.to_socket_addrs().ok()?.next()
+}
+
+pub struct NetPlugin {
+    pub mode: NetMode,
+}
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        let server = match self.mode {
+            NetMode::Host(address) => {
+                NetServer::bind(address, DEFAULT_MAX_PLAYERS).unwrap_or_else(|error| {
+                    error!("Couldn't host on {address}, playing alone: {error}");
+                    NetServer::local()
+                })
+            }
+            _ => NetServer::local(),
+        };
+        #[cfg(feature = "net-conditions")]
+        app.add_plugins(crate::net_conditions::NetConditionsPlugin);
+        app.insert_resource(server)
+            .add_event::<ConnectToServer>()
+            .add_systems(Startup, load_puppet_assets)
+            .add_systems(
+                PreUpdate,
+                (
+                    share_spawn_point.run_if(resource_changed::<SpawnPoint>),
+                    share_game_mode.run_if(resource_changed::<GameMode>),
+                    receive_client_messages,
+                    receive_server_messages.run_if(resource_exists::<NetClient>),
+                ),
+            )
+            .add_systems(
+                FixedUpdate,
+                send_input_frame
+                    .after(player_controller)
+                    .before(consume_jumps)
+                    .run_if(controlling_characters.and_then(resource_exists::<NetClient>)),
+            )
+            .add_systems(
+                Update,
+                (
+                    ease_remote_characters,
+                    send_chat,
+                    send_scoreboard_requests,
+                    connect_to_server,
+                    follow_server_game_mode.run_if(resource_exists::<NetClient>),
+                    add_local_replica,
+                    move_puppets,
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    send_snapshots.run_if(on_timer(SNAPSHOT_INTERVAL)),
+                    send_scoreboard
+                        .run_if(resource_changed::<Scoreboard>.or_else(on_timer(REFRESH_INTERVAL))),
+                    send_replicas
+                        .after(StoreReplicas)
+                        .run_if(on_timer(SNAPSHOT_INTERVAL)),
+                    forget_sent_replicas.run_if(on_timer(REFRESH_INTERVAL)),
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    join_server,
+                    send_idle_frame.run_if(not(controlling_characters)),
+                    send_client_replica
+                        .after(StoreReplicas)
+                        .run_if(on_timer(SNAPSHOT_INTERVAL)),
+                    forget_sent_client_replica.run_if(on_timer(REFRESH_INTERVAL)),
+                )
+                    .run_if(resource_exists::<NetClient>),
+            );
+
+        if let NetMode::Connect(address) = self.mode {
+            match NetClient::connect(address) {
+                Ok(client) => {
+                    app.insert_resource(client);
+                }
+                Err(error) => error!("Couldn't connect to {address}, playing alone: {error}"),
+            }
+        }
+    }
+}
+
+/// Runs only the server, without a window, until the process is stopped.
+pub fn run_dedicated_server(config: ServerConfig) {
+    let mut app = App::new();
+    // Set up logging first, so binding can report how it went.
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(SERVER_TICK)),
+        LogPlugin::default(),
+    ));
+    let server = match NetServer::bind(config.address, config.max_players) {
+        Ok(server) => NetServer {
+            motd: config.motd,
+            game_mode: config.game_mode,
+            admins: config.admins,
+            dedicated: true,
+            ..server
+        },
+        Err(error) => {
+            error!("Couldn't serve on {}: {error}", config.address);
+            return;
+        }
+    };
+    app.insert_resource(server)
+        .init_resource::<Scoreboard>()
+        .add_plugins(HealthReplicationPlugin)
+        .add_systems(PreUpdate, receive_client_messages)
+        .add_systems(
+            PostUpdate,
+            (
+                send_snapshots.run_if(on_timer(SNAPSHOT_INTERVAL)),
+                send_scoreboard
+                    .run_if(resource_changed::<Scoreboard>.or_else(on_timer(REFRESH_INTERVAL))),
+                send_replicas.run_if(on_timer(SNAPSHOT_INTERVAL)),
+                forget_sent_replicas.run_if(on_timer(REFRESH_INTERVAL)),
+            ),
+        )
+        .run();
+}
+
+/// What a client tells the server.
+#[derive(Serialize, Deserialize, Debug)]
+enum ClientMessage {
+    Join,
+    Input(InputFrame),
+    /// Something the player said, for everyone.
+    Chat(String),
+    /// The player's replicated components that changed.
+    Replica(Vec<ComponentUpdate>),
+    /// Asks the server to change the scoreboard.
+    Scoreboard(ScoreboardChange),
+    Leave,
+    /// Asks how the server is doing, without joining it.
+    Status,
+}
+
+/// One frame of a client's movement.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct InputFrame {
+    /// Counts up by one each frame, so the server can say which frames it
+    /// has applied.
+    seq: u32,
+    /// How long the frame lasted, in seconds of the client's game time.
+    dt: f32,
+    direction: Vec3,
+    jump: bool,
+    sprint: bool,
+    /// Whether the player flies, in creative.
+    fly: bool,
+    /// How fast the player rises while flying, from -1 up to 1.
+    vertical: f32,
+    damped_flight: bool,
+    /// Whether the player respawned this frame. Mobs are each player's own,
+    /// and so are deaths, but the server picks where players respawn.
+    respawned: bool,
+}
+
+impl InputFrame {
+    /// Whether the frame's numbers are all finite. Clamping doesn't get rid
+    /// of NaN, and JSON numbers too big for an `f32` become infinite, which
+    /// clamping a length turns into NaN.
+    fn is_finite(&self) -> bool {
+        self.dt.is_finite() && self.direction.is_finite() && self.vertical.is_finite()
+    }
+
+    /// Steps a character through this frame, the same way on the server and
+    /// when the client replays it, respawning at `spawn_point`. Returns
+    /// whether the character moved.
+    fn apply(&self, position: &mut Position, rotation: &mut Rotation, spawn_point: Vec3) -> bool {
+        if self.respawned {
+            position.teleport(spawn_point);
+        }
+        let input = MovementInput {
+            direction: self.direction,
+            jump: self.jump,
+            sprint: self.sprint,
+            fly: self.fly,
+            vertical: self.vertical,
+            damped_flight: self.damped_flight,
+        };
+        let moving = input.direction != Vec3::ZERO;
+        step_motion(&input, self.sprint && moving, position, rotation, self.dt);
+        moving
+    }
+}
+
+/// What the server tells its clients.
+#[derive(Serialize, Deserialize, Debug)]
+enum ServerMessage {
+    Welcome {
+        id: u32,
+        /// Where the server puts the player when they join and respawn.
+        spawn_point: Vec3,
+    },
+    /// Turns away a client asking to join.
+    Refused {
+        reason: String,
+    },
+    Snapshot {
+        /// The last input frame from this client the server applied, or 0
+        /// for none yet.
+        ack: u32,
+        /// The mode the client's player plays in.
+        game_mode: GameMode,
+        players: Vec<PlayerState>,
+    },
+    /// Something a player said.
+    Chat {
+        from: String,
+        text: String,
+    },
+    /// A player's replicated components that changed.
+    Replica {
+        id: u32,
+        updates: Vec<ComponentUpdate>,
+    },
+    Scoreboard(Scoreboard),
+    /// Something for the player to know, such as how a change to the
+    /// scoreboard they asked for went.
+    Notice(String),
+    Status(ServerStatus),
+}
+
+/// How a server is doing, as it tells players looking for one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerStatus {
+    pub motd: String,
+    /// How many clients have joined, and how many can.
+    pub players: usize,
+    pub max_players: usize,
+    /// The version of the game the server runs.
+    pub version: String,
+}
+
+/// The name a player goes by, shown above them and beside what they say.
+pub fn player_name(id: u32) -> String {
+    format!("Player {id}")
+}
+
+/// The id of the local player: the one the server gave them when this game
+/// joined one, once it has, or the host's otherwise.
+pub fn local_player_id(client: Option<&NetClient>) -> Option<u32> {
+    match client {
+        Some(client) => client.id,
+        None => Some(HOST_ID),
+    }
+}
+
+/// Where a player is and what they are doing.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PlayerState {
+    id: u32,
+    position: Vec3,
+    vertical_velocity: f32,
+    flight_velocity: Vec3,
+    radians_y: f32,
+    anim_state: AnimState,
+}
+
+fn send(socket: &UdpSocket, address: SocketAddr, message: &impl Serialize) {
+    let bytes = match serde_json::to_vec(message) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            error!("Couldn't encode a message: {error}");
+            return;
+        }
+    };
+    #[cfg(feature = "net-conditions")]
+    if crate::net_conditions::hold_outgoing(socket, address, &bytes) {
+        return;
+    }
+    if let Err(error) = socket.send_to(&bytes, address) {
+        warn!("Couldn't send to {address}: {error}");
+    }
+}
+
+/// Every message waiting on `socket`, with who sent it. Malformed ones are
+/// skipped.
+fn receive<T: for<'de> Deserialize<'de>>(socket: &UdpSocket) -> Vec<(SocketAddr, T)> {
+    let datagrams = receive_datagrams(socket);
+    #[cfg(feature = "net-conditions")]
+    let datagrams = crate::net_conditions::hold_incoming(socket, datagrams);
+    datagrams
+        .into_iter()
+        .filter_map(|(address, bytes)| match serde_json::from_slice(&bytes) {
+            Ok(message) => Some((address, message)),
+            Err(error) => {
+                warn!("Ignoring a malformed message from {address}: {error}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn receive_datagrams(socket: &UdpSocket) -> Vec<(SocketAddr, Vec<u8>)> {
+    let mut buffer = [0; MAX_MESSAGE_SIZE];
+    let mut datagrams = Vec::new();
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, address)) => datagrams.push((address, buffer[..length].to_vec())),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return datagrams,
+            // On some platforms a client going away shows up as an error on
+            // the next receive. It doesn't stop the others being read.
+            Err(error) if error.kind() == ErrorKind::ConnectionReset => continue,
+            Err(error) => {
+                warn!("Couldn't receive: {error}");
+                return datagrams;
+            }
+        }
+    }
+}
+
+/// The server every game runs, and the clients that joined it.
+#[derive(Resource)]
+pub struct NetServer {
+    /// Where the server listens, or `None` in single player.
+    socket: Option<UdpSocket>,
+    clients: HashMap<SocketAddr, RemoteClient>,
+    next_id: u32,
+    /// How many clients can join at once.
+    max_players: usize,
+    /// The message of the day, told to players asking how the server is.
+    motd: String,
+    /// Where players join and respawn: the host's spawn point.
+    spawn_point: Vec3,
+    /// The mode everyone plays in: the host's, or the one a dedicated
+    /// server's config sets.
+    game_mode: GameMode,
+    /// The addresses of the clients who may change the scoreboard, besides
+    /// the host.
+    admins: Vec<IpAddr>,
+    /// Whether the server runs on its own, without a player of its own.
+    dedicated: bool,
+}
+
+/// A client that joined the server.
+struct RemoteClient {
+    id: u32,
+    /// The character the client controls.
+    entity: Entity,
+    /// Real seconds since the client was last heard from.
+    silent_for: f32,
+    /// The last input frame applied to the client's character.
+    ack: u32,
+    /// Seconds of movement the client can still make.
+    movement_credit: f32,
+    /// The replicated components last sent to the client, by player id.
+    sent_replicas: HashMap<u32, HashMap<String, serde_json::Value>>,
+}
+
+impl NetServer {
+    /// A server nobody else can reach.
+    fn local() -> Self {
+        Self {
+            socket: None,
+            clients: HashMap::new(),
+            next_id: HOST_ID + 1,
+            max_players: 0,
+            motd: DEFAULT_MOTD.to_string(),
+            spawn_point: Vec3::ZERO,
+            game_mode: GameMode::Survival,
+            admins: Vec::new(),
+            dedicated: false,
+        }
+    }
+
+    fn bind(address: SocketAddr, max_players: usize) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        info!(
+            "Serving on {} for up to {max_players} players",
+            socket.local_addr()?
+        );
+        Ok(Self {
+            socket: Some(socket),
+            max_players,
+            ..Self::local()
+        })
+    }
+}
+
+/// Marks a character moved by a client's input.
+#[derive(Component)]
+struct RemoteCharacter {
+    id: u32,
+}
+
+/// The parts of a character that a client's messages change.
+type RemoteCharacterState = (
+    &'static mut Position,
+    &'static mut Rotation,
+    &'static mut Checks,
+);
+
+/// Joins and respawns the host's players at the host's spawn point.
+fn share_spawn_point(spawn_point: Res<SpawnPoint>, mut server: ResMut<NetServer>) {
+    server.spawn_point = spawn_point.0;
+}
+
+/// Has the host's players play in the host's game mode.
+fn share_game_mode(game_mode: Res<GameMode>, mut server: ResMut<NetServer>) {
+    server.game_mode = *game_mode;
+}
+
+/// Lets clients join and leave, steps their characters through their input
+/// frames, and passes on what they say.
+fn receive_client_messages(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut server: ResMut<NetServer>,
+    assets: Option<Res<PuppetAssets>>,
+    mut chat: Option<ResMut<ChatLog>>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut characters: Query<RemoteCharacterState, With<RemoteCharacter>>,
+) {
+    let NetServer {
+        socket: Some(socket),
+        clients,
+        next_id,
+        max_players,
+        motd,
+        spawn_point,
+        game_mode,
+        admins,
+        dedicated,
+    } = &mut *server
+    else {
+        return;
+    };
+
+    for client in clients.values_mut() {
+        client.silent_for += time.delta_seconds();
+        client.movement_credit =
+            (client.movement_credit + time.delta_seconds()).min(MAX_MOVEMENT_CREDIT);
+    }
+    for (address, message) in receive::<ClientMessage>(socket) {
+        match message {
+            ClientMessage::Join => {
+                if !clients.contains_key(&address) && clients.len() >= *max_players {
+                    let reason = "the server is full".to_string();
+                    send(socket, address, &ServerMessage::Refused { reason });
+                    continue;
+                }
+                let client = clients.entry(address).or_insert_with(|| {
+                    let id = *next_id;
+                    *next_id += 1;
+                    info!("Player {id} joined from {address}");
+                    let mut character = commands.spawn((
+                        Position::at(Vec3::ZERO),
+                        Rotation { radians_y: 0.0 },
+                        Checks::default(),
+                        Replica::default(),
+                        RemoteCharacter { id },
+                    ));
+                    match assets.as_deref() {
+                        // Hosts see the players they serve.
+                        Some(assets) => character.insert(assets.bundle(id)),
+                        None => character.insert(TransformBundle::default()),
+                    };
+                    RemoteClient {
+                        id,
+                        entity: character.id(),
+                        silent_for: 0.0,
+                        ack: 0,
+                        movement_credit: 0.0,
+                        sent_replicas: HashMap::new(),
+                    }
+                });
+                client.silent_for = 0.0;
+                // Answer every join, in case an earlier welcome got lost.
+                let welcome = ServerMessage::Welcome {
+                    id: client.id,
+                    spawn_point: *spawn_point,
+                };
+                send(socket, address, &welcome);
+            }
+            ClientMessage::Input(mut frame) => {
+                let Some(client) = clients.get_mut(&address) else {
+                    continue;
+                };
+                client.silent_for = 0.0;
+                // Frames that arrive late or twice have been applied already.
+                if frame.seq <= client.ack {
+                    continue;
+                }
+                client.ack = frame.seq;
+                // A frame that isn't finite would make the character NaN for
+                // everyone, so it is dropped whole, and the client is put back
+                // where the server has it.
+                if !frame.is_finite() {
+                    continue;
+                }
+                let Ok((mut position, mut rotation, mut checks)) =
+                    characters.get_mut(client.entity)
+                else {
+                    continue;
+                };
+                // Keep the client from moving faster than a player can, or
+                // for longer than it has had time to.
+                frame.direction = frame.direction.clamp_length_max(1.0) * Vec3::new(1.0, 0.0, 1.0);
+                frame.vertical = frame.vertical.clamp(-1.0, 1.0);
+                // Or flying, unless the server is in creative.
+                frame.fly &= *game_mode == GameMode::Creative;
+                frame.dt = frame
+                    .dt
+                    .clamp(0.0, MAX_INPUT_STEP)
+                    .min(client.movement_credit);
+                client.movement_credit -= frame.dt;
+                checks.is_moving = frame.apply(&mut position, &mut rotation, *spawn_point);
+                checks.is_sprinting = frame.sprint && checks.is_moving;
+            }
+            ClientMessage::Chat(mut text) => {
+                let Some(client) = clients.get_mut(&address) else {
+                    continue;
+                };
+                client.silent_for = 0.0;
+                if let Some((cut, _)) = text.char_indices().nth(MAX_CHAT_LENGTH) {
+                    text.truncate(cut);
+                }
+                let from = player_name(client.id);
+                info!("{from}: {text}");
+                if let Some(chat) = chat.as_deref_mut() {
+                    chat.message(from.clone(), text.clone());
+                }
+                let message = ServerMessage::Chat { from, text };
+                for address in clients.keys() {
+                    send(socket, *address, &message);
+                }
+            }
+            ClientMessage::Replica(updates) => {
+                let Some(client) = clients.get_mut(&address) else {
+                    continue;
+                };
+                client.silent_for = 0.0;
+                // Hosts show the components on the character as well.
+                commands.add(receive_updates(client.entity, updates));
+            }
+            ClientMessage::Scoreboard(change) => {
+                let players = player_ids(clients, *dedicated);
+                let Some(client) = clients.get_mut(&address) else {
+                    continue;
+                };
+                client.silent_for = 0.0;
+                let notice = if !admins.contains(&address.ip()) {
+                    "Only the host and the server's admins can change the scoreboard".to_string()
+                } else {
+                    match change_scoreboard(&mut scoreboard, change, &players) {
+                        Ok(done) => {
+                            info!("{}: {done}", player_name(client.id));
+                            done
+                        }
+                        Err(error) => error,
+                    }
+                };
+                send(socket, address, &ServerMessage::Notice(notice));
+            }
+            ClientMessage::Leave => {
+                if let Some(client) = clients.get_mut(&address) {
+                    client.silent_for = f32::INFINITY;
+                }
+            }
+            ClientMessage::Status => {
+                let status = ServerStatus {
+                    motd: motd.clone(),
+                    players: clients.len(),
+                    max_players: *max_players,
+                    version: GAME_VERSION.to_string(),
+                };
+                send(socket, address, &ServerMessage::Status(status));
+            }
+        }
+    }
+
+    clients.retain(|address, client| {
+        if client.silent_for < CLIENT_TIMEOUT {
+            return true;
+        }
+        info!("Player {} at {address} left", client.id);
+        commands.entity(client.entity).despawn_recursive();
+        scoreboard.forget(client.id);
+        false
+    });
+}
+
+/// Eases the characters of the players a host serves towards where their
+/// input has taken them, the same way the local player is.
+fn ease_remote_characters(
+    time: Res<Time>,
+    mut characters: Query<(&mut Position, &Rotation, &mut Transform), With<RemoteCharacter>>,
+) {
+    let follow = follow_blend(PLAYER_FOLLOW_RATE, time.delta_seconds());
+    let turn = follow_blend(PLAYER_TURN_RATE, time.delta_seconds());
+    for (mut position, rotation, mut transform) in characters.iter_mut() {
+        position.current = position.current.lerp(position.target, follow);
+        transform.translation = position.current;
+        transform.rotation = transform
+            .rotation
+            .lerp(Quat::from_rotation_y(rotation.radians_y), turn);
+    }
+}
+
+/// Tells every client where all players are, and which of its input frames
+/// that includes.
+fn send_snapshots(
+    server: Res<NetServer>,
+    host: Query<(&Position, &Rotation, &AnimFsm), With<Player>>,
+    characters: Query<(&RemoteCharacter, &Position, &Rotation, &Checks)>,
+) {
+    let Some(socket) = &server.socket else {
+        return;
+    };
+    if server.clients.is_empty() {
+        return;
+    }
+
+    let mut players: Vec<PlayerState> = characters
+        .iter()
+        .map(|(character, position, rotation, checks)| PlayerState {
+            id: character.id,
+            position: position.target,
+            vertical_velocity: position.vertical_velocity,
+            flight_velocity: position.flight_velocity,
+            radians_y: rotation.radians_y,
+            anim_state: if checks.is_moving {
+                AnimState::Run
+            } else {
+                AnimState::Idle
+            },
+        })
+        .collect();
+    if let Ok((position, rotation, anim_fsm)) = host.get_single() {
+        players.push(PlayerState {
+            id: HOST_ID,
+            position: position.target,
+            vertical_velocity: position.vertical_velocity,
+            flight_velocity: position.flight_velocity,
+            radians_y: rotation.radians_y,
+            anim_state: anim_fsm.state(),
+        });
+    }
+
+    for (address, client) in &server.clients {
+        let snapshot = ServerMessage::Snapshot {
+            ack: client.ack,
+            game_mode: server.game_mode,
+            players: players.clone(),
+        };
+        send(socket, *address, &snapshot);
+    }
+}
+
+/// Sends each client the replicated components of the players near it that
+/// changed since it was last sent them.
+fn send_replicas(
+    mut server: ResMut<NetServer>,
+    host: Query<(&Position, &Replica), With<Player>>,
+    characters: Query<(&RemoteCharacter, &Position, &Replica)>,
+) {
+    let NetServer {
+        socket: Some(socket),
+        clients,
+        ..
+    } = &mut *server
+    else {
+        return;
+    };
+
+    let players: Vec<(u32, Vec3, &Replica)> = characters
+        .iter()
+        .map(|(character, position, replica)| (character.id, position.target, replica))
+        .chain(
+            host.iter()
+                .map(|(position, replica)| (HOST_ID, position.target, replica)),
+        )
+        .collect();
+    for (address, client) in clients.iter_mut() {
+        let Some(&(_, own, _)) = players.iter().find(|(id, ..)| *id == client.id) else {
+            continue;
+        };
+        for &(id, position, replica) in &players {
+            if id == client.id {
+                continue;
+            }
+            // Forget what was sent about players out of sight, so they are
+            // sent everything again when they come back.
+            if position.distance(own) > INTEREST_RADIUS {
+                client.sent_replicas.remove(&id);
+                continue;
+            }
+            let updates = replica.changes(client.sent_replicas.entry(id).or_default());
+            if !updates.is_empty() {
+                send(socket, *address, &ServerMessage::Replica { id, updates });
+            }
+        }
+    }
+}
+
+/// The ids of everyone playing: the clients, and the host's own player
+/// unless the server is dedicated.
+fn player_ids(clients: &HashMap<SocketAddr, RemoteClient>, dedicated: bool) -> Vec<u32> {
+    let host = (!dedicated).then_some(HOST_ID);
+    host.into_iter()
+        .chain(clients.values().map(|client| client.id))
+        .collect()
+}
+
+/// Makes a change to the scoreboard, unless it would no longer fit in a
+/// message to the clients.
+fn change_scoreboard(
+    scoreboard: &mut Scoreboard,
+    change: ScoreboardChange,
+    players: &[u32],
+) -> Result<String, String> {
+    let mut changed = scoreboard.clone();
+    let done = changed.apply(change, players)?;
+    let size = serde_json::to_vec(&ServerMessage::Scoreboard(changed.clone()))
+        .map_err(|error| error.to_string())?
+        .len();
+    if size > MAX_MESSAGE_SIZE {
+        return Err("The scoreboard is full".to_string());
+    }
+    *scoreboard = changed;
+    Ok(done)
+}
+
+/// Sends every client the scoreboard.
+fn send_scoreboard(server: Res<NetServer>, scoreboard: Res<Scoreboard>) {
+    let Some(socket) = &server.socket else {
+        return;
+    };
+    let message = ServerMessage::Scoreboard(scoreboard.clone());
+    for address in server.clients.keys() {
+        send(socket, *address, &message);
+    }
+}
+
+fn forget_sent_replicas(mut server: ResMut<NetServer>) {
+    for client in server.clients.values_mut() {
+        client.sent_replicas.clear();
+    }
+}
+
+/// Gives the local player a replica, so their components are shared.
+fn add_local_replica(
+    mut commands: Commands,
+    players: Query<Entity, (With<Player>, Without<Replica>)>,
+) {
+    for entity in players.iter() {
+        commands.entity(entity).insert(Replica::default());
+    }
+}
+
+/// The connection to a server this game joined.
+#[derive(Resource)]
+pub struct NetClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    /// The id the server gave the local player, once it answered.
+    id: Option<u32>,
+    /// Real seconds until the client asks to join again.
+    join_retry: f32,
+    /// The number of the last input frame sent.
+    seq: u32,
+    /// The last input frame the server said it applied.
+    ack: u32,
+    /// The input frames sent that the server hasn't said it applied yet,
+    /// oldest first.
+    pending: VecDeque<InputFrame>,
+    /// The local player's replicated components last sent.
+    sent_replica: HashMap<String, serde_json::Value>,
+    /// Where the server puts the local player when they respawn.
+    spawn_point: Vec3,
+    /// The mode the server has the local player play in, once it has said.
+    game_mode: Option<GameMode>,
+}
+
+impl NetClient {
+    fn connect(server: SocketAddr) -> std::io::Result<Self> {
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        info!("Joining {server}");
+        Ok(Self {
+            socket,
+            server,
+            id: None,
+            join_retry: 0.0,
+            seq: 0,
+            ack: 0,
+            pending: VecDeque::new(),
+            sent_replica: HashMap::new(),
+            spawn_point: Vec3::ZERO,
+            game_mode: None,
+        })
+    }
+
+    /// Where the server puts the local player when they respawn, once it
+    /// has said.
+    pub fn spawn_point(&self) -> Option<Vec3> {
+        self.id.map(|_| self.spawn_point)
+    }
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        send(&self.socket, self.server, &ClientMessage::Leave);
+    }
+}
+
+/// Asks to leave the server this game joined, if it did, and join the one
+/// at this address instead.
+#[derive(Event)]
+pub struct ConnectToServer(pub SocketAddr);
+
+fn connect_to_server(
+    mut commands: Commands,
+    mut requests: EventReader<ConnectToServer>,
+    server: Res<NetServer>,
+    mut chat: ResMut<ChatLog>,
+    puppets: Query<Entity, With<Puppet>>,
+) {
+    let Some(ConnectToServer(address)) = requests.read().last() else {
+        return;
+    };
+    if server.socket.is_some() {
+        chat.info("Can't join another server while hosting one");
+        return;
+    }
+    match NetClient::connect(*address) {
+        Ok(client) => {
+            // The players on the old server aren't on the new one.
+            for puppet in puppets.iter() {
+                commands.entity(puppet).despawn_recursive();
+            }
+            chat.info(format!("Joining {address}"));
+            // Replacing the old client leaves its server.
+            commands.insert_resource(client);
+        }
+        Err(error) => {
+            error!("Couldn't connect to {address}: {error}");
+            chat.info(format!("Couldn't join {address}: {error}"));
+        }
+    }
+}
+
+/// Asks servers how they are doing, for players looking for one to join.
+/// Answers arrive whenever the servers send them, so asking never waits.
+pub struct StatusQuery {
+    socket: UdpSocket,
+}
+
+impl StatusQuery {
+    pub fn new() -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket })
+    }
+
+    pub fn ask(&self, server: SocketAddr) {
+        send(&self.socket, server, &ClientMessage::Status);
+    }
+
+    /// Asks every server on the local network that listens on the default
+    /// port.
+    pub fn ask_local_network(&self) {
+        let everyone = SocketAddr::from((Ipv4Addr::BROADCAST, DEFAULT_PORT));
+        send(&self.socket, everyone, &ClientMessage::Status);
+    }
+
+    /// The answers that came in since this was last called, with who sent
+    /// them.
+    pub fn answers(&self) -> Vec<(SocketAddr, ServerStatus)> {
+        receive::<ServerMessage>(&self.socket)
+            .into_iter()
+            .filter_map(|(address, message)| match message {
+                ServerMessage::Status(status) => Some((address, status)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Turns an address a player typed in to join into a socket address, the
+/// same way as `--connect` does. This can take a while for host names.
+pub fn resolve_server(address: &str) -> Option<SocketAddr> {
+    resolve(Some(address), true)
+}
+
+/// Another player, as the latest snapshots placed them.
+#[derive(Component)]
+struct Puppet {
+    id: u32,
+    /// Where the player has been lately, oldest first.
+    samples: VecDeque<PuppetSample>,
+}
+
+/// Where a snapshot put another player.
+#[derive(Clone, Copy)]
+struct PuppetSample {
+    /// The real time the snapshot arrived, in seconds since startup.
+    time: f32,
+    position: Vec3,
+    radians_y: f32,
+}
+
+impl Puppet {
+    fn new(id: u32, sample: PuppetSample) -> Self {
+        Self {
+            id,
+            samples: VecDeque::from([sample]),
+        }
+    }
+
+    /// Where the player was at `time`, between the samples either side of
+    /// it. Forgets the samples it no longer needs.
+    fn sample_at(&mut self, time: f32) -> Option<(Vec3, Quat)> {
+        while self.samples.len() > 1 && self.samples[1].time <= time {
+            self.samples.pop_front();
+        }
+        let from = *self.samples.front()?;
+        let to = self.samples.get(1).copied().unwrap_or(from);
+        let blend = if to.time > from.time {
+            ((time - from.time) / (to.time - from.time)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Some((
+            from.position.lerp(to.position, blend),
+            Quat::from_rotation_y(from.radians_y).slerp(Quat::from_rotation_y(to.radians_y), blend),
+        ))
+    }
+}
+
+/// The scene and animations other players are shown with.
+#[derive(Resource)]
+struct PuppetAssets {
+    scene: Handle<Scene>,
+    clips: ClipSet,
+}
+
+impl PuppetAssets {
+    /// The parts that show another player in the world.
+    fn bundle(&self, id: u32) -> impl Bundle {
+        (
+            SceneBundle {
+                scene: self.scene.clone(),
+                transform: Transform::from_scale(Vec3::splat(PLAYER_SCALE)),
+                ..default()
+            },
+            AnimationBinding::new(self.clips.clone()),
+            AnimFsm::default(),
+            Nameplate::new(player_name(id), NAMEPLATE_HEIGHT),
+        )
+    }
+}
+
+fn load_puppet_assets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    let mut graph = AnimationGraph::new();
+    let [survey, run] = [0, 2].map(|index| {
+        graph.add_clip(
+            asset_server.load(GltfAssetLabel::Animation(index).from_asset("models/Fox.glb")),
+            1.0,
+            graph.root,
+        )
+    });
+    commands.insert_resource(PuppetAssets {
+        scene: asset_server.load("models/Fox.glb#Scene0"),
+        clips: ClipSet::new(graphs.add(graph))
+            .with_clip(AnimState::Idle, survey, 2.0)
+            .with_clip(AnimState::Run, run, 3.0),
+    });
+}
+
+/// The local player, as the client sends their input.
+type LocalPlayer = (Entity, &'static MovementInput, &'static Checks);
+
+/// Plays in the server's game mode.
+fn follow_server_game_mode(client: Res<NetClient>, mut game_mode: ResMut<GameMode>) {
+    if let Some(mode) = client.game_mode {
+        game_mode.set_if_neq(mode);
+    }
+}
+
+/// Asks to join until the server answers.
+fn join_server(real_time: Res<Time<Real>>, mut client: ResMut<NetClient>) {
+    if client.id.is_some() {
+        return;
+    }
+    client.join_retry -= real_time.delta_seconds();
+    if client.join_retry <= 0.0 {
+        client.join_retry = JOIN_RETRY_INTERVAL;
+        send(&client.socket, client.server, &ClientMessage::Join);
+    }
+}
+
+/// Sends the server the local player's input for this movement step,
+/// keeping hold of it until the server says it was applied.
+fn send_input_frame(
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    mut respawns: EventReader<Respawned>,
+    players: Query<LocalPlayer, With<Player>>,
+) {
+    let Ok((entity, input, checks)) = players.get_single() else {
+        return;
+    };
+    let respawned = respawns.read().any(|respawn| respawn.entity == entity);
+    if client.id.is_none() {
+        return;
+    }
+    client.seq += 1;
+    let frame = InputFrame {
+        seq: client.seq,
+        dt: time.delta_seconds(),
+        direction: input.direction,
+        jump: input.jump,
+        sprint: checks.is_sprinting,
+        fly: input.fly,
+        vertical: input.vertical,
+        damped_flight: input.damped_flight,
+        respawned,
+    };
+    push_input_frame(&mut client, frame);
+}
+
+/// Sends the server a frame of standing still every frame the local player
+/// isn't moving under their own control, such as while the world is stopped,
+/// so the server knows the client is still there.
+fn send_idle_frame(mut client: ResMut<NetClient>) {
+    if client.id.is_none() {
+        return;
+    }
+    client.seq += 1;
+    let frame = InputFrame {
+        seq: client.seq,
+        dt: 0.0,
+        direction: Vec3::ZERO,
+        jump: false,
+        sprint: false,
+        fly: false,
+        vertical: 0.0,
+        damped_flight: false,
+        respawned: false,
+    };
+    push_input_frame(&mut client, frame);
+}
+
+fn push_input_frame(client: &mut NetClient, frame: InputFrame) {
+    send(
+        &client.socket,
+        client.server,
+        &ClientMessage::Input(frame.clone()),
+    );
+    client.pending.push_back(frame);
+    if client.pending.len() > MAX_PENDING_INPUTS {
+        client.pending.pop_front();
+    }
+}
+
+/// Sends what the local player says to everyone: through the server when
+/// this game joined one, or straight to the clients when it is the server.
+fn send_chat(
+    mut messages: EventReader<SendChat>,
+    server: Res<NetServer>,
+    client: Option<Res<NetClient>>,
+    mut chat: ResMut<ChatLog>,
+) {
+    for SendChat(text) in messages.read() {
+        if let Some(client) = client.as_deref() {
+            // The server passes the message back, so it shows once it has
+            // gone round.
+            match client.id {
+                Some(_) => send(
+                    &client.socket,
+                    client.server,
+                    &ClientMessage::Chat(text.clone()),
+                ),
+                None => chat.info("Not connected to the server yet"),
+            }
+            continue;
+        }
+
+        let from = player_name(HOST_ID);
+        if let Some(socket) = &server.socket {
+            let message = ServerMessage::Chat {
+                from: from.clone(),
+                text: text.clone(),
+            };
+            for address in server.clients.keys() {
+                send(socket, *address, &message);
+            }
+        }
+        chat.message(from, text.clone());
+    }
+}
+
+/// Sends the server the local player's replicated components that changed
+/// since they were last sent.
+fn send_client_replica(mut client: ResMut<NetClient>, players: Query<&Replica, With<Player>>) {
+    let (Some(_), Ok(replica)) = (client.id, players.get_single()) else {
+        return;
+    };
+    let updates = replica.changes(&mut client.sent_replica);
+    if !updates.is_empty() {
+        send(
+            &client.socket,
+            client.server,
+            &ClientMessage::Replica(updates),
+        );
+    }
+}
+
+fn forget_sent_client_replica(mut client: ResMut<NetClient>) {
+    client.sent_replica.clear();
+}
+
+/// Asks the server for the changes to the scoreboard the local player
+/// wants, or makes them when this game is the server.
+fn send_scoreboard_requests(
+    mut requests: EventReader<ScoreboardRequest>,
+    client: Option<Res<NetClient>>,
+    server: Res<NetServer>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut chat: ResMut<ChatLog>,
+) {
+    for ScoreboardRequest(change) in requests.read() {
+        match client.as_deref() {
+            Some(client) if client.id.is_some() => send(
+                &client.socket,
+                client.server,
+                &ClientMessage::Scoreboard(change.clone()),
+            ),
+            Some(_) => chat.info("Not connected to the server yet"),
+            None => match change_scoreboard(
+                &mut scoreboard,
+                change.clone(),
+                &player_ids(&server.clients, server.dedicated),
+            ) {
+                Ok(done) | Err(done) => chat.info(done),
+            },
+        }
+    }
+}
+
+/// Takes the id the server hands out, corrects the local player by where
+/// the server put them, and places the other players where its snapshots
+/// put them, adding and removing puppets as they come and go.
+fn receive_server_messages(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut client: ResMut<NetClient>,
+    assets: Option<Res<PuppetAssets>>,
+    mut chat: ResMut<ChatLog>,
+    mut local: Query<(&mut Position, &mut Rotation), With<Player>>,
+    mut puppets: Query<(Entity, &mut Puppet, &mut AnimFsm)>,
+) {
+    let client = &mut *client;
+    for (address, message) in receive::<ServerMessage>(&client.socket) {
+        if address != client.server {
+            continue;
+        }
+        match message {
+            ServerMessage::Welcome { id, spawn_point } => {
+                client.spawn_point = spawn_point;
+                if client.id != Some(id) {
+                    info!("Joined {address} as player {id}");
+                    client.id = Some(id);
+                }
+            }
+            ServerMessage::Refused { reason } => {
+                if client.join_retry.is_finite() {
+                    error!("Couldn't join {address}: {reason}");
+                    chat.info(format!("Couldn't join the server: {reason}"));
+                }
+                // Stop asking.
+                client.join_retry = f32::INFINITY;
+            }
+            ServerMessage::Chat { from, text } => chat.message(from, text),
+            ServerMessage::Notice(text) => chat.info(text),
+            ServerMessage::Scoreboard(scoreboard) => commands.insert_resource(scoreboard),
+            // Only the server browser asks for these.
+            ServerMessage::Status(_) => {}
+            ServerMessage::Replica { id, updates } => {
+                // Puppets that aren't shown yet get everything again with
+                // the next refresh.
+                if let Some((entity, ..)) = puppets.iter().find(|(_, puppet, _)| puppet.id == id) {
+                    commands.add(apply_updates(entity, updates));
+                }
+            }
+            ServerMessage::Snapshot {
+                ack,
+                game_mode,
+                players,
+            } => {
+                // Snapshots can arrive out of order; a newer one already
+                // said more.
+                if ack < client.ack {
+                    continue;
+                }
+                client.ack = ack;
+                client.game_mode = Some(game_mode);
+                client.pending.retain(|frame| frame.seq > ack);
+
+                let (own, others): (Vec<PlayerState>, Vec<PlayerState>) = players
+                    .into_iter()
+                    .partition(|player| Some(player.id) == client.id);
+
+                // Start from where the server had the player as of the last
+                // frame it applied, and step them through the rest again.
+                // Their shown position keeps easing towards the result, so
+                // corrections don't snap.
+                if let (Some(own), Ok((mut position, mut rotation))) =
+                    (own.first(), local.get_single_mut())
+                {
+                    position.target = own.position;
+                    position.vertical_velocity = own.vertical_velocity;
+                    position.flight_velocity = own.flight_velocity;
+                    rotation.radians_y = own.radians_y;
+                    for frame in &client.pending {
+                        frame.apply(&mut position, &mut rotation, client.spawn_point);
+                    }
+                }
+
+                let others: HashMap<u32, PlayerState> = others
+                    .into_iter()
+                    .map(|player| (player.id, player))
+                    .collect();
+                let sample = |state: &PlayerState| PuppetSample {
+                    time: time.elapsed_seconds(),
+                    position: state.position,
+                    radians_y: state.radians_y,
+                };
+
+                let mut shown = Vec::new();
+                for (entity, mut puppet, mut anim_fsm) in puppets.iter_mut() {
+                    let Some(state) = others.get(&puppet.id) else {
+                        commands.entity(entity).despawn_recursive();
+                        continue;
+                    };
+                    puppet.samples.push_back(sample(state));
+                    if anim_fsm.state() != state.anim_state {
+                        anim_fsm.set(state.anim_state);
+                    }
+                    shown.push(puppet.id);
+                }
+
+                let Some(assets) = assets.as_deref() else {
+                    continue;
+                };
+                for state in others.values().filter(|state| !shown.contains(&state.id)) {
+                    commands.spawn((
+                        assets.bundle(state.id),
+                        Puppet::new(state.id, sample(state)),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Shows each puppet where its player was a moment ago, moving smoothly
+/// between snapshots.
+fn move_puppets(time: Res<Time<Real>>, mut puppets: Query<(&mut Puppet, &mut Transform)>) {
+    let shown_time = time.elapsed_seconds() - INTERPOLATION_DELAY;
+    for (mut puppet, mut transform) in puppets.iter_mut() {
+        if let Some((position, rotation)) = puppet.sample_at(shown_time) {
+            transform.translation = position;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: f32, x: f32, radians_y: f32) -> PuppetSample {
+        PuppetSample {
+            time,
+            position: Vec3::X * x,
+            radians_y,
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// An input frame as a client would send it, with its numbers written
+    /// out in JSON.
+    fn input_frame(dt: &str, direction_x: &str, vertical: &str) -> InputFrame {
+        serde_json::from_str(&format!(
+            r#"{{"seq": 1, "dt": {dt}, "direction": [{direction_x}, 0.0, 0.0], "jump": false,
+                "sprint": false, "fly": true, "vertical": {vertical}, "damped_flight": false,
+                "respawned": false}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn input_frames_must_be_finite() {
+        assert!(input_frame("0.05", "1.0", "1.0").is_finite());
+        // Too big for an f32, so infinite.
+        assert!(!input_frame("0.05", "1e39", "1.0").is_finite());
+        assert!(!input_frame("1e39", "1.0", "1.0").is_finite());
+        assert!(!input_frame("0.05", "1.0", "-1e39").is_finite());
+    }
+
+    #[test]
+    fn puppets_are_placed_between_their_samples() {
+        let mut puppet = Puppet::new(1, sample(0.0, 0.0, 0.0));
+        puppet.samples.push_back(sample(1.0, 10.0, 1.0));
+
+        let (position, rotation) = puppet.sample_at(0.5).unwrap();
+        assert!((position.x - 5.0).abs() < 1e-5);
+        assert!(rotation.angle_between(Quat::from_rotation_y(0.5)) < 1e-4);
+    }
+
+    #[test]
+    fn puppets_forget_samples_they_are_past() {
+        let mut puppet = Puppet::new(1, sample(0.0, 0.0, 0.0));
+        puppet.samples.push_back(sample(1.0, 10.0, 0.0));
+        puppet.samples.push_back(sample(2.0, 20.0, 0.0));
+
+        let (position, _) = puppet.sample_at(1.5).unwrap();
+        assert!((position.x - 15.0).abs() < 1e-5);
+        assert_eq!(puppet.samples.len(), 2);
+    }
+
+    #[test]
+    fn puppets_wait_at_their_last_sample() {
+        let mut puppet = Puppet::new(1, sample(0.0, 0.0, 0.0));
+        puppet.samples.push_back(sample(1.0, 10.0, 0.0));
+
+        let (position, _) = puppet.sample_at(3.0).unwrap();
+        assert_eq!(position.x, 10.0);
+        assert_eq!(puppet.samples.len(), 1);
+
+        // Before the first sample, it stays at the first.
+        let mut puppet = Puppet::new(1, sample(1.0, 10.0, 0.0));
+        puppet.samples.push_back(sample(2.0, 20.0, 0.0));
+        let (position, _) = puppet.sample_at(0.0).unwrap();
+        assert_eq!(position.x, 10.0);
+    }
+
+    #[test]
+    fn server_flags_override_the_defaults() {
+        let config =
+            ServerConfig::from_args(&args(&["--server", "127.0.0.1:5000", "--max-players", "4"]))
+                .unwrap();
+        assert_eq!(config.address, "127.0.0.1:5000".parse().unwrap());
+        assert_eq!(config.max_players, 4);
+        assert_eq!(config.motd, DEFAULT_MOTD);
+        assert_eq!(config.game_mode, GameMode::Survival);
+    }
+
+    #[test]
+    fn the_port_flag_sets_the_port() {
+        let config = ServerConfig::from_args(&args(&["--server", "--port", "5001"])).unwrap();
+        assert_eq!(config.address.port(), 5001);
+    }
+
+    #[test]
+    fn flags_win_over_the_config_file() {
+        let path = std::env::temp_dir().join(format!("voxel-server-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "port = 5002\nmax_players = 2\nmotd = \"Hi\"\ngame_mode = \"creative\"\nadmins = [\"10.0.0.2\"]\n",
+        )
+        .unwrap();
+        let path_arg = path.to_str().unwrap();
+
+        let config = ServerConfig::from_args(&args(&["--config", path_arg, "--max-players", "3"]));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.address.port(), 5002);
+        assert_eq!(config.max_players, 3);
+        assert_eq!(config.motd, "Hi");
+        assert_eq!(config.game_mode, GameMode::Creative);
+        assert_eq!(config.admins, [IpAddr::from([10, 0, 0, 2])]);
+    }
+
+    #[test]
+    fn bad_server_flags_are_errors() {
+        for bad in [
+            &["--port", "none"][..],
+            &["--port"],
+            &["--max-players", "many"],
+            &["--config", "no/such/server.toml"],
+        ] {
+            assert!(
+                ServerConfig::from_args(&args(bad)).is_err(),
+                "{bad:?} should be an error"
+            );
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Bad network conditions on demand, for testing the netcode without a bad
+//! network. Only built with the `net-conditions` feature.
+//!
+//! F9 opens a window to turn them on and set them while playing: latency
+//! and jitter added to every datagram, some of them lost, and a cap on how
+//! fast they can go out. They apply to everything this game sends and
+//! receives, so a host and a client each see their own conditions, and both
+//! directions are slowed down. A dedicated server has no window, so it runs
+//! without them.
+//!
+//! Held datagrams wait in a queue until they are due, then go out or are
+//! handed to whoever receives next. The queues are behind a lock rather than
+//! in a resource, because [`send`](crate::net) and `receive` only have the
+//! socket to go on.
+
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+/// The most a link can be behind on sending before more datagrams are
+/// dropped, like a router's buffer filling up.
+const MAX_QUEUE_DELAY: Duration = Duration::from_secs(1);
+
+/// The conditions in effect, and the datagrams they are holding back.
+static SIMULATOR: Mutex<Option<Simulator>> = Mutex::new(None);
+
+pub struct NetConditionsPlugin;
+
+impl Plugin for NetConditionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetConditions>()
+            .init_resource::<NetConditionsWindow>()
+            .add_systems(
+                Update,
+                (
+                    toggle_net_conditions_window,
+                    net_conditions_window.run_if(|window: Res<NetConditionsWindow>| window.open),
+                    apply_net_conditions.run_if(resource_changed::<NetConditions>),
+                )
+                    .chain(),
+            )
+            // Send what came due this frame after everything else sent.
+            .add_systems(Last, flush_outgoing);
+    }
+}
+
+/// The network conditions to simulate.
+#[derive(Resource, Clone, PartialEq)]
+pub struct NetConditions {
+    pub enabled: bool,
+    /// How long each datagram is held back, in milliseconds.
+    pub latency_ms: f32,
+    /// How much more a datagram can be held back at random, in
+    /// milliseconds. Datagrams can overtake each other by up to this much.
+    pub jitter_ms: f32,
+    /// How many datagrams in every hundred are lost.
+    pub loss_percent: f32,
+    /// How many kilobytes a second can go each way, or 0 for no cap.
+    pub bandwidth_kbps: f32,
+}
+
+impl Default for NetConditions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latency_ms: 100.0,
+            jitter_ms: 20.0,
+            loss_percent: 2.0,
+            bandwidth_kbps: 0.0,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct NetConditionsWindow {
+    open: bool,
+}
+
+/// The conditions in effect, and the datagrams held back by them.
+struct Simulator {
+    conditions: NetConditions,
+    /// State of the xorshift generator that picks the jitter and losses.
+    rng: u64,
+    outgoing: Link,
+    /// Datagrams received, by the local address of the socket they came in
+    /// on.
+    incoming: HashMap<SocketAddr, Link>,
+}
+
+/// One direction datagrams travel in, and those on their way.
+#[derive(Default)]
+struct Link {
+    /// When the link is done sending what it already has, under the
+    /// bandwidth cap.
+    busy_until: Option<Instant>,
+    held: Vec<Held>,
+}
+
+/// A datagram being held back.
+struct Held {
+    due: Instant,
+    /// The socket to send it from, for outgoing datagrams.
+    socket: Option<UdpSocket>,
+    /// Who it goes to when outgoing, or who sent it when incoming.
+    address: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+impl Simulator {
+    /// A number from 0 up to 1.
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// When a datagram of `length` bytes put on `link` now arrives, or
+    /// `None` if it is lost.
+    fn schedule(&mut self, link: LinkId, length: usize) -> Option<Instant> {
+        let conditions = self.conditions.clone();
+        if self.random() * 100.0 < conditions.loss_percent {
+            return None;
+        }
+        let jitter = self.random() * conditions.jitter_ms;
+        let now = Instant::now();
+        let link = match link {
+            LinkId::Outgoing => &mut self.outgoing,
+            LinkId::Incoming(local) => self.incoming.entry(local).or_default(),
+        };
+
+        let mut sent = now;
+        if conditions.bandwidth_kbps > 0.0 {
+            let start = link.busy_until.map_or(now, |busy| busy.max(now));
+            if start - now > MAX_QUEUE_DELAY {
+                return None;
+            }
+            sent = start
+                + Duration::from_secs_f32(length as f32 / (conditions.bandwidth_kbps * 1000.0));
+            link.busy_until = Some(sent);
+        }
+        Some(sent + Duration::from_secs_f32((conditions.latency_ms + jitter) / 1000.0))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum LinkId {
+    Outgoing,
+    Incoming(SocketAddr),
+}
+
+fn simulator() -> MutexGuard<'static, Option<Simulator>> {
+    SIMULATOR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Holds back a datagram about to be sent, if the conditions are on.
+/// Returns whether it was held back or lost, and so shouldn't be sent now.
+pub fn hold_outgoing(socket: &UdpSocket, address: SocketAddr, bytes: &[u8]) -> bool {
+    let mut simulator = simulator();
+    let Some(simulator) = simulator.as_mut() else {
+        return false;
+    };
+    let Some(due) = simulator.schedule(LinkId::Outgoing, bytes.len()) else {
+        return true;
+    };
+    match socket.try_clone() {
+        Ok(socket) => {
+            simulator.outgoing.held.push(Held {
+                due,
+                socket: Some(socket),
+                address,
+                bytes: bytes.to_vec(),
+            });
+            true
+        }
+        // Better sent on time than not at all.
+        Err(_) => false,
+    }
+}
+
+/// Holds back the datagrams just received on `socket`, and hands back those
+/// held earlier that are due now, if the conditions are on.
+pub fn hold_incoming(
+    socket: &UdpSocket,
+    received: Vec<(SocketAddr, Vec<u8>)>,
+) -> Vec<(SocketAddr, Vec<u8>)> {
+    let mut simulator = simulator();
+    let (Some(simulator), Ok(local)) = (simulator.as_mut(), socket.local_addr()) else {
+        return received;
+    };
+    for (address, bytes) in received {
+        if let Some(due) = simulator.schedule(LinkId::Incoming(local), bytes.len()) {
+            simulator
+                .incoming
+                .entry(local)
+                .or_default()
+                .held
+                .push(Held {
+                    due,
+                    socket: None,
+                    address,
+                    bytes,
+                });
+        }
+    }
+
+    let now = Instant::now();
+    let link = simulator.incoming.entry(local).or_default();
+    let (mut due, held): (Vec<Held>, Vec<Held>) =
+        link.held.drain(..).partition(|held| held.due <= now);
+    link.held = held;
+    due.sort_by_key(|held| held.due);
+    due.into_iter()
+        .map(|held| (held.address, held.bytes))
+        .collect()
+}
+
+/// Sends the held back datagrams that are due.
+fn flush_outgoing() {
+    let mut simulator = simulator();
+    let Some(simulator) = simulator.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    let (mut due, held): (Vec<Held>, Vec<Held>) = simulator
+        .outgoing
+        .held
+        .drain(..)
+        .partition(|held| held.due <= now);
+    simulator.outgoing.held = held;
+    due.sort_by_key(|held| held.due);
+    for held in due {
+        if let Some(socket) = held.socket {
+            if let Err(error) = socket.send_to(&held.bytes, held.address) {
+                warn!("Couldn't send to {}: {error}", held.address);
+            }
+        }
+    }
+}
+
+/// Puts changed conditions into effect. Turning them off sends what was
+/// being held back from sending straight away; what was being held back
+/// from arriving is lost.
+fn apply_net_conditions(conditions: Res<NetConditions>) {
+    let mut simulator = simulator();
+    if !conditions.enabled {
+        if let Some(simulator) = simulator.take() {
+            for held in simulator.outgoing.held {
+                if let Some(socket) = held.socket {
+                    let _ = socket.send_to(&held.bytes, held.address);
+                }
+            }
+        }
+        return;
+    }
+    match simulator.as_mut() {
+        Some(simulator) => simulator.conditions = conditions.clone(),
+        None => {
+            *simulator = Some(Simulator {
+                conditions: conditions.clone(),
+                rng: 0x2545_f491_4f6c_dd1d,
+                outgoing: Link::default(),
+                incoming: HashMap::new(),
+            })
+        }
+    }
+}
+
+fn toggle_net_conditions_window(
+    input: Res<ButtonInput<KeyCode>>,
+    mut window: ResMut<NetConditionsWindow>,
+) {
+    if input.just_pressed(KeyCode::F9) {
+        window.open = !window.open;
+    }
+}
+
+fn net_conditions_window(mut contexts: EguiContexts, mut conditions: ResMut<NetConditions>) {
+    let mut edited = conditions.clone();
+    egui::Window::new("Network conditions")
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(&mut edited.enabled, "Simulate");
+            ui.add_enabled_ui(edited.enabled, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut edited.latency_ms, 0.0..=1000.0)
+                        .suffix(" ms")
+                        .text("Latency"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut edited.jitter_ms, 0.0..=500.0)
+                        .suffix(" ms")
+                        .text("Jitter"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut edited.loss_percent, 0.0..=50.0)
+                        .suffix("%")
+                        .text("Packet loss"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut edited.bandwidth_kbps, 0.0..=1000.0)
+                        .logarithmic(true)
+                        .suffix(" kB/s")
+                        .text("Bandwidth (0 for no cap)"),
+                );
+            });
+        });
+    conditions.set_if_neq(edited);
+}
@@ -0,0 +1,348 @@
+//! Photo mode, toggled with P, for framing and taking screenshots.
+//!
+//! The simulation pauses and the camera detaches from the player: move it
+//! with the movement keys, Space and Shift, look around by dragging with the
+//! right mouse button, and roll with Q and E. A panel exposes the depth of
+//! field, bloom, tonemapping, field of view, time of day and game speed, and
+//! takes a screenshot of the current view at the screenshot scale set in the
+//! menu.
+//! Lens changes made here are undone when photo mode is left; depth of field
+//! changes are kept like any other setting.
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping},
+    input::mouse::MouseMotion,
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    benchmark::BenchmarkState,
+    day_night::TimeOfDay,
+    menu::PauseState,
+    net::NetClient,
+    post_processing::{FollowsSettings, PostProcessing},
+    screenshot::{TakeScreenshot, SCREENSHOT_DIR},
+    settings::ControlSettings,
+    simulation::GameSpeed,
+    MainCamera,
+};
+
+/// How fast the photo camera flies, in world units per second.
+const PHOTO_FLY_SPEED: f32 = 10.0;
+/// How far the photo camera turns per pixel of mouse movement, in radians.
+const PHOTO_LOOK_SENSITIVITY: f32 = 0.003;
+/// How fast Q and E roll the photo camera, in radians per second.
+const PHOTO_ROLL_SPEED: f32 = 1.0;
+/// How far the photo camera may pitch up or down, short of straight up or
+/// down so the controls don't flip.
+const PHOTO_MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+/// The tonemapping operators offered in the panel.
+const TONEMAPPING_CHOICES: [(Tonemapping, &str); 6] = [
+    (Tonemapping::TonyMcMapface, "Tony McMapface"),
+    (Tonemapping::AcesFitted, "ACES"),
+    (Tonemapping::AgX, "AgX"),
+    (Tonemapping::BlenderFilmic, "Blender Filmic"),
+    (Tonemapping::Reinhard, "Reinhard"),
+    (Tonemapping::None, "None"),
+];
+
+pub struct PhotoModePlugin;
+
+impl Plugin for PhotoModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<PhotoMode>()
+            .add_systems(OnEnter(PhotoMode::On), enter_photo_mode)
+            .add_systems(OnExit(PhotoMode::On), exit_photo_mode)
+            .add_systems(
+                Update,
+                (
+                    toggle_photo_mode.run_if(
+                        in_state(PauseState::Running)
+                            .and_then(not(in_state(BenchmarkState::Running))),
+                    ),
+                    (fly_photo_camera, take_photo, photo_panel)
+                        .chain()
+                        .run_if(in_state(PhotoMode::On)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether photo mode is active.
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum PhotoMode {
+    #[default]
+    Off,
+    On,
+}
+
+/// The photo camera's orientation, and the camera settings to restore when
+/// photo mode ends.
+#[derive(Resource)]
+struct PhotoSession {
+    yaw: f32,
+    pitch: f32,
+    roll: f32,
+    fov: f32,
+    post_processing: PostProcessing,
+    /// Set when a photo was asked for; the panel is hidden for the frame the
+    /// photo is taken in.
+    capture_requested: bool,
+    /// Where the last photo went, shown in the panel.
+    status: Option<String>,
+}
+
+fn toggle_photo_mode(
+    input: Res<ButtonInput<KeyCode>>,
+    state: Res<State<PhotoMode>>,
+    mut next_state: ResMut<NextState<PhotoMode>>,
+) {
+    match state.get() {
+        PhotoMode::Off if input.just_pressed(KeyCode::KeyP) => next_state.set(PhotoMode::On),
+        PhotoMode::On if input.any_just_pressed([KeyCode::KeyP, KeyCode::Escape]) => {
+            next_state.set(PhotoMode::Off)
+        }
+        _ => {}
+    }
+}
+
+/// Detaches the camera from the player and from the settings, remembering
+/// how to put it back.
+fn enter_photo_mode(
+    mut commands: Commands,
+    cameras: Query<(Entity, &Transform, &Projection, &PostProcessing), With<MainCamera>>,
+) {
+    let Ok((camera, transform, projection, post_processing)) = cameras.get_single() else {
+        return;
+    };
+    commands.entity(camera).remove::<FollowsSettings>();
+    let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+    let fov = match projection {
+        Projection::Perspective(perspective) => perspective.fov,
+        Projection::Orthographic(_) => PerspectiveProjection::default().fov,
+    };
+    commands.insert_resource(PhotoSession {
+        yaw,
+        pitch,
+        roll,
+        fov,
+        post_processing: post_processing.clone(),
+        capture_requested: false,
+        status: None,
+    });
+}
+
+/// Puts the lens and post-processing back the way they were, and lets the
+/// camera follow the settings again. The camera itself goes back to
+/// following the player on its own.
+fn exit_photo_mode(
+    mut commands: Commands,
+    session: Option<Res<PhotoSession>>,
+    mut cameras: Query<(Entity, &mut Projection, &mut PostProcessing), With<MainCamera>>,
+) {
+    let Some(session) = session else {
+        return;
+    };
+    for (camera, mut projection, mut post_processing) in cameras.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = session.fov;
+        }
+        *post_processing = session.post_processing.clone();
+        commands.entity(camera).insert(FollowsSettings);
+    }
+    commands.remove_resource::<PhotoSession>();
+}
+
+/// Flies the camera freely. It runs on real time, since game time is paused.
+fn fly_photo_camera(
+    time: Res<Time<Real>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    controls: Res<ControlSettings>,
+    mut session: ResMut<PhotoSession>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let dt = time.delta_seconds();
+
+    let look: Vec2 = mouse_motion.read().map(|motion| motion.delta).sum();
+    if mouse_buttons.pressed(MouseButton::Right) {
+        session.yaw -= look.x * PHOTO_LOOK_SENSITIVITY;
+        session.pitch = (session.pitch - look.y * PHOTO_LOOK_SENSITIVITY)
+            .clamp(-PHOTO_MAX_PITCH, PHOTO_MAX_PITCH);
+    }
+    if keyboard.pressed(KeyCode::KeyQ) {
+        session.roll += PHOTO_ROLL_SPEED * dt;
+    }
+    if keyboard.pressed(KeyCode::KeyE) {
+        session.roll -= PHOTO_ROLL_SPEED * dt;
+    }
+
+    let Ok(mut transform) = cameras.get_single_mut() else {
+        return;
+    };
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, session.yaw, session.pitch, session.roll);
+
+    let mut direction = Vec3::ZERO;
+    if keyboard.pressed(controls.move_forward) {
+        direction += *transform.forward();
+    }
+    if keyboard.pressed(controls.move_back) {
+        direction -= *transform.forward();
+    }
+    if keyboard.pressed(controls.move_left) {
+        direction -= *transform.right();
+    }
+    if keyboard.pressed(controls.move_right) {
+        direction += *transform.right();
+    }
+    if keyboard.pressed(KeyCode::Space) {
+        direction += Vec3::Y;
+    }
+    if keyboard.pressed(KeyCode::ShiftLeft) {
+        direction -= Vec3::Y;
+    }
+    transform.translation += direction.normalize_or_zero() * PHOTO_FLY_SPEED * dt;
+}
+
+/// Takes a screenshot of the frame being rendered when a photo was asked
+/// for.
+fn take_photo(session: Res<PhotoSession>, mut screenshots: EventWriter<TakeScreenshot>) {
+    if session.capture_requested {
+        screenshots.send(TakeScreenshot);
+    }
+}
+
+fn photo_panel(
+    mut contexts: EguiContexts,
+    mut session: ResMut<PhotoSession>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut game_speed: ResMut<GameSpeed>,
+    client: Option<Res<NetClient>>,
+    mut cameras: Query<(&mut Projection, &mut PostProcessing), With<MainCamera>>,
+    mut next_state: ResMut<NextState<PhotoMode>>,
+) {
+    // Keep the panel out of the photo.
+    if session.capture_requested {
+        session.capture_requested = false;
+        return;
+    }
+    let Ok((mut projection, mut post_processing)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    egui::Window::new("Photo mode")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .show(contexts.ctx_mut(), |ui| {
+            // Edit a copy so the camera's post-processing is only marked as
+            // changed when something actually changed.
+            let mut edited = post_processing.clone();
+            crate::menu::dof_controls(ui, &mut edited.dof);
+            ui.separator();
+
+            let mut bloom = edited.bloom.is_some();
+            ui.checkbox(&mut bloom, "Bloom");
+            match (bloom, edited.bloom.as_mut()) {
+                (true, Some(bloom)) => {
+                    ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=1.0).text("Intensity"));
+                }
+                (true, None) => edited.bloom = Some(BloomSettings::NATURAL),
+                (false, Some(_)) => edited.bloom = None,
+                (false, None) => {}
+            }
+
+            egui::ComboBox::from_label("Tonemapping")
+                .selected_text(tonemapping_label(edited.tonemapping))
+                .show_ui(ui, |ui| {
+                    for (choice, label) in TONEMAPPING_CHOICES {
+                        ui.selectable_value(&mut edited.tonemapping, choice, label);
+                    }
+                });
+
+            let grading = &mut edited.color_grading.global;
+            ui.add(egui::Slider::new(&mut grading.exposure, -3.0..=3.0).text("Exposure"));
+            ui.add(egui::Slider::new(&mut grading.temperature, -1.0..=1.0).text("Temperature"));
+            ui.add(egui::Slider::new(&mut grading.post_saturation, 0.0..=2.0).text("Saturation"));
+
+            if post_processing_changed(&post_processing, &edited) {
+                *post_processing = edited;
+            }
+            ui.separator();
+
+            if let Projection::Perspective(perspective) = projection.as_ref() {
+                let mut fov = perspective.fov.to_degrees();
+                ui.add(
+                    egui::Slider::new(&mut fov, 10.0..=120.0)
+                        .suffix("°")
+                        .text("Field of view"),
+                );
+                if fov != perspective.fov.to_degrees() {
+                    if let Projection::Perspective(perspective) = projection.as_mut() {
+                        perspective.fov = fov.to_radians();
+                    }
+                }
+            }
+
+            let mut hour = time_of_day.hour;
+            ui.add(egui::Slider::new(&mut hour, 0.0..=24.0).text("Time of day"));
+            if hour != time_of_day.hour {
+                time_of_day.hour = hour % 24.0;
+            }
+
+            // The world stands still in photo mode, so this sets up slow
+            // motion shots for after it. A server runs at its own speed.
+            let mut speed = game_speed.speed();
+            ui.add_enabled(
+                client.is_none(),
+                egui::Slider::new(&mut speed, GameSpeed::MIN..=GameSpeed::MAX)
+                    .logarithmic(true)
+                    .suffix("x")
+                    .text("Game speed"),
+            )
+            .on_hover_text("How fast the world runs once photo mode is left.")
+            .on_disabled_hover_text("The server runs at normal speed.");
+            if speed != game_speed.speed() {
+                game_speed.set_speed(speed);
+            }
+            ui.separator();
+
+            ui.label("Move with the movement keys, Space and Shift. Drag with the right mouse button to look and roll with Q and E.");
+            if let Some(status) = &session.status {
+                ui.label(status);
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Take photo").clicked() {
+                    session.capture_requested = true;
+                    session.status = Some(format!("Saved to {SCREENSHOT_DIR}/"));
+                }
+                if ui.button("Exit").clicked() {
+                    next_state.set(PhotoMode::Off);
+                }
+            });
+        });
+}
+
+/// Whether anything the panel can change differs between `a` and `b`.
+fn post_processing_changed(a: &PostProcessing, b: &PostProcessing) -> bool {
+    let (a_grading, b_grading) = (&a.color_grading.global, &b.color_grading.global);
+    a.dof != b.dof
+        || a.bloom.as_ref().map(|bloom| bloom.intensity)
+            != b.bloom.as_ref().map(|bloom| bloom.intensity)
+        || a.tonemapping != b.tonemapping
+        || a_grading.exposure != b_grading.exposure
+        || a_grading.temperature != b_grading.temperature
+        || a_grading.post_saturation != b_grading.post_saturation
+}
+
+fn tonemapping_label(tonemapping: Tonemapping) -> &'static str {
+    TONEMAPPING_CHOICES
+        .iter()
+        .find(|(choice, _)| *choice == tonemapping)
+        .map_or("Other", |(_, label)| label)
+}
@@ -0,0 +1,110 @@
+//! Post-processing owned by each camera rather than by the whole game.
+//!
+//! A camera's [`PostProcessing`] holds its depth of field, bloom,
+//! tonemapping and color grading, and is written into the camera's render
+//! components whenever it changes. Cameras marked [`FollowsSettings`] take
+//! their depth of field and bloom from the player's settings; any other
+//! camera, such as the photo mode camera or a second player's view, keeps
+//! its own.
+
+use bevy::{
+    core_pipeline::{bloom::BloomSettings, dof::DepthOfFieldSettings, tonemapping::Tonemapping},
+    prelude::*,
+    render::view::ColorGrading,
+};
+
+use crate::{governor::PerformanceGovernor, settings::GraphicsSettings, AppSettings};
+
+pub struct PostProcessingPlugin;
+
+impl Plugin for PostProcessingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, (follow_settings, apply_post_processing).chain());
+    }
+}
+
+/// The post-processing a camera renders with.
+#[derive(Component, Clone)]
+pub struct PostProcessing {
+    pub dof: AppSettings,
+    /// The camera's bloom, or `None` for no bloom.
+    pub bloom: Option<BloomSettings>,
+    pub tonemapping: Tonemapping,
+    pub color_grading: ColorGrading,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            dof: AppSettings::default(),
+            bloom: Some(BloomSettings::NATURAL),
+            tonemapping: Tonemapping::TonyMcMapface,
+            color_grading: ColorGrading::default(),
+        }
+    }
+}
+
+/// Marks a camera whose depth of field and bloom follow the player's
+/// settings.
+#[derive(Component)]
+pub struct FollowsSettings;
+
+/// Copies the player's settings into the cameras that follow them, when the
+/// settings change or a camera starts following them.
+fn follow_settings(
+    app_settings: Res<AppSettings>,
+    graphics_settings: Res<GraphicsSettings>,
+    mut cameras: Query<(&mut PostProcessing, Ref<FollowsSettings>)>,
+) {
+    let settings_changed = app_settings.is_changed() || graphics_settings.is_changed();
+    for (mut post_processing, follows) in cameras.iter_mut() {
+        if !settings_changed && !follows.is_added() {
+            continue;
+        }
+
+        post_processing.dof = *app_settings;
+        match (graphics_settings.bloom, post_processing.bloom.is_some()) {
+            (true, false) => post_processing.bloom = Some(BloomSettings::NATURAL),
+            (false, true) => post_processing.bloom = None,
+            _ => {}
+        }
+    }
+}
+
+/// Writes each camera's post-processing into its render components whenever
+/// it or the quality the governor allows change.
+fn apply_post_processing(
+    mut commands: Commands,
+    governor: Res<PerformanceGovernor>,
+    cameras: Query<(Entity, Ref<PostProcessing>)>,
+) {
+    for (camera, post_processing) in cameras.iter() {
+        if !post_processing.is_changed() && !governor.is_changed() {
+            continue;
+        }
+
+        let mut camera = commands.entity(camera);
+        let dof = post_processing.dof;
+        match Option::<DepthOfFieldSettings>::from(dof) {
+            Some(mut dof_settings) => {
+                governor.dof_cap.min(dof.quality).apply(&mut dof_settings);
+                camera.insert(dof_settings);
+            }
+            None => {
+                camera.remove::<DepthOfFieldSettings>();
+            }
+        }
+        match &post_processing.bloom {
+            Some(bloom) => {
+                camera.insert(bloom.clone());
+            }
+            None => {
+                camera.remove::<BloomSettings>();
+            }
+        }
+        camera.insert((
+            post_processing.tonemapping,
+            post_processing.color_grading.clone(),
+        ));
+    }
+}
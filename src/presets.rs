@@ -0,0 +1,235 @@
+//! Graphics presets, and picking one to suit the hardware on first launch.
+//!
+//! When there is no config file yet, the GPU, CPU core count and memory are
+//! probed and a dialog offers the preset they suggest, which the player can
+//! accept, swap for another, or skip to keep the defaults. The graphics tab
+//! of the settings menu can apply any preset later on.
+
+use std::thread;
+
+use bevy::{core_pipeline::dof::DepthOfFieldMode, prelude::*, render::renderer::RenderAdapterInfo};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{config::FirstRun, governor::DofQuality, settings::GraphicsSettings, AppSettings};
+
+pub struct PresetsPlugin;
+
+impl Plugin for PresetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, detect_hardware.run_if(resource_exists::<FirstRun>))
+            .add_systems(
+                Update,
+                preset_dialog.run_if(resource_exists::<PresetDialog>),
+            );
+    }
+}
+
+/// A set of graphics settings to match some class of hardware.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphicsPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl GraphicsPreset {
+    pub const ALL: [GraphicsPreset; 4] = [
+        GraphicsPreset::Low,
+        GraphicsPreset::Medium,
+        GraphicsPreset::High,
+        GraphicsPreset::Ultra,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GraphicsPreset::Low => "Low",
+            GraphicsPreset::Medium => "Medium",
+            GraphicsPreset::High => "High",
+            GraphicsPreset::Ultra => "Ultra",
+        }
+    }
+
+    /// Sets the view distance, effects and depth of field to this preset's,
+    /// leaving focus and everything else alone.
+    pub fn apply(self, graphics: &mut GraphicsSettings, app: &mut AppSettings) {
+        let (render_distance, effects, reflections, dof, quality) = match self {
+            GraphicsPreset::Low => (300.0, false, false, None, DofQuality::Low),
+            GraphicsPreset::Medium => (
+                600.0,
+                true,
+                false,
+                Some(DepthOfFieldMode::Gaussian),
+                DofQuality::Medium,
+            ),
+            GraphicsPreset::High => (
+                1000.0,
+                true,
+                true,
+                Some(DepthOfFieldMode::Bokeh),
+                DofQuality::High,
+            ),
+            GraphicsPreset::Ultra => (
+                2000.0,
+                true,
+                true,
+                Some(DepthOfFieldMode::Bokeh),
+                DofQuality::High,
+            ),
+        };
+        graphics.render_distance = render_distance;
+        graphics.shadows = effects;
+        graphics.clouds = effects;
+        graphics.bloom = effects && self != GraphicsPreset::Medium;
+        graphics.reflections = reflections;
+        app.mode = dof;
+        app.quality = quality;
+    }
+}
+
+/// What kind of GPU renders the game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum GpuKind {
+    Discrete,
+    Integrated,
+    /// Rendering on the CPU, or on a virtual GPU.
+    Software,
+    Unknown,
+}
+
+/// What the game found out about the machine it runs on.
+struct Hardware {
+    gpu: String,
+    gpu_kind: GpuKind,
+    cores: usize,
+    memory_gib: Option<f32>,
+}
+
+impl Hardware {
+    fn detect(adapter: Option<&RenderAdapterInfo>) -> Self {
+        // Bevy doesn't re-export wgpu's device type, so go by its name.
+        let gpu_kind = match adapter.map(|adapter| format!("{:?}", adapter.device_type)) {
+            Some(kind) if kind == "DiscreteGpu" => GpuKind::Discrete,
+            Some(kind) if kind == "IntegratedGpu" => GpuKind::Integrated,
+            Some(kind) if kind == "Cpu" || kind == "VirtualGpu" => GpuKind::Software,
+            _ => GpuKind::Unknown,
+        };
+        Self {
+            gpu: adapter.map_or_else(|| "Unknown GPU".to_string(), |adapter| adapter.name.clone()),
+            gpu_kind,
+            cores: thread::available_parallelism().map_or(1, |cores| cores.get()),
+            memory_gib: total_memory_gib(),
+        }
+    }
+
+    /// The preset this hardware should run smoothly at.
+    fn recommended_preset(&self) -> GraphicsPreset {
+        // Assume a middling amount of memory where it can't be found out.
+        let memory = self.memory_gib.unwrap_or(8.0);
+        match self.gpu_kind {
+            GpuKind::Software => GraphicsPreset::Low,
+            GpuKind::Integrated if self.cores >= 4 && memory >= 8.0 => GraphicsPreset::Medium,
+            GpuKind::Integrated => GraphicsPreset::Low,
+            GpuKind::Discrete if self.cores >= 8 && memory >= 16.0 => GraphicsPreset::Ultra,
+            GpuKind::Discrete if self.cores >= 4 => GraphicsPreset::High,
+            GpuKind::Discrete | GpuKind::Unknown => GraphicsPreset::Medium,
+        }
+    }
+}
+
+/// The machine's memory in GiB, where the platform tells.
+fn total_memory_gib() -> Option<f32> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib: f32 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib / (1024.0 * 1024.0))
+}
+
+/// The first launch dialog, with the preset the player has picked in it.
+#[derive(Resource)]
+struct PresetDialog {
+    hardware: Hardware,
+    recommended: GraphicsPreset,
+    choice: GraphicsPreset,
+}
+
+fn detect_hardware(mut commands: Commands, adapter: Option<Res<RenderAdapterInfo>>) {
+    let hardware = Hardware::detect(adapter.as_deref());
+    let recommended = hardware.recommended_preset();
+    info!(
+        "Detected {} ({:?}), {} cores, {} of memory; recommending the {} preset",
+        hardware.gpu,
+        hardware.gpu_kind,
+        hardware.cores,
+        hardware.memory_gib.map_or_else(
+            || "unknown amount".to_string(),
+            |gib| format!("{gib:.1} GiB")
+        ),
+        recommended.label()
+    );
+    commands.insert_resource(PresetDialog {
+        hardware,
+        recommended,
+        choice: recommended,
+    });
+}
+
+/// Asks the player to confirm the preset picked for their hardware.
+fn preset_dialog(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut dialog: ResMut<PresetDialog>,
+    mut graphics: ResMut<GraphicsSettings>,
+    mut app_settings: ResMut<AppSettings>,
+) {
+    let mut answered = false;
+    egui::Window::new("Graphics preset")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            let hardware = &dialog.hardware;
+            ui.label(format!("GPU: {}", hardware.gpu));
+            ui.label(format!("CPU cores: {}", hardware.cores));
+            if let Some(gib) = hardware.memory_gib {
+                ui.label(format!("Memory: {gib:.1} GiB"));
+            }
+            ui.label(format!(
+                "The {} preset is recommended for this machine.",
+                dialog.recommended.label()
+            ));
+            ui.separator();
+
+            let mut choice = dialog.choice;
+            egui::ComboBox::from_label("Preset")
+                .selected_text(choice.label())
+                .show_ui(ui, |ui| {
+                    for preset in GraphicsPreset::ALL {
+                        ui.selectable_value(&mut choice, preset, preset.label());
+                    }
+                });
+            dialog.choice = choice;
+
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    choice.apply(&mut graphics, &mut app_settings);
+                    answered = true;
+                }
+                if ui.button("Keep defaults").clicked() {
+                    answered = true;
+                }
+            });
+            ui.label("Presets can be changed later in the graphics settings.");
+        });
+
+    if answered {
+        commands.remove_resource::<PresetDialog>();
+        commands.remove_resource::<FirstRun>();
+    }
+}
@@ -0,0 +1,437 @@
+//! Rocks and arrows the player throws at mobs.
+//!
+//! Y toggles throw mode. While in it, left click or the attack key throws
+//! one of the selected hotbar item, if it is something throwable, instead of
+//! swinging, and in creative the item isn't used up: cobblestone is thrown
+//! as a rock, arrows as arrows. A reticle on the ground marks where the
+//! throw would land. Projectiles fly under gravity, drift with the wind,
+//! hurt and push back the first mob they hit, and stick in the ground where
+//! they land, throwing up a puff of debris that the wind blows along.
+
+use std::f32::consts::TAU;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::EguiContexts;
+
+use crate::{
+    game_mode::GameMode,
+    health::{Dead, Health},
+    inventory::{Inventory, ItemId},
+    menu::PauseState,
+    mobs::Mob,
+    photo_mode::PhotoMode,
+    settings::ControlSettings,
+    simulation::WorldSimulation,
+    wind::Wind,
+    Player, Position, Rotation,
+};
+
+/// The downward acceleration of projectiles and debris.
+const PROJECTILE_GRAVITY: f32 = -20.0;
+/// How much each unit of wind speed speeds projectiles up along the wind,
+/// per second.
+const PROJECTILE_WIND_PUSH: f32 = 0.5;
+/// How high above the player's feet projectiles are thrown from.
+const THROW_HEIGHT: f32 = 1.0;
+/// How close a projectile has to pass to a mob's middle to hit it.
+const HIT_RADIUS: f32 = 0.6;
+/// How high above its feet a mob's middle is.
+const MOB_HIT_HEIGHT: f32 = 0.4;
+/// How far a hit pushes a mob along the projectile's path.
+const KNOCKBACK_DISTANCE: f32 = 1.0;
+/// How long projectiles stay stuck in the ground, in seconds.
+const STUCK_TIME: f32 = 10.0;
+/// How many bits of debris a landing throws up.
+const DEBRIS_COUNT: usize = 6;
+const DEBRIS_SIZE: f32 = 0.08;
+const DEBRIS_SPEED: f32 = 3.0;
+/// How long debris lasts, in seconds.
+const DEBRIS_TIME: f32 = 0.5;
+/// Like `PROJECTILE_WIND_PUSH`, but for debris, which is lighter.
+const DEBRIS_WIND_PUSH: f32 = 2.0;
+const RETICLE_RADIUS: f32 = 0.4;
+const RETICLE_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.6);
+const ROCK_COLOR: Color = Color::srgb(0.45, 0.45, 0.45);
+const ARROW_COLOR: Color = Color::srgb(0.55, 0.4, 0.25);
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ThrowMode>()
+            .add_systems(Startup, setup_projectile_assets)
+            .add_systems(
+                Update,
+                (
+                    (toggle_throw_mode, throw_projectile.run_if(throwing))
+                        .chain()
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                    (fly_projectiles, update_debris)
+                        .chain()
+                        .in_set(WorldSimulation),
+                    update_reticle,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether the player throws rather than swings when attacking.
+#[derive(Resource, Default)]
+pub struct ThrowMode(bool);
+
+/// Whether the player is in throw mode.
+pub fn throwing(mode: Res<ThrowMode>) -> bool {
+    mode.0
+}
+
+/// The things that can be thrown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ProjectileKind {
+    Rock,
+    Arrow,
+}
+
+impl ProjectileKind {
+    /// What the item `item` is thrown as, if it can be thrown at all.
+    fn for_item(item: &ItemId) -> Option<Self> {
+        match item.0.as_str() {
+            "cobblestone" => Some(ProjectileKind::Rock),
+            "arrow" => Some(ProjectileKind::Arrow),
+            _ => None,
+        }
+    }
+
+    /// How fast the projectile leaves the player's hand.
+    fn speed(self) -> f32 {
+        match self {
+            ProjectileKind::Rock => 16.0,
+            ProjectileKind::Arrow => 28.0,
+        }
+    }
+
+    /// How far above the horizontal the projectile is thrown, in radians.
+    fn pitch(self) -> f32 {
+        match self {
+            ProjectileKind::Rock => 0.35,
+            ProjectileKind::Arrow => 0.12,
+        }
+    }
+
+    /// How much health a hit takes.
+    fn damage(self) -> f32 {
+        match self {
+            ProjectileKind::Rock => 3.0,
+            ProjectileKind::Arrow => 5.0,
+        }
+    }
+
+    /// The velocity of the projectile thrown by someone facing `facing`.
+    fn launch_velocity(self, facing: Vec3) -> Vec3 {
+        (facing * self.pitch().cos() + Vec3::Y * self.pitch().sin()) * self.speed()
+    }
+}
+
+/// A thrown rock or arrow.
+#[derive(Component)]
+struct Projectile {
+    kind: ProjectileKind,
+    velocity: Vec3,
+    /// Seconds the projectile has been stuck in the ground, once it landed.
+    stuck: Option<f32>,
+}
+
+/// A bit of debris thrown up by a landing projectile.
+#[derive(Component)]
+struct Debris {
+    velocity: Vec3,
+    age: f32,
+}
+
+/// Marks the ring on the ground showing where a throw would land.
+#[derive(Component)]
+struct Reticle;
+
+/// The meshes and materials projectiles and their effects are drawn with.
+#[derive(Resource)]
+struct ProjectileAssets {
+    rock: Handle<Mesh>,
+    arrow: Handle<Mesh>,
+    debris: Handle<Mesh>,
+    rock_material: Handle<StandardMaterial>,
+    arrow_material: Handle<StandardMaterial>,
+}
+
+impl ProjectileAssets {
+    fn look(&self, kind: ProjectileKind) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+        match kind {
+            ProjectileKind::Rock => (self.rock.clone(), self.rock_material.clone()),
+            ProjectileKind::Arrow => (self.arrow.clone(), self.arrow_material.clone()),
+        }
+    }
+}
+
+fn setup_projectile_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ProjectileAssets {
+        rock: meshes.add(Sphere::new(0.15)),
+        arrow: meshes.add(Cuboid::new(0.05, 0.05, 0.6)),
+        debris: meshes.add(Cuboid::from_length(DEBRIS_SIZE)),
+        rock_material: materials.add(ROCK_COLOR),
+        arrow_material: materials.add(ARROW_COLOR),
+    });
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Annulus::new(RETICLE_RADIUS * 0.7, RETICLE_RADIUS)),
+            material: materials.add(StandardMaterial {
+                base_color: RETICLE_COLOR,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            // Lying flat, just above the ground.
+            transform: Transform::from_xyz(0.0, 0.01, 0.0)
+                .with_rotation(Quat::from_rotation_x(-TAU / 4.0)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        Reticle,
+    ));
+}
+
+/// The player, as long as they are alive to throw.
+type LivingPlayer = (With<Player>, Without<Dead>);
+
+/// The living mobs a projectile can hit.
+type Targets = (With<Mob>, Without<Dead>);
+
+/// The horizontal direction the player faces.
+fn facing(rotation: &Rotation) -> Vec3 {
+    Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos())
+}
+
+fn toggle_throw_mode(input: Res<ButtonInput<KeyCode>>, mut mode: ResMut<ThrowMode>) {
+    if input.just_pressed(KeyCode::KeyY) {
+        mode.0 = !mode.0;
+        info!("Throw mode {}", if mode.0 { "on" } else { "off" });
+    }
+}
+
+/// The buttons that throw.
+#[derive(SystemParam)]
+struct ThrowButtons<'w> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    controls: Res<'w, ControlSettings>,
+}
+
+/// Throws one of the selected item when the attack button is pressed in
+/// throw mode.
+fn throw_projectile(
+    mut commands: Commands,
+    buttons: ThrowButtons,
+    game_mode: Res<GameMode>,
+    mut contexts: EguiContexts,
+    assets: Res<ProjectileAssets>,
+    mut players: Query<(&Position, &Rotation, &mut Inventory), LivingPlayer>,
+) {
+    // Clicks on windows such as the crafting grid aren't throws.
+    let clicked = buttons.mouse_buttons.just_pressed(MouseButton::Left)
+        && !contexts.ctx_mut().wants_pointer_input();
+    if !(clicked || buttons.keys.just_pressed(buttons.controls.attack)) {
+        return;
+    }
+    let Ok((position, rotation, mut inventory)) = players.get_single_mut() else {
+        return;
+    };
+
+    let Some(kind) = inventory
+        .selected_stack()
+        .and_then(|stack| ProjectileKind::for_item(&stack.item))
+    else {
+        info!("Select cobblestone or arrows to throw");
+        return;
+    };
+    if *game_mode == GameMode::Survival {
+        inventory.take_selected(1);
+    }
+
+    let velocity = kind.launch_velocity(facing(rotation));
+    let (mesh, material) = assets.look(kind);
+    commands.spawn((
+        PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(position.current + Vec3::Y * THROW_HEIGHT)
+                .looking_to(velocity, Vec3::Y),
+            ..default()
+        },
+        Projectile {
+            kind,
+            velocity,
+            stuck: None,
+        },
+    ));
+}
+
+/// Moves projectiles along their arcs, hurting the first mob in their way,
+/// and leaves them stuck in the ground where they land.
+fn fly_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<ProjectileAssets>,
+    wind: Res<Wind>,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+    mut mobs: Query<(&mut Position, &mut Health), Targets>,
+) {
+    let dt = time.delta_seconds();
+    let acceleration = Vec3::Y * PROJECTILE_GRAVITY + wind_push(&wind, PROJECTILE_WIND_PUSH);
+
+    for (entity, mut projectile, mut transform) in projectiles.iter_mut() {
+        if let Some(stuck) = projectile.stuck.as_mut() {
+            *stuck += dt;
+            if *stuck > STUCK_TIME {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+
+        projectile.velocity += acceleration * dt;
+        let start = transform.translation;
+        let end = start + projectile.velocity * dt;
+
+        // Fast projectiles cover more than a mob's width in a frame, so test
+        // the whole step rather than where it ends.
+        let hit = mobs.iter_mut().find(|(position, _)| {
+            let middle = position.current + Vec3::Y * MOB_HIT_HEIGHT;
+            closest_point_on_segment(start, end, middle).distance(middle) < HIT_RADIUS
+        });
+        if let Some((mut position, mut health)) = hit {
+            health.damage(projectile.kind.damage());
+            let push = (projectile.velocity * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+            position.target += push * KNOCKBACK_DISTANCE;
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        if end.y <= 0.0 {
+            transform.translation = end.with_y(0.0);
+            projectile.stuck = Some(0.0);
+            spawn_debris(
+                &mut commands,
+                &assets,
+                projectile.kind,
+                transform.translation,
+            );
+            continue;
+        }
+        transform.translation = end;
+        transform.look_to(projectile.velocity, Vec3::Y);
+    }
+}
+
+/// The point of the segment from `start` to `end` closest to `point`.
+fn closest_point_on_segment(start: Vec3, end: Vec3, point: Vec3) -> Vec3 {
+    let segment = end - start;
+    let t = (point - start).dot(segment) / segment.length_squared().max(f32::EPSILON);
+    start + segment * t.clamp(0.0, 1.0)
+}
+
+/// Throws up a ring of debris where a projectile landed.
+fn spawn_debris(
+    commands: &mut Commands,
+    assets: &ProjectileAssets,
+    kind: ProjectileKind,
+    spot: Vec3,
+) {
+    let (_, material) = assets.look(kind);
+    for index in 0..DEBRIS_COUNT {
+        let angle = index as f32 / DEBRIS_COUNT as f32 * TAU;
+        let velocity = Vec3::new(angle.cos(), 1.5, angle.sin()) * DEBRIS_SPEED;
+        commands.spawn((
+            PbrBundle {
+                mesh: assets.debris.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(spot),
+                ..default()
+            },
+            Debris { velocity, age: 0.0 },
+        ));
+    }
+}
+
+/// Lets debris fall, drift with the wind and shrink away.
+fn update_debris(
+    mut commands: Commands,
+    time: Res<Time>,
+    wind: Res<Wind>,
+    mut debris: Query<(Entity, &mut Debris, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    let acceleration = Vec3::Y * PROJECTILE_GRAVITY + wind_push(&wind, DEBRIS_WIND_PUSH);
+
+    for (entity, mut bit, mut transform) in debris.iter_mut() {
+        bit.age += dt;
+        if bit.age > DEBRIS_TIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        bit.velocity += acceleration * dt;
+        transform.translation += bit.velocity * dt;
+        transform.translation.y = transform.translation.y.max(DEBRIS_SIZE / 2.0);
+        transform.scale = Vec3::splat(1.0 - bit.age / DEBRIS_TIME);
+    }
+}
+
+/// How the wind speeds something up along the ground, `push` per unit of
+/// wind speed.
+fn wind_push(wind: &Wind, push: f32) -> Vec3 {
+    Vec3::new(wind.direction.x, 0.0, wind.direction.y) * wind.strength * push
+}
+
+/// Shows the reticle where the selected item would land if thrown now, in
+/// throw mode.
+fn update_reticle(
+    mode: Res<ThrowMode>,
+    wind: Res<Wind>,
+    players: Query<(&Position, &Rotation, &Inventory), LivingPlayer>,
+    mut reticles: Query<(&mut Transform, &mut Visibility), With<Reticle>>,
+) {
+    let Ok((mut transform, mut visibility)) = reticles.get_single_mut() else {
+        return;
+    };
+
+    let kind =
+        players
+            .get_single()
+            .ok()
+            .filter(|_| mode.0)
+            .and_then(|(position, rotation, inventory)| {
+                let stack = inventory.selected_stack()?;
+                Some((position, rotation, ProjectileKind::for_item(&stack.item)?))
+            });
+    let Some((position, rotation, kind)) = kind else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+
+    // Where the arc comes back down to the ground: solve
+    // height + vy t + g t² / 2 = 0 for the later root, then drift with the
+    // wind for that long as well.
+    let velocity = kind.launch_velocity(facing(rotation));
+    let height = position.current.y + THROW_HEIGHT;
+    let a = PROJECTILE_GRAVITY / 2.0;
+    let discriminant = velocity.y * velocity.y - 4.0 * a * height;
+    let flight_time = (-velocity.y - discriminant.max(0.0).sqrt()) / (2.0 * a);
+    let landing = position.current
+        + velocity * flight_time
+        + wind_push(&wind, PROJECTILE_WIND_PUSH) * flight_time * flight_time / 2.0;
+
+    transform.translation = Vec3::new(landing.x, 0.01, landing.z);
+    visibility.set_if_neq(Visibility::Inherited);
+}
@@ -0,0 +1,506 @@
+//! Quests for the player to work through, following the definitions in
+//! `assets/quests/*.ron`.
+//!
+//! A quest is a list of objectives: picking up some number of an item,
+//! reaching a place, or defeating some number of a kind of mob. Quests start
+//! as soon as the quests they follow are complete, or right away if they
+//! follow none, unless they wait to be given, such as by an NPC through
+//! [`StartQuest`]. They are complete once all their objectives are.
+//! Objectives only count what happens while their quest is under way.
+//!
+//! J opens the journal, which lists the quests under way and those completed,
+//! and picks the quest to track. The tracked quest's objectives are shown at
+//! the side of the screen. Progress is saved to `quests/progress.json`; like
+//! mobs and items, quests are each player's own.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    health::Died,
+    inventory::ItemId,
+    item_drop::ItemsPickedUp,
+    menu::PauseState,
+    mobs::{Mob, MobKind},
+    photo_mode::PhotoMode,
+    Player, Position,
+};
+
+/// The directory quests are loaded from.
+const QUEST_DIR: &str = "assets/quests";
+/// Where the player's progress through the quests is saved.
+const PROGRESS_PATH: &str = "quests/progress.json";
+
+pub struct QuestsPlugin;
+
+impl Plugin for QuestsPlugin {
+    fn build(&self, app: &mut App) {
+        let quests = load_quests();
+        let mut log = load_quest_log();
+        log.fit(&quests);
+        app.insert_resource(quests)
+            .insert_resource(log)
+            .init_resource::<Journal>()
+            .add_event::<StartQuest>()
+            .add_systems(Startup, spawn_quest_hud)
+            .add_systems(
+                Update,
+                (
+                    toggle_journal
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                    start_quests,
+                    advance_quests,
+                    journal.run_if(|journal: Res<Journal>| journal.open),
+                    update_quest_hud,
+                    save_quest_log.run_if(resource_changed::<QuestLog>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A quest, as written in a quest file.
+#[derive(Deserialize)]
+struct Quest {
+    /// The name progress is saved under. Changing it starts the quest over.
+    id: String,
+    title: String,
+    description: String,
+    /// The quests that have to be complete before this one starts.
+    #[serde(default)]
+    after: Vec<String>,
+    /// Whether the quest waits for a [`StartQuest`] rather than starting
+    /// on its own.
+    #[serde(default)]
+    given: bool,
+    objectives: Vec<Objective>,
+}
+
+/// Something a quest asks the player to do.
+#[derive(Deserialize)]
+enum Objective {
+    /// Pick up `count` of an item.
+    Collect { item: ItemId, count: u32 },
+    /// Come within `radius` of `position`, which is called `place`.
+    Reach {
+        place: String,
+        position: Vec3,
+        radius: f32,
+    },
+    /// Defeat `count` mobs of a kind.
+    Defeat { mob: MobKind, count: u32 },
+}
+
+impl Objective {
+    /// The progress at which the objective is met.
+    fn goal(&self) -> u32 {
+        match self {
+            Objective::Collect { count, .. } | Objective::Defeat { count, .. } => *count,
+            Objective::Reach { .. } => 1,
+        }
+    }
+
+    /// What the objective asks for, and how far along it is.
+    fn describe(&self, progress: u32) -> String {
+        let check = if progress >= self.goal() {
+            "[x]"
+        } else {
+            "[ ]"
+        };
+        match self {
+            Objective::Collect { item, count } => {
+                format!("{check} Pick up {}: {progress}/{count}", item.label())
+            }
+            Objective::Reach { place, .. } => format!("{check} Reach {place}"),
+            Objective::Defeat { mob, count } => {
+                format!("{check} Defeat {}: {progress}/{count}", mob.label())
+            }
+        }
+    }
+}
+
+/// Every quest that was loaded.
+#[derive(Resource)]
+struct Quests(Vec<Quest>);
+
+/// Asks for the quest with this id to start, if the quests it follows are
+/// complete.
+#[derive(Event)]
+pub struct StartQuest(pub String);
+
+/// How far the player is with a quest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum QuestState {
+    NotStarted,
+    Active,
+    Completed,
+}
+
+/// How far the player has got with each quest they started, and which one
+/// they track.
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct QuestLog {
+    /// Progress on the quests started, by quest id.
+    quests: HashMap<String, QuestProgress>,
+    /// The id of the quest shown on the HUD.
+    tracked: Option<String>,
+}
+
+/// How far along a started quest is.
+#[derive(Default, Serialize, Deserialize)]
+struct QuestProgress {
+    /// The progress on each objective, in order.
+    objectives: Vec<u32>,
+    completed: bool,
+}
+
+impl QuestLog {
+    pub fn state(&self, id: &str) -> QuestState {
+        match self.quests.get(id) {
+            None => QuestState::NotStarted,
+            Some(progress) if progress.completed => QuestState::Completed,
+            Some(_) => QuestState::Active,
+        }
+    }
+
+    /// Whether the quest with this id was completed.
+    fn completed(&self, id: &str) -> bool {
+        self.quests
+            .get(id)
+            .is_some_and(|progress| progress.completed)
+    }
+
+    /// The progress of the quest with this id, if it is under way.
+    fn active(&self, id: &str) -> Option<&QuestProgress> {
+        self.quests.get(id).filter(|progress| !progress.completed)
+    }
+
+    /// Matches the saved progress up with quests that gained or lost
+    /// objectives since it was saved, and stops tracking quests that are
+    /// gone.
+    fn fit(&mut self, quests: &Quests) {
+        for quest in quests.0.iter() {
+            if let Some(progress) = self.quests.get_mut(&quest.id) {
+                progress.objectives.resize(quest.objectives.len(), 0);
+            }
+        }
+        if let Some(tracked) = &self.tracked {
+            if !quests.0.iter().any(|quest| quest.id == *tracked) {
+                self.tracked = None;
+            }
+        }
+    }
+
+    /// Tracks the first quest under way, if there is one.
+    fn track_next(&mut self, quests: &Quests) {
+        self.tracked = quests
+            .0
+            .iter()
+            .find(|quest| self.active(&quest.id).is_some())
+            .map(|quest| quest.id.clone());
+    }
+}
+
+/// Whether the journal is open.
+#[derive(Resource, Default)]
+struct Journal {
+    open: bool,
+}
+
+/// Marks the text that shows the tracked quest.
+#[derive(Component)]
+struct QuestHud;
+
+/// Reads every quest file, skipping and warning about those that can't be
+/// read.
+fn load_quests() -> Quests {
+    let entries = match fs::read_dir(QUEST_DIR) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to read {QUEST_DIR}, no quests loaded: {error}");
+            return Quests(Vec::new());
+        }
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    paths.sort();
+
+    let quests = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path)
+                .map_err(|error| warn!("Failed to read {}: {error}", path.display()))
+                .ok()?;
+            ron::from_str(&text)
+                .map_err(|error| warn!("Failed to parse {}: {error}", path.display()))
+                .ok()
+        })
+        .collect();
+    Quests(quests)
+}
+
+/// Reads the saved progress, or starts with none.
+fn load_quest_log() -> QuestLog {
+    let text = match fs::read_to_string(PROGRESS_PATH) {
+        Ok(text) => text,
+        Err(error) => {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to read {PROGRESS_PATH}: {error}");
+            }
+            return QuestLog::default();
+        }
+    };
+
+    serde_json::from_str(&text).unwrap_or_else(|error| {
+        warn!("Failed to parse {PROGRESS_PATH}, starting the quests over: {error}");
+        QuestLog::default()
+    })
+}
+
+fn spawn_quest_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 18.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(12.0),
+            // Below the race times.
+            top: Val::Percent(30.0),
+            ..default()
+        }),
+        QuestHud,
+    ));
+}
+
+fn toggle_journal(input: Res<ButtonInput<KeyCode>>, mut journal: ResMut<Journal>) {
+    if input.just_pressed(KeyCode::KeyJ) {
+        journal.open = !journal.open;
+    }
+}
+
+/// Starts the quests whose prerequisites are complete, those that wait to
+/// be given once they are.
+fn start_quests(
+    quests: Res<Quests>,
+    mut log: ResMut<QuestLog>,
+    mut requests: EventReader<StartQuest>,
+) {
+    let requested: Vec<&String> = requests.read().map(|request| &request.0).collect();
+    let ready: Vec<&Quest> = quests
+        .0
+        .iter()
+        .filter(|quest| {
+            !log.quests.contains_key(&quest.id)
+                && quest.after.iter().all(|after| log.completed(after))
+                && (!quest.given || requested.contains(&&quest.id))
+        })
+        .collect();
+    if ready.is_empty() {
+        return;
+    }
+
+    for quest in ready {
+        info!("Started the quest {}", quest.title);
+        log.quests.insert(
+            quest.id.clone(),
+            QuestProgress {
+                objectives: vec![0; quest.objectives.len()],
+                completed: false,
+            },
+        );
+    }
+    if log.tracked.is_none() {
+        log.track_next(&quests);
+    }
+}
+
+/// Counts what the player picked up, reached and defeated towards the
+/// objectives of the quests under way, and completes the quests whose
+/// objectives are all met.
+fn advance_quests(
+    quests: Res<Quests>,
+    mut log: ResMut<QuestLog>,
+    mut pickups: EventReader<ItemsPickedUp>,
+    mut deaths: EventReader<Died>,
+    mobs: Query<&Mob>,
+    players: Query<&Position, With<Player>>,
+) {
+    let pickups: Vec<&ItemsPickedUp> = pickups.read().collect();
+    let defeated: Vec<MobKind> = deaths
+        .read()
+        .filter_map(|death| Some(mobs.get(death.entity).ok()?.kind))
+        .collect();
+    let player = players.get_single().ok().map(|position| position.current);
+
+    // Only mark the log changed when progress is made, so it isn't saved
+    // every frame.
+    let mut changed = false;
+    let started = &mut log.bypass_change_detection().quests;
+    for quest in quests.0.iter() {
+        let Some(progress) = started
+            .get_mut(&quest.id)
+            .filter(|progress| !progress.completed)
+        else {
+            continue;
+        };
+
+        for (objective, done) in quest.objectives.iter().zip(progress.objectives.iter_mut()) {
+            let made = match objective {
+                Objective::Collect { item, .. } => pickups
+                    .iter()
+                    .filter(|pickup| pickup.item == *item)
+                    .map(|pickup| pickup.count)
+                    .sum(),
+                Objective::Reach {
+                    position, radius, ..
+                } => u32::from(player.is_some_and(|player| {
+                    ((player - *position) * Vec3::new(1.0, 0.0, 1.0)).length() <= *radius
+                })),
+                Objective::Defeat { mob, .. } => {
+                    defeated.iter().filter(|kind| *kind == mob).count() as u32
+                }
+            };
+            let advanced = (*done + made).min(objective.goal());
+            if advanced != *done {
+                *done = advanced;
+                changed = true;
+            }
+        }
+
+        if quest
+            .objectives
+            .iter()
+            .zip(&progress.objectives)
+            .all(|(objective, done)| *done >= objective.goal())
+        {
+            info!("Completed the quest {}", quest.title);
+            progress.completed = true;
+            changed = true;
+        }
+    }
+
+    if log
+        .tracked
+        .as_ref()
+        .is_some_and(|tracked| log.active(tracked).is_none())
+    {
+        log.track_next(&quests);
+    }
+    if changed {
+        log.set_changed();
+    }
+}
+
+fn journal(
+    mut contexts: EguiContexts,
+    mut journal: ResMut<Journal>,
+    quests: Res<Quests>,
+    mut log: ResMut<QuestLog>,
+) {
+    let mut open = journal.open;
+    let mut tracked = log.tracked.clone();
+    egui::Window::new("Journal")
+        .open(&mut open)
+        .collapsible(false)
+        .default_width(320.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.heading("Under way");
+            let mut any = false;
+            for quest in quests.0.iter() {
+                let Some(progress) = log.active(&quest.id) else {
+                    continue;
+                };
+                any = true;
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.strong(&quest.title);
+                    ui.radio_value(&mut tracked, Some(quest.id.clone()), "Track");
+                });
+                ui.label(&quest.description);
+                for (objective, done) in quest.objectives.iter().zip(&progress.objectives) {
+                    ui.label(objective.describe(*done));
+                }
+            }
+            if !any {
+                ui.label("No quests under way.");
+            }
+
+            ui.add_space(8.0);
+            ui.heading("Completed");
+            let completed: Vec<&Quest> = quests
+                .0
+                .iter()
+                .filter(|quest| log.completed(&quest.id))
+                .collect();
+            if completed.is_empty() {
+                ui.label("No quests completed yet.");
+            }
+            for quest in completed {
+                ui.label(&quest.title);
+            }
+        });
+    journal.open = open;
+    if log.tracked != tracked {
+        log.tracked = tracked;
+    }
+}
+
+/// Shows the tracked quest and its objectives.
+fn update_quest_hud(
+    quests: Res<Quests>,
+    log: Res<QuestLog>,
+    mut huds: Query<&mut Text, With<QuestHud>>,
+) {
+    if !log.is_changed() {
+        return;
+    }
+
+    let mut text = String::new();
+    let tracked = log
+        .tracked
+        .as_ref()
+        .and_then(|tracked| quests.0.iter().find(|quest| quest.id == *tracked));
+    if let Some((quest, progress)) = tracked.and_then(|quest| Some((quest, log.active(&quest.id)?)))
+    {
+        text += &quest.title;
+        for (objective, done) in quest.objectives.iter().zip(&progress.objectives) {
+            text.push('\n');
+            text += &objective.describe(*done);
+        }
+    }
+
+    for mut hud in huds.iter_mut() {
+        hud.sections[0].value.clone_from(&text);
+    }
+}
+
+/// Saves the progress whenever it changes, but not when it was only just
+/// loaded.
+fn save_quest_log(log: Res<QuestLog>) {
+    if log.is_added() {
+        return;
+    }
+    if let Err(error) = write_quest_log(&log) {
+        warn!("Failed to save quest progress: {error}");
+    }
+}
+
+fn write_quest_log(log: &QuestLog) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = Path::new(PROGRESS_PATH).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(PROGRESS_PATH, serde_json::to_string_pretty(log)?)?;
+    Ok(())
+}
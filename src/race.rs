@@ -0,0 +1,359 @@
+//! A race mode: the player lays out a course of checkpoint blocks and races
+//! through them against their best time.
+//!
+//! C places a checkpoint block where the player stands and X removes the last
+//! one. The first checkpoint is the start and the last is the finish. Running
+//! into the start starts the clock, each checkpoint reached in order records a
+//! split, and the finish stops it. The course and its best time are saved to
+//! `races/course.json`, and editing the course clears the best time.
+
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{menu::PauseState, simulation::WorldSimulation, Player, Position};
+
+/// Where the course and its best time are saved.
+const COURSE_PATH: &str = "races/course.json";
+/// How close the player has to come to a checkpoint's center to reach it.
+const CHECKPOINT_REACH: f32 = 1.5;
+const START_COLOR: Color = Color::srgba(0.2, 0.9, 0.3, 0.6);
+const CHECKPOINT_COLOR: Color = Color::srgba(0.95, 0.8, 0.2, 0.6);
+const FINISH_COLOR: Color = Color::srgba(0.9, 0.2, 0.2, 0.6);
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_course())
+            .init_resource::<Race>()
+            .add_event::<CheckpointReached>()
+            .add_event::<RaceFinished>()
+            .add_systems(Startup, (setup_checkpoint_assets, spawn_race_hud))
+            .add_systems(
+                Update,
+                (
+                    edit_course.run_if(in_state(PauseState::Running)),
+                    spawn_checkpoints.run_if(resource_changed::<Course>),
+                    tick_race.in_set(WorldSimulation),
+                    detect_checkpoints,
+                    advance_race,
+                    record_best_time,
+                    update_race_hud,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The checkpoints of the course, in order, and the best time through them.
+#[derive(Resource, Default, Serialize, Deserialize)]
+struct Course {
+    /// The block each checkpoint occupies.
+    checkpoints: Vec<IVec3>,
+    best: Option<RaceTimes>,
+}
+
+/// The times at which a race reached each checkpoint after the start, in
+/// seconds. The last one is the finishing time.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct RaceTimes {
+    splits: Vec<f32>,
+}
+
+impl RaceTimes {
+    fn total(&self) -> Option<f32> {
+        self.splits.last().copied()
+    }
+}
+
+/// The race in progress, or the last one finished.
+#[derive(Resource, Default)]
+struct Race {
+    running: bool,
+    elapsed: f32,
+    /// The index of the checkpoint to reach next.
+    next: usize,
+    times: RaceTimes,
+}
+
+/// Sent when the player runs into a checkpoint.
+#[derive(Event)]
+struct CheckpointReached {
+    index: usize,
+}
+
+/// Sent when the player crosses the finish in order.
+#[derive(Event)]
+struct RaceFinished {
+    times: RaceTimes,
+}
+
+/// A checkpoint block of the course.
+#[derive(Component)]
+struct Checkpoint;
+
+/// The mesh and materials that checkpoint blocks are drawn with.
+#[derive(Resource)]
+struct CheckpointAssets {
+    mesh: Handle<Mesh>,
+    start: Handle<StandardMaterial>,
+    checkpoint: Handle<StandardMaterial>,
+    finish: Handle<StandardMaterial>,
+}
+
+/// Marks the text of the race timer.
+#[derive(Component)]
+struct RaceHud;
+
+fn setup_checkpoint_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            emissive: LinearRgba::from(color) * 0.5,
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        })
+    };
+    commands.insert_resource(CheckpointAssets {
+        start: material(START_COLOR),
+        checkpoint: material(CHECKPOINT_COLOR),
+        finish: material(FINISH_COLOR),
+        mesh: meshes.add(Cuboid::from_length(1.0)),
+    });
+}
+
+fn spawn_race_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(12.0),
+            top: Val::Px(8.0),
+            ..default()
+        }),
+        RaceHud,
+    ));
+}
+
+/// Places a checkpoint block at the player's feet with C, and removes the
+/// last one with X. Any edit ends the race in progress and clears the best
+/// time, which no longer applies to the new course.
+fn edit_course(
+    input: Res<ButtonInput<KeyCode>>,
+    mut course: ResMut<Course>,
+    mut race: ResMut<Race>,
+    players: Query<&Position, With<Player>>,
+) {
+    if input.just_pressed(KeyCode::KeyC) {
+        let Ok(position) = players.get_single() else {
+            return;
+        };
+        let block = position.current.floor().as_ivec3();
+        if course.checkpoints.last() == Some(&block) {
+            return;
+        }
+        course.checkpoints.push(block);
+    } else if input.just_pressed(KeyCode::KeyX) {
+        if course.checkpoints.pop().is_none() {
+            return;
+        }
+    } else {
+        return;
+    }
+
+    course.best = None;
+    *race = Race::default();
+    save_course(&course);
+}
+
+/// Respawns the checkpoint blocks to match the course.
+fn spawn_checkpoints(
+    mut commands: Commands,
+    course: Res<Course>,
+    assets: Res<CheckpointAssets>,
+    checkpoints: Query<Entity, With<Checkpoint>>,
+) {
+    for checkpoint in checkpoints.iter() {
+        commands.entity(checkpoint).despawn_recursive();
+    }
+
+    let last = course.checkpoints.len().saturating_sub(1);
+    for (index, block) in course.checkpoints.iter().enumerate() {
+        let material = match index {
+            0 => assets.start.clone(),
+            _ if index == last => assets.finish.clone(),
+            _ => assets.checkpoint.clone(),
+        };
+        commands.spawn((
+            PbrBundle {
+                mesh: assets.mesh.clone(),
+                material,
+                transform: Transform::from_translation(block.as_vec3() + Vec3::splat(0.5)),
+                ..default()
+            },
+            Checkpoint,
+        ));
+    }
+}
+
+fn tick_race(time: Res<Time>, mut race: ResMut<Race>) {
+    if race.running {
+        race.elapsed += time.delta_seconds();
+    }
+}
+
+/// Sends [`CheckpointReached`] when the player runs into a checkpoint, once
+/// per visit.
+fn detect_checkpoints(
+    course: Res<Course>,
+    players: Query<&Position, With<Player>>,
+    mut reached: EventWriter<CheckpointReached>,
+    mut inside: Local<Option<usize>>,
+) {
+    let Ok(position) = players.get_single() else {
+        return;
+    };
+
+    let touching = course.checkpoints.iter().position(|block| {
+        let center = block.as_vec3() + Vec3::new(0.5, 0.0, 0.5);
+        position.current.distance(center) < CHECKPOINT_REACH
+    });
+    if touching != *inside {
+        if let Some(index) = touching {
+            reached.send(CheckpointReached { index });
+        }
+        *inside = touching;
+    }
+}
+
+/// Starts the clock at the start, takes a split at each checkpoint reached in
+/// order, and sends [`RaceFinished`] at the finish.
+fn advance_race(
+    course: Res<Course>,
+    mut race: ResMut<Race>,
+    mut reached: EventReader<CheckpointReached>,
+    mut finished: EventWriter<RaceFinished>,
+) {
+    if course.checkpoints.len() < 2 {
+        reached.clear();
+        return;
+    }
+
+    for &CheckpointReached { index } in reached.read() {
+        if index == 0 {
+            *race = Race {
+                running: true,
+                next: 1,
+                ..default()
+            };
+        } else if race.running && index == race.next {
+            let elapsed = race.elapsed;
+            race.times.splits.push(elapsed);
+            race.next += 1;
+            if race.next == course.checkpoints.len() {
+                race.running = false;
+                finished.send(RaceFinished {
+                    times: race.times.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn record_best_time(mut course: ResMut<Course>, mut finished: EventReader<RaceFinished>) {
+    for RaceFinished { times } in finished.read() {
+        let (Some(time), best) = (
+            times.total(),
+            course.best.as_ref().and_then(RaceTimes::total),
+        ) else {
+            continue;
+        };
+        info!("Finished the course in {time:.2}s");
+        if best.is_none_or(|best| time < best) {
+            course.best = Some(times.clone());
+            save_course(&course);
+        }
+    }
+}
+
+/// Shows the clock, the splits so far, and how each compares with the best
+/// run.
+fn update_race_hud(
+    course: Res<Course>,
+    race: Res<Race>,
+    mut huds: Query<&mut Text, With<RaceHud>>,
+) {
+    if !course.is_changed() && !race.is_changed() {
+        return;
+    }
+
+    let mut text = String::new();
+    if course.checkpoints.len() >= 2 {
+        let total = course.checkpoints.len() - 1;
+        text += &format!(
+            "{:.2}s  [{}/{total}]\n",
+            race.elapsed,
+            race.times.splits.len()
+        );
+        let best_splits = course.best.as_ref().map(|best| &best.splits);
+        for (index, split) in race.times.splits.iter().enumerate() {
+            text += &format!("{}: {split:.2}s", index + 1);
+            if let Some(best) = best_splits.and_then(|splits| splits.get(index)) {
+                text += &format!(" ({:+.2})", split - best);
+            }
+            text.push('\n');
+        }
+        if let Some(best) = course.best.as_ref().and_then(RaceTimes::total) {
+            text += &format!("Best: {best:.2}s");
+        }
+    }
+
+    for mut hud in huds.iter_mut() {
+        hud.sections[0].value.clone_from(&text);
+    }
+}
+
+/// Reads the saved course, or starts with an empty one.
+fn load_course() -> Course {
+    let text = match fs::read_to_string(COURSE_PATH) {
+        Ok(text) => text,
+        Err(error) => {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to read {COURSE_PATH}: {error}");
+            }
+            return Course::default();
+        }
+    };
+
+    serde_json::from_str(&text).unwrap_or_else(|error| {
+        warn!("Failed to parse {COURSE_PATH}, starting with an empty course: {error}");
+        Course::default()
+    })
+}
+
+fn save_course(course: &Course) {
+    if let Err(error) = write_course(course) {
+        warn!("Failed to save the race course: {error}");
+    }
+}
+
+fn write_course(course: &Course) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = Path::new(COURSE_PATH).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(COURSE_PATH, serde_json::to_string_pretty(course)?)?;
+    Ok(())
+}
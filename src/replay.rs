@@ -0,0 +1,172 @@
+//! Records the player's pose and animation every frame so a run can be
+//! played back later, and saves recordings to disk.
+
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{AnimFsm, AnimState},
+    Player,
+};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Recorder>()
+            .add_systems(PostUpdate, record_player);
+    }
+}
+
+/// The player's pose and animation at one point in a recording.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    /// Seconds since the recording started.
+    pub time: f32,
+    pub transform: Transform,
+    pub anim_state: AnimState,
+}
+
+/// A recorded run, in time order.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Recording {
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |frame| frame.time)
+    }
+
+    /// The pose at `time`, interpolated between the neighboring frames, and
+    /// the animation state of the frame before it.
+    pub fn sample(&self, time: f32) -> Option<(Transform, AnimState)> {
+        let next = self.frames.partition_point(|frame| frame.time <= time);
+        let previous = self.frames.get(next.checked_sub(1)?)?;
+        let Some(next) = self.frames.get(next) else {
+            return Some((previous.transform, previous.anim_state));
+        };
+
+        let t = (time - previous.time) / (next.time - previous.time).max(f32::EPSILON);
+        let transform = Transform {
+            translation: previous
+                .transform
+                .translation
+                .lerp(next.transform.translation, t),
+            rotation: previous
+                .transform
+                .rotation
+                .slerp(next.transform.rotation, t),
+            scale: previous.transform.scale.lerp(next.transform.scale, t),
+        };
+        Some((transform, previous.anim_state))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Records the player while a recording is in progress.
+#[derive(Resource, Default)]
+pub struct Recorder {
+    recording: Option<Recording>,
+    elapsed: f32,
+}
+
+impl Recorder {
+    /// Starts a new recording, discarding any unfinished one.
+    pub fn start(&mut self) {
+        self.recording = Some(Recording::default());
+        self.elapsed = 0.0;
+    }
+
+    /// Finishes the current recording and returns it.
+    pub fn stop(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+}
+
+/// Adds a frame with the player's final pose for this update.
+fn record_player(
+    time: Res<Time>,
+    mut recorder: ResMut<Recorder>,
+    players: Query<(&Transform, &AnimFsm), With<Player>>,
+) {
+    let Recorder { recording, elapsed } = &mut *recorder;
+    let (Some(recording), Ok((transform, anim_fsm))) = (recording, players.get_single()) else {
+        return;
+    };
+
+    // Paused time doesn't advance, so there's nothing new to record.
+    if !recording.frames.is_empty() && time.delta_seconds() == 0.0 {
+        return;
+    }
+    if !recording.frames.is_empty() {
+        *elapsed += time.delta_seconds();
+    }
+
+    recording.frames.push(ReplayFrame {
+        time: *elapsed,
+        transform: *transform,
+        anim_state: anim_fsm.state(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time: f32, x: f32, anim_state: AnimState) -> ReplayFrame {
+        ReplayFrame {
+            time,
+            transform: Transform::from_xyz(x, 0.0, 0.0),
+            anim_state,
+        }
+    }
+
+    fn recording() -> Recording {
+        Recording {
+            frames: vec![
+                frame(0.0, 0.0, AnimState::Idle),
+                frame(1.0, 10.0, AnimState::Run),
+            ],
+        }
+    }
+
+    #[test]
+    fn samples_between_frames_are_interpolated() {
+        let (transform, anim_state) = recording().sample(0.25).unwrap();
+        assert!((transform.translation.x - 2.5).abs() < 1e-5);
+        assert_eq!(anim_state, AnimState::Idle);
+    }
+
+    #[test]
+    fn samples_on_a_frame_are_that_frame() {
+        let (transform, anim_state) = recording().sample(1.0).unwrap();
+        assert_eq!(transform.translation.x, 10.0);
+        assert_eq!(anim_state, AnimState::Run);
+    }
+
+    #[test]
+    fn samples_past_the_end_hold_the_last_frame() {
+        let (transform, _) = recording().sample(5.0).unwrap();
+        assert_eq!(transform.translation.x, 10.0);
+    }
+
+    #[test]
+    fn there_is_nothing_to_sample_before_the_start() {
+        assert!(recording().sample(-1.0).is_none());
+        assert!(Recording::default().sample(0.0).is_none());
+    }
+}
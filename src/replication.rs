@@ -0,0 +1,290 @@
+//! Sharing components of the players' characters with everyone in the
+//! session, without a message of their own for each.
+//!
+//! Modules mark components to share with [`ReplicateExt::replicate`], giving
+//! each a name that stays the same between builds. Every player is
+//! authoritative over their own character's components, the same as over
+//! their deaths: each frame, the values of the local player's marked
+//! components are written into its [`Replica`] as JSON. The network code
+//! sends the values that changed to the server, which keeps them on that
+//! player's character and passes them on to the clients near enough to see
+//! it. Whoever receives them puts the components on the character they show
+//! for that player, or takes them off when they were removed. A server only
+//! keeps and passes on components marked to be shared, and only so many of
+//! them, so a client can't make it hold on to anything it likes; a dedicated
+//! server marks them too.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::{ecs::world::Command, prelude::*};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Player;
+
+/// A component's value as sent over the network.
+type ComponentValue = Value;
+
+/// The most replicated components a character can have.
+const MAX_REPLICATED_COMPONENTS: usize = 64;
+
+/// The systems that write the local player's components into its
+/// [`Replica`], in [`PostUpdate`]. Anything sending replicas runs after
+/// them.
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct StoreReplicas;
+
+/// How to put each replicated component on a character, by name.
+#[derive(Resource, Default)]
+struct Replication(HashMap<&'static str, ApplyFn>);
+
+/// Puts a component on a character from its value, or takes it off for
+/// `None`.
+type ApplyFn = fn(&mut EntityWorldMut, Option<ComponentValue>) -> serde_json::Result<()>;
+
+/// The replicated components of a character, by name, as last written or
+/// received.
+#[derive(Component, Default)]
+pub struct Replica {
+    values: BTreeMap<String, ComponentValue>,
+}
+
+impl Replica {
+    /// Records what a client said changed about its character, up to
+    /// [`MAX_REPLICATED_COMPONENTS`] of them.
+    pub fn update(&mut self, updates: &[ComponentUpdate]) {
+        for update in updates {
+            match &update.value {
+                Some(value) => {
+                    if self.values.len() < MAX_REPLICATED_COMPONENTS
+                        || self.values.contains_key(&update.name)
+                    {
+                        self.values.insert(update.name.clone(), value.clone());
+                    }
+                }
+                None => {
+                    self.values.remove(&update.name);
+                }
+            }
+        }
+    }
+
+    /// What changed since the values in `sent`, which are then brought up to
+    /// date.
+    pub fn changes(&self, sent: &mut HashMap<String, ComponentValue>) -> Vec<ComponentUpdate> {
+        let mut updates: Vec<ComponentUpdate> = self
+            .values
+            .iter()
+            .filter(|(name, value)| sent.get(*name) != Some(value))
+            .map(|(name, value)| ComponentUpdate {
+                name: name.clone(),
+                value: Some(value.clone()),
+            })
+            .collect();
+        updates.extend(
+            sent.keys()
+                .filter(|name| !self.values.contains_key(*name))
+                .map(|name| ComponentUpdate {
+                    name: name.clone(),
+                    value: None,
+                }),
+        );
+
+        for update in &updates {
+            match &update.value {
+                Some(value) => sent.insert(update.name.clone(), value.clone()),
+                None => sent.remove(&update.name),
+            };
+        }
+        updates
+    }
+}
+
+/// A replicated component that changed, or `None` if it was removed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ComponentUpdate {
+    name: String,
+    value: Option<ComponentValue>,
+}
+
+/// Marks components to be shared with everyone in the session.
+pub trait ReplicateExt {
+    /// Shares the local player's `C`, under `name`.
+    fn replicate<C: Component + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self;
+}
+
+impl ReplicateExt for App {
+    fn replicate<C: Component + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(Replication::default)
+            .0
+            .insert(name, apply::<C>);
+        self.add_systems(
+            PostUpdate,
+            (move |mut players: Query<(Option<Ref<C>>, &mut Replica), With<Player>>| {
+                for (component, mut replica) in players.iter_mut() {
+                    match component {
+                        Some(component)
+                            if component.is_changed() || !replica.values.contains_key(name) =>
+                        {
+                            match serde_json::to_value(&*component) {
+                                Ok(value) => {
+                                    replica.values.insert(name.to_string(), value);
+                                }
+                                Err(error) => warn!("Failed to replicate {name}: {error}"),
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            if replica.values.contains_key(name) {
+                                replica.values.remove(name);
+                            }
+                        }
+                    }
+                }
+            })
+            .in_set(StoreReplicas),
+        )
+    }
+}
+
+fn apply<C: Component + DeserializeOwned>(
+    entity: &mut EntityWorldMut,
+    value: Option<ComponentValue>,
+) -> serde_json::Result<()> {
+    match value {
+        Some(value) => {
+            entity.insert(serde_json::from_value::<C>(value)?);
+        }
+        None => {
+            entity.remove::<C>();
+        }
+    }
+    Ok(())
+}
+
+/// Records what a client said changed about the character `entity` in its
+/// [`Replica`] and puts the components on it, skipping those this game
+/// doesn't know.
+pub fn receive_updates(entity: Entity, updates: Vec<ComponentUpdate>) -> impl Command {
+    move |world: &mut World| {
+        let Some(replication) = world.get_resource::<Replication>() else {
+            return;
+        };
+        let updates: Vec<_> = updates
+            .into_iter()
+            .filter(|update| replication.0.contains_key(update.name.as_str()))
+            .collect();
+        if let Some(mut replica) = world.get_mut::<Replica>(entity) {
+            replica.update(&updates);
+        }
+        apply_updates(entity, updates).apply(world);
+    }
+}
+
+/// Puts the components in `updates` on the character `entity`, skipping
+/// those this game doesn't know.
+pub fn apply_updates(entity: Entity, updates: Vec<ComponentUpdate>) -> impl Command {
+    move |world: &mut World| {
+        let Some(appliers) = world.get_resource::<Replication>() else {
+            return;
+        };
+        let updates: Vec<_> = updates
+            .into_iter()
+            .filter_map(|update| Some((*appliers.0.get(update.name.as_str())?, update)))
+            .collect();
+        let Some(mut entity) = world.get_entity_mut(entity) else {
+            return;
+        };
+        for (apply, update) in updates {
+            if let Err(error) = apply(&mut entity, update.value) {
+                warn!("Failed to read the replicated {}: {error}", update.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn update(name: &str, value: Option<Value>) -> ComponentUpdate {
+        ComponentUpdate {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    fn names(updates: &[ComponentUpdate]) -> Vec<(&str, bool)> {
+        updates
+            .iter()
+            .map(|update| (update.name.as_str(), update.value.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn changes_are_only_sent_once() {
+        let mut replica = Replica::default();
+        replica.update(&[update("health", Some(json!(20.0)))]);
+        let mut sent = HashMap::new();
+
+        assert_eq!(names(&replica.changes(&mut sent)), [("health", true)]);
+        assert!(replica.changes(&mut sent).is_empty());
+
+        replica.update(&[update("health", Some(json!(18.0)))]);
+        assert_eq!(names(&replica.changes(&mut sent)), [("health", true)]);
+        assert_eq!(sent["health"], json!(18.0));
+    }
+
+    #[test]
+    fn removed_components_are_sent_as_removed() {
+        let mut replica = Replica::default();
+        replica.update(&[update("dead", Some(json!(null)))]);
+        let mut sent = HashMap::new();
+        replica.changes(&mut sent);
+
+        replica.update(&[update("dead", None)]);
+        assert_eq!(names(&replica.changes(&mut sent)), [("dead", false)]);
+        assert!(sent.is_empty());
+        assert!(replica.changes(&mut sent).is_empty());
+    }
+
+    #[test]
+    fn forgetting_what_was_sent_sends_everything_again() {
+        let mut replica = Replica::default();
+        replica.update(&[
+            update("dead", Some(json!(null))),
+            update("health", Some(json!(0.0))),
+        ]);
+        let mut sent = HashMap::new();
+        replica.changes(&mut sent);
+
+        sent.clear();
+        assert_eq!(
+            names(&replica.changes(&mut sent)),
+            [("dead", true), ("health", true)]
+        );
+    }
+
+    #[test]
+    fn replicas_hold_a_limited_number_of_components() {
+        let mut replica = Replica::default();
+        let updates: Vec<_> = (0..MAX_REPLICATED_COMPONENTS + 10)
+            .map(|index| update(&format!("component{index}"), Some(json!(index))))
+            .collect();
+        replica.update(&updates);
+        assert_eq!(replica.values.len(), MAX_REPLICATED_COMPONENTS);
+
+        // Components it already has still change.
+        replica.update(&[update("component0", Some(json!("changed")))]);
+        assert_eq!(replica.values["component0"], json!("changed"));
+    }
+}
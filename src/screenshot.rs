@@ -0,0 +1,369 @@
+//! Screenshots, taken with F12 and saved as timestamped PNGs in
+//! `screenshots/`.
+//!
+//! At a screenshot scale of 1 the window is captured as it is, UI included.
+//! Above that, a temporary camera renders the scene into an offscreen image
+//! that many times the window's resolution, which is read back from the GPU
+//! and saved without the UI.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    core_pipeline::{bloom::BloomSettings, dof::DepthOfFieldSettings, tonemapping::Tonemapping},
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_resource::{
+            BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+            ImageDataLayout, Maintain, MapMode, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::{GpuImage, TextureFormatPixelInfo},
+        view::{screenshot::ScreenshotManager, ColorGrading},
+        Extract, Render, RenderApp, RenderSet,
+    },
+    tasks::IoTaskPool,
+    window::PrimaryWindow,
+};
+
+use crate::{settings::GraphicsSettings, MainCamera};
+
+/// The directory screenshots are saved in.
+pub const SCREENSHOT_DIR: &str = "screenshots";
+/// The largest multiple of the window's resolution screenshots can be taken
+/// at.
+pub const MAX_SCREENSHOT_SCALE: u32 = 4;
+/// How many frames the offscreen camera renders before its image is saved,
+/// giving the pipelines for its new render target time to compile.
+const SUPERSAMPLE_WARMUP_FRAMES: u32 = 4;
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TakeScreenshot>()
+            .add_systems(
+                Update,
+                request_screenshot
+                    .run_if(|input: Res<ButtonInput<KeyCode>>| input.just_pressed(KeyCode::F12)),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    advance_supersampled_capture.run_if(resource_exists::<SupersampledCapture>),
+                    take_window_screenshot.run_if(not(supersampling)),
+                    start_supersampled_capture.run_if(
+                        supersampling.and_then(not(resource_exists::<SupersampledCapture>)),
+                    ),
+                )
+                    .chain(),
+            );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_systems(ExtractSchedule, extract_supersampled_capture)
+            .add_systems(
+                Render,
+                copy_supersampled_capture
+                    .after(RenderSet::Render)
+                    .before(RenderSet::Cleanup),
+            );
+    }
+}
+
+/// Asks for a screenshot of the frame being rendered.
+#[derive(Event)]
+pub struct TakeScreenshot;
+
+/// A supersampled screenshot being rendered.
+#[derive(Resource)]
+struct SupersampledCapture {
+    camera: Entity,
+    image: Handle<Image>,
+    path: PathBuf,
+    /// How many more frames to render before the image is saved.
+    frames_left: u32,
+}
+
+/// The offscreen image to save this frame, in the render world.
+#[derive(Resource)]
+struct ExtractedCapture {
+    image: AssetId<Image>,
+    path: PathBuf,
+}
+
+/// Everything about a camera that affects what it renders.
+type CameraView = (
+    &'static Camera,
+    &'static Transform,
+    &'static Projection,
+    &'static Tonemapping,
+    &'static ColorGrading,
+    Option<&'static BloomSettings>,
+    Option<&'static DepthOfFieldSettings>,
+);
+
+fn request_screenshot(mut screenshots: EventWriter<TakeScreenshot>) {
+    screenshots.send(TakeScreenshot);
+}
+
+fn supersampling(graphics_settings: Res<GraphicsSettings>) -> bool {
+    graphics_settings.screenshot_scale > 1
+}
+
+fn take_window_screenshot(
+    mut requests: EventReader<TakeScreenshot>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    windows: Query<Entity, With<PrimaryWindow>>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+
+    let result = screenshot_path().and_then(|path| {
+        screenshot_manager.save_screenshot_to_disk(window, path)?;
+        Ok(())
+    });
+    if let Err(error) = result {
+        warn!("Failed to take a screenshot: {error}");
+    }
+}
+
+/// Spawns a copy of the camera that renders into an image the screenshot
+/// scale times the size of the window.
+fn start_supersampled_capture(
+    mut commands: Commands,
+    mut requests: EventReader<TakeScreenshot>,
+    graphics_settings: Res<GraphicsSettings>,
+    render_device: Res<RenderDevice>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<CameraView, With<MainCamera>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+    let (Ok(window), Ok((camera, transform, projection, tonemapping, color_grading, bloom, dof))) =
+        (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+    let path = match screenshot_path() {
+        Ok(path) => path,
+        Err(error) => {
+            warn!("Failed to take a screenshot: {error}");
+            return;
+        }
+    };
+
+    // Stay within the largest texture the GPU supports.
+    let (width, height) = (window.physical_width(), window.physical_height());
+    let max_scale = render_device.limits().max_texture_dimension_2d / width.max(height).max(1);
+    let scale = graphics_settings
+        .screenshot_scale
+        .min(MAX_SCREENSHOT_SCALE)
+        .min(max_scale)
+        .max(1);
+
+    let size = Extent3d {
+        width: width * scale,
+        height: height * scale,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT;
+    let image = images.add(image);
+
+    let mut capture_camera = commands.spawn(Camera3dBundle {
+        camera: Camera {
+            hdr: camera.hdr,
+            target: RenderTarget::Image(image.clone()),
+            ..default()
+        },
+        transform: *transform,
+        projection: projection.clone(),
+        tonemapping: *tonemapping,
+        color_grading: color_grading.clone(),
+        ..default()
+    });
+    if let Some(bloom) = bloom {
+        capture_camera.insert(bloom.clone());
+    }
+    if let Some(dof) = dof {
+        capture_camera.insert(*dof);
+    }
+    let capture_camera = capture_camera.id();
+
+    info!(
+        "Rendering a {}x{} screenshot to {}",
+        size.width,
+        size.height,
+        path.display()
+    );
+    commands.insert_resource(SupersampledCapture {
+        camera: capture_camera,
+        image,
+        path,
+        frames_left: SUPERSAMPLE_WARMUP_FRAMES,
+    });
+}
+
+/// Counts down the warmup frames, and cleans up once the image has been
+/// captured.
+fn advance_supersampled_capture(
+    mut commands: Commands,
+    mut capture: ResMut<SupersampledCapture>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if capture.frames_left > 0 {
+        capture.frames_left -= 1;
+        return;
+    }
+
+    // The last frame rendered was captured.
+    commands.entity(capture.camera).despawn_recursive();
+    images.remove(&capture.image);
+    commands.remove_resource::<SupersampledCapture>();
+}
+
+/// Hands the offscreen image to the render world on the frame it is to be
+/// saved.
+fn extract_supersampled_capture(
+    mut commands: Commands,
+    capture: Extract<Option<Res<SupersampledCapture>>>,
+) {
+    match capture.as_deref() {
+        Some(capture) if capture.frames_left == 0 => {
+            commands.insert_resource(ExtractedCapture {
+                image: capture.image.id(),
+                path: capture.path.clone(),
+            });
+        }
+        _ => commands.remove_resource::<ExtractedCapture>(),
+    }
+}
+
+/// Copies the rendered offscreen image back from the GPU and saves it in the
+/// background.
+fn copy_supersampled_capture(
+    mut commands: Commands,
+    capture: Option<Res<ExtractedCapture>>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(capture) = capture else {
+        return;
+    };
+    commands.remove_resource::<ExtractedCapture>();
+    let Some(gpu_image) = gpu_images.get(capture.image) else {
+        warn!("The screenshot image wasn't ready to be captured");
+        return;
+    };
+
+    // Rows in the buffer have to be padded to the copy alignment.
+    let UVec2 {
+        x: width,
+        y: height,
+    } = gpu_image.size;
+    let row_bytes = width as usize * gpu_image.texture_format.pixel_size();
+    let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("supersampled_screenshot_buffer"),
+        size: (padded_row_bytes * height as usize) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        gpu_image.texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes as u32),
+                rows_per_image: None,
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    render_queue.submit([encoder.finish()]);
+
+    // Waiting for the GPU stalls this one frame, which is fine for a
+    // screenshot.
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    render_device.poll(Maintain::Wait);
+    if !matches!(receiver.recv(), Ok(Ok(()))) {
+        warn!("Failed to read the screenshot back from the GPU");
+        return;
+    }
+
+    let mut data = Vec::with_capacity(row_bytes * height as usize);
+    for row in slice.get_mapped_range().chunks(padded_row_bytes) {
+        data.extend_from_slice(&row[..row_bytes]);
+    }
+    buffer.unmap();
+
+    let image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        gpu_image.texture_format,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    let path = capture.path.clone();
+    IoTaskPool::get()
+        .spawn(async move {
+            match save_image(image, &path) {
+                Ok(()) => info!("Screenshot saved to {}", path.display()),
+                Err(error) => warn!("Failed to save the screenshot: {error}"),
+            }
+        })
+        .detach();
+}
+
+/// A timestamped path in the screenshot directory, creating the directory if
+/// needed.
+fn screenshot_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    fs::create_dir_all(SCREENSHOT_DIR)?;
+    Ok(PathBuf::from(SCREENSHOT_DIR).join(format!("screenshot-{timestamp}.png")))
+}
+
+fn save_image(image: Image, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    // Drop the alpha channel, which holds brightness rather than coverage
+    // when rendering in HDR.
+    image.try_into_dynamic()?.to_rgb8().save(path)?;
+    Ok(())
+}
@@ -0,0 +1,512 @@
+//! The multiplayer menu, opened from the pause menu, for finding a server to
+//! join.
+//!
+//! It lists the servers saved in the config along with any it finds on the
+//! local network. They are all asked how they are doing when the menu
+//! opens and every few seconds while it stays open, without waiting on
+//! their answers: each shows its message of the day, player count, version
+//! and ping once it answers. Servers can be added, saved from the local
+//! network, removed and starred as favorites, which sort first. Joining
+//! one closes the menu; servers running another version of the game can't
+//! be joined.
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, poll_once, IoTaskPool, Task},
+};
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    menu::PauseState,
+    net::{resolve_server, ConnectToServer, ServerStatus, StatusQuery, GAME_VERSION},
+};
+
+/// How often the servers are asked again while the menu is open, in
+/// seconds.
+const QUERY_INTERVAL: f32 = 5.0;
+/// How long a server has to answer before it is shown as not answering, in
+/// seconds.
+const ANSWER_TIMEOUT: f32 = 2.0;
+const FAVORITE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 215, 90);
+const WRONG_VERSION_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 120, 100);
+
+pub struct ServerBrowserPlugin;
+
+impl Plugin for ServerBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MultiplayerSettings>()
+            .init_resource::<ServerBrowser>()
+            .add_systems(OnExit(PauseState::Paused), close_server_browser)
+            .add_systems(
+                Update,
+                (query_servers, receive_answers, server_browser)
+                    .chain()
+                    .run_if(in_state(PauseState::Paused))
+                    .run_if(server_browser_open),
+            );
+    }
+}
+
+/// A resource that stores the servers the player saved.
+#[derive(Clone, PartialEq, Resource, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct MultiplayerSettings {
+    pub servers: Vec<SavedServer>,
+}
+
+/// A server the player saved, to find it again.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedServer {
+    pub name: String,
+    /// The address as typed: a host name or IP, with or without a port.
+    pub address: String,
+    #[serde(default)]
+    pub favorite: bool,
+}
+
+/// What the list is sorted by, after favorites.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum SortBy {
+    #[default]
+    Name,
+    Ping,
+    Players,
+}
+
+impl SortBy {
+    const ALL: [SortBy; 3] = [SortBy::Name, SortBy::Ping, SortBy::Players];
+
+    fn label(self) -> &'static str {
+        match self {
+            SortBy::Name => "Name",
+            SortBy::Ping => "Ping",
+            SortBy::Players => "Players",
+        }
+    }
+}
+
+/// The state of the server browser, and what the servers said.
+#[derive(Resource, Default)]
+pub struct ServerBrowser {
+    open: bool,
+    /// The socket the servers are asked through, made the first time it is
+    /// needed.
+    query: Option<StatusQuery>,
+    /// Looking up the saved servers' addresses, which can take a while for
+    /// host names.
+    resolving: Option<Task<Vec<Resolved>>>,
+    /// Each saved address as looked up the last time.
+    resolved: HashMap<String, Option<SocketAddr>>,
+    /// Counts up by one each time the servers are asked.
+    round: u32,
+    /// The real time the servers were last asked, in seconds since startup.
+    asked_at: f32,
+    /// Real seconds until the servers are asked again.
+    until_query: f32,
+    answers: HashMap<SocketAddr, Answer>,
+    sort: SortBy,
+    favorites_only: bool,
+    /// The server being added.
+    new_name: String,
+    new_address: String,
+}
+
+/// An address as typed, and where it was found, or `None` if it couldn't
+/// be.
+type Resolved = (String, Option<SocketAddr>);
+
+/// What a server said when it was asked how it is doing.
+struct Answer {
+    status: ServerStatus,
+    /// How long the answer took, in seconds.
+    ping: f32,
+    /// The time it was asked, as counted by [`ServerBrowser::round`].
+    round: u32,
+}
+
+impl ServerBrowser {
+    /// Opens the browser, asking the servers again straight away.
+    pub fn open(&mut self) {
+        self.open = true;
+        self.until_query = 0.0;
+        self.answers.clear();
+    }
+
+    /// What a server said, while it still holds: until the server is asked
+    /// again and doesn't answer in time.
+    fn answer(&self, address: SocketAddr, now: f32) -> Option<&Answer> {
+        let answer = self.answers.get(&address)?;
+        let waiting = now - self.asked_at < ANSWER_TIMEOUT;
+        (answer.round == self.round || (answer.round + 1 == self.round && waiting))
+            .then_some(answer)
+    }
+}
+
+pub fn server_browser_open(browser: Res<ServerBrowser>) -> bool {
+    browser.open
+}
+
+fn close_server_browser(mut browser: ResMut<ServerBrowser>) {
+    browser.open = false;
+}
+
+/// Looks up the saved servers' addresses every so often, then asks them and
+/// the local network how they are doing.
+fn query_servers(
+    time: Res<Time<Real>>,
+    mut browser: ResMut<ServerBrowser>,
+    settings: Res<MultiplayerSettings>,
+) {
+    let browser = &mut *browser;
+    browser.until_query -= time.delta_seconds();
+    if browser.until_query <= 0.0 && browser.resolving.is_none() {
+        browser.until_query = QUERY_INTERVAL;
+        let addresses: Vec<String> = settings
+            .servers
+            .iter()
+            .map(|server| server.address.clone())
+            .collect();
+        browser.resolving = Some(IoTaskPool::get().spawn(async move {
+            addresses
+                .into_iter()
+                .map(|address| {
+                    let resolved = resolve_server(&address);
+                    (address, resolved)
+                })
+                .collect()
+        }));
+    }
+
+    let Some(task) = browser.resolving.as_mut() else {
+        return;
+    };
+    let Some(resolved) = block_on(poll_once(task)) else {
+        return;
+    };
+    browser.resolving = None;
+    browser.resolved = resolved.into_iter().collect();
+
+    if browser.query.is_none() {
+        browser.query = StatusQuery::new()
+            .map_err(|error| warn!("Couldn't look for servers: {error}"))
+            .ok();
+    }
+    let Some(query) = &browser.query else {
+        return;
+    };
+    for address in browser.resolved.values().flatten() {
+        query.ask(*address);
+    }
+    query.ask_local_network();
+    browser.round += 1;
+    browser.asked_at = time.elapsed_seconds();
+}
+
+fn receive_answers(time: Res<Time<Real>>, mut browser: ResMut<ServerBrowser>) {
+    let Some(query) = &browser.query else {
+        return;
+    };
+    let answers = query.answers();
+    let ping = (time.elapsed_seconds() - browser.asked_at).max(0.0);
+    let round = browser.round;
+    for (address, status) in answers {
+        browser.answers.insert(
+            address,
+            Answer {
+                status,
+                ping,
+                round,
+            },
+        );
+    }
+}
+
+/// A server as listed in the browser.
+struct Listing<'a> {
+    name: String,
+    /// The address as shown.
+    address: String,
+    /// Where the server was found, if it was.
+    socket: Option<SocketAddr>,
+    /// Its index among the saved servers, or `None` for servers found on the
+    /// local network.
+    saved: Option<usize>,
+    favorite: bool,
+    answer: Option<&'a Answer>,
+}
+
+/// Shows the servers, and joins, saves, stars or removes them as asked.
+fn server_browser(
+    mut contexts: EguiContexts,
+    time: Res<Time<Real>>,
+    mut browser: ResMut<ServerBrowser>,
+    mut settings: ResMut<MultiplayerSettings>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut joins: EventWriter<ConnectToServer>,
+) {
+    let now = time.elapsed_seconds();
+    let mut new_name = std::mem::take(&mut browser.new_name);
+    let mut new_address = std::mem::take(&mut browser.new_address);
+    let listings = listings(&browser, &settings, now);
+    let waiting = browser.resolving.is_some() || now - browser.asked_at < ANSWER_TIMEOUT;
+
+    let mut join = None;
+    let mut save = None;
+    let mut toggle_favorite = None;
+    let mut remove = None;
+    let mut add = false;
+    let mut back = false;
+    let mut refresh = false;
+    let mut sort = browser.sort;
+    let mut favorites_only = browser.favorites_only;
+
+    egui::Window::new("Multiplayer")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Sort by")
+                    .selected_text(sort.label())
+                    .show_ui(ui, |ui| {
+                        for option in SortBy::ALL {
+                            ui.selectable_value(&mut sort, option, option.label());
+                        }
+                    });
+                ui.checkbox(&mut favorites_only, "Favorites only");
+                refresh = ui.button("Refresh").clicked();
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(320.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("servers")
+                        .num_columns(6)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.label("");
+                            ui.strong("Server");
+                            ui.strong("Players");
+                            ui.strong("Ping");
+                            ui.strong("Version");
+                            ui.label("");
+                            ui.end_row();
+
+                            let shown = listings
+                                .iter()
+                                .filter(|listing| !favorites_only || listing.favorite);
+                            for listing in shown {
+                                let star = if listing.favorite { "★" } else { "☆" };
+                                let star = egui::RichText::new(star).color(FAVORITE_COLOR);
+                                match listing.saved {
+                                    Some(saved) => {
+                                        if ui.button(star).on_hover_text("Favorite").clicked() {
+                                            toggle_favorite = Some(saved);
+                                        }
+                                    }
+                                    None => {
+                                        ui.label("LAN");
+                                    }
+                                }
+
+                                ui.vertical(|ui| {
+                                    ui.label(&listing.name);
+                                    let detail = match listing.answer {
+                                        Some(answer) => {
+                                            format!("{} · {}", answer.status.motd, listing.address)
+                                        }
+                                        None => listing.address.clone(),
+                                    };
+                                    ui.weak(detail);
+                                });
+
+                                let compatible = match listing.answer {
+                                    Some(answer) => {
+                                        ui.label(format!(
+                                            "{}/{}",
+                                            answer.status.players, answer.status.max_players
+                                        ));
+                                        ui.label(format!("{:.0} ms", answer.ping * 1000.0));
+                                        let compatible = answer.status.version == GAME_VERSION;
+                                        let version = egui::RichText::new(&answer.status.version);
+                                        if compatible {
+                                            ui.label(version);
+                                        } else {
+                                            ui.label(version.color(WRONG_VERSION_COLOR))
+                                                .on_hover_text(
+                                                    "This server runs another version of the game",
+                                                );
+                                        }
+                                        compatible
+                                    }
+                                    None => {
+                                        ui.label("");
+                                        ui.label(match listing.socket {
+                                            None if browser.resolving.is_none() => "Unknown host",
+                                            _ if waiting => "…",
+                                            _ => "No answer",
+                                        });
+                                        ui.label("");
+                                        // A server that doesn't answer can still be tried.
+                                        true
+                                    }
+                                };
+
+                                ui.horizontal(|ui| {
+                                    let joinable = listing.socket.is_some() && compatible;
+                                    if ui
+                                        .add_enabled(joinable, egui::Button::new("Join"))
+                                        .clicked()
+                                    {
+                                        join = listing.socket;
+                                    }
+                                    match listing.saved {
+                                        Some(saved) => {
+                                            if ui.button("Remove").clicked() {
+                                                remove = Some(saved);
+                                            }
+                                        }
+                                        None => {
+                                            if ui.button("Save").clicked() {
+                                                save = Some(SavedServer {
+                                                    name: listing.name.clone(),
+                                                    address: listing.address.clone(),
+                                                    favorite: false,
+                                                });
+                                            }
+                                        }
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                    if listings.is_empty() {
+                        ui.label("No servers yet. Add one below, or start one on this network.");
+                    }
+                });
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut new_name).hint_text("Name"));
+                ui.add(egui::TextEdit::singleline(&mut new_address).hint_text("Address"));
+                add = ui
+                    .add_enabled(!new_address.trim().is_empty(), egui::Button::new("Add"))
+                    .clicked();
+            });
+            ui.separator();
+
+            back = ui.button("Back").clicked();
+        });
+
+    drop(listings);
+
+    let browser = &mut *browser;
+    browser.sort = sort;
+    browser.favorites_only = favorites_only;
+    browser.new_name = new_name;
+    browser.new_address = new_address;
+    if let Some(server) = save {
+        settings.servers.push(server);
+    }
+    if let Some(index) = toggle_favorite {
+        settings.servers[index].favorite ^= true;
+    }
+    if let Some(index) = remove {
+        settings.servers.remove(index);
+    }
+    if add {
+        let address = browser.new_address.trim().to_string();
+        let name = match browser.new_name.trim() {
+            "" => address.clone(),
+            name => name.to_string(),
+        };
+        settings.servers.push(SavedServer {
+            name,
+            address,
+            favorite: false,
+        });
+        browser.new_name.clear();
+        browser.new_address.clear();
+    }
+    if add || refresh {
+        browser.until_query = 0.0;
+    }
+    if back {
+        browser.open = false;
+    }
+    if let Some(address) = join {
+        joins.send(ConnectToServer(address));
+        browser.open = false;
+        next_state.set(PauseState::Running);
+    }
+}
+
+/// The saved servers and those found on the local network, in the order
+/// they are shown.
+fn listings<'a>(
+    browser: &'a ServerBrowser,
+    settings: &MultiplayerSettings,
+    now: f32,
+) -> Vec<Listing<'a>> {
+    let mut listings: Vec<Listing> = settings
+        .servers
+        .iter()
+        .enumerate()
+        .map(|(index, server)| {
+            let socket = browser.resolved.get(&server.address).copied().flatten();
+            Listing {
+                name: server.name.clone(),
+                address: server.address.clone(),
+                socket,
+                saved: Some(index),
+                favorite: server.favorite,
+                answer: socket.and_then(|socket| browser.answer(socket, now)),
+            }
+        })
+        .collect();
+
+    let mut found: Vec<(SocketAddr, &Answer)> = browser
+        .answers
+        .keys()
+        .filter(|socket| {
+            !listings
+                .iter()
+                .any(|listing| listing.socket == Some(**socket))
+        })
+        .filter_map(|socket| Some((*socket, browser.answer(*socket, now)?)))
+        .collect();
+    found.sort_by_key(|(socket, _)| *socket);
+    listings.extend(found.into_iter().map(|(socket, answer)| Listing {
+        name: answer.status.motd.clone(),
+        address: socket.to_string(),
+        socket: Some(socket),
+        saved: None,
+        favorite: false,
+        answer: Some(answer),
+    }));
+
+    // Servers that haven't answered go last when sorting by what they said.
+    listings.sort_by(|a, b| {
+        b.favorite
+            .cmp(&a.favorite)
+            .then_with(|| match browser.sort {
+                SortBy::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                SortBy::Ping => {
+                    let ping = |listing: &Listing| {
+                        listing.answer.map_or(f32::INFINITY, |answer| answer.ping)
+                    };
+                    ping(a).total_cmp(&ping(b))
+                }
+                SortBy::Players => {
+                    let players =
+                        |listing: &Listing| listing.answer.map(|answer| answer.status.players);
+                    players(b).cmp(&players(a))
+                }
+            })
+    });
+    listings
+}
@@ -0,0 +1,257 @@
+//! Graphics, audio and control options that the user can change at runtime.
+
+use bevy::{audio::Volume, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::MainCamera;
+
+/// The range the render distance can be set to, in world units.
+pub const MIN_RENDER_DISTANCE: f32 = 50.0;
+pub const MAX_RENDER_DISTANCE: f32 = 2000.0;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GraphicsSettings>()
+            .init_resource::<AudioSettings>()
+            .init_resource::<ControlSettings>()
+            .add_systems(
+                Update,
+                (
+                    apply_graphics_settings.run_if(resource_changed::<GraphicsSettings>),
+                    apply_audio_settings.run_if(resource_changed::<AudioSettings>),
+                ),
+            );
+    }
+}
+
+/// A resource that stores the graphics options the user can change.
+#[derive(Clone, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// Whether the scrolling cloud layer and its shadows are drawn.
+    pub clouds: bool,
+    pub bloom: bool,
+    pub shadows: bool,
+    /// Whether mirrors render reflections. They are plain panels otherwise.
+    pub reflections: bool,
+    /// How far the camera can see, in world units.
+    pub render_distance: f32,
+    /// How many times the window's resolution screenshots are taken at.
+    /// Above 1 they are rendered offscreen, without the UI.
+    pub screenshot_scale: u32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            clouds: true,
+            bloom: true,
+            shadows: true,
+            reflections: true,
+            render_distance: 1000.0,
+            screenshot_scale: 1,
+        }
+    }
+}
+
+/// A resource that stores the volume, between 0 and 1.
+#[derive(Clone, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master_volume: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { master_volume: 1.0 }
+    }
+}
+
+/// The actions that can be bound to a key.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ControlAction {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Sprint,
+    Attack,
+    Whistle,
+    Interact,
+}
+
+impl ControlAction {
+    pub const ALL: [ControlAction; 9] = [
+        ControlAction::MoveForward,
+        ControlAction::MoveBack,
+        ControlAction::MoveLeft,
+        ControlAction::MoveRight,
+        ControlAction::Jump,
+        ControlAction::Sprint,
+        ControlAction::Attack,
+        ControlAction::Whistle,
+        ControlAction::Interact,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ControlAction::MoveForward => "Move forward",
+            ControlAction::MoveBack => "Move back",
+            ControlAction::MoveLeft => "Move left",
+            ControlAction::MoveRight => "Move right",
+            ControlAction::Jump => "Jump",
+            ControlAction::Sprint => "Sprint",
+            ControlAction::Attack => "Attack",
+            ControlAction::Whistle => "Whistle",
+            ControlAction::Interact => "Interact",
+        }
+    }
+}
+
+/// The keys that do the same thing whatever the controls are, and what they
+/// do. Binding an action to one of them would do both at once.
+const HOTKEYS: &[(KeyCode, &str)] = &[
+    (KeyCode::Escape, "the pause menu"),
+    (KeyCode::KeyT, "chat"),
+    (KeyCode::Slash, "chat commands"),
+    (KeyCode::KeyY, "throw mode"),
+    (KeyCode::KeyQ, "dropping items"),
+    (KeyCode::KeyI, "crafting"),
+    (KeyCode::KeyJ, "the quest log"),
+    (KeyCode::KeyB, "setting the spawn point"),
+    (KeyCode::KeyO, "spectating"),
+    (KeyCode::KeyP, "photo mode"),
+    (KeyCode::KeyC, "placing race checkpoints"),
+    (KeyCode::KeyX, "removing race checkpoints"),
+    (KeyCode::KeyG, "marking the viewer lens"),
+    (KeyCode::KeyV, "viewer blocks"),
+    (KeyCode::KeyM, "mirror blocks"),
+    (KeyCode::KeyF, "auto focus"),
+    (KeyCode::Tab, "the depth of field mode"),
+    (KeyCode::ArrowUp, "the focal distance"),
+    (KeyCode::ArrowDown, "the focal distance"),
+    (KeyCode::ArrowLeft, "the aperture"),
+    (KeyCode::ArrowRight, "the aperture"),
+    (KeyCode::BracketLeft, "the game speed"),
+    (KeyCode::BracketRight, "the game speed"),
+    (KeyCode::Backslash, "the game speed"),
+    (KeyCode::F3, "the debug overlay"),
+    (KeyCode::F5, "ghost runs"),
+    (KeyCode::F6, "ghost runs"),
+    (KeyCode::F7, "ghost runs"),
+    (KeyCode::F9, "the network conditions panel"),
+    (KeyCode::F12, "screenshots"),
+    (KeyCode::Digit1, "the hotbar"),
+    (KeyCode::Digit2, "the hotbar"),
+    (KeyCode::Digit3, "the hotbar"),
+    (KeyCode::Digit4, "the hotbar"),
+    (KeyCode::Digit5, "the hotbar"),
+    (KeyCode::Digit6, "the hotbar"),
+    (KeyCode::Digit7, "the hotbar"),
+    (KeyCode::Digit8, "the hotbar"),
+    (KeyCode::Digit9, "the hotbar"),
+];
+
+/// What `key` does whatever the controls are, if anything.
+pub fn hotkey(key: KeyCode) -> Option<&'static str> {
+    HOTKEYS
+        .iter()
+        .find(|(hotkey, _)| *hotkey == key)
+        .map(|(_, name)| *name)
+}
+
+/// A resource that stores the key bound to each action.
+#[derive(Clone, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    pub move_forward: KeyCode,
+    pub move_back: KeyCode,
+    pub move_left: KeyCode,
+    pub move_right: KeyCode,
+    pub jump: KeyCode,
+    pub sprint: KeyCode,
+    /// Attacks, like left clicking.
+    pub attack: KeyCode,
+    /// Calls the companion over.
+    pub whistle: KeyCode,
+    /// Talks to whoever is nearby.
+    pub interact: KeyCode,
+    /// Whether flying eases into and out of moves, rather than starting and
+    /// stopping at once.
+    pub damped_flight: bool,
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            move_forward: KeyCode::KeyW,
+            move_back: KeyCode::KeyS,
+            move_left: KeyCode::KeyA,
+            move_right: KeyCode::KeyD,
+            jump: KeyCode::Space,
+            sprint: KeyCode::ShiftLeft,
+            attack: KeyCode::KeyR,
+            whistle: KeyCode::KeyH,
+            interact: KeyCode::KeyE,
+            damped_flight: true,
+        }
+    }
+}
+
+impl ControlSettings {
+    pub fn key(&self, action: ControlAction) -> KeyCode {
+        match action {
+            ControlAction::MoveForward => self.move_forward,
+            ControlAction::MoveBack => self.move_back,
+            ControlAction::MoveLeft => self.move_left,
+            ControlAction::MoveRight => self.move_right,
+            ControlAction::Jump => self.jump,
+            ControlAction::Sprint => self.sprint,
+            ControlAction::Attack => self.attack,
+            ControlAction::Whistle => self.whistle,
+            ControlAction::Interact => self.interact,
+        }
+    }
+
+    pub fn key_mut(&mut self, action: ControlAction) -> &mut KeyCode {
+        match action {
+            ControlAction::MoveForward => &mut self.move_forward,
+            ControlAction::MoveBack => &mut self.move_back,
+            ControlAction::MoveLeft => &mut self.move_left,
+            ControlAction::MoveRight => &mut self.move_right,
+            ControlAction::Jump => &mut self.jump,
+            ControlAction::Sprint => &mut self.sprint,
+            ControlAction::Attack => &mut self.attack,
+            ControlAction::Whistle => &mut self.whistle,
+            ControlAction::Interact => &mut self.interact,
+        }
+    }
+}
+
+/// Pushes the graphics settings into the camera and lights. Bloom reaches
+/// the camera through its post-processing.
+fn apply_graphics_settings(
+    graphics_settings: Res<GraphicsSettings>,
+    mut cameras: Query<&mut Projection, With<MainCamera>>,
+    mut lights: Query<&mut DirectionalLight>,
+) {
+    for mut projection in cameras.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.far = graphics_settings.render_distance;
+        }
+    }
+
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = graphics_settings.shadows;
+    }
+}
+
+fn apply_audio_settings(
+    audio_settings: Res<AudioSettings>,
+    mut global_volume: ResMut<GlobalVolume>,
+) {
+    global_volume.volume = Volume::new(audio_settings.master_volume);
+}
@@ -0,0 +1,123 @@
+//! Keeps the world still while the game is paused or in photo mode.
+//!
+//! The game clock, [`Time<Virtual>`], stops whenever the pause menu is open
+//! or photo mode is on, so anything driven by game time holds still, fixed
+//! updates included. Systems that advance the world on their own go in the
+//! [`WorldSimulation`] set as well, which doesn't run at all while stopped,
+//! so they don't do work, such as touching assets, for no time passing. The
+//! menus, the HUD and photo mode's camera keep running on real time.
+//!
+//! While running, the clock goes at the [`GameSpeed`], which `[` and `]`
+//! halve and double and `\` resets. Animations and fixed updates follow the
+//! clock, and replays are recorded in game time, so they play back the same
+//! whatever the speed was while recording. Joined to a server, the clock
+//! always goes at normal speed, hit-stop included: the server moves players
+//! in real time, so a faster or slower client would keep being corrected.
+
+use bevy::prelude::*;
+
+use crate::{menu::PauseState, net::NetClient, photo_mode::PhotoMode};
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameSpeed>()
+            .configure_sets(Update, WorldSimulation.run_if(world_running))
+            .add_systems(
+                Update,
+                (
+                    adjust_game_speed.run_if(not(resource_exists::<NetClient>)),
+                    run_game_clock,
+                )
+                    .chain()
+                    .before(WorldSimulation),
+            );
+    }
+}
+
+/// The systems that move the world along: the clock and weather, mobs,
+/// projectiles, dropped items, recovery over time and the like.
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct WorldSimulation;
+
+/// How fast game time runs compared to real time.
+#[derive(Resource, PartialEq)]
+pub struct GameSpeed {
+    speed: f32,
+    /// A brief slow down on top of the speed, such as hit-stop, where 1 is
+    /// none.
+    pub slow_down: f32,
+}
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            slow_down: 1.0,
+        }
+    }
+}
+
+impl GameSpeed {
+    /// The range the game speed can be set to.
+    pub const MIN: f32 = 0.1;
+    pub const MAX: f32 = 4.0;
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(Self::MIN, Self::MAX);
+    }
+}
+
+/// Whether the world is running, rather than stopped by the pause menu or
+/// photo mode.
+pub fn world_running(pause: Res<State<PauseState>>, photo_mode: Res<State<PhotoMode>>) -> bool {
+    *pause.get() == PauseState::Running && *photo_mode.get() == PhotoMode::Off
+}
+
+/// Halves the game speed on `[`, doubles it on `]` and puts it back to
+/// normal on `\`.
+fn adjust_game_speed(input: Res<ButtonInput<KeyCode>>, mut game_speed: ResMut<GameSpeed>) {
+    let speed = if input.just_pressed(KeyCode::BracketLeft) {
+        game_speed.speed / 2.0
+    } else if input.just_pressed(KeyCode::BracketRight) {
+        game_speed.speed * 2.0
+    } else if input.just_pressed(KeyCode::Backslash) {
+        1.0
+    } else {
+        return;
+    };
+    game_speed.set_speed(speed);
+}
+
+/// Stops the game clock while the world is stopped and starts it again after,
+/// and keeps it going at the game speed. Both the pause menu and photo mode
+/// stop the world, so the clock only goes again once neither does. Joined to
+/// a server, the game speed stays at normal.
+fn run_game_clock(
+    pause: Res<State<PauseState>>,
+    photo_mode: Res<State<PhotoMode>>,
+    client: Option<Res<NetClient>>,
+    mut game_speed: ResMut<GameSpeed>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if client.is_some() {
+        game_speed.set_if_neq(GameSpeed::default());
+    }
+
+    let running = world_running(pause, photo_mode);
+    if running && time.is_paused() {
+        time.unpause();
+    } else if !running && !time.is_paused() {
+        time.pause();
+    }
+
+    let relative_speed = game_speed.speed * game_speed.slow_down;
+    if time.relative_speed() != relative_speed {
+        time.set_relative_speed(relative_speed);
+    }
+}
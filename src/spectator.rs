@@ -0,0 +1,202 @@
+//! Spectating, toggled with O, for looking around the world away from the
+//! player.
+//!
+//! The camera detaches from the player and flies freely, through the ground
+//! and anything else: the movement keys move it across relative to where it
+//! looks, jump and sprint move it up and down, and dragging with the right
+//! mouse button looks around. The mouse wheel speeds it up or slows it down.
+//! Unlike photo mode the world keeps running, with the player standing where
+//! they were left. O again snaps the camera back to following the player.
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::{MouseMotion, MouseWheel},
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    benchmark::BenchmarkState, follow_blend, menu::PauseState, photo_mode::PhotoMode,
+    settings::ControlSettings, MainCamera, MovementInput, Player, FLY_DAMPING_RATE,
+};
+
+/// How fast the spectator camera flies at first, in world units per second,
+/// and how slow and fast the mouse wheel can make it.
+const SPECTATOR_SPEED: f32 = 20.0;
+const MIN_SPECTATOR_SPEED: f32 = 2.0;
+const MAX_SPECTATOR_SPEED: f32 = 200.0;
+/// How many times faster each step of the mouse wheel makes the camera.
+const SPECTATOR_SPEED_STEP: f32 = 1.2;
+/// How far the spectator camera turns per pixel of mouse movement, in
+/// radians.
+const SPECTATOR_LOOK_SENSITIVITY: f32 = 0.003;
+/// How far the spectator camera may pitch up or down, short of straight up or
+/// down so the controls don't flip.
+const SPECTATOR_MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<Spectating>()
+            .add_systems(OnEnter(Spectating::On), start_spectating)
+            .add_systems(OnExit(Spectating::On), stop_spectating)
+            // The benchmark flies the camera itself.
+            .add_systems(OnEnter(BenchmarkState::Running), leave_spectating)
+            .add_systems(
+                Update,
+                (
+                    toggle_spectating.run_if(
+                        in_state(PauseState::Running)
+                            .and_then(in_state(PhotoMode::Off))
+                            .and_then(not(in_state(BenchmarkState::Running))),
+                    ),
+                    (fly_spectator_camera, spectator_hud).chain().run_if(
+                        in_state(Spectating::On)
+                            .and_then(in_state(PauseState::Running))
+                            .and_then(in_state(PhotoMode::Off)),
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether the camera is detached from the player.
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Spectating {
+    #[default]
+    Off,
+    On,
+}
+
+/// How the spectator camera is moving.
+#[derive(Resource)]
+struct SpectatorSession {
+    /// How fast the camera flies, in world units per second.
+    speed: f32,
+    velocity: Vec3,
+}
+
+/// The mouse input that steers the spectator camera.
+#[derive(SystemParam)]
+struct SpectatorMouse<'w, 's> {
+    buttons: Res<'w, ButtonInput<MouseButton>>,
+    motion: EventReader<'w, 's, MouseMotion>,
+    wheel: EventReader<'w, 's, MouseWheel>,
+}
+
+fn toggle_spectating(
+    input: Res<ButtonInput<KeyCode>>,
+    state: Res<State<Spectating>>,
+    mut next_state: ResMut<NextState<Spectating>>,
+) {
+    if input.just_pressed(KeyCode::KeyO) {
+        next_state.set(match state.get() {
+            Spectating::Off => Spectating::On,
+            Spectating::On => Spectating::Off,
+        });
+    }
+}
+
+fn leave_spectating(mut next_state: ResMut<NextState<Spectating>>) {
+    next_state.set(Spectating::Off);
+}
+
+/// Stops the player where they are, since the keys now fly the camera
+/// instead. A flying player stays up in the air.
+fn start_spectating(mut commands: Commands, mut players: Query<&mut MovementInput, With<Player>>) {
+    for mut input in players.iter_mut() {
+        input.direction = Vec3::ZERO;
+        input.vertical = 0.0;
+        input.jump = false;
+        input.sprint = false;
+    }
+    commands.insert_resource(SpectatorSession {
+        speed: SPECTATOR_SPEED,
+        velocity: Vec3::ZERO,
+    });
+}
+
+/// The camera goes back to following the player on its own.
+fn stop_spectating(mut commands: Commands) {
+    commands.remove_resource::<SpectatorSession>();
+}
+
+/// Flies the camera like a flying player, but without the player. It runs
+/// on real time, so it keeps up however fast the game runs.
+fn fly_spectator_camera(
+    time: Res<Time<Real>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut mouse: SpectatorMouse,
+    controls: Res<ControlSettings>,
+    mut session: ResMut<SpectatorSession>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let dt = time.delta_seconds();
+
+    let scroll: f32 = mouse.wheel.read().map(|event| event.y).sum();
+    session.speed = (session.speed * SPECTATOR_SPEED_STEP.powf(scroll))
+        .clamp(MIN_SPECTATOR_SPEED, MAX_SPECTATOR_SPEED);
+
+    let Ok(mut transform) = cameras.get_single_mut() else {
+        return;
+    };
+    // Start from wherever the camera looks, which photo mode may have
+    // changed since the last frame.
+    let (mut yaw, mut pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+    let look: Vec2 = mouse.motion.read().map(|motion| motion.delta).sum();
+    if mouse.buttons.pressed(MouseButton::Right) {
+        yaw -= look.x * SPECTATOR_LOOK_SENSITIVITY;
+        pitch = (pitch - look.y * SPECTATOR_LOOK_SENSITIVITY)
+            .clamp(-SPECTATOR_MAX_PITCH, SPECTATOR_MAX_PITCH);
+    }
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+
+    let forward = Quat::from_rotation_y(yaw) * Vec3::NEG_Z;
+    let right = forward.cross(Vec3::Y);
+    let mut direction = Vec3::ZERO;
+    if keyboard.pressed(controls.move_forward) {
+        direction += forward;
+    }
+    if keyboard.pressed(controls.move_back) {
+        direction -= forward;
+    }
+    if keyboard.pressed(controls.move_left) {
+        direction -= right;
+    }
+    if keyboard.pressed(controls.move_right) {
+        direction += right;
+    }
+    if keyboard.pressed(controls.jump) {
+        direction += Vec3::Y;
+    }
+    if keyboard.pressed(controls.sprint) {
+        direction -= Vec3::Y;
+    }
+
+    let wanted = direction.normalize_or_zero() * session.speed;
+    session.velocity = if controls.damped_flight {
+        session
+            .velocity
+            .lerp(wanted, follow_blend(FLY_DAMPING_RATE, dt))
+    } else {
+        wanted
+    };
+    transform.translation += session.velocity * dt;
+}
+
+/// Says the camera is detached, how fast it flies, and how to get back.
+fn spectator_hud(mut contexts: EguiContexts, session: Res<SpectatorSession>) {
+    egui::Area::new(egui::Id::new("spectator"))
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 8.0))
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!(
+                "Spectating at {:.0} units/s (scroll to change). O returns to the player.",
+                session.speed
+            ));
+        });
+}
@@ -0,0 +1,164 @@
+//! Stamina, spent by sprinting and jumping and recovered while resting.
+//!
+//! `player_controller` only lets a character sprint while it has stamina
+//! left, and charges it for each jump. After a short rest the stamina refills
+//! again. The player's stamina is shown as a bar above their hearts, and the
+//! rates can be tuned in the `[stamina]` section of `config.toml`.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{simulation::WorldSimulation, Player};
+
+const STAMINA_BAR_WIDTH: f32 = 200.0;
+const STAMINA_BAR_HEIGHT: f32 = 6.0;
+const STAMINA_COLOR: Color = Color::srgb(0.95, 0.75, 0.2);
+const EMPTY_STAMINA_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.5);
+
+pub struct StaminaPlugin;
+
+impl Plugin for StaminaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StaminaSettings>()
+            .add_systems(Startup, spawn_stamina_bar)
+            .add_systems(
+                Update,
+                (recover_stamina.in_set(WorldSimulation), update_stamina_bar).chain(),
+            );
+    }
+}
+
+/// How fast stamina is spent and recovered.
+#[derive(Clone, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaminaSettings {
+    /// The stamina of a fully rested character.
+    pub max: f32,
+    /// How much stamina sprinting costs per second.
+    pub sprint_cost: f32,
+    /// How much stamina each jump costs.
+    pub jump_cost: f32,
+    /// How much stamina comes back per second while resting.
+    pub recovery_rate: f32,
+    /// How long a character has to rest before stamina starts coming back,
+    /// in seconds.
+    pub recovery_delay: f32,
+}
+
+impl Default for StaminaSettings {
+    fn default() -> Self {
+        Self {
+            max: 100.0,
+            sprint_cost: 20.0,
+            jump_cost: 10.0,
+            recovery_rate: 25.0,
+            recovery_delay: 1.0,
+        }
+    }
+}
+
+/// How much more a character can sprint and jump.
+#[derive(Component)]
+pub struct Stamina {
+    pub current: f32,
+    /// Seconds since stamina was last spent.
+    rested: f32,
+}
+
+impl Stamina {
+    pub fn new(settings: &StaminaSettings) -> Self {
+        Self {
+            current: settings.max,
+            rested: 0.0,
+        }
+    }
+
+    /// Whether there is any stamina left to sprint with.
+    pub fn can_sprint(&self) -> bool {
+        self.current > 0.0
+    }
+
+    /// Spends `amount` of stamina, down to zero.
+    pub fn spend(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+        self.rested = 0.0;
+    }
+}
+
+/// The filled part of the stamina bar.
+#[derive(Component)]
+struct StaminaFill;
+
+fn spawn_stamina_bar(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                // Just above the hearts.
+                bottom: Val::Px(90.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(STAMINA_BAR_WIDTH),
+                    height: Val::Px(STAMINA_BAR_HEIGHT),
+                    ..default()
+                },
+                background_color: EMPTY_STAMINA_COLOR.into(),
+                ..default()
+            })
+            .with_children(|bar| {
+                bar.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: STAMINA_COLOR.into(),
+                        ..default()
+                    },
+                    StaminaFill,
+                ));
+            });
+        });
+}
+
+/// Refills the stamina of characters that have rested long enough.
+fn recover_stamina(
+    time: Res<Time>,
+    settings: Res<StaminaSettings>,
+    mut characters: Query<&mut Stamina>,
+) {
+    let dt = time.delta_seconds();
+    for mut stamina in characters.iter_mut() {
+        if stamina.rested < settings.recovery_delay {
+            stamina.rested += dt;
+        } else if stamina.current < settings.max {
+            stamina.current = (stamina.current + settings.recovery_rate * dt).min(settings.max);
+        } else if stamina.current > settings.max {
+            // The maximum was lowered in the config.
+            stamina.current = settings.max;
+        }
+    }
+}
+
+fn update_stamina_bar(
+    settings: Res<StaminaSettings>,
+    players: Query<&Stamina, (With<Player>, Changed<Stamina>)>,
+    mut fills: Query<&mut Style, With<StaminaFill>>,
+) {
+    let Ok(stamina) = players.get_single() else {
+        return;
+    };
+
+    let fill = stamina.current / settings.max.max(f32::EPSILON);
+    for mut style in fills.iter_mut() {
+        style.width = Val::Percent(fill.clamp(0.0, 1.0) * 100.0);
+    }
+}
@@ -0,0 +1,594 @@
+//! Teams and scores for minigames, kept by the server.
+//!
+//! The [`Scoreboard`] holds the teams, each with a color, whether its
+//! members may hurt each other, and its players, and the scores players have
+//! for each objective. Only the server changes it: the `/team` and `/score`
+//! chat commands ask the server to, and it sends everyone the result. It
+//! only takes changes from the host, or from the admins a dedicated server's
+//! config lists, and only about players who are playing. The number of teams
+//! and objectives, and the length of their names, are limited, so the
+//! scoreboard stays small enough to send. The objective picked with
+//! `/score show` is listed down the right of the screen, best score first,
+//! each player in their team's color.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatCommandsExt, ChatLog, CommandArgs},
+    net::{local_player_id, player_name, NetClient},
+};
+
+/// How many teams and objectives there can be at once.
+const MAX_TEAMS: usize = 16;
+const MAX_OBJECTIVES: usize = 16;
+/// The most characters a team or objective name can have.
+const MAX_NAME_LENGTH: usize = 24;
+const SIDEBAR_FONT_SIZE: f32 = 18.0;
+const NO_TEAM_COLOR: Color = Color::WHITE;
+
+pub struct TeamsPlugin;
+
+impl Plugin for TeamsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Scoreboard>()
+            .add_event::<ScoreboardRequest>()
+            .add_chat_command(
+                "team",
+                "<add|remove|join|leave|friendlyfire|list> ...",
+                "Sets up teams",
+                team_command,
+            )
+            .add_chat_command(
+                "score",
+                "<add|set|reset|show|list> ...",
+                "Keeps score of objectives",
+                score_command,
+            )
+            .add_systems(Startup, spawn_sidebar)
+            .add_systems(Update, update_sidebar);
+    }
+}
+
+/// The teams and scores of a session.
+#[derive(Resource, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Scoreboard {
+    teams: BTreeMap<String, Team>,
+    /// Each objective's scores, by player id.
+    objectives: BTreeMap<String, BTreeMap<u32, i32>>,
+    /// The objective listed on the sidebar.
+    shown: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct Team {
+    color: TeamColor,
+    /// Whether members can hurt each other.
+    friendly_fire: bool,
+    /// The ids of the players on the team.
+    players: BTreeSet<u32>,
+}
+
+/// The colors teams can have.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TeamColor {
+    Red,
+    Blue,
+    Green,
+    Yellow,
+    Purple,
+    Orange,
+}
+
+impl TeamColor {
+    const ALL: [TeamColor; 6] = [
+        TeamColor::Red,
+        TeamColor::Blue,
+        TeamColor::Green,
+        TeamColor::Yellow,
+        TeamColor::Purple,
+        TeamColor::Orange,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            TeamColor::Red => "red",
+            TeamColor::Blue => "blue",
+            TeamColor::Green => "green",
+            TeamColor::Yellow => "yellow",
+            TeamColor::Purple => "purple",
+            TeamColor::Orange => "orange",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|color| color.name() == name)
+    }
+
+    fn color(self) -> Color {
+        match self {
+            TeamColor::Red => Color::srgb(0.95, 0.3, 0.3),
+            TeamColor::Blue => Color::srgb(0.35, 0.55, 1.0),
+            TeamColor::Green => Color::srgb(0.35, 0.85, 0.4),
+            TeamColor::Yellow => Color::srgb(0.95, 0.85, 0.3),
+            TeamColor::Purple => Color::srgb(0.75, 0.45, 0.95),
+            TeamColor::Orange => Color::srgb(1.0, 0.6, 0.2),
+        }
+    }
+}
+
+/// A change to the scoreboard, which the server makes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ScoreboardChange {
+    AddTeam {
+        team: String,
+        color: TeamColor,
+    },
+    RemoveTeam {
+        team: String,
+    },
+    /// Puts a player on a team, taking them off any other.
+    JoinTeam {
+        team: String,
+        player: u32,
+    },
+    LeaveTeam {
+        player: u32,
+    },
+    SetFriendlyFire {
+        team: String,
+        on: bool,
+    },
+    AddScore {
+        objective: String,
+        player: u32,
+        amount: i32,
+    },
+    SetScore {
+        objective: String,
+        player: u32,
+        score: i32,
+    },
+    /// Drops an objective and all its scores.
+    ResetObjective {
+        objective: String,
+    },
+    /// Lists an objective on the sidebar, or `None` for nothing.
+    ShowObjective {
+        objective: Option<String>,
+    },
+}
+
+/// Asks the server to change the scoreboard.
+#[derive(Event)]
+pub struct ScoreboardRequest(pub ScoreboardChange);
+
+impl Scoreboard {
+    /// Makes a change, saying how it went. `players` are the ids of the
+    /// players playing, the only ones who can join teams or have scores.
+    pub fn apply(&mut self, change: ScoreboardChange, players: &[u32]) -> Result<String, String> {
+        match change {
+            ScoreboardChange::AddTeam { team, color } => {
+                if self.teams.contains_key(&team) {
+                    return Err(format!("There is already a team called {team}"));
+                }
+                check_name(&team)?;
+                if self.teams.len() >= MAX_TEAMS {
+                    return Err(format!("There can't be more than {MAX_TEAMS} teams"));
+                }
+                self.teams.insert(
+                    team.clone(),
+                    Team {
+                        color,
+                        friendly_fire: false,
+                        players: BTreeSet::new(),
+                    },
+                );
+                Ok(format!("Added the {} team {team}", color.name()))
+            }
+            ScoreboardChange::RemoveTeam { team } => {
+                self.teams.remove(&team).ok_or_else(|| no_team(&team))?;
+                Ok(format!("Removed the team {team}"))
+            }
+            ScoreboardChange::JoinTeam { team, player } => {
+                if !self.teams.contains_key(&team) {
+                    return Err(no_team(&team));
+                }
+                check_player(player, players)?;
+                self.forget_team(player);
+                if let Some(joined) = self.teams.get_mut(&team) {
+                    joined.players.insert(player);
+                }
+                Ok(format!("{} joined {team}", player_name(player)))
+            }
+            ScoreboardChange::LeaveTeam { player } => match self.forget_team(player) {
+                Some(team) => Ok(format!("{} left {team}", player_name(player))),
+                None => Err(format!("{} isn't on a team", player_name(player))),
+            },
+            ScoreboardChange::SetFriendlyFire { team, on } => {
+                self.teams
+                    .get_mut(&team)
+                    .ok_or_else(|| no_team(&team))?
+                    .friendly_fire = on;
+                Ok(format!(
+                    "Friendly fire is {} for {team}",
+                    if on { "on" } else { "off" }
+                ))
+            }
+            ScoreboardChange::AddScore {
+                objective,
+                player,
+                amount,
+            } => {
+                check_player(player, players)?;
+                self.check_objective(&objective)?;
+                let score = self
+                    .objectives
+                    .entry(objective.clone())
+                    .or_default()
+                    .entry(player)
+                    .or_default();
+                *score = score.saturating_add(amount);
+                Ok(format!("{} has {score} {objective}", player_name(player)))
+            }
+            ScoreboardChange::SetScore {
+                objective,
+                player,
+                score,
+            } => {
+                check_player(player, players)?;
+                self.check_objective(&objective)?;
+                self.objectives
+                    .entry(objective.clone())
+                    .or_default()
+                    .insert(player, score);
+                Ok(format!("{} has {score} {objective}", player_name(player)))
+            }
+            ScoreboardChange::ResetObjective { objective } => {
+                self.objectives
+                    .remove(&objective)
+                    .ok_or_else(|| no_objective(&objective))?;
+                if self.shown.as_ref() == Some(&objective) {
+                    self.shown = None;
+                }
+                Ok(format!("Reset {objective}"))
+            }
+            ScoreboardChange::ShowObjective { objective } => {
+                if let Some(objective) = &objective {
+                    if !self.objectives.contains_key(objective) {
+                        return Err(no_objective(objective));
+                    }
+                }
+                let said = match &objective {
+                    Some(objective) => format!("Showing {objective}"),
+                    None => "Hid the scores".to_string(),
+                };
+                self.shown = objective;
+                Ok(said)
+            }
+        }
+    }
+
+    /// Whether scores can be kept for this objective: it already has some, or
+    /// there is room for another with a name that isn't too long.
+    fn check_objective(&self, objective: &str) -> Result<(), String> {
+        if self.objectives.contains_key(objective) {
+            return Ok(());
+        }
+        check_name(objective)?;
+        if self.objectives.len() >= MAX_OBJECTIVES {
+            return Err(format!(
+                "There can't be more than {MAX_OBJECTIVES} objectives"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Takes a player who left off their team and out of the scores.
+    pub fn forget(&mut self, player: u32) {
+        self.forget_team(player);
+        for scores in self.objectives.values_mut() {
+            scores.remove(&player);
+        }
+    }
+
+    /// Takes a player off their team, returning its name.
+    fn forget_team(&mut self, player: u32) -> Option<String> {
+        let (name, team) = self
+            .teams
+            .iter_mut()
+            .find(|(_, team)| team.players.contains(&player))?;
+        team.players.remove(&player);
+        Some(name.clone())
+    }
+
+    /// The team a player is on.
+    fn team_of(&self, player: u32) -> Option<&Team> {
+        self.teams
+            .values()
+            .find(|team| team.players.contains(&player))
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "Names can't be longer than {MAX_NAME_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+fn check_player(player: u32, players: &[u32]) -> Result<(), String> {
+    if !players.contains(&player) {
+        return Err(format!("{} isn't playing", player_name(player)));
+    }
+    Ok(())
+}
+
+fn no_team(team: &str) -> String {
+    format!("There is no team called {team}")
+}
+
+fn no_objective(objective: &str) -> String {
+    format!("There is no objective called {objective}")
+}
+
+/// Reads the player a command is about: the id given, or the local player's
+/// own when there is none.
+fn player_arg(arg: Option<&str>, own: Option<u32>) -> Option<u32> {
+    match arg {
+        Some(arg) => arg.parse().ok(),
+        None => own,
+    }
+}
+
+fn team_command(
+    In(args): In<CommandArgs>,
+    scoreboard: Res<Scoreboard>,
+    client: Option<Res<NetClient>>,
+    mut log: ResMut<ChatLog>,
+    mut requests: EventWriter<ScoreboardRequest>,
+) {
+    let own = local_player_id(client.as_deref());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let change = match args[..] {
+        ["add", team, color] => TeamColor::parse(color).map(|color| ScoreboardChange::AddTeam {
+            team: team.to_string(),
+            color,
+        }),
+        ["remove", team] => Some(ScoreboardChange::RemoveTeam {
+            team: team.to_string(),
+        }),
+        ["join", team] | ["join", team, _] => {
+            player_arg(args.get(2).copied(), own).map(|player| ScoreboardChange::JoinTeam {
+                team: team.to_string(),
+                player,
+            })
+        }
+        ["leave"] | ["leave", _] => player_arg(args.get(1).copied(), own)
+            .map(|player| ScoreboardChange::LeaveTeam { player }),
+        ["friendlyfire", team, on] => match on {
+            "on" => Some(true),
+            "off" => Some(false),
+            _ => None,
+        }
+        .map(|on| ScoreboardChange::SetFriendlyFire {
+            team: team.to_string(),
+            on,
+        }),
+        ["list"] => {
+            if scoreboard.teams.is_empty() {
+                log.info("There are no teams");
+            }
+            for (name, team) in &scoreboard.teams {
+                let players: Vec<String> = team.players.iter().map(|id| player_name(*id)).collect();
+                log.info(format!(
+                    "{name} ({}, friendly fire {}): {}",
+                    team.color.name(),
+                    if team.friendly_fire { "on" } else { "off" },
+                    if players.is_empty() {
+                        "nobody".to_string()
+                    } else {
+                        players.join(", ")
+                    },
+                ));
+            }
+            return;
+        }
+        _ => None,
+    };
+    match change {
+        Some(change) => {
+            requests.send(ScoreboardRequest(change));
+        }
+        None => {
+            let colors: Vec<&str> = TeamColor::ALL.iter().map(|color| color.name()).collect();
+            log.info(format!(
+                "Usage: /team add <name> <{}>, /team remove <name>, /team join <name> [player], \
+                 /team leave [player], /team friendlyfire <name> <on|off>, /team list",
+                colors.join("|")
+            ));
+        }
+    }
+}
+
+fn score_command(
+    In(args): In<CommandArgs>,
+    scoreboard: Res<Scoreboard>,
+    client: Option<Res<NetClient>>,
+    mut log: ResMut<ChatLog>,
+    mut requests: EventWriter<ScoreboardRequest>,
+) {
+    let own = local_player_id(client.as_deref());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let change = match args[..] {
+        ["add", objective, amount] | ["add", objective, amount, _] => amount
+            .parse()
+            .ok()
+            .zip(player_arg(args.get(3).copied(), own))
+            .map(|(amount, player)| ScoreboardChange::AddScore {
+                objective: objective.to_string(),
+                player,
+                amount,
+            }),
+        ["set", objective, score] | ["set", objective, score, _] => score
+            .parse()
+            .ok()
+            .zip(player_arg(args.get(3).copied(), own))
+            .map(|(score, player)| ScoreboardChange::SetScore {
+                objective: objective.to_string(),
+                player,
+                score,
+            }),
+        ["reset", objective] => Some(ScoreboardChange::ResetObjective {
+            objective: objective.to_string(),
+        }),
+        ["show", objective] => Some(ScoreboardChange::ShowObjective {
+            objective: (objective != "off").then(|| objective.to_string()),
+        }),
+        ["list"] => {
+            if scoreboard.objectives.is_empty() {
+                log.info("There are no objectives");
+            }
+            for (objective, scores) in &scoreboard.objectives {
+                let scores: Vec<String> = scores
+                    .iter()
+                    .map(|(player, score)| format!("{} {score}", player_name(*player)))
+                    .collect();
+                log.info(format!("{objective}: {}", scores.join(", ")));
+            }
+            return;
+        }
+        _ => None,
+    };
+    match change {
+        Some(change) => {
+            requests.send(ScoreboardRequest(change));
+        }
+        None => log.info(
+            "Usage: /score add <objective> <amount> [player], \
+             /score set <objective> <score> [player], /score reset <objective>, \
+             /score show <objective|off>, /score list",
+        ),
+    }
+}
+
+/// The list of scores down the right of the screen.
+#[derive(Component)]
+struct Sidebar;
+
+fn spawn_sidebar(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::default().with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(12.0),
+            // Below the tracked quest.
+            top: Val::Percent(55.0),
+            ..default()
+        }),
+        Sidebar,
+    ));
+}
+
+/// Lists the shown objective's scores, best first, each player in their
+/// team's color.
+fn update_sidebar(scoreboard: Res<Scoreboard>, mut sidebars: Query<&mut Text, With<Sidebar>>) {
+    if !scoreboard.is_changed() {
+        return;
+    }
+
+    let style = |color: Color| TextStyle {
+        font_size: SIDEBAR_FONT_SIZE,
+        color,
+        ..default()
+    };
+    let mut sections = Vec::new();
+    let shown = scoreboard
+        .shown
+        .as_ref()
+        .and_then(|objective| Some((objective, scoreboard.objectives.get(objective)?)));
+    if let Some((objective, scores)) = shown {
+        sections.push(TextSection::new(objective.clone(), style(NO_TEAM_COLOR)));
+        let mut scores: Vec<(&u32, &i32)> = scores.iter().collect();
+        scores.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (player, score) in scores {
+            let color = scoreboard
+                .team_of(*player)
+                .map_or(NO_TEAM_COLOR, |team| team.color.color());
+            sections.push(TextSection::new(
+                format!("\n{}  {score}", player_name(*player)),
+                style(color),
+            ));
+        }
+    }
+
+    for mut sidebar in sidebars.iter_mut() {
+        sidebar.sections.clone_from(&sections);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_team(team: &str) -> ScoreboardChange {
+        ScoreboardChange::AddTeam {
+            team: team.to_string(),
+            color: TeamColor::Red,
+        }
+    }
+
+    fn set_score(objective: &str, player: u32) -> ScoreboardChange {
+        ScoreboardChange::SetScore {
+            objective: objective.to_string(),
+            player,
+            score: 1,
+        }
+    }
+
+    #[test]
+    fn teams_and_objectives_are_limited() {
+        let mut scoreboard = Scoreboard::default();
+        for index in 0..MAX_TEAMS {
+            scoreboard
+                .apply(add_team(&format!("team{index}")), &[])
+                .unwrap();
+        }
+        assert!(scoreboard.apply(add_team("one_more"), &[]).is_err());
+
+        for index in 0..MAX_OBJECTIVES {
+            let objective = format!("objective{index}");
+            scoreboard.apply(set_score(&objective, 1), &[1]).unwrap();
+        }
+        assert!(scoreboard.apply(set_score("one_more", 1), &[1]).is_err());
+        // Objectives that are already kept can still change.
+        assert!(scoreboard.apply(set_score("objective0", 1), &[1]).is_ok());
+    }
+
+    #[test]
+    fn names_are_limited() {
+        let mut scoreboard = Scoreboard::default();
+        let long = "x".repeat(MAX_NAME_LENGTH + 1);
+        assert!(scoreboard.apply(add_team(&long), &[]).is_err());
+        assert!(scoreboard.apply(set_score(&long, 1), &[1]).is_err());
+        assert_eq!(scoreboard, Scoreboard::default());
+    }
+
+    #[test]
+    fn only_players_who_are_playing_count() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.apply(add_team("red"), &[]).unwrap();
+        let join = |player| ScoreboardChange::JoinTeam {
+            team: "red".to_string(),
+            player,
+        };
+        assert!(scoreboard.apply(join(7), &[1, 2]).is_err());
+        assert!(scoreboard.apply(set_score("kills", 7), &[1, 2]).is_err());
+        assert!(scoreboard.apply(join(2), &[1, 2]).is_ok());
+        assert!(scoreboard.apply(set_score("kills", 2), &[1, 2]).is_ok());
+    }
+}
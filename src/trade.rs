@@ -0,0 +1,348 @@
+//! Buying and selling at market stalls, at the prices in
+//! `assets/trade/*.ron`.
+//!
+//! Coins are the currency: an item like any other, carried in the
+//! inventory. Each shop has a stall with a keeper to talk to, and asking the
+//! keeper to trade opens the shop's window, listing what it sells and buys
+//! and for how much. A trade only goes through when the player has the coins
+//! or items for it and room for what they get back. There are no villages
+//! yet, so stalls stand at fixed places.
+
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::Deserialize;
+
+use crate::{
+    animation::{AnimFsm, AnimState, AnimationBinding, ClipSet},
+    dialogue::Npc,
+    inventory::{Inventory, ItemId},
+    nameplates::Nameplate,
+    photo_mode::PhotoMode,
+    Player, Position,
+};
+
+/// The directory shops are loaded from.
+const SHOP_DIR: &str = "assets/trade";
+/// The item prices are paid in.
+const CURRENCY: &str = "coin";
+/// The stalls in the world: the shop they house, where its keeper stands and
+/// the way they face, in radians.
+const STALLS: [(&str, Vec3, f32); 1] = [("general_store", Vec3::new(-9.0, 0.0, -7.0), 2.4)];
+/// How far the player can walk from a stall before its window closes.
+const LEAVE_DISTANCE: f32 = 5.0;
+/// How big shopkeepers are, the same as the player.
+const KEEPER_SCALE: f32 = 0.012;
+/// How far above the ground shop names float.
+const NAMEPLATE_HEIGHT: f32 = 1.4;
+const COUNTER_SIZE: Vec3 = Vec3::new(2.4, 0.9, 0.7);
+const ROOF_HEIGHT: f32 = 2.4;
+const WOOD_COLOR: Color = Color::srgb(0.45, 0.3, 0.18);
+const AWNING_COLOR: Color = Color::srgb(0.75, 0.2, 0.2);
+
+pub struct TradePlugin;
+
+impl Plugin for TradePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_shops())
+            .add_event::<OpenTrade>()
+            .add_systems(Startup, spawn_stalls)
+            .add_systems(
+                Update,
+                (
+                    open_trade,
+                    close_distant_trade.run_if(resource_exists::<Trade>),
+                    trade_window
+                        .run_if(resource_exists::<Trade>)
+                        .run_if(in_state(PhotoMode::Off)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A shop, as written in a shop file.
+#[derive(Deserialize)]
+struct Shop {
+    /// The name the shop is opened by.
+    id: String,
+    /// What the stall is called, shown above its keeper.
+    name: String,
+    /// The id of the dialogue the shop's keeper speaks.
+    keeper: String,
+    offers: Vec<Offer>,
+}
+
+/// An item a shop trades in, and its prices in coins.
+#[derive(Deserialize)]
+struct Offer {
+    item: ItemId,
+    /// What the player pays for one, if the shop sells it.
+    #[serde(default)]
+    buy: Option<u32>,
+    /// What the shop pays for one, if it buys it. This has to be less than
+    /// crafting one out of bought items costs, or crafting and selling would
+    /// make coins out of nothing.
+    #[serde(default)]
+    sell: Option<u32>,
+}
+
+/// Every shop that was loaded, by id.
+#[derive(Resource)]
+struct Shops(HashMap<String, Shop>);
+
+/// Opens the trade window of the shop with this id.
+#[derive(Event)]
+pub struct OpenTrade(pub String);
+
+/// The shop the player is trading with.
+#[derive(Resource)]
+struct Trade {
+    shop: String,
+    /// Where the shop's keeper stands.
+    stall: Vec3,
+    /// Why the last trade failed, shown in the window.
+    status: Option<String>,
+}
+
+/// Reads every shop file, skipping and warning about those that can't be
+/// read.
+fn load_shops() -> Shops {
+    let entries = match fs::read_dir(SHOP_DIR) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to read {SHOP_DIR}, no shops loaded: {error}");
+            return Shops(HashMap::new());
+        }
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    paths.sort();
+
+    let shops = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path)
+                .map_err(|error| warn!("Failed to read {}: {error}", path.display()))
+                .ok()?;
+            ron::from_str::<Shop>(&text)
+                .map_err(|error| warn!("Failed to parse {}: {error}", path.display()))
+                .ok()
+        })
+        .map(|shop| (shop.id.clone(), shop))
+        .collect();
+    Shops(shops)
+}
+
+/// Puts up each stall, with its keeper behind the counter.
+fn spawn_stalls(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    shops: Res<Shops>,
+) {
+    let mut graph = AnimationGraph::new();
+    let survey = graph.add_clip(
+        asset_server.load(GltfAssetLabel::Animation(0).from_asset("models/Fox.glb")),
+        1.0,
+        graph.root,
+    );
+    let clips = ClipSet::new(graphs.add(graph)).with_clip(AnimState::Idle, survey, 1.0);
+
+    let wood = materials.add(WOOD_COLOR);
+    let awning = materials.add(AWNING_COLOR);
+    let counter = meshes.add(Cuboid::from_size(COUNTER_SIZE));
+    let post = meshes.add(Cuboid::new(0.1, ROOF_HEIGHT, 0.1));
+    let roof = meshes.add(Cuboid::new(COUNTER_SIZE.x + 0.4, 0.1, 2.2));
+
+    for (id, position, radians_y) in STALLS {
+        let Some(shop) = shops.0.get(id) else {
+            warn!("No shop called {id}, leaving its stall out");
+            continue;
+        };
+        let rotation = Quat::from_rotation_y(radians_y);
+
+        // The counter stands in front of the keeper, under a roof on posts
+        // at its corners.
+        commands
+            .spawn(SpatialBundle::from_transform(
+                Transform::from_translation(position).with_rotation(rotation),
+            ))
+            .with_children(|stall| {
+                stall.spawn(PbrBundle {
+                    mesh: counter.clone(),
+                    material: wood.clone(),
+                    transform: Transform::from_xyz(0.0, COUNTER_SIZE.y / 2.0, 1.0),
+                    ..default()
+                });
+                for (x, z) in [(-1.0, 1.0), (1.0, 1.0), (-1.0, -0.8), (1.0, -0.8)] {
+                    stall.spawn(PbrBundle {
+                        mesh: post.clone(),
+                        material: wood.clone(),
+                        transform: Transform::from_xyz(
+                            x * COUNTER_SIZE.x / 2.0,
+                            ROOF_HEIGHT / 2.0,
+                            z,
+                        ),
+                        ..default()
+                    });
+                }
+                stall.spawn(PbrBundle {
+                    mesh: roof.clone(),
+                    material: awning.clone(),
+                    transform: Transform::from_xyz(0.0, ROOF_HEIGHT, 0.1),
+                    ..default()
+                });
+            });
+
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load("models/Fox.glb#Scene0"),
+                transform: Transform::from_translation(position)
+                    .with_rotation(rotation)
+                    .with_scale(Vec3::splat(KEEPER_SCALE)),
+                ..default()
+            },
+            AnimationBinding::new(clips.clone()),
+            AnimFsm::default(),
+            Nameplate::new(shop.name.clone(), NAMEPLATE_HEIGHT),
+            Npc {
+                dialogue: shop.keeper.clone(),
+            },
+        ));
+    }
+}
+
+/// Opens the window of the shop asked for.
+fn open_trade(mut commands: Commands, mut requests: EventReader<OpenTrade>, shops: Res<Shops>) {
+    for OpenTrade(id) in requests.read() {
+        let stall = STALLS
+            .iter()
+            .find(|(stall, ..)| stall == id)
+            .map(|(_, position, _)| *position);
+        let (Some(stall), true) = (stall, shops.0.contains_key(id)) else {
+            warn!("No shop called {id} to trade with");
+            continue;
+        };
+        commands.insert_resource(Trade {
+            shop: id.clone(),
+            stall,
+            status: None,
+        });
+    }
+}
+
+/// Closes the trade window once the player walks away from the stall.
+fn close_distant_trade(
+    mut commands: Commands,
+    trade: Res<Trade>,
+    players: Query<&Position, With<Player>>,
+) {
+    let in_reach = players
+        .get_single()
+        .is_ok_and(|player| player.current.distance(trade.stall) <= LEAVE_DISTANCE);
+    if !in_reach {
+        commands.remove_resource::<Trade>();
+    }
+}
+
+fn trade_window(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut trade: ResMut<Trade>,
+    shops: Res<Shops>,
+    mut players: Query<&mut Inventory, With<Player>>,
+) {
+    let (Some(shop), Ok(mut inventory)) = (shops.0.get(&trade.shop), players.get_single_mut())
+    else {
+        commands.remove_resource::<Trade>();
+        return;
+    };
+    let currency = ItemId::new(CURRENCY);
+
+    let mut open = true;
+    egui::Window::new(&shop.name)
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("Coins: {}", inventory.count(&currency)));
+            ui.separator();
+
+            egui::Grid::new("trade_offers")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for offer in &shop.offers {
+                        ui.label(format!(
+                            "{} ({} held)",
+                            offer.item.label(),
+                            inventory.count(&offer.item)
+                        ));
+                        match offer.buy {
+                            Some(price) => {
+                                if ui.button(format!("Buy for {price}")).clicked() {
+                                    trade.status = buy(&offer.item, price, &mut inventory).err();
+                                }
+                            }
+                            None => {
+                                ui.label("");
+                            }
+                        }
+                        match offer.sell {
+                            Some(price) => {
+                                if ui.button(format!("Sell for {price}")).clicked() {
+                                    trade.status = sell(&offer.item, price, &mut inventory).err();
+                                }
+                            }
+                            None => {
+                                ui.label("");
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            if let Some(status) = &trade.status {
+                ui.label(status);
+            }
+        });
+    if !open {
+        commands.remove_resource::<Trade>();
+    }
+}
+
+/// Pays `price` coins for one of `item`, if the inventory holds the coins
+/// and has room for the item.
+fn buy(item: &ItemId, price: u32, inventory: &mut Inventory) -> Result<(), String> {
+    // Trade in a copy, so that nothing changes unless everything fits.
+    let mut traded = inventory.clone();
+    if !traded.remove(&ItemId::new(CURRENCY), price) {
+        return Err("Not enough coins".to_string());
+    }
+    if traded.add(item, 1) > 0 {
+        return Err("Not enough room in the inventory".to_string());
+    }
+    *inventory = traded;
+    Ok(())
+}
+
+/// Sells one of `item` for `price` coins, if the inventory holds one and has
+/// room for the coins.
+fn sell(item: &ItemId, price: u32, inventory: &mut Inventory) -> Result<(), String> {
+    let mut traded = inventory.clone();
+    if !traded.remove(item, 1) {
+        return Err(format!("No {} to sell", item.label()));
+    }
+    if traded.add(&ItemId::new(CURRENCY), price) > 0 {
+        return Err("Not enough room in the inventory".to_string());
+    }
+    *inventory = traded;
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! Viewer blocks, whose front face shows what a camera somewhere else sees.
+//!
+//! G marks the spot new viewers look from: where the player stands, facing
+//! the way they face. V places a viewer block in front of the player showing
+//! the marked view, and Shift+V removes the nearest one. Each viewer has its
+//! own camera rendering into a texture on its face. Only the few viewers
+//! nearest the player render at all, and the further away one is, the lower
+//! the resolution it renders at.
+
+use bevy::{
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+};
+
+use crate::{menu::PauseState, photo_mode::PhotoMode, Player, Position, Rotation};
+
+/// How many viewers can render at once. The nearest ones win.
+const MAX_ACTIVE_VIEWERS: usize = 4;
+/// Viewers further than this from the player stop rendering.
+const VIEWER_RANGE: f32 = 48.0;
+/// The resolution viewers right next to the player render at.
+const MAX_VIEWER_RESOLUTION: u32 = 512;
+/// The lowest resolution viewers render at, however far away.
+const MIN_VIEWER_RESOLUTION: u32 = 64;
+/// How much further away a viewer has to be for its resolution to halve.
+const RESOLUTION_HALVING_DISTANCE: f32 = 8.0;
+/// How far in front of the player viewers are placed.
+const PLACE_DISTANCE: f32 = 1.5;
+/// How close a viewer has to be to the player to be removed.
+const REMOVE_REACH: f32 = 3.0;
+/// How high above the player's feet the marked view looks from.
+const EYE_HEIGHT: f32 = 1.0;
+const VIEWER_FRAME_COLOR: Color = Color::srgb(0.15, 0.15, 0.18);
+
+pub struct ViewerPlugin;
+
+impl Plugin for ViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ViewerLens>()
+            .add_systems(Startup, setup_viewer_assets)
+            .add_systems(
+                Update,
+                (
+                    (mark_viewer_lens, place_viewer, remove_viewer)
+                        .run_if(in_state(PauseState::Running).and_then(in_state(PhotoMode::Off))),
+                    schedule_viewers,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The spot new viewers look from, once one has been marked.
+#[derive(Resource, Default)]
+struct ViewerLens(Option<Transform>);
+
+/// A viewer block, and the camera that renders onto its face.
+#[derive(Component)]
+struct Viewer {
+    camera: Entity,
+    image: Handle<Image>,
+    screen: Handle<StandardMaterial>,
+    /// The width and height of the image the camera renders into.
+    resolution: u32,
+}
+
+/// The meshes and material that viewer blocks are built from.
+#[derive(Resource)]
+struct ViewerAssets {
+    block: Handle<Mesh>,
+    screen: Handle<Mesh>,
+    frame: Handle<StandardMaterial>,
+}
+
+fn setup_viewer_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ViewerAssets {
+        block: meshes.add(Cuboid::from_length(1.0)),
+        screen: meshes.add(Rectangle::from_length(0.9)),
+        frame: materials.add(VIEWER_FRAME_COLOR),
+    });
+}
+
+/// The horizontal direction the player faces.
+fn facing(rotation: &Rotation) -> Vec3 {
+    Vec3::new(rotation.radians_y.sin(), 0.0, rotation.radians_y.cos())
+}
+
+/// Marks the spot the player stands at, looking the way they face, as the
+/// view for new viewers.
+fn mark_viewer_lens(
+    input: Res<ButtonInput<KeyCode>>,
+    mut lens: ResMut<ViewerLens>,
+    players: Query<(&Position, &Rotation), With<Player>>,
+) {
+    if !input.just_pressed(KeyCode::KeyG) {
+        return;
+    }
+    let Ok((position, rotation)) = players.get_single() else {
+        return;
+    };
+
+    let eye = position.current + Vec3::Y * EYE_HEIGHT;
+    lens.0 = Some(Transform::from_translation(eye).looking_to(facing(rotation), Vec3::Y));
+    info!("Viewers will look from {eye}");
+}
+
+/// Places a viewer showing the marked view in front of the player.
+fn place_viewer(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    assets: Res<ViewerAssets>,
+    lens: Res<ViewerLens>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    players: Query<(&Position, &Rotation), With<Player>>,
+) {
+    if !input.just_pressed(KeyCode::KeyV) || input.pressed(KeyCode::ShiftLeft) {
+        return;
+    }
+    let Ok((position, rotation)) = players.get_single() else {
+        return;
+    };
+    let Some(lens) = lens.0 else {
+        info!("Mark a view for the viewer with G first");
+        return;
+    };
+
+    let image = images.add(viewer_image(MIN_VIEWER_RESOLUTION));
+    let camera = commands
+        .spawn(Camera3dBundle {
+            camera: Camera {
+                target: RenderTarget::Image(image.clone()),
+                // Render before the main camera, which shows the result.
+                order: -1,
+                is_active: false,
+                ..default()
+            },
+            transform: lens,
+            ..default()
+        })
+        .id();
+    let screen = materials.add(StandardMaterial {
+        base_color_texture: Some(image.clone()),
+        unlit: true,
+        ..default()
+    });
+
+    // The block's front face, which the screen is on, faces the player.
+    let forward = facing(rotation);
+    let mut center = (position.current + forward * PLACE_DISTANCE).floor() + Vec3::splat(0.5);
+    center.y = center.y.max(0.5);
+    commands
+        .spawn((
+            PbrBundle {
+                mesh: assets.block.clone(),
+                material: assets.frame.clone(),
+                transform: Transform::from_translation(center).looking_to(forward, Vec3::Y),
+                ..default()
+            },
+            Viewer {
+                camera,
+                image,
+                screen: screen.clone(),
+                resolution: MIN_VIEWER_RESOLUTION,
+            },
+        ))
+        .with_children(|block| {
+            block.spawn(PbrBundle {
+                mesh: assets.screen.clone(),
+                material: screen,
+                transform: Transform::from_xyz(0.0, 0.0, 0.501),
+                ..default()
+            });
+        });
+}
+
+/// Removes the viewer nearest the player, along with its camera.
+fn remove_viewer(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    players: Query<&Position, With<Player>>,
+    viewers: Query<(Entity, &Viewer, &Transform)>,
+) {
+    if !input.just_pressed(KeyCode::KeyV) || !input.pressed(KeyCode::ShiftLeft) {
+        return;
+    }
+    let Ok(position) = players.get_single() else {
+        return;
+    };
+
+    let nearest = viewers
+        .iter()
+        .map(|(entity, viewer, transform)| {
+            let distance = transform.translation.distance(position.current);
+            (entity, viewer, distance)
+        })
+        .filter(|(_, _, distance)| *distance < REMOVE_REACH)
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    if let Some((entity, viewer, _)) = nearest {
+        commands.entity(entity).despawn_recursive();
+        commands.entity(viewer.camera).despawn_recursive();
+        images.remove(&viewer.image);
+        materials.remove(&viewer.screen);
+    }
+}
+
+/// A square image for a viewer's camera to render into.
+fn viewer_image(resolution: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+/// Lets only the viewers nearest the player render, each at a resolution
+/// that falls off with distance. Viewers that stop rendering keep showing
+/// their last image.
+fn schedule_viewers(
+    players: Query<&Transform, With<Player>>,
+    mut viewers: Query<(&mut Viewer, &Transform), Without<Player>>,
+    mut cameras: Query<&mut Camera>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+
+    let mut viewers: Vec<_> = viewers
+        .iter_mut()
+        .map(|(viewer, transform)| (transform.translation.distance(player.translation), viewer))
+        .collect();
+    viewers.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    for (rank, (distance, mut viewer)) in viewers.into_iter().enumerate() {
+        let active = rank < MAX_ACTIVE_VIEWERS && distance < VIEWER_RANGE;
+        let Ok(mut camera) = cameras.get_mut(viewer.camera) else {
+            continue;
+        };
+        if camera.is_active != active {
+            camera.is_active = active;
+        }
+        if !active {
+            continue;
+        }
+
+        let halvings = (distance / RESOLUTION_HALVING_DISTANCE) as u32;
+        let resolution = MAX_VIEWER_RESOLUTION
+            .checked_shr(halvings)
+            .unwrap_or(0)
+            .max(MIN_VIEWER_RESOLUTION);
+        if resolution == viewer.resolution {
+            continue;
+        }
+        viewer.resolution = resolution;
+        if let Some(image) = images.get_mut(&viewer.image) {
+            image.resize(Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            });
+        }
+        // Touch the screen's material so it picks up the resized texture.
+        materials.get_mut(&viewer.screen);
+    }
+}
@@ -0,0 +1,287 @@
+//! Visual regression tests, only built with the `visual-tests` feature.
+//!
+//! Started with `--visual-test`, the game stops its clock and renders each
+//! of the [`SHOTS`] in turn, from a fixed camera pose at a fixed time of
+//! day, then compares the frame with its reference image in
+//! `tests/visual/`. A shot passes when only a few pixels differ by more than
+//! a little, which allows for small differences between GPUs and drivers.
+//! The results are logged, and the game exits with status 1 if any shot
+//! failed or had no reference to compare with. Adding `--record` saves the
+//! frames as the new references instead.
+//!
+//! The window is set to a fixed size, since frames of different sizes can't
+//! be compared. With the clock stopped from the first frame, mobs, clouds
+//! and animations stay where they started, so only the rendering can change
+//! the picture.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+        view::screenshot::ScreenshotManager,
+    },
+    transform::TransformSystem,
+    window::{PrimaryWindow, WindowResolution},
+};
+
+use crate::{day_night::TimeOfDay, MainCamera};
+
+/// The directory the reference images are kept in.
+const REFERENCE_DIR: &str = "tests/visual";
+/// The size the window is set to for the shots.
+const SHOT_WIDTH: f32 = 1280.0;
+const SHOT_HEIGHT: f32 = 720.0;
+/// How many frames are rendered before the first shot, so the models and
+/// textures have loaded and the pipelines compiled.
+const FIRST_SHOT_WARMUP_FRAMES: u32 = 120;
+/// How many frames are rendered before each later shot, so the shadows and
+/// lighting catch up with the new camera and time of day.
+const SHOT_WARMUP_FRAMES: u32 = 10;
+/// How far apart a channel of a pixel can be from the reference, out of
+/// 255, before the pixel counts as different.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// The share of pixels that can be different before the shot fails.
+const MAX_DIFFERENT_PIXELS: f32 = 0.005;
+
+/// Where each shot is taken from.
+struct Shot {
+    name: &'static str,
+    camera: Vec3,
+    look_at: Vec3,
+    /// The time of day, which sets the sun and sky.
+    hour: f32,
+}
+
+/// The shots taken, covering the scene under the light of different times
+/// of day.
+const SHOTS: [Shot; 4] = [
+    Shot {
+        name: "noon",
+        camera: Vec3::new(8.0, 8.0, 0.0),
+        look_at: Vec3::ZERO,
+        hour: 12.0,
+    },
+    Shot {
+        name: "evening",
+        camera: Vec3::new(-10.0, 5.0, 10.0),
+        look_at: Vec3::ZERO,
+        hour: 18.5,
+    },
+    Shot {
+        name: "midnight",
+        camera: Vec3::new(8.0, 8.0, 0.0),
+        look_at: Vec3::ZERO,
+        hour: 0.0,
+    },
+    Shot {
+        name: "fox_close_up",
+        camera: Vec3::new(0.0, 1.2, 3.0),
+        look_at: Vec3::new(0.0, 0.5, 0.0),
+        hour: 9.0,
+    },
+];
+
+pub struct VisualTestPlugin;
+
+impl Plugin for VisualTestPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = std::env::args().collect();
+        if !args.iter().any(|arg| arg == "--visual-test") {
+            return;
+        }
+        app.insert_resource(VisualTest {
+            record: args.iter().any(|arg| arg == "--record"),
+            shot: 0,
+            frames_left: FIRST_SHOT_WARMUP_FRAMES,
+            capture: None,
+            failures: Vec::new(),
+        })
+        .add_systems(Startup, size_window)
+        .add_systems(
+            PostUpdate,
+            (
+                stop_clock,
+                pose_shot.before(TransformSystem::TransformPropagate),
+            ),
+        )
+        .add_systems(Last, take_shots);
+    }
+}
+
+/// How far the test has got.
+#[derive(Resource)]
+struct VisualTest {
+    /// Whether the shots are saved as the references rather than compared
+    /// with them.
+    record: bool,
+    /// The index of the shot being taken.
+    shot: usize,
+    /// How many more frames to render before the shot is taken.
+    frames_left: u32,
+    /// Where the frame of the shot is put once it has been rendered, after
+    /// it was asked for.
+    capture: Option<Arc<Mutex<Option<Image>>>>,
+    /// What went wrong with each shot that failed.
+    failures: Vec<String>,
+}
+
+fn size_window(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    for mut window in windows.iter_mut() {
+        window.resolution = WindowResolution::new(SHOT_WIDTH, SHOT_HEIGHT);
+    }
+}
+
+/// Keeps the game clock from moving at all. The clock is advanced at the
+/// start of each frame, so pausing it at the end of every frame holds it
+/// still whatever the rest of the game does with it.
+fn stop_clock(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+/// Puts the camera and the sun where the shot being taken wants them.
+fn pose_shot(
+    test: Res<VisualTest>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let Some(shot) = SHOTS.get(test.shot) else {
+        return;
+    };
+    time_of_day.hour = shot.hour;
+    for mut transform in cameras.iter_mut() {
+        *transform = Transform::from_translation(shot.camera).looking_at(shot.look_at, Vec3::Y);
+    }
+}
+
+/// Takes each shot once it has had time to settle, then checks or saves it,
+/// and exits with the results after the last one.
+fn take_shots(
+    mut test: ResMut<VisualTest>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    windows: Query<Entity, With<PrimaryWindow>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let test = &mut *test;
+    if let Some(capture) = &test.capture {
+        let Some(frame) = capture.lock().ok().and_then(|mut frame| frame.take()) else {
+            return;
+        };
+        test.capture = None;
+        let shot = &SHOTS[test.shot];
+        let result = if test.record {
+            record(shot, frame)
+        } else {
+            compare(shot, frame)
+        };
+        match result {
+            Ok(done) => info!("{}: {done}", shot.name),
+            Err(error) => {
+                error!("{}: {error}", shot.name);
+                test.failures.push(format!("{}: {error}", shot.name));
+            }
+        }
+
+        test.shot += 1;
+        test.frames_left = SHOT_WARMUP_FRAMES;
+        if test.shot == SHOTS.len() {
+            if test.failures.is_empty() {
+                info!("All {} shots passed", SHOTS.len());
+                exit.send(AppExit::Success);
+            } else {
+                error!(
+                    "{} of {} shots failed:\n{}",
+                    test.failures.len(),
+                    SHOTS.len(),
+                    test.failures.join("\n")
+                );
+                exit.send(AppExit::from_code(1));
+            }
+        }
+        return;
+    }
+
+    if test.shot >= SHOTS.len() {
+        return;
+    }
+    if test.frames_left > 0 {
+        test.frames_left -= 1;
+        return;
+    }
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let capture = Arc::new(Mutex::new(None));
+    let rendered = capture.clone();
+    let taken = screenshot_manager.take_screenshot(window, move |frame| {
+        if let Ok(mut rendered) = rendered.lock() {
+            *rendered = Some(frame);
+        }
+    });
+    if taken.is_ok() {
+        test.capture = Some(capture);
+    }
+}
+
+fn reference_path(shot: &Shot) -> PathBuf {
+    PathBuf::from(REFERENCE_DIR).join(format!("{}.png", shot.name))
+}
+
+fn record(shot: &Shot, frame: Image) -> Result<String, Box<dyn std::error::Error>> {
+    let path = reference_path(shot);
+    fs::create_dir_all(REFERENCE_DIR)?;
+    frame.try_into_dynamic()?.to_rgb8().save(&path)?;
+    Ok(format!("recorded {}", path.display()))
+}
+
+fn compare(shot: &Shot, frame: Image) -> Result<String, Box<dyn std::error::Error>> {
+    let path = reference_path(shot);
+    let bytes =
+        fs::read(&path).map_err(|error| format!("no reference at {}: {error}", path.display()))?;
+    let reference = Image::from_buffer(
+        &bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    )?
+    .try_into_dynamic()?
+    .to_rgb8();
+    let frame = frame.try_into_dynamic()?.to_rgb8();
+
+    if frame.dimensions() != reference.dimensions() {
+        return Err(format!(
+            "the frame is {:?} but the reference is {:?}",
+            frame.dimensions(),
+            reference.dimensions()
+        )
+        .into());
+    }
+    let different = frame
+        .as_raw()
+        .chunks_exact(3)
+        .zip(reference.as_raw().chunks_exact(3))
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(*b)
+                .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let share = different as f32 / (frame.width() * frame.height()).max(1) as f32;
+    if share > MAX_DIFFERENT_PIXELS {
+        return Err(format!(
+            "{:.2}% of pixels differ from the reference, more than {:.2}%",
+            share * 100.0,
+            MAX_DIFFERENT_PIXELS * 100.0
+        )
+        .into());
+    }
+    Ok(format!("passed, {:.2}% of pixels differ", share * 100.0))
+}
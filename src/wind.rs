@@ -0,0 +1,125 @@
+//! A global wind that slowly changes direction and gusts over time.
+//!
+//! Anything that should drift with the weather (the clouds, and projectiles
+//! and the debris they throw up) reads the [`Wind`] resource instead of
+//! keeping its own notion of wind. The weather sets where the wind blows
+//! from and how hard with [`Wind::set_weather`]; for now that is the `/wind`
+//! chat command.
+
+use bevy::prelude::*;
+
+use crate::{
+    chat::{ChatCommandsExt, ChatLog, CommandArgs},
+    simulation::WorldSimulation,
+};
+
+/// How fast the wind heading wanders back and forth.
+const WIND_TURN_SPEED: f32 = 0.03;
+/// How far the wind heading can wander away from its prevailing direction.
+const WIND_TURN_RANGE: f32 = 0.8;
+/// How often gusts come and go, in cycles per second.
+const GUST_FREQUENCY: f32 = 0.35;
+
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Wind>()
+            .add_chat_command(
+                "wind",
+                "[<heading> <strength> [gustiness]]",
+                "Shows or sets the weather's wind",
+                wind_command,
+            )
+            .add_systems(Update, update_wind.in_set(WorldSimulation));
+    }
+}
+
+/// The current wind, shared by everything that is pushed around by it.
+#[derive(Resource)]
+pub struct Wind {
+    /// The horizontal direction the wind blows towards, on the XZ plane.
+    pub direction: Vec2,
+    /// The current wind speed in world units per second, gusts included.
+    pub strength: f32,
+    /// The heading the wind wanders around, in radians. Set by the weather.
+    prevailing_heading: f32,
+    /// The steady wind speed without gusts. Set by the weather.
+    base_strength: f32,
+    /// How much gusts add on top of `base_strength`, from 0 (none) to 1.
+    gustiness: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::X,
+            strength: 1.5,
+            prevailing_heading: 0.0,
+            base_strength: 1.5,
+            gustiness: 0.4,
+        }
+    }
+}
+
+impl Wind {
+    /// Sets the weather's wind: the heading it wanders around, in radians,
+    /// its steady speed, and how much gusts add on top, from 0 to 1.
+    pub fn set_weather(&mut self, prevailing_heading: f32, base_strength: f32, gustiness: f32) {
+        self.prevailing_heading = prevailing_heading.rem_euclid(std::f32::consts::TAU);
+        self.base_strength = base_strength.max(0.0);
+        self.gustiness = gustiness.clamp(0.0, 1.0);
+    }
+}
+
+/// Shows the wind, or sets the weather's heading in degrees, its speed and,
+/// optionally, its gustiness.
+fn wind_command(In(args): In<CommandArgs>, mut wind: ResMut<Wind>, mut log: ResMut<ChatLog>) {
+    if args.is_empty() {
+        log.info(format!(
+            "The wind blows towards {:.0}° at {:.1}, around {:.0}° at {:.1} with {:.0}% gusts",
+            wind.direction.to_angle().to_degrees().rem_euclid(360.0),
+            wind.strength,
+            wind.prevailing_heading.to_degrees(),
+            wind.base_strength,
+            wind.gustiness * 100.0
+        ));
+        return;
+    }
+
+    let numbers: Option<Vec<f32>> = args
+        .iter()
+        .map(|arg| arg.parse::<f32>().ok().filter(|number| number.is_finite()))
+        .collect();
+    let (heading, strength, gustiness) = match numbers.as_deref() {
+        Some(&[heading, strength]) => (heading, strength, wind.gustiness),
+        Some(&[heading, strength, gustiness]) => (heading, strength, gustiness),
+        _ => {
+            log.info("Usage: /wind [<heading in degrees> <strength> [gustiness 0-1]]");
+            return;
+        }
+    };
+    wind.set_weather(heading.to_radians(), strength, gustiness);
+    log.info(format!(
+        "Set the wind to {:.0}° at {:.1} with {:.0}% gusts",
+        wind.prevailing_heading.to_degrees(),
+        wind.base_strength,
+        wind.gustiness * 100.0
+    ));
+}
+
+/// Moves the wind heading and strength along smooth periodic curves, so the
+/// wind never changes abruptly.
+fn update_wind(time: Res<Time>, mut wind: ResMut<Wind>) {
+    let t = time.elapsed_seconds();
+
+    // Two incommensurate sine waves keep the wander from visibly repeating.
+    let wander = (t * WIND_TURN_SPEED).sin() * 0.7 + (t * WIND_TURN_SPEED * 2.3 + 1.3).sin() * 0.3;
+    let heading = wind.prevailing_heading + wander * WIND_TURN_RANGE;
+    wind.direction = Vec2::from_angle(heading);
+
+    let gust = ((t * GUST_FREQUENCY * std::f32::consts::TAU).sin() * 0.6
+        + (t * GUST_FREQUENCY * 2.7).sin() * 0.4)
+        .max(0.0);
+    wind.strength = wind.base_strength * (1.0 + gust * wind.gustiness);
+}